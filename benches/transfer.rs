@@ -0,0 +1,48 @@
+//! Throughput of a single file sent over loopback TCP, for a range of chunk and file sizes.
+//! Run with `cargo bench --bench transfer`, or against a real host with `junkanoo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use junkanoo::bench::loopback_transfer;
+use std::fs;
+use tempfile::TempDir;
+
+const KIB: usize = 1024;
+const MIB: usize = 1024 * KIB;
+
+const FILE_SIZES: [usize; 3] = [64 * KIB, 4 * MIB, 64 * MIB];
+const CHUNK_SIZES: [usize; 5] = [16 * KIB, 64 * KIB, 256 * KIB, MIB, 4 * MIB];
+
+fn transfer(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let source = TempDir::new().unwrap();
+    let destination = TempDir::new().unwrap();
+
+    let mut group = c.benchmark_group("loopback_transfer");
+    group.sample_size(10);
+    for file_size in FILE_SIZES {
+        let path = source.path().join(format!("{file_size}.bin"));
+        // Varied bytes, so compression or hashing shortcuts can't skew the numbers
+        #[allow(clippy::cast_possible_truncation)]
+        let contents: Vec<u8> = (0..file_size).map(|i| (i * 31 % 251) as u8).collect();
+        fs::write(&path, contents).unwrap();
+
+        group.throughput(Throughput::Bytes(file_size as u64));
+        for chunk_size in CHUNK_SIZES {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}KiB chunks", chunk_size / KIB), file_size),
+                &chunk_size,
+                |b, &chunk_size| {
+                    b.iter(|| {
+                        runtime
+                            .block_on(loopback_transfer(&path, destination.path(), chunk_size))
+                            .unwrap()
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, transfer);
+criterion_main!(benches);
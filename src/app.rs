@@ -1,22 +1,98 @@
-use crate::service::node::Client;
-use libp2p::{Multiaddr, PeerId};
+use crate::cli::preview;
+use crate::cli::theme::Theme;
+use crate::config::{NavigationConfig, NotificationConfig, Severity};
+use crate::keys::KeyBindings;
+use crate::plan::{self, DownloadPlan};
+use crate::report::SessionReport;
+use crate::sensitive;
+use crate::service::archive::ArchiveFormat;
+use crate::service::client::NetworkClient;
+use crate::service::greeting::Greeting;
+use crate::service::hashing::ManifestDiff;
+use crate::service::limiter::BandwidthSchedule;
+use crate::service::node::{FileConflict, RequestedFile};
+use crate::service::probe::TransportChoice;
+use crate::service::utils::{self, ConflictPolicy, SymlinkPolicy};
+use crate::transfers::{ShareStats, Transfer, TransferHistory, TransferManager, TransferSummary};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tokio::sync::mpsc::Sender;
 
+/// Subdirectory downloads of a labelled share are saved to. Separators and leading dots
+/// are dropped so a label can't point outside the working directory.
+#[must_use]
+pub fn label_directory(label: &str) -> Option<PathBuf> {
+    let name: String = label
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    (!name.is_empty()).then(|| PathBuf::from(name))
+}
+
+/// Parse an address entered in the UI, it must name the peer to download from.
+///
+/// # Errors
+///
+/// If the input is not an address or doesn't end in a peer ID.
+pub fn parse_peer_address(input: &str) -> Result<Multiaddr, String> {
+    let addr: Multiaddr = input
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid peer address: {e}"))?;
+    if !addr
+        .iter()
+        .any(|protocol| matches!(protocol, Protocol::P2p(_)))
+    {
+        return Err("Peer address must contain a peer ID component (/p2p/...)".to_string());
+    }
+    Ok(addr)
+}
+
+/// How long the copy icon shows a checkmark after the address was copied.
+const CLIPBOARD_FEEDBACK: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Default for `--confirm-above`, 5 GB.
+const DEFAULT_CONFIRM_THRESHOLD: u64 = 5_000_000_000;
+
+/// Characters of a peer ID shown where there's no room for all of it.
+const PEER_ID_SUFFIX: usize = 8;
+
+/// How long a remote file stays highlighted before its preview is fetched, so scrolling
+/// through a listing doesn't ask the host for every file passed.
+const PREVIEW_DELAY: std::time::Duration = std::time::Duration::from_millis(150);
+
+/// Remote previews kept, the least recently fetched are dropped first.
+const PREVIEW_CACHE_SIZE: usize = 64;
+
 #[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct App {
     pub directory_items: Vec<DirectoryItem>,
     pub all_shared_items: Vec<DirectoryItem>,
     pub directory_cache: HashMap<PathBuf, Vec<DirectoryItem>>,
     pub selected_index: Option<usize>,
+    /// Where visual mode (`V`) started, `y` and `n` act on the rows from here to the cursor.
+    pub selection_anchor: Option<usize>,
+    /// First row of the file list on screen, moved along so the cursor stays in view.
+    pub list_offset: usize,
     pub current_path: PathBuf,
     pub connection_state: ConnectionState,
     pub peer_id: PeerId,
+    /// Name peers see instead of our peer ID.
+    pub display_name: Option<String>,
     pub connected_peer_id: Option<PeerId>,
+    /// What a peer told about itself in the handshake, kept across reconnects.
+    pub peer_greeting: Option<(PeerId, Greeting)>,
+    /// Software each peer reported over identify, e.g. `junkanoo/0.3.1`.
+    pub peer_agents: HashMap<PeerId, String>,
     pub listening_addrs: Vec<Multiaddr>,
     pub state: AppState,
     pub is_host: bool,
@@ -26,9 +102,105 @@ pub struct App {
     pub items_to_download: HashSet<PathBuf>,
     pub items_being_downloaded: HashSet<PathBuf>,
     pub warning: Option<Warning>,
+    pub notifications: NotificationConfig,
+    pub navigation: NavigationConfig,
+    pub keys: KeyBindings,
+    pub theme: Theme,
+    pub key_repeat: KeyRepeat,
+    pub bookmarks: BTreeMap<String, PathBuf>,
+    /// Transfer rate limits by time of day from the config.
+    pub bandwidth: BandwidthSchedule,
+    /// Highlighted entry while the bookmark picker is open.
+    pub bookmark_picker: Option<usize>,
     pub refresh_sender: Option<Sender<()>>,
-    pub client: Option<Client>,
-    pub clipboard_success: bool,
+    /// Told whenever what the host shares may have changed, so it is published again.
+    pub share_sender: Option<Sender<()>>,
+    /// Address typed by a downloader started without one, shown while it is being entered.
+    pub address_input: Option<String>,
+    /// Hands the entered address to the network task, which dials it.
+    pub address_sender: Option<Sender<Multiaddr>>,
+    pub client: Option<Arc<dyn NetworkClient>>,
+    /// When the address was last copied, its icon shows a checkmark for a moment after.
+    pub clipboard_copied_at: Option<tokio::time::Instant>,
+    pub dht_peers: Option<usize>,
+    /// Transports picked after probing the network at startup.
+    pub transport: TransportChoice,
+    /// Quality score of the link to the connected peer and its average round trip time.
+    pub connection_quality: Option<(u8, std::time::Duration)>,
+    pub share_expires_at: Option<std::time::Instant>,
+    pub share_opens_at: Option<std::time::SystemTime>,
+    /// Name of the share, set by the host and shown to both sides.
+    pub share_label: Option<String>,
+    /// Code downloaders can give instead of an address, see `download --code`.
+    pub share_code: Option<String>,
+    /// Whether the share code can be looked up yet.
+    pub share_code_published: bool,
+    pub should_quit: bool,
+    /// Quit once the transfer is done, see `--exit-on-complete`.
+    pub exit_on_complete: bool,
+    /// Tell the desktop when transfers end, see `--notify`.
+    pub desktop_notifications: bool,
+    /// Download the whole share as deltas against existing copies, see `junkanoo sync`.
+    pub sync: bool,
+    /// Downloads go here instead of a directory named after the share, see `sync [DIR]`.
+    pub sync_directory: Option<PathBuf>,
+    /// Files `sync --push` offered that the host is still fetching.
+    pub pushing: Option<HashSet<String>>,
+    /// Only work out what a download would do, see `--dry-run`.
+    pub dry_run: bool,
+    /// Selected directories arrive as one archive each in this format, see `--archive`.
+    pub archive: Option<ArchiveFormat>,
+    /// Set by a dry run, printed once the UI closed.
+    pub download_plan: Option<DownloadPlan>,
+    /// What the downloads of this session came to, printed once the UI closed.
+    pub download_summary: Option<TransferSummary>,
+    /// Process exit code once the UI loop ends.
+    pub exit_code: i32,
+    pub transfers: TransferManager,
+    /// Starts of remote files fetched for the preview pane.
+    pub remote_previews: PreviewCache,
+    /// Remote directories listed so far, their items are in `all_shared_items`.
+    pub remote_listing: RemoteListing,
+    /// Transfers from earlier sessions, to compare throughput against.
+    pub transfer_history: TransferHistory,
+    pub show_transfers: bool,
+    /// Uploads since the share started, shown on the host's dashboard.
+    pub share_stats: ShareStats,
+    /// Files delivered in this session, written out with `--report`.
+    pub session_report: SessionReport,
+    /// Up, Down, `p` and `c` act on the transfer list rather than the files.
+    pub transfers_focused: bool,
+    /// Highlighted entry of the transfer list.
+    pub selected_transfer: usize,
+    /// Up, Down and `x` act on the listening addresses rather than the files.
+    pub addresses_focused: bool,
+    /// Highlighted listening address, the one `x` copies.
+    pub selected_address: usize,
+    /// Details of the connected peer, including the raw peer ID to verify it by.
+    pub show_peer_info: bool,
+    /// Downloads larger than this many bytes need a confirmation first.
+    pub confirm_threshold: u64,
+    /// Set while a large download waits for the user to confirm it.
+    pub confirming_download: bool,
+    pub sort_order: SortOrder,
+    /// Changes since the shared directory was last shared, awaiting the host's go-ahead.
+    pub manifest_diff: Option<ManifestDiff>,
+    /// Files of a download that exist locally, awaiting a decision, see `--on-conflict ask`.
+    pub conflict_prompt: Option<ConflictPrompt>,
+    /// Filter applied to the listing, see [`fuzzy_match`].
+    pub search_query: String,
+    /// Whether key presses go to the search line.
+    pub search_active: bool,
+    /// Include dotfiles in the listing.
+    pub show_hidden: bool,
+    /// How symlinks in shared directories are handled, skipped ones aren't listed either.
+    pub symlinks: SymlinkPolicy,
+    /// Name patterns of files that are only shared after the host confirmed them.
+    pub sensitive_patterns: Vec<String>,
+    /// Selected sensitive paths held back until the host decides on them.
+    pub sensitive_pending: Vec<PathBuf>,
+    /// Sensitive paths the host chose to share anyway.
+    pub sensitive_confirmed: HashSet<PathBuf>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -41,6 +213,229 @@ pub struct DirectoryItem {
     pub depth: usize,
     pub selected: bool,
     pub preview: String,
+    /// Size in bytes, zero for directories.
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub modified: Option<SystemTime>,
+    /// SHA-256 of the file's contents, published by the host.
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl DirectoryItem {
+    /// Size and modification time of the item on disk, unknown if it can't be read.
+    #[must_use]
+    pub fn read_metadata(path: &Path, is_dir: bool) -> (u64, Option<SystemTime>) {
+        fs::metadata(path).map_or((0, None), |metadata| {
+            let size = if is_dir { 0 } else { metadata.len() };
+            (size, metadata.modified().ok())
+        })
+    }
+}
+
+/// Existing files a download asked about, with what to do about each so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictPrompt {
+    pub conflicts: Vec<FileConflict>,
+    /// Overwrite, skip or rename, one for each conflict. Skipping is the default.
+    pub decisions: Vec<ConflictPolicy>,
+    /// Highlighted conflict.
+    pub selected: usize,
+}
+
+impl ConflictPrompt {
+    #[must_use]
+    pub fn new(conflicts: Vec<FileConflict>) -> Self {
+        Self {
+            decisions: vec![ConflictPolicy::Skip; conflicts.len()],
+            conflicts,
+            selected: 0,
+        }
+    }
+
+    pub fn navigate(&mut self, down: bool) {
+        let last = self.conflicts.len().saturating_sub(1);
+        self.selected = if down {
+            (self.selected + 1).min(last)
+        } else {
+            self.selected.saturating_sub(1)
+        };
+    }
+
+    /// Next decision for the highlighted file: skip, overwrite, rename, then skip again.
+    pub fn cycle(&mut self) {
+        if let Some(decision) = self.decisions.get_mut(self.selected) {
+            *decision = match decision {
+                ConflictPolicy::Skip => ConflictPolicy::Overwrite,
+                ConflictPolicy::Overwrite => ConflictPolicy::Rename,
+                ConflictPolicy::Rename | ConflictPolicy::Ask => ConflictPolicy::Skip,
+            };
+        }
+    }
+
+    pub fn decide_all(&mut self, decision: ConflictPolicy) {
+        self.decisions.fill(decision);
+    }
+}
+
+/// Starts of remote files fetched for the preview pane. A preview is only used while the
+/// host's hash of the file is the one it was fetched for.
+#[derive(Debug, Clone, Default)]
+pub struct PreviewCache {
+    previews: HashMap<PathBuf, (Option<String>, String)>,
+    /// Cached paths, oldest first.
+    order: VecDeque<PathBuf>,
+    loading: HashSet<PathBuf>,
+    /// The file highlighted last and since when.
+    highlighted: Option<(PathBuf, Instant)>,
+}
+
+impl PreviewCache {
+    #[must_use]
+    pub fn get(&self, item: &DirectoryItem) -> Option<&str> {
+        self.previews
+            .get(&item.path)
+            .filter(|(hash, _)| *hash == item.hash)
+            .map(|(_, preview)| preview.as_str())
+    }
+
+    /// Whether the preview of `item`, the highlighted file, should be fetched now. It is
+    /// once it stayed highlighted for [`PREVIEW_DELAY`], unless cached or on its way.
+    pub fn due(&mut self, item: Option<&DirectoryItem>, now: Instant) -> bool {
+        let Some(item) = item.filter(|item| !item.is_dir) else {
+            self.highlighted = None;
+            return false;
+        };
+        let since = match &self.highlighted {
+            Some((path, since)) if *path == item.path => *since,
+            _ => {
+                self.highlighted = Some((item.path.clone(), now));
+                now
+            }
+        };
+        if now.duration_since(since) < PREVIEW_DELAY
+            || self.loading.contains(&item.path)
+            || self.get(item).is_some()
+        {
+            return false;
+        }
+        self.loading.insert(item.path.clone());
+        true
+    }
+
+    /// Mark a preview as requested, e.g. a larger one than the cached.
+    pub fn start(&mut self, path: &Path) {
+        self.loading.insert(path.to_path_buf());
+    }
+
+    /// Keep the preview fetched for the file at `path` with `hash`.
+    pub fn insert(&mut self, path: PathBuf, hash: Option<String>, preview: String) {
+        self.loading.remove(&path);
+        self.order.retain(|cached| *cached != path);
+        self.order.push_back(path.clone());
+        self.previews.insert(path, (hash, preview));
+        while self.order.len() > PREVIEW_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.previews.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Which directories of the host's share are known. Hosts that negotiated `list` send one
+/// directory at a time as the downloader enters it, those of older releases everything.
+///
+/// Listings are kept by path with the generation they were received in. The generation
+/// goes up whenever the host changes its share, older listings are fetched again when
+/// next needed.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteListing {
+    /// Whether directories are listed as they are entered.
+    pub on_demand: bool,
+    generation: u64,
+    /// Directories whose children are known, the top of the share as the empty path.
+    listed: HashMap<PathBuf, u64>,
+    /// Directories everything below which is known.
+    expanded: HashMap<PathBuf, u64>,
+    /// Selected directories to list everything below of, to download them.
+    expanding: HashSet<PathBuf>,
+    /// Listings asked for, with the generation they were asked for in.
+    loading: HashMap<PathBuf, u64>,
+    /// Directories to list again although they are known, see [`Self::refresh`].
+    stale: HashSet<PathBuf>,
+}
+
+impl RemoteListing {
+    #[must_use]
+    pub fn is_listed(&self, directory: &Path) -> bool {
+        !self.stale.contains(directory)
+            && (self.listed.get(directory) == Some(&self.generation) || self.is_expanded(directory))
+    }
+
+    #[must_use]
+    pub fn is_expanded(&self, directory: &Path) -> bool {
+        !self.on_demand
+            || std::iter::once(Path::new(""))
+                .chain(directory.ancestors())
+                .any(|ancestor| self.expanded.get(ancestor) == Some(&self.generation))
+    }
+
+    /// List everything below `directory`, unless already known.
+    pub fn expand(&mut self, directory: &Path) {
+        if !self.is_expanded(directory) {
+            self.expanding.insert(directory.to_path_buf());
+        }
+    }
+
+    /// Whether selected directories are still being listed, downloading now would miss
+    /// their files.
+    #[must_use]
+    pub fn is_expanding(&self) -> bool {
+        !self.expanding.is_empty()
+    }
+
+    /// Mark the listing of `directory` as requested, false if it already is.
+    fn start(&mut self, directory: &Path) -> bool {
+        if self.loading.contains_key(directory) {
+            return false;
+        }
+        self.loading
+            .insert(directory.to_path_buf(), self.generation);
+        true
+    }
+
+    /// Record that `directory` was listed, with `recursive` everything below it. A listing
+    /// asked for before the share changed counts as outdated.
+    pub fn finish(&mut self, directory: PathBuf, recursive: bool) {
+        let generation = self.loading.remove(&directory).unwrap_or(self.generation);
+        self.stale.remove(&directory);
+        if recursive {
+            self.expanding.remove(&directory);
+            self.expanded.insert(directory.clone(), generation);
+        }
+        self.listed.insert(directory, generation);
+    }
+
+    /// Give up on listing `directory` until the host changes its share.
+    pub fn fail(&mut self, directory: PathBuf) {
+        self.loading.remove(&directory);
+        self.expanding.remove(&directory);
+        self.stale.remove(&directory);
+        self.listed.insert(directory, self.generation);
+    }
+
+    /// List `directory` again, e.g. because the user asked to.
+    pub fn refresh(&mut self, directory: &Path) {
+        if self.on_demand {
+            self.stale.insert(directory.to_path_buf());
+        }
+    }
+
+    /// The host changed its share, directories are listed again when next needed.
+    pub const fn invalidate(&mut self) {
+        self.generation += 1;
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -49,16 +444,157 @@ pub enum AppState {
     Download,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+/// How the file browser orders entries, directories always come first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortOrder {
+    /// The next mode for the `s` key: each key ascending, then descending.
+    #[must_use]
+    pub const fn next(self) -> Self {
+        if !self.descending {
+            return Self {
+                key: self.key,
+                descending: true,
+            };
+        }
+        let key = match self.key {
+            SortKey::Name => SortKey::Size,
+            SortKey::Size => SortKey::Modified,
+            SortKey::Modified => SortKey::Type,
+            SortKey::Type => SortKey::Name,
+        };
+        Self {
+            key,
+            descending: false,
+        }
+    }
+
+    #[must_use]
+    pub fn compare(self, a: &DirectoryItem, b: &DirectoryItem) -> std::cmp::Ordering {
+        let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase());
+        let ordering = match self.key {
+            SortKey::Name => by_name(),
+            SortKey::Size => a.size.cmp(&b.size).then_with(by_name),
+            SortKey::Modified => a.modified.cmp(&b.modified).then_with(by_name),
+            SortKey::Type => extension(&a.name)
+                .cmp(&extension(&b.name))
+                .then_with(by_name),
+        };
+        let ordering = if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then(a.depth.cmp(&b.depth))
+            .then(ordering)
+    }
+
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match (self.key, self.descending) {
+            (SortKey::Name, false) => "name ↑",
+            (SortKey::Name, true) => "name ↓",
+            (SortKey::Size, false) => "size ↑",
+            (SortKey::Size, true) => "size ↓",
+            (SortKey::Modified, false) => "modified ↑",
+            (SortKey::Modified, true) => "modified ↓",
+            (SortKey::Type, false) => "type ↑",
+            (SortKey::Type, true) => "type ↓",
+        }
+    }
+}
+
+/// Match `query` as a case-insensitive subsequence of `name`, returning the positions of
+/// the matched characters in `name`.
+pub fn fuzzy_match(query: &str, name: &str) -> Option<Vec<usize>> {
+    let mut positions = Vec::new();
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    for (position, c) in name.chars().enumerate() {
+        let Some(wanted) = query.peek() else {
+            break;
+        };
+        if c.to_lowercase().eq(std::iter::once(*wanted)) {
+            positions.push(position);
+            query.next();
+        }
+    }
+    query.peek().is_none().then_some(positions)
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
 #[derive(Clone, Debug)]
 pub struct Warning {
     pub message: String,
-    pub timer: std::time::Instant,
+    /// When it was shown, on tokio's clock so tests can move time forward.
+    pub timer: tokio::time::Instant,
+    pub severity: Severity,
+}
+
+/// Notices Up or Down being held from how quickly the terminal repeats the key.
+#[derive(Clone, Debug, Default)]
+pub struct KeyRepeat {
+    /// Direction and time of the previous press.
+    last: Option<(bool, Instant)>,
+    repeats: u32,
+}
+
+impl KeyRepeat {
+    /// Register a press, returns how many rows the cursor moves for it.
+    pub fn press(&mut self, down: bool, now: Instant, config: &NavigationConfig) -> usize {
+        let held = self.last.is_some_and(|(last_down, at)| {
+            last_down == down && now.saturating_duration_since(at) <= config.repeat_interval()
+        });
+        self.repeats = if held {
+            self.repeats.saturating_add(1)
+        } else {
+            0
+        };
+        self.last = Some((down, now));
+        if self.repeats >= config.accelerate_after {
+            config.accelerated_rows.max(1)
+        } else {
+            1
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConnectionState {
     Disconnected,
     Connected,
+    /// Lost the peer, this redial attempt is pending.
+    Reconnecting(u32),
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
@@ -68,10 +604,15 @@ impl App {
             all_shared_items: Vec::new(),
             directory_cache: HashMap::new(),
             selected_index: None,
+            selection_anchor: None,
+            list_offset: 0,
             current_path: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
             connection_state: ConnectionState::Disconnected,
             peer_id: PeerId::random(),
+            display_name: None,
             connected_peer_id: None,
+            peer_greeting: None,
+            peer_agents: HashMap::new(),
             state: AppState::Share,
             is_host: true,
             is_loading: false,
@@ -81,16 +622,73 @@ impl App {
             items_to_download: HashSet::new(),
             items_being_downloaded: HashSet::new(),
             warning: None,
+            notifications: NotificationConfig::default(),
+            navigation: NavigationConfig::default(),
+            keys: KeyBindings::default(),
+            theme: Theme::default(),
+            key_repeat: KeyRepeat::default(),
+            bookmarks: BTreeMap::new(),
+            bandwidth: BandwidthSchedule::default(),
+            bookmark_picker: None,
             refresh_sender: None,
+            share_sender: None,
+            address_input: None,
+            address_sender: None,
             client: None,
-            clipboard_success: false,
+            clipboard_copied_at: None,
+            dht_peers: None,
+            transport: TransportChoice::default(),
+            connection_quality: None,
+            share_expires_at: None,
+            share_opens_at: None,
+            share_label: None,
+            share_code: None,
+            share_code_published: false,
+            should_quit: false,
+            exit_on_complete: false,
+            desktop_notifications: false,
+            sync: false,
+            sync_directory: None,
+            pushing: None,
+            dry_run: false,
+            archive: None,
+            download_plan: None,
+            download_summary: None,
+            exit_code: 0,
+            transfers: TransferManager::default(),
+            remote_previews: PreviewCache::default(),
+            remote_listing: RemoteListing::default(),
+            transfer_history: TransferHistory::default(),
+            show_transfers: false,
+            share_stats: ShareStats::default(),
+            session_report: SessionReport::default(),
+            transfers_focused: false,
+            selected_transfer: 0,
+            addresses_focused: false,
+            selected_address: 0,
+            show_peer_info: false,
+            confirm_threshold: DEFAULT_CONFIRM_THRESHOLD,
+            confirming_download: false,
+            sort_order: SortOrder::default(),
+            manifest_diff: None,
+            conflict_prompt: None,
+            search_query: String::new(),
+            search_active: false,
+            show_hidden: false,
+            symlinks: SymlinkPolicy::default(),
+            sensitive_patterns: sensitive::DEFAULT_PATTERNS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            sensitive_pending: Vec::new(),
+            sensitive_confirmed: HashSet::new(),
         };
 
         app.populate_directory_items();
         app
     }
 
-    pub fn set_client(&mut self, client: Client) {
+    pub fn set_client(&mut self, client: Arc<dyn NetworkClient>) {
         self.client = Some(client);
     }
 
@@ -104,10 +702,23 @@ impl App {
             tracing::info!("Current path in download mode: {:?}", current);
             tracing::info!("All shared items: {:?}", self.all_shared_items);
 
-            // In download mode, we want to show all items at the root level
             let mut children: Vec<DirectoryItem> = if current.as_os_str().is_empty() {
-                // At root level, show all items
-                self.all_shared_items.clone()
+                // At root level, show the items that aren't inside a shared directory
+                let directories: HashSet<&Path> = self
+                    .all_shared_items
+                    .iter()
+                    .filter(|item| item.is_dir)
+                    .map(|item| item.path.as_path())
+                    .collect();
+                self.all_shared_items
+                    .iter()
+                    .filter(|item| {
+                        item.path
+                            .parent()
+                            .is_none_or(|parent| !directories.contains(parent))
+                    })
+                    .cloned()
+                    .collect()
             } else {
                 // For subdirectories, filter by parent
                 self.all_shared_items
@@ -126,13 +737,12 @@ impl App {
                     .collect()
             };
 
+            if !self.show_hidden {
+                children.retain(|item| !is_hidden(&item.name));
+            }
             tracing::info!("Found {} items in current directory", children.len());
 
-            children.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
-            });
+            children.sort_by(|a, b| self.sort_order.compare(a, b));
 
             // Update indices for the new items
             for (i, item) in children.iter_mut().enumerate() {
@@ -170,25 +780,11 @@ impl App {
         is_dir: bool,
         index: usize,
     ) -> DirectoryItem {
-        let selected = match self.state {
-            AppState::Share => self.items_to_share.iter().any(|selected_path| {
-                let abs_selected = self.current_path.join(selected_path);
-                path == abs_selected
-            }),
-            AppState::Download => self.items_to_download.iter().any(|selected_path| {
-                tracing::info!(
-                    "Comparing download paths - selected: {:?}, current: {:?}",
-                    selected_path,
-                    path
-                );
-                selected_path == &path
-            }),
-        };
+        let selected = self.selection().contains(&path);
 
         let depth = path
             .strip_prefix(&self.current_path)
-            .map(|rel_path| rel_path.components().count())
-            .unwrap_or(0);
+            .map_or(0, |rel_path| rel_path.components().count());
 
         let display_path = if self.state == AppState::Download {
             // In download mode, use just the name for display
@@ -207,12 +803,19 @@ impl App {
                 |_| "Unable to read file contents".to_string(),
                 |file| {
                     let reader = BufReader::new(file);
-                    let mut buffer = String::new();
-                    reader.take(4000).read_to_string(&mut buffer).ok();
-                    buffer.chars().take(1000).collect()
+                    let mut buffer = Vec::new();
+                    reader.take(4000).read_to_end(&mut buffer).ok();
+                    let preview = preview::from_bytes(&path, &buffer);
+                    // A hex dump has a size of its own
+                    if preview::binary_mime(&preview).is_some() {
+                        preview
+                    } else {
+                        preview.chars().take(1000).collect()
+                    }
                 },
             )
         };
+        let (size, modified) = DirectoryItem::read_metadata(&path, is_dir);
         DirectoryItem {
             name,
             path,         // Keep the absolute path
@@ -222,10 +825,18 @@ impl App {
             depth,
             selected,
             preview,
+            size,
+            modified,
+            hash: None,
         }
     }
 
     pub fn populate_directory_items(&mut self) {
+        self.load_directory_items();
+        self.apply_search();
+    }
+
+    fn load_directory_items(&mut self) {
         if self.handle_download_mode() || self.handle_cached_items() {
             return;
         }
@@ -241,7 +852,7 @@ impl App {
                     .to_string();
                 let is_dir = path.is_dir();
 
-                if self.should_show_item(&path, is_dir) {
+                if self.should_show_item(&path) {
                     self.directory_items
                         .push(self.create_directory_item(path, name, is_dir, index));
                 }
@@ -251,45 +862,24 @@ impl App {
         }
     }
 
-    fn should_show_item(&self, path: &PathBuf, is_dir: bool) -> bool {
-        if self.state == AppState::Share && !self.items_to_share.is_empty() {
-            if let Some(root_dir) = self.get_root_shared_dir() {
-                if self.current_path != root_dir && self.current_path.starts_with(&root_dir) {
-                    return if is_dir {
-                        self.items_to_share.iter().any(|selected_path| {
-                            let abs_selected = self.current_path.join(selected_path);
-                            path.starts_with(&abs_selected) || abs_selected.starts_with(path)
-                        })
-                    } else {
-                        let abs_path = path.clone();
-                        self.items_to_share.iter().any(|selected_path| {
-                            let abs_selected = self.current_path.join(selected_path);
-                            abs_path == abs_selected
-                        })
-                    };
-                }
-            }
+    fn should_show_item(&self, path: &Path) -> bool {
+        if self.symlinks == SymlinkPolicy::Skip && path.is_symlink() {
+            return false;
+        }
+        if !self.show_hidden
+            && path
+                .file_name()
+                .is_some_and(|name| is_hidden(&name.to_string_lossy()))
+        {
+            return false;
         }
         true
     }
 
-    fn get_root_shared_dir(&self) -> Option<PathBuf> {
-        self.items_to_share
-            .iter()
-            .find(|path| path.is_dir())
-            .map(|path| self.current_path.join(path))
-    }
-
     fn sort_and_cache_items(&mut self) {
+        let sort_order = self.sort_order;
         self.directory_items
-            .sort_by(|a, b| match (a.is_dir, b.is_dir) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => match a.depth.cmp(&b.depth) {
-                    std::cmp::Ordering::Equal => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
-                    other => other,
-                },
-            });
+            .sort_by(|a, b| sort_order.compare(a, b));
 
         for (i, item) in self.directory_items.iter_mut().enumerate() {
             item.index = i;
@@ -303,6 +893,181 @@ impl App {
         }
     }
 
+    /// Show or hide the transfer list, it loses focus when hidden.
+    pub const fn toggle_transfers(&mut self) {
+        self.show_transfers = !self.show_transfers;
+        self.transfers_focused &= self.show_transfers;
+    }
+
+    pub fn navigate_transfers(&mut self, down: bool) {
+        let count = self.transfers.transfers().len();
+        self.selected_transfer = if down {
+            (self.selected_transfer + 1).min(count.saturating_sub(1))
+        } else {
+            self.selected_transfer.saturating_sub(1)
+        };
+    }
+
+    /// Move the focus on with Tab: from the files to the transfer list if it is shown, then
+    /// to the listening addresses and back to the files.
+    pub const fn cycle_focus(&mut self) {
+        if self.transfers_focused {
+            self.transfers_focused = false;
+            self.addresses_focused = !self.listening_addrs.is_empty();
+        } else if self.addresses_focused {
+            self.addresses_focused = false;
+        } else if self.show_transfers {
+            self.transfers_focused = true;
+        } else {
+            self.addresses_focused = !self.listening_addrs.is_empty();
+        }
+    }
+
+    pub fn navigate_addresses(&mut self, down: bool) {
+        let count = self.listening_addrs.len();
+        self.selected_address = if down {
+            (self.selected_address + 1).min(count.saturating_sub(1))
+        } else {
+            self.selected_address.saturating_sub(1)
+        };
+    }
+
+    /// A listening address as peers dial it, with our peer ID.
+    #[must_use]
+    pub fn full_address(&self, address: &Multiaddr) -> String {
+        if address
+            .iter()
+            .any(|protocol| matches!(protocol, Protocol::P2p(_)))
+        {
+            address.to_string()
+        } else {
+            format!("{address}/p2p/{}", self.peer_id)
+        }
+    }
+
+    /// The highlighted listening address with our peer ID, the one to copy.
+    #[must_use]
+    pub fn selected_address(&self) -> Option<String> {
+        let last = self.listening_addrs.len().checked_sub(1)?;
+        Some(self.full_address(&self.listening_addrs[self.selected_address.min(last)]))
+    }
+
+    /// The transfer highlighted in the list, if any.
+    #[must_use]
+    pub fn selected_transfer(&self) -> Option<&Transfer> {
+        self.transfers.transfers().get(self.selected_transfer)
+    }
+
+    /// Show or hide dotfiles, cached listings are dropped so the view updates right away.
+    pub fn toggle_hidden(&mut self) {
+        self.show_hidden = !self.show_hidden;
+        self.directory_cache.clear();
+        self.populate_directory_items();
+    }
+
+    /// Selected paths that look sensitive and were not confirmed by the host yet.
+    #[must_use]
+    pub fn unconfirmed_sensitive_items(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self
+            .items_to_share
+            .iter()
+            .filter(|path| !self.sensitive_confirmed.contains(*path))
+            .filter(|path| sensitive::is_sensitive(path, &self.sensitive_patterns))
+            .cloned()
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Share the pending sensitive paths after all.
+    pub fn confirm_sensitive_items(&mut self) {
+        self.sensitive_confirmed
+            .extend(std::mem::take(&mut self.sensitive_pending));
+        self.share_changed();
+    }
+
+    /// Drop the pending sensitive paths from the selection.
+    pub fn unselect_sensitive_items(&mut self) {
+        for path in std::mem::take(&mut self.sensitive_pending) {
+            self.items_to_share.remove(&path);
+        }
+        self.share_changed();
+    }
+
+    /// Publish the share after the host looked at what changed since it was last shared.
+    pub fn confirm_manifest_diff(&mut self) {
+        self.manifest_diff = None;
+        self.share_changed();
+    }
+
+    /// Open the search line, typed characters filter the listing from now on.
+    pub const fn start_search(&mut self) {
+        self.search_active = true;
+    }
+
+    pub fn push_search_char(&mut self, c: char) {
+        self.search_query.push(c);
+        self.populate_directory_items();
+    }
+
+    pub fn pop_search_char(&mut self) {
+        self.search_query.pop();
+        self.populate_directory_items();
+    }
+
+    /// Close the search line and show the whole directory again.
+    pub fn clear_search(&mut self) {
+        self.search_active = false;
+        self.search_query.clear();
+        self.populate_directory_items();
+    }
+
+    /// Keep only the entries matching the search query.
+    fn apply_search(&mut self) {
+        if self.search_query.is_empty() {
+            return;
+        }
+        let query = self.search_query.clone();
+        self.directory_items
+            .retain(|item| fuzzy_match(&query, &item.name).is_some());
+        for (i, item) in self.directory_items.iter_mut().enumerate() {
+            item.index = i;
+        }
+        self.selected_index = if self.directory_items.is_empty() {
+            None
+        } else {
+            Some(
+                self.selected_index
+                    .unwrap_or(0)
+                    .min(self.directory_items.len() - 1),
+            )
+        };
+    }
+
+    /// Switch to the next sort mode and reorder the listing, keeping the cursor on the
+    /// same entry.
+    pub fn cycle_sort(&mut self) {
+        self.sort_order = self.sort_order.next();
+        self.directory_cache.clear();
+
+        let current = self
+            .selected_index
+            .and_then(|index| self.directory_items.get(index))
+            .map(|item| item.path.clone());
+        let sort_order = self.sort_order;
+        self.directory_items
+            .sort_by(|a, b| sort_order.compare(a, b));
+        for (i, item) in self.directory_items.iter_mut().enumerate() {
+            item.index = i;
+        }
+        if let Some(current) = current {
+            self.selected_index = self
+                .directory_items
+                .iter()
+                .position(|item| item.path == current);
+        }
+    }
+
     pub const fn navigate_next_file(&mut self) {
         if self.directory_items.is_empty() {
             return;
@@ -327,6 +1092,61 @@ impl App {
         };
     }
 
+    /// Move the cursor for an Up or Down press, several rows at a time while the key is held.
+    pub fn navigate(&mut self, down: bool, now: Instant) {
+        let rows = self.key_repeat.press(down, now, &self.navigation);
+        for _ in 0..rows {
+            if down {
+                self.navigate_next_file();
+            } else {
+                self.navigate_previous_file();
+            }
+        }
+    }
+
+    /// Open the bookmark picker, or explain how to add bookmarks when there are none.
+    pub fn open_bookmarks(&mut self) {
+        if self.bookmarks.is_empty() {
+            self.set_warning(
+                "No bookmarks yet, add them under [bookmarks] in config.toml".to_string(),
+            );
+            return;
+        }
+        self.bookmark_picker = Some(0);
+    }
+
+    pub fn navigate_bookmarks(&mut self, down: bool) {
+        let Some(index) = self.bookmark_picker else {
+            return;
+        };
+        let last = self.bookmarks.len().saturating_sub(1);
+        self.bookmark_picker = Some(if down {
+            (index + 1).min(last)
+        } else {
+            index.saturating_sub(1)
+        });
+    }
+
+    /// Browse the bookmark at `index` in the picker's order and close the picker.
+    pub fn jump_to_bookmark(&mut self, index: usize) {
+        self.bookmark_picker = None;
+        let Some((name, path)) = self.bookmarks.iter().nth(index) else {
+            return;
+        };
+        // Selections are keyed by absolute path
+        match fs::canonicalize(path) {
+            Ok(path) if path.is_dir() => {
+                self.current_path = path;
+                self.selected_index = None;
+                self.populate_directory_items();
+            }
+            _ => {
+                let message = format!("Bookmark '{name}' points to a missing directory");
+                self.set_warning(message);
+            }
+        }
+    }
+
     pub fn enter_directory(&mut self) -> bool {
         if let Some(index) = self.selected_index {
             if let Some(item) = self.directory_items.get(index) {
@@ -342,190 +1162,292 @@ impl App {
     }
 
     pub fn go_up_previous_directory(&mut self) {
-        if let Some(parent) = self.current_path.parent() {
-            // Check if we're in a shared directory
-            if self.state == AppState::Share {
-                // Get the root shared directory (the first selected directory)
-                if let Some(root_shared_dir) = self.items_to_share.iter().find(|path| path.is_dir())
-                {
-                    // Only allow going up if we're not at or below the root shared directory
-                    if !self.current_path.starts_with(root_shared_dir) {
-                        self.current_path = parent.to_path_buf();
-                        self.populate_directory_items();
-                    }
-                } else {
-                    // If no directory is selected, allow normal navigation
-                    self.current_path = parent.to_path_buf();
-                    self.populate_directory_items();
-                }
-            } else {
-                // In download mode, allow normal navigation
-                self.current_path = parent.to_path_buf();
-                self.populate_directory_items();
+        let Some(parent) = self.current_path.parent() else {
+            return;
+        };
+
+        self.current_path = parent.to_path_buf();
+        self.populate_directory_items();
+    }
+
+    /// Paths selected in the current mode.
+    ///
+    /// Selections are keyed by the item's full path, absolute on the local machine when
+    /// sharing and as listed by the host when downloading, so they stay valid no matter
+    /// which directory is being viewed.
+    #[must_use]
+    pub const fn selection(&self) -> &HashSet<PathBuf> {
+        match self.state {
+            AppState::Share => &self.items_to_share,
+            AppState::Download => &self.items_to_download,
+        }
+    }
+
+    const fn selection_mut(&mut self) -> &mut HashSet<PathBuf> {
+        match self.state {
+            AppState::Share => &mut self.items_to_share,
+            AppState::Download => &mut self.items_to_download,
+        }
+    }
+
+    #[must_use]
+    pub fn is_selected(&self, item: &DirectoryItem) -> bool {
+        self.selection().contains(&item.path)
+    }
+
+    /// The item and, for directories, everything below it.
+    fn selection_paths(&self, item: &DirectoryItem) -> Vec<PathBuf> {
+        let mut paths = vec![item.path.clone()];
+        if item.is_dir {
+            match self.state {
+                AppState::Share => paths.extend(utils::walk(&item.path, self.symlinks)),
+                // Remote directories can only be expanded from what the host listed
+                AppState::Download => paths.extend(
+                    self.all_shared_items
+                        .iter()
+                        .filter(|shared| shared.path.starts_with(&item.path))
+                        .map(|shared| shared.path.clone()),
+                ),
             }
         }
+        paths
     }
 
-    pub fn select_item(&mut self) {
-        if let Some(index) = self.selected_index {
-            if let Some(item) = self.directory_items.get_mut(index) {
-                tracing::info!("Attempting to select item: {:?}", item);
-                match self.state {
-                    AppState::Share | AppState::Download => {
-                        let items_set = match self.state {
-                            AppState::Share => &mut self.items_to_share,
-                            AppState::Download => &mut self.items_to_download,
-                        };
-
-                        if item.is_dir {
-                            // Add the directory itself with its relative path
-                            if let Ok(rel_path) = item.path.strip_prefix(&self.current_path) {
-                                let path_buf = rel_path.to_path_buf();
-                                tracing::info!("Adding directory to selection: {:?}", path_buf);
-                                items_set.insert(path_buf);
-
-                                // Add all files and subdirectories with their relative paths
-                                for entry in walkdir::WalkDir::new(&item.path)
-                                    .into_iter()
-                                    .filter_map(std::result::Result::ok)
-                                {
-                                    if let Ok(entry_rel_path) =
-                                        entry.path().strip_prefix(&self.current_path)
-                                    {
-                                        tracing::info!(
-                                            "Adding sub-item to selection: {:?}",
-                                            entry_rel_path
-                                        );
-                                        items_set.insert(entry_rel_path.to_path_buf());
-                                    }
-                                }
-                            }
-                        } else {
-                            match self.state {
-                                AppState::Share => {
-                                    // For share mode, store relative to current directory
-                                    if let Ok(rel_path) = item.path.strip_prefix(&self.current_path)
-                                    {
-                                        tracing::info!(
-                                            "Adding file to share selection: {:?}",
-                                            rel_path
-                                        );
-                                        items_set.insert(rel_path.to_path_buf());
-                                    } else {
-                                        tracing::info!(
-                                            "Adding file to share selection with name: {:?}",
-                                            item.name
-                                        );
-                                        items_set.insert(PathBuf::from(&item.name));
-                                    }
-                                }
-                                AppState::Download => {
-                                    // For download mode, store the absolute path from the sharing node
-                                    tracing::info!("Download mode - item to insert: {:?}", item);
-                                    tracing::info!("Current path: {:?}", self.current_path);
-
-                                    // Ensure we have a valid path
-                                    if item.path.as_os_str().is_empty() {
-                                        tracing::warn!("Empty path for item: {:?}", item);
-                                        return;
-                                    }
-
-                                    // Use the absolute path for downloads
-                                    tracing::info!(
-                                        "Adding file to download selection with absolute path: {:?}",
-                                        item.path
-                                    );
-                                    items_set.insert(item.path.clone());
-                                }
-                            }
-                        }
-                        item.selected = true;
-                        tracing::info!("Item selected. Current selection: {:?}", items_set);
-
-                        // Update the cached version
-                        if let Some(cached_items) = self.directory_cache.get_mut(&self.current_path)
-                        {
-                            if let Some(cached_item) = cached_items.get_mut(index) {
-                                cached_item.selected = true;
-                            }
-                        }
-                        // Notify UI to refresh
-                        if let Some(refresh_sender) = &self.refresh_sender {
-                            let _ = refresh_sender.try_send(());
-                        }
-                    }
-                }
+    fn set_item_selected(&mut self, index: usize, selected: bool) {
+        if let Some(item) = self.directory_items.get_mut(index) {
+            item.selected = selected;
+        }
+        // Update the cached version
+        if let Some(cached_items) = self.directory_cache.get_mut(&self.current_path) {
+            if let Some(cached_item) = cached_items.get_mut(index) {
+                cached_item.selected = selected;
             }
         }
+        // Notify UI to refresh
+        if let Some(refresh_sender) = &self.refresh_sender {
+            let _ = refresh_sender.try_send(());
+        }
     }
 
-    pub fn unselect_item(&mut self) {
-        if let Some(index) = self.selected_index {
-            if let Some(item) = self.directory_items.get_mut(index) {
-                match self.state {
-                    AppState::Share => {
-                        if let Ok(rel_path) = item.path.strip_prefix(&self.current_path) {
-                            let path_buf = rel_path.to_path_buf();
-                            self.items_to_share.remove(&path_buf);
-                        }
-                    }
-                    AppState::Download => {
-                        // In download mode, we use the absolute path
-                        tracing::info!("Unselecting item in download mode: {:?}", item);
-                        self.items_to_download.remove(&item.path);
-                    }
-                }
-                item.selected = false;
-                // Update the cached version
-                if let Some(cached_items) = self.directory_cache.get_mut(&self.current_path) {
-                    if let Some(cached_item) = cached_items.get_mut(index) {
-                        cached_item.selected = false;
-                    }
-                }
-                // Notify UI to refresh
-                if let Some(refresh_sender) = &self.refresh_sender {
-                    let _ = refresh_sender.try_send(());
+    /// Hand the decisions on the prompted conflicts to the download waiting for them.
+    pub fn resolve_conflicts(&mut self) {
+        let Some(prompt) = self.conflict_prompt.take() else {
+            return;
+        };
+        if let Some(client) = self.client.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = client.resolve_conflicts(prompt.decisions).await {
+                    tracing::error!("Failed to resolve conflicts: {}", e);
                 }
-            }
+            });
+        }
+    }
+
+    /// Start visual mode at the cursor, or leave it.
+    pub const fn toggle_visual(&mut self) {
+        self.selection_anchor = match self.selection_anchor {
+            Some(_) => None,
+            None => self.selected_index,
+        };
+    }
+
+    /// Rows from the anchor to the cursor while in visual mode.
+    #[must_use]
+    pub fn visual_range(&self) -> Option<RangeInclusive<usize>> {
+        let (anchor, cursor) = (self.selection_anchor?, self.selected_index?);
+        Some(anchor.min(cursor)..=anchor.max(cursor))
+    }
+
+    /// Rows `y` and `n` act on: the visual range, otherwise the one under the cursor.
+    fn target_indices(&self) -> Vec<usize> {
+        self.visual_range().map_or_else(
+            || self.selected_index.into_iter().collect(),
+            Iterator::collect,
+        )
+    }
+
+    /// Scroll the file list, `rows` high, just enough to show the cursor.
+    pub const fn scroll_to_cursor(&mut self, rows: usize) {
+        let Some(index) = self.selected_index else {
+            self.list_offset = 0;
+            return;
+        };
+        if index < self.list_offset {
+            self.list_offset = index;
+        } else if rows > 0 && index >= self.list_offset + rows {
+            self.list_offset = index + 1 - rows;
         }
     }
 
+    /// Index of the item shown `row` rows below the top of the file list.
+    #[must_use]
+    pub fn item_at_row(&self, row: usize) -> Option<usize> {
+        let index = self.list_offset + row;
+        (index < self.directory_items.len()).then_some(index)
+    }
+
+    /// Select the item under the cursor if it isn't, unselect it otherwise.
+    pub fn toggle_selection(&mut self) {
+        let selected = self
+            .selected_index
+            .and_then(|index| self.directory_items.get(index))
+            .is_some_and(|item| self.is_selected(item));
+        if selected {
+            self.unselect_item();
+        } else {
+            self.select_item();
+        }
+    }
+
+    /// Select the item under the cursor, or every item of the visual range and leave
+    /// visual mode.
+    pub fn select_item(&mut self) {
+        for index in self.target_indices() {
+            self.select_index(index);
+        }
+        self.selection_anchor = None;
+        self.sync_share();
+    }
+
+    fn select_index(&mut self, index: usize) {
+        let Some(item) = self.directory_items.get(index) else {
+            return;
+        };
+        tracing::info!("Attempting to select item: {:?}", item);
+
+        // Ensure we have a valid path
+        if item.path.as_os_str().is_empty() {
+            tracing::warn!("Empty path for item: {:?}", item);
+            return;
+        }
+
+        let paths = self.selection_paths(item);
+        if self.state == AppState::Download && item.is_dir {
+            self.remote_listing.expand(&item.path);
+        }
+        self.selection_mut().extend(paths);
+        tracing::info!("Item selected. Current selection: {:?}", self.selection());
+        self.set_item_selected(index, true);
+    }
+
+    /// Unselect the item under the cursor, or every item of the visual range and leave
+    /// visual mode.
+    pub fn unselect_item(&mut self) {
+        for index in self.target_indices() {
+            self.unselect_index(index);
+        }
+        self.selection_anchor = None;
+        self.sync_share();
+    }
+
+    fn unselect_index(&mut self, index: usize) {
+        let Some(path) = self
+            .directory_items
+            .get(index)
+            .map(|item| item.path.clone())
+        else {
+            return;
+        };
+        tracing::info!("Unselecting item: {:?}", path);
+        self.selection_mut().remove(&path);
+        self.set_item_selected(index, false);
+    }
+
     pub fn unselect_all(&mut self) {
-        match self.state {
-            AppState::Share => {
-                self.items_to_share.clear();
-                for item in &mut self.directory_items {
-                    item.selected = false;
-                }
-                // Update cache
-                if let Some(cached_items) = self.directory_cache.get_mut(&self.current_path) {
-                    for item in cached_items.iter_mut() {
-                        item.selected = false;
-                    }
-                }
-            }
-            AppState::Download => {
-                self.items_to_download.clear();
-                for item in &mut self.directory_items {
-                    item.selected = false;
-                }
-                // Update cache
-                if let Some(cached_items) = self.directory_cache.get_mut(&self.current_path) {
-                    for item in cached_items.iter_mut() {
-                        item.selected = false;
-                    }
-                }
+        self.selection_mut().clear();
+        for item in &mut self.directory_items {
+            item.selected = false;
+        }
+        // Update cache
+        for cached_items in self.directory_cache.values_mut() {
+            for item in cached_items.iter_mut() {
+                item.selected = false;
             }
         }
+        self.sync_share();
+    }
+
+    /// Once sharing started, selection changes carry over to the live share.
+    fn sync_share(&mut self) {
+        if self.state == AppState::Share && !self.items_being_shared.is_empty() {
+            self.items_being_shared.clone_from(&self.items_to_share);
+        }
+        self.share_changed();
+    }
+
+    /// Have the share published again, pending notifications are merged into one.
+    pub fn share_changed(&self) {
+        if let Some(share_sender) = &self.share_sender {
+            let _ = share_sender.try_send(());
+        }
+    }
+
+    /// The greeting of the connected peer, if it sent one.
+    #[must_use]
+    pub fn connected_greeting(&self) -> Option<&Greeting> {
+        match (&self.peer_greeting, self.connected_peer_id) {
+            (Some((greeted, greeting)), Some(peer_id)) if *greeted == peer_id => Some(greeting),
+            _ => None,
+        }
     }
 
-    pub const fn disconnect(&mut self) {
+    /// The release of junkanoo the connected peer runs, e.g. `v0.3.1`, or the software it
+    /// reported if it isn't junkanoo.
+    #[must_use]
+    pub fn connected_version(&self) -> Option<String> {
+        let agent = self.peer_agents.get(&self.connected_peer_id?)?;
+        Some(
+            agent
+                .strip_prefix("junkanoo/")
+                .map_or_else(|| agent.clone(), |version| format!("v{version}")),
+        )
+    }
+
+    /// How a peer is shown in lists: its display name if it greeted us with one, otherwise
+    /// the end of its peer ID.
+    #[must_use]
+    pub fn peer_name(&self, peer_id: &PeerId) -> String {
+        self.greeted_name(peer_id).unwrap_or_else(|| {
+            let id = peer_id.to_string();
+            format!("…{}", &id[id.len().saturating_sub(PEER_ID_SUFFIX)..])
+        })
+    }
+
+    /// Display name `peer_id` greeted us with, if it gave one.
+    #[must_use]
+    pub fn greeted_name(&self, peer_id: &PeerId) -> Option<String> {
+        self.peer_greeting
+            .as_ref()
+            .filter(|(greeted, _)| greeted == peer_id)
+            .and_then(|(_, greeting)| greeting.display_name.clone())
+    }
+
+    /// Display name of the connected peer, if it told us one.
+    #[must_use]
+    pub fn connected_peer_name(&self) -> Option<String> {
+        self.connected_greeting()?.display_name.clone()
+    }
+
+    pub fn disconnect(&mut self) {
         if self.is_connected() && !self.is_loading() {
             self.connection_state = ConnectionState::Disconnected;
+            if let (Some(peer_id), Some(client)) = (self.connected_peer_id, self.client.clone()) {
+                tokio::spawn(async move {
+                    if let Err(e) = client.disconnect(peer_id).await {
+                        tracing::error!("Failed to disconnect from {}: {}", peer_id, e);
+                    }
+                });
+            }
             self.connected_peer_id = None;
+            self.peer_greeting = None;
         }
     }
 
+    /// Share the items picked to share with the connected peer.
+    ///
+    /// # Panics
+    ///
+    /// If no peer is connected.
     pub fn start_share(&mut self) {
         assert!(
             self.is_connected(),
@@ -534,6 +1456,191 @@ impl App {
         self.items_being_shared = self.items_to_share.clone();
     }
 
+    /// Ask the host for the directory being viewed again, in case it changed without a new
+    /// version of the share being noticed yet.
+    pub fn refresh_remote_directory(&mut self) {
+        let current = self.current_path.clone();
+        self.remote_listing.refresh(&current);
+    }
+
+    /// Remote directories to list now and whether everything below them: the one being
+    /// viewed, selected ones and with `sync` the whole share. They are marked as
+    /// requested, so each is only returned once.
+    pub fn due_listings(&mut self) -> Vec<(PathBuf, bool)> {
+        if self.state != AppState::Download || !self.remote_listing.on_demand {
+            return Vec::new();
+        }
+        let listing = &mut self.remote_listing;
+        let mut due = Vec::new();
+        if self.sync {
+            if !listing.is_expanded(Path::new("")) {
+                due.push((PathBuf::new(), true));
+            }
+        } else {
+            if !listing.is_listed(&self.current_path) {
+                due.push((self.current_path.clone(), false));
+            }
+            due.extend(listing.expanding.iter().map(|path| (path.clone(), true)));
+        }
+        due.retain(|(path, _)| listing.start(path));
+        due
+    }
+
+    /// Show the items the host listed for `directory`, the empty path for the top of the
+    /// share, in place of what was known of it. With `recursive` they are everything below
+    /// it. Selected downloads the host no longer offers are dropped, items of a selected
+    /// directory are selected with it.
+    pub fn apply_listing(
+        &mut self,
+        directory: PathBuf,
+        recursive: bool,
+        mut items: Vec<DirectoryItem>,
+    ) {
+        for item in &mut items {
+            // Keep the host's path for requests, use just the name for display
+            item.display_path = PathBuf::from(&item.name);
+        }
+        let top = directory.as_os_str().is_empty();
+        let directories: HashSet<PathBuf> = self
+            .all_shared_items
+            .iter()
+            .filter(|item| item.is_dir)
+            .map(|item| item.path.clone())
+            .collect();
+        let (replaced, mut kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.all_shared_items)
+            .into_iter()
+            .partition(|item| {
+                if recursive {
+                    top || (item.path.starts_with(&directory) && item.path != directory)
+                } else {
+                    item.path
+                        .parent()
+                        .filter(|parent| directories.contains(*parent))
+                        .map_or(top, |parent| parent == directory)
+                }
+            });
+        let listed: HashSet<&Path> = items.iter().map(|item| item.path.as_path()).collect();
+        let gone: HashSet<PathBuf> = replaced
+            .into_iter()
+            .filter(|item| !listed.contains(item.path.as_path()))
+            .map(|item| item.path)
+            .collect();
+        // Whatever was known below a directory that is gone went with it
+        let survives = |path: &Path| !path.ancestors().any(|ancestor| gone.contains(ancestor));
+        kept.retain(|item| survives(&item.path));
+        self.items_to_download.retain(|path| survives(path));
+        if self.items_to_download.contains(&directory) {
+            self.items_to_download
+                .extend(items.iter().map(|item| item.path.clone()));
+        }
+
+        kept.extend(items);
+        kept.sort_by(|a, b| match (a.is_dir, b.is_dir) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => a
+                .depth
+                .cmp(&b.depth)
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())),
+        });
+        self.all_shared_items = kept;
+        self.remote_listing.finish(directory, recursive);
+        self.populate_directory_items();
+    }
+
+    /// Only files are transferred, selected directories contribute their contents.
+    fn files_to_download(&self) -> impl Iterator<Item = &DirectoryItem> {
+        self.all_shared_items
+            .iter()
+            .filter(|item| !item.is_dir && self.items_to_download.contains(&item.path))
+    }
+
+    /// The selected directories not below another selected one, with `--archive` each is
+    /// downloaded as a single archive instead of file by file.
+    #[must_use]
+    pub fn archives_to_download(&self) -> Vec<&DirectoryItem> {
+        if self.archive.is_none() {
+            return Vec::new();
+        }
+        let selected = |item: &&DirectoryItem| {
+            item.is_dir
+                && !item.display_path.as_os_str().is_empty()
+                && self.items_to_download.contains(&item.path)
+        };
+        let directories: Vec<&DirectoryItem> =
+            self.all_shared_items.iter().filter(selected).collect();
+        directories
+            .iter()
+            .filter(|item| {
+                !directories
+                    .iter()
+                    .any(|other| other.path != item.path && item.path.starts_with(&other.path))
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Total size of the selected files.
+    #[must_use]
+    pub fn selected_download_size(&self) -> u64 {
+        self.files_to_download().map(|item| item.size).sum()
+    }
+
+    /// How many files are selected and their total size.
+    #[must_use]
+    pub fn selected_download(&self) -> (usize, u64) {
+        self.files_to_download()
+            .fold((0, 0), |(count, size), item| (count + 1, size + item.size))
+    }
+
+    /// Free space where downloads are saved, if the selection doesn't fit into it.
+    #[must_use]
+    pub fn download_space_shortfall(&self) -> Option<u64> {
+        let mut directory = std::env::current_dir().unwrap_or_default();
+        if let Some(download_directory) = self.download_directory() {
+            directory.push(download_directory);
+        }
+        plan::available_space(&directory).filter(|&free| free < self.selected_download_size())
+    }
+
+    /// Path of a shared item below the root of the share, as saved by a download.
+    fn path_in_share(&self, item: &DirectoryItem) -> PathBuf {
+        let root = self
+            .all_shared_items
+            .iter()
+            .find(|item| item.depth == 1)
+            .and_then(|top| top.path.parent());
+        root.and_then(|root| item.path.strip_prefix(root).ok())
+            .map_or_else(|| PathBuf::from(&item.name), Path::to_path_buf)
+    }
+
+    /// Where downloads are saved, relative to the working directory.
+    fn download_directory(&self) -> Option<PathBuf> {
+        self.sync_directory
+            .clone()
+            .or_else(|| self.share_label.as_deref().and_then(label_directory))
+    }
+
+    /// What downloading the selection would do, without transferring anything.
+    #[must_use]
+    pub fn plan_download(&self) -> DownloadPlan {
+        let mut directory = std::env::current_dir().unwrap_or_default();
+        if let Some(download_directory) = self.download_directory() {
+            directory.push(download_directory);
+        }
+        DownloadPlan::new(
+            &directory,
+            self.files_to_download()
+                .map(|item| (self.path_in_share(item), item)),
+        )
+    }
+
+    /// Whether the selection is large enough to ask before downloading it.
+    #[must_use]
+    pub fn needs_download_confirmation(&self) -> bool {
+        self.selected_download_size() > self.confirm_threshold
+    }
+
     pub async fn start_download(&mut self) {
         if !self.is_connected() {
             tracing::error!("Cannot start downloading - not connected to a peer");
@@ -550,35 +1657,50 @@ impl App {
         self.items_being_downloaded
             .clone_from(&self.items_to_download);
 
-        // Get the absolute paths from all_shared_items
-        let file_names: Vec<String> = self
-            .items_to_download
+        let archives: Vec<String> = self
+            .archives_to_download()
             .iter()
-            .filter_map(|path| {
-                // Find the original item in all_shared_items to get the absolute path
-                self.all_shared_items
+            .map(|item| item.path.to_string_lossy().to_string())
+            .collect();
+        let files: Vec<RequestedFile> = self
+            .files_to_download()
+            .filter(|item| {
+                !archives
                     .iter()
-                    .find(|item| {
-                        item.name == path.file_name().unwrap_or_default().to_string_lossy()
-                    })
-                    .map(|item| {
-                        let path_str = item.path.to_string_lossy().to_string();
-                        tracing::info!("Using absolute path for download: {}", path_str);
-                        path_str
-                    })
+                    .any(|archive| item.path.starts_with(archive))
+            })
+            .map(|item| RequestedFile {
+                path: item.path.to_string_lossy().to_string(),
+                hash: item.hash.clone(),
             })
             .collect();
 
-        tracing::info!("Starting download of files: {:?}", file_names);
+        tracing::info!("Starting download of files: {:?}", files);
 
-        if let Some(client) = &mut self.client {
-            match client.request_files(peer_id, file_names).await {
-                Ok(_) => {
-                    tracing::info!("Download completed successfully");
-                }
-                Err(e) => {
-                    tracing::error!("Failed to request files: {}", e);
-                }
+        let directory = self.download_directory();
+        if let Some(client) = &self.client {
+            // Requested together, so none ends before all of them were queued
+            let format = self.archive.unwrap_or_default();
+            let archives = archives
+                .into_iter()
+                .map(|path| client.request_archive(peer_id, path, directory.clone(), format));
+            let files = (!files.is_empty() || archives.len() == 0)
+                .then(|| client.request_files(peer_id, files, directory.clone(), self.sync));
+            let (archives, files) = futures::join!(
+                futures::future::join_all(archives),
+                futures::future::OptionFuture::from(files)
+            );
+            let failed = archives
+                .into_iter()
+                .filter_map(Result::err)
+                .chain(files.and_then(Result::err));
+            let mut succeeded = true;
+            for e in failed {
+                tracing::error!("Failed to request files: {}", e);
+                succeeded = false;
+            }
+            if succeeded {
+                tracing::info!("Download completed successfully");
             }
         }
 
@@ -587,34 +1709,98 @@ impl App {
         }
     }
 
+    #[must_use]
     pub const fn refresh_sender(&self) -> Option<&Sender<()>> {
         self.refresh_sender.as_ref()
     }
 
+    #[must_use]
     pub const fn is_connected(&self) -> bool {
         matches!(self.connection_state, ConnectionState::Connected)
     }
 
+    #[must_use]
     pub const fn is_loading(&self) -> bool {
         self.is_loading
     }
 
+    #[must_use]
     pub const fn is_warning(&self) -> bool {
         self.warning.is_some()
     }
 
+    #[must_use]
     pub fn warning_message(&self) -> &str {
         self.warning.as_ref().map_or("", |w| &w.message)
     }
 
+    /// Replace the address input with the clipboard's contents.
+    pub fn paste_address(&mut self) {
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => self.address_input = Some(text.trim().to_string()),
+            Err(e) => self.set_warning(format!("Cannot read the clipboard: {e}")),
+        }
+    }
+
+    /// Dial the entered address if it is valid, otherwise say what is wrong with it.
+    pub fn submit_address(&mut self) {
+        let input = self.address_input.clone().unwrap_or_default();
+        match parse_peer_address(&input) {
+            Ok(addr) => {
+                if let Some(sender) = self.address_sender.take() {
+                    let _ = sender.try_send(addr);
+                }
+                self.address_input = None;
+            }
+            Err(e) => self.set_warning(e),
+        }
+    }
+
     pub fn set_warning(&mut self, message: String) {
+        self.notify(Severity::Warning, message);
+    }
+
+    /// Show a message in place of the file list, see [`NotificationConfig`] for how long.
+    /// An error that wasn't dismissed yet is only replaced by another error.
+    pub fn notify(&mut self, severity: Severity, message: String) {
+        if severity != Severity::Error
+            && self
+                .warning
+                .as_ref()
+                .is_some_and(|warning| warning.severity == Severity::Error)
+        {
+            return;
+        }
         self.warning = Some(Warning {
             message,
-            timer: std::time::Instant::now(),
+            timer: tokio::time::Instant::now(),
+            severity,
         });
     }
 
+    /// Whether the current notification was shown for long enough.
+    #[must_use]
+    pub fn warning_expired(&self) -> bool {
+        self.warning.as_ref().is_some_and(|warning| {
+            self.notifications
+                .timeout(warning.severity)
+                .is_some_and(|timeout| warning.timer.elapsed() >= timeout)
+        })
+    }
+
     pub fn clear_warning(&mut self) {
         self.warning = None;
     }
+
+    /// The address was copied to the clipboard.
+    pub fn copied_address(&mut self) {
+        self.clipboard_copied_at = Some(tokio::time::Instant::now());
+    }
+
+    /// Whether the address was copied recently enough to still show a checkmark.
+    #[must_use]
+    pub fn clipboard_success(&self) -> bool {
+        self.clipboard_copied_at
+            .is_some_and(|copied_at| copied_at.elapsed() < CLIPBOARD_FEEDBACK)
+    }
 }
@@ -0,0 +1,128 @@
+//! Transfer throughput measurements.
+//!
+//! Behind the hidden `bench` subcommand and the Criterion benches in `benches/transfer.rs`,
+//! so changes to chunking and buffering in [`service::utils`](crate::service::utils) can be
+//! weighed with numbers.
+
+use async_std::net::{TcpListener, TcpStream};
+use futures::AsyncWriteExt;
+use libp2p::Multiaddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::format;
+use crate::service::error::JunkanooError;
+use crate::service::utils::{FileReceiver, FileTransfer};
+use crate::{JunkanooNode, NodeConfig};
+
+/// How long one file took to arrive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub path: String,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Sample {
+    /// Bytes per second.
+    #[must_use]
+    pub fn throughput(&self) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let bytes = self.bytes as f64;
+        bytes / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Send `source` over a fresh loopback TCP connection into `directory`, both ends reading
+/// and writing `chunk_size` bytes at a time. Files already there are overwritten.
+///
+/// # Errors
+///
+/// If the connection can't be set up, or either end fails to send or save the file.
+pub async fn loopback_transfer(
+    source: &Path,
+    directory: &Path,
+    chunk_size: usize,
+) -> Result<Duration, JunkanooError> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let transfer = FileTransfer::new(&source.to_path_buf()).with_chunk_size(chunk_size);
+    let receiver = FileReceiver::new()
+        .with_directory(Some(directory.to_path_buf()))
+        .with_chunk_size(chunk_size);
+
+    let started = Instant::now();
+    let sending = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        transfer.stream_file(&mut stream).await?;
+        stream.close().await.map_err(JunkanooError::from)
+    };
+    let receiving = async {
+        let (mut stream, _) = listener.accept().await?;
+        receiver.receive_file(&mut stream).await
+    };
+    let (sent, saved) = futures::join!(sending, receiving);
+    sent?;
+    saved?;
+    Ok(started.elapsed())
+}
+
+/// Download every file the host at `address` shares into `directory`, one at a time, and
+/// time each.
+///
+/// # Errors
+///
+/// If the host can't be reached, or a file fails to download.
+pub async fn download_all(
+    config: NodeConfig,
+    address: Multiaddr,
+    directory: &Path,
+) -> Result<Vec<Sample>, JunkanooError> {
+    let node = JunkanooNode::start(config).await?;
+    let mut download = node.download(address, None).await?;
+    let files: Vec<(PathBuf, u64)> = download
+        .items()
+        .iter()
+        .filter(|item| !item.is_dir)
+        .map(|item| (item.path.clone(), item.size))
+        .collect();
+
+    let mut samples = Vec::with_capacity(files.len());
+    for (path, bytes) in files {
+        let started = Instant::now();
+        download
+            .download(std::slice::from_ref(&path), Some(directory.to_path_buf()))
+            .await?;
+        samples.push(Sample {
+            path: path.to_string_lossy().into_owned(),
+            bytes,
+            elapsed: started.elapsed(),
+        });
+    }
+    download.close().await?;
+    Ok(samples)
+}
+
+/// One line per file with its size, time and throughput, then the total.
+#[must_use]
+pub fn report(samples: &[Sample]) -> String {
+    let total = Sample {
+        path: format!("{} files in total", samples.len()),
+        bytes: samples.iter().map(|sample| sample.bytes).sum(),
+        elapsed: samples.iter().map(|sample| sample.elapsed).sum(),
+    };
+    samples
+        .iter()
+        .chain([&total])
+        .map(|sample| {
+            format!(
+                "{:>12}  {:>9.3}s  {:>12}  {}",
+                format::size(sample.bytes),
+                sample.elapsed.as_secs_f64(),
+                format::speed(sample.throughput()),
+                sample.path
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
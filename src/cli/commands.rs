@@ -1,4 +1,17 @@
-use clap::{arg, Command};
+use chrono::NaiveTime;
+use clap::{arg, ArgAction, Command};
+use libp2p::Multiaddr;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::app::parse_peer_address;
+use crate::service::archive;
+use crate::service::identity::parse_seed;
+use crate::service::limiter::{parse_rate, parse_size};
+use crate::service::secret::parse_secret;
+use crate::service::share_code;
+use crate::service::utils::ConflictPolicy;
 
 #[allow(clippy::cognitive_complexity)]
 pub fn get_args() -> Command {
@@ -6,22 +19,296 @@ pub fn get_args() -> Command {
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
-        .subcommand_required(true)
-        .arg_required_else_help(true)
         .arg(arg!(-v --debug "Print debug information"))
-        .arg(arg!(-a --address <IP_ADDRESS> "IP address to listen on"))
-        .arg(arg!(-p --port <PORT> "Port number to listen on"))
-        .subcommand(
-            Command::new("share")
-                .about("Send a file or directory to another peer")
-                .arg(arg!([FILE_PATH] "The file path or directory to send (defaults to current directory)")),
-        )
-        .subcommand(
-            Command::new("download")
-                .about("Receive a file or directory from another peer")
-                .arg(arg!(<PEER_ADDR_IDENTIFIER> "The multiaddr to connect to"))
-                .arg_required_else_help(true),
+        .arg(arg!(--json "Emit newline-delimited JSON events instead of text output"))
+        .arg(arg!(--plain "Print lines of text and read typed commands instead of the full-screen UI, for screen readers and dumb terminals"))
+        .arg(
+            arg!(--"state-dir" <DIR> "Keep transfer history, recent shares and hash caches here")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--report <FILE> "Write an HTML summary of the files delivered, with hashes and peer IDs, on exit")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(arg!(--name <NAME> "Name peers see instead of the peer ID, defaults to the host name"))
+        .arg(
+            arg!(--"identity-seed" <PHRASE> "Derive the peer ID from this BIP39 phrase, the same wherever it is used")
+                .env("JUNKANOO_IDENTITY_SEED")
+                .hide_env_values(true)
+                .value_parser(parse_seed),
+        )
+        .arg(
+            arg!(-a --address <IP_ADDRESS> "IP address to listen on, all IPv4 and IPv6 interfaces by default")
+                .value_parser(clap::value_parser!(IpAddr)),
+        )
+        .arg(
+            arg!(-p --port <PORT> "Fixed port for QUIC and TCP, e.g. one forwarded by the router")
+                .value_parser(clap::value_parser!(u16)),
+        )
+        .arg(
+            arg!(--listen <MULTIADDR> "Listen on exactly this address instead, repeatable")
+                .value_parser(clap::value_parser!(Multiaddr))
+                .action(ArgAction::Append)
+                .conflicts_with_all(["address", "port"]),
+        )
+        .arg(arg!(--"lan-only" "Don't join the public DHT, only connect on the local network"))
+        .arg(
+            arg!(--rendezvous <MULTIADDR> "Register share codes at and look them up from this rendezvous point, instead of the DHT")
+                .value_parser(parse_peer_address),
+        )
+        .arg(arg!(--"no-compress" "Never compress file transfers"))
+        .arg(arg!(--"show-hidden" "List dotfiles in the file browser"))
+        .arg(arg!(--"exit-on-complete" "Exit once the transfer finished, non-zero if it failed"))
+        .arg(arg!(--notify "Show a desktop notification when a download ends or a peer finished downloading from us"))
+        .arg(
+            arg!(--"confirm-above" <SIZE> "Ask before downloading more than this, e.g. 10GB")
+                .value_parser(parse_size)
+                .default_value("5GB"),
+        )
+        .arg(
+            arg!(--parallel <COUNT> "Files to download at the same time, capped by the host")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"max-upload" <RATE> "Limit the upload rate, e.g. 5MiB/s")
+                .value_parser(parse_rate),
+        )
+        .arg(
+            arg!(--"max-download" <RATE> "Limit the download rate, e.g. 5MiB/s")
+                .value_parser(parse_rate),
+        )
+        .arg(
+            arg!(--"max-connections" <COUNT> "Maximum number of established connections")
+                .value_parser(clap::value_parser!(u32).range(1..)),
+        )
+        .arg(
+            arg!(--"request-timeout" <DURATION> "How long the other peer has to answer a request, 30s by default")
+                .value_parser(parse_duration),
+        )
+        .subcommand(share_command())
+        .subcommand(download_command())
+        .subcommand(sync_command())
+        .subcommand(ls_command())
+        .subcommand(get_command())
+        .subcommand(bench_command())
+}
+
+fn share_command() -> Command {
+    Command::new("share")
+        .about("Send a file or directory to another peer")
+        .arg(arg!([FILE_PATH] "The file path or directory to send (defaults to current directory), @name of a bookmark, or - for stdin"))
+        .arg(arg!(--as <NAME> "Name of the file stdin is shared as with -, stdin by default"))
+        .arg(
+            arg!(--expires <DURATION> "Stop sharing and exit after this long, e.g. 30m")
+                .value_parser(parse_duration),
+        )
+        .arg(arg!(--label <NAME> "Name shown to downloaders, who save into a directory of that name"))
+        .arg(arg!(--once "Close the share after the first peer finished downloading"))
+        .arg(
+            arg!(--password <PASSWORD> "Only serve downloaders that know this password")
+                .value_parser(parse_secret),
+        )
+        .arg(arg!(--"read-only" "Open shared files read-only up front and never write to disk"))
+        .arg(
+            arg!(--"accept-push" "Take missing and changed files peers push with 'sync --push' into the shared directory")
+                .conflicts_with("read-only"),
+        )
+        .arg(arg!(--"follow-symlinks" "Share what symlinks point to, descending into linked directories (the default)"))
+        .arg(
+            arg!(--"skip-symlinks" "Leave symlinks out of the share")
+                .conflicts_with("follow-symlinks"),
+        )
+        .arg(
+            arg!(--"copy-links" "Share symlinks as links, downloaders recreate them")
+                .conflicts_with_all(["follow-symlinks", "skip-symlinks"]),
+        )
+        .arg(
+            arg!(--"start-at" <TIME> "Only answer requests from this local time on, e.g. 22:00")
+                .value_parser(parse_time_of_day),
+        )
+        .arg(
+            arg!(--window <DURATION> "Stop sharing and exit this long after the share opened")
+                .value_parser(parse_duration)
+                .conflicts_with("expires"),
+        )
+        .arg(
+            arg!(--sensitive <PATTERN> "Also ask before sharing files matching this, e.g. '*.kdbx', repeatable")
+                .action(ArgAction::Append),
+        )
+}
+
+fn download_command() -> Command {
+    Command::new("download")
+        .about("Receive a file or directory from another peer")
+        .arg(arg!([PEER_ADDR_IDENTIFIER]... "The multiaddrs of the host, tried in order until one connects, asked for in the UI when left out"))
+        .arg(
+            arg!(--code <CODE> "Find the host by the code its share shows, e.g. 7-guitar-sunset-otter-cedar-walnut")
+                .value_parser(share_code::parse)
+                .conflicts_with("PEER_ADDR_IDENTIFIER"),
+        )
+        .arg(
+            arg!(--password <PASSWORD> "Password of the share, if the host set one")
+                .value_parser(parse_secret),
+        )
+        .arg(arg!(--preserve "Keep the permissions and modification times files have on the host"))
+        .arg(
+            arg!(--"on-conflict" <POLICY> "What to do with files that exist with other content: overwrite, skip, rename or ask")
+                .value_parser(parse_conflict_policy),
+        )
+        .arg(archive_arg())
+        .arg(arg!(--"dry-run" "Print what the selection would transfer and where, without transferring it"))
+}
+
+fn sync_command() -> Command {
+    Command::new("sync")
+        .about("Refresh a previous download of a share, only changed parts of files are transferred")
+        .arg_required_else_help(true)
+        .arg(arg!(<PEER_ADDR_IDENTIFIER> "The multiaddr to connect to"))
+        .arg(
+            arg!([DIR] "Directory to sync, instead of one named after the share")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--push "Send DIR's missing and changed files to a host started with --accept-push instead")
+                .requires("DIR")
+                .conflicts_with("dry-run"),
+        )
+        .arg(
+            arg!(--password <PASSWORD> "Password of the share, if the host set one")
+                .value_parser(parse_secret),
+        )
+        .arg(arg!(--preserve "Keep the permissions and modification times files have on the host"))
+        .arg(arg!(--"dry-run" "Print what would be refreshed and where, without transferring it"))
+}
+
+fn ls_command() -> Command {
+    Command::new("ls")
+        .about("Print what a host shares and exit, to check it before downloading")
+        .arg_required_else_help(true)
+        .arg(
+            arg!(<PEER_ADDR_IDENTIFIER> "The multiaddr to connect to")
+                .value_parser(parse_peer_address),
+        )
+        .arg(
+            arg!(--password <PASSWORD> "Password of the share, if the host set one")
+                .value_parser(parse_secret),
+        )
+}
+
+fn get_command() -> Command {
+    Command::new("get")
+        .about("Download paths of a share straight away, without the UI, and exit non-zero if any failed")
+        .arg_required_else_help(true)
+        .arg(
+            arg!(<PEER_ADDR_IDENTIFIER> "The multiaddr to connect to")
+                .value_parser(parse_peer_address),
         )
+        .arg(
+            arg!(<PATH>... "Files or directories as listed by 'junkanoo ls'")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(-o --output <DIR> "Save into this directory instead of the working directory")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(-O --"output-document" <FILE> "Write the one file downloaded to FILE instead, - for stdout")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with("output"),
+        )
+        .arg(archive_arg().conflicts_with("output-document"))
+        .arg(
+            arg!(--password <PASSWORD> "Password of the share, if the host set one")
+                .value_parser(parse_secret),
+        )
+}
+
+fn bench_command() -> Command {
+    Command::new("bench")
+        .about(
+            "Download everything a host shares into a scratch directory and print the throughput",
+        )
+        .hide(true)
+        .arg_required_else_help(true)
+        .arg(
+            arg!(<PEER_ADDR_IDENTIFIER> "The multiaddr to connect to")
+                .value_parser(parse_peer_address),
+        )
+}
+
+/// `--archive [FORMAT]` of `download` and `get`, tar.gz when given without a format.
+fn archive_arg() -> clap::Arg {
+    arg!(--archive [FORMAT] "Receive selected directories as one archive each, tar or tar.gz (the default), much faster for many small files")
+        .value_parser(archive::parse_format)
+        .num_args(0..=1)
+        .default_missing_value("tar.gz")
+}
+
+/// Parse a duration such as `30m`, `1h30m`, `45s` or `2d`. A bare number is in seconds.
+///
+/// # Errors
+///
+/// If the input is empty or has an unknown unit.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration '{input}', expected e.g. 1h30m"))?;
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            other => return Err(format!("unknown duration unit '{other}' in '{input}'")),
+        };
+        total += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("missing unit after '{number}' in '{input}'"));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Parse a local time of day written as `HH:MM`.
+///
+/// # Errors
+///
+/// If the input is not a time of day.
+pub fn parse_time_of_day(input: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(input.trim(), "%H:%M")
+        .map_err(|_| format!("invalid time '{input}', expected e.g. 22:00"))
+}
+
+/// Parse what to do with files that already exist, see `download --on-conflict`.
+///
+/// # Errors
+///
+/// If the input names no policy.
+pub fn parse_conflict_policy(input: &str) -> Result<ConflictPolicy, String> {
+    match input.trim().to_lowercase().as_str() {
+        "overwrite" => Ok(ConflictPolicy::Overwrite),
+        "skip" => Ok(ConflictPolicy::Skip),
+        "rename" => Ok(ConflictPolicy::Rename),
+        "ask" => Ok(ConflictPolicy::Ask),
+        _ => Err(format!(
+            "invalid policy '{input}', expected overwrite, skip, rename or ask"
+        )),
+    }
 }
 
 #[cfg(test)]
@@ -32,8 +319,9 @@ mod tests {
     fn test_command_structure() {
         let app = get_args();
         assert_eq!(app.get_name(), "junkanoo");
-        assert!(app.is_subcommand_required_set());
-        assert!(app.is_arg_required_else_help_set());
+        // Without a subcommand the start screen offers recent shares and hosts
+        assert!(!app.is_subcommand_required_set());
+        assert!(!app.is_arg_required_else_help_set());
     }
 
     #[test]
@@ -45,15 +333,58 @@ mod tests {
             .get_subcommands()
             .find(|cmd| cmd.get_name() == "share")
             .unwrap();
-        assert_eq!(send.get_arguments().count(), 1);
+        assert_eq!(send.get_arguments().count(), 14);
 
         // Test receive subcommand
         let download = app
             .get_subcommands()
             .find(|cmd| cmd.get_name() == "download")
             .unwrap();
-        assert!(download.is_arg_required_else_help_set());
-        assert_eq!(download.get_arguments().count(), 1);
+        assert!(!download.is_arg_required_else_help_set());
+        assert_eq!(download.get_arguments().count(), 7);
+
+        let sync = app
+            .get_subcommands()
+            .find(|cmd| cmd.get_name() == "sync")
+            .unwrap();
+        assert!(sync.is_arg_required_else_help_set());
+        assert_eq!(sync.get_arguments().count(), 6);
+
+        // Only for measuring, so left out of the help
+        let bench = app
+            .get_subcommands()
+            .find(|cmd| cmd.get_name() == "bench")
+            .unwrap();
+        assert!(bench.is_hide_set());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(
+            parse_duration("2d"),
+            Ok(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("10").is_ok());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(
+            parse_time_of_day("22:00"),
+            Ok(NaiveTime::from_hms_opt(22, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_time_of_day("7:05"),
+            Ok(NaiveTime::from_hms_opt(7, 5, 0).unwrap())
+        );
+        assert!(parse_time_of_day("24:00").is_err());
+        assert!(parse_time_of_day("10pm").is_err());
     }
 
     #[test]
@@ -62,4 +393,56 @@ mod tests {
         let debug = app.get_arguments().find(|arg| arg.get_id() == "debug");
         assert!(debug.is_some());
     }
+
+    #[test]
+    fn test_exit_on_complete_for_both_roles() {
+        for role in [&["share"][..], &["download", "/ip4/127.0.0.1/tcp/4001"][..]] {
+            let args = ["junkanoo", "--exit-on-complete"].iter().chain(role);
+            let matches = get_args().try_get_matches_from(args).unwrap();
+            assert!(matches.get_flag("exit-on-complete"));
+        }
+    }
+
+    #[test]
+    fn test_listen_options() {
+        use crate::service::node::listen_addrs;
+
+        let matches = get_args()
+            .try_get_matches_from([
+                "junkanoo",
+                "--listen",
+                "/ip4/0.0.0.0/udp/4001/quic-v1",
+                "--listen",
+                "/ip6/::/tcp/4001",
+                "share",
+            ])
+            .unwrap();
+        assert_eq!(matches.get_many::<Multiaddr>("listen").unwrap().count(), 2);
+        assert!(get_args()
+            .try_get_matches_from(["junkanoo", "--listen", "/ip4/0.0.0.0/tcp/1", "-p", "2"])
+            .is_err());
+        assert!(get_args()
+            .try_get_matches_from(["junkanoo", "-p", "70000", "share"])
+            .is_err());
+
+        // QUIC and TCP on both address families unless told otherwise
+        let addrs: Vec<String> = listen_addrs(None, 4001)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            addrs,
+            [
+                "/ip4/0.0.0.0/udp/4001/quic-v1",
+                "/ip4/0.0.0.0/tcp/4001",
+                "/ip6/::/udp/4001/quic-v1",
+                "/ip6/::/tcp/4001",
+            ]
+        );
+        let addrs = listen_addrs(Some("192.168.1.20".parse().unwrap()), 0);
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs
+            .iter()
+            .all(|addr| addr.to_string().starts_with("/ip4/192.168.1.20/")));
+    }
 }
@@ -1,2 +1,6 @@
 pub mod commands;
+pub mod output;
+pub mod plain;
+pub mod preview;
+pub mod theme;
 pub mod ui;
@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::app::DirectoryItem;
+use crate::plan::DownloadPlan;
+use crate::transfers::TransferSummary;
+
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Switch non-TUI output to newline-delimited JSON, set once from `--json`.
+pub fn set_json(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// One line of `--json` output.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent {
+    Listing {
+        items: Vec<ListingEntry>,
+    },
+    Progress {
+        path: String,
+        bytes: u64,
+        total: u64,
+    },
+    Completed {
+        files: Vec<String>,
+    },
+    Failed {
+        files: Vec<String>,
+        error: String,
+    },
+    Error {
+        message: String,
+    },
+    /// What a `--dry-run` download would do.
+    Plan(DownloadPlan),
+    /// How the downloads went, once they ended.
+    Summary(TransferSummary),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListingEntry {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    /// Size in bytes, left out for directories.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl From<&DirectoryItem> for ListingEntry {
+    fn from(item: &DirectoryItem) -> Self {
+        Self {
+            name: item.name.clone(),
+            path: item.display_path.to_string_lossy().to_string(),
+            is_dir: item.is_dir,
+            size: (!item.is_dir).then_some(item.size),
+        }
+    }
+}
+
+/// Write a single event as one JSON line on stdout.
+pub fn emit(event: &JsonEvent) {
+    let mut stdout = std::io::stdout().lock();
+    if let Ok(line) = serde_json::to_string(event) {
+        let _ = writeln!(stdout, "{line}");
+        let _ = stdout.flush();
+    }
+}
+
+/// Report an error to the user, as an `error` event in JSON mode or on stderr otherwise.
+pub fn error(message: &str) {
+    tracing::error!("{}", message);
+    if is_json() {
+        emit(&JsonEvent::Error {
+            message: message.to_string(),
+        });
+    } else {
+        eprintln!("Error: {message}");
+    }
+}
@@ -0,0 +1,321 @@
+//! `--plain`: lines of text instead of the full-screen UI, for screen readers and dumb
+//! terminals. What changes is printed once as it happens, commands are typed at a prompt.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::app::{App, ConnectionState};
+use crate::config::Severity;
+use crate::format;
+use crate::transfers::{Transfer, TransferState};
+
+pub const HELP: &str = "Commands:
+  ls              list the current directory again
+  cd NAME         open a directory, cd .. goes back up
+  select NAME     select a file or directory, select * selects everything listed
+  unselect NAME   unselect it again, unselect * unselects everything
+  download        download the selection, or share it when hosting
+  refresh         ask the host for the current directory again
+  status          connection, addresses and transfers
+  help            show this list
+  quit            disconnect and exit
+Questions are answered with yes or no, conflicting files with overwrite, skip or rename.";
+
+/// A line typed at the prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    List,
+    Open(String),
+    Back,
+    Select(String),
+    Unselect(String),
+    Download,
+    Refresh,
+    Status,
+    /// `yes`, `no`, `overwrite`, `skip` or `rename`, for the question asked last.
+    Answer(String),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parse a line typed at the prompt.
+    ///
+    /// # Errors
+    ///
+    /// If the line names no command, or its argument is missing or not understood.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (word, argument) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(word, argument)| (word, argument.trim()));
+        let named = |command: fn(String) -> Self| {
+            if argument.is_empty() {
+                Err(format!("{word} needs a name, e.g. {word} notes.txt"))
+            } else {
+                Ok(command(argument.to_string()))
+            }
+        };
+        match word.to_lowercase().as_str() {
+            "ls" | "list" => Ok(Self::List),
+            "cd" | "open" if argument == ".." => Ok(Self::Back),
+            "cd" | "open" => named(Self::Open),
+            "up" | "back" => Ok(Self::Back),
+            "select" => named(Self::Select),
+            "unselect" => named(Self::Unselect),
+            "download" | "share" | "get" => Ok(Self::Download),
+            "refresh" => Ok(Self::Refresh),
+            "status" => Ok(Self::Status),
+            answer @ ("y" | "yes" | "n" | "no" | "overwrite" | "skip" | "rename") => {
+                Ok(Self::Answer(answer.to_string()))
+            }
+            "help" | "?" => Ok(Self::Help),
+            "quit" | "exit" | "q" => Ok(Self::Quit),
+            _ => Err(format!(
+                "Unknown command '{word}', type help for the commands"
+            )),
+        }
+    }
+}
+
+/// Row of `name` in the listing, directories may be written with a trailing `/`.
+#[must_use]
+pub fn find_item(app: &App, name: &str) -> Option<usize> {
+    let name = name.trim_end_matches('/');
+    app.directory_items
+        .iter()
+        .position(|item| item.name == name || item.display_path.to_string_lossy() == name)
+}
+
+/// The current directory, one row per line with nested rows indented.
+#[must_use]
+pub fn listing(app: &App) -> Vec<String> {
+    let directory = app.current_path.display().to_string();
+    let mut lines = vec![if directory.is_empty() {
+        "Contents of the share:".to_string()
+    } else {
+        format!("Contents of {directory}:")
+    }];
+    if app.directory_items.is_empty() {
+        lines.push("  nothing here".to_string());
+    }
+    lines.extend(app.directory_items.iter().map(|item| {
+        let indent = "  ".repeat(item.depth + 1);
+        let selected = if app.is_selected(item) {
+            ", selected"
+        } else {
+            ""
+        };
+        if item.is_dir {
+            format!("{indent}{}/ directory{selected}", item.name)
+        } else {
+            format!(
+                "{indent}{} {}{selected}",
+                item.name,
+                format::size(item.size)
+            )
+        }
+    }));
+    lines
+}
+
+/// Answer to `status`.
+pub fn status(app: &App) -> Vec<String> {
+    let mut lines = vec![connection(app).unwrap_or_else(|| "Not connected".to_string())];
+    if app.is_host {
+        lines.extend(
+            app.listening_addrs
+                .iter()
+                .map(|address| format!("Listening on {}", app.full_address(address))),
+        );
+        if let Some(code) = app.share_code.as_ref().filter(|_| app.share_code_published) {
+            lines.push(format!("Share code {code}"));
+        }
+    }
+    let selected = if app.is_host {
+        app.items_to_share.len()
+    } else {
+        app.items_to_download.len()
+    };
+    lines.push(format!("{selected} selected"));
+    lines.extend(app.transfers.transfers().iter().filter_map(transfer_line));
+    lines
+}
+
+fn connection(app: &App) -> Option<String> {
+    match app.connection_state {
+        ConnectionState::Connected => Some(format!(
+            "Connected to {}",
+            app.connected_peer_name()
+                .unwrap_or_else(|| "a peer".to_string())
+        )),
+        ConnectionState::Reconnecting(attempt) => Some(format!(
+            "Lost the connection, reconnecting, attempt {attempt}"
+        )),
+        ConnectionState::Disconnected => None,
+    }
+}
+
+fn transfer_line(transfer: &Transfer) -> Option<String> {
+    let path = &transfer.path;
+    Some(match &transfer.state {
+        TransferState::Queued => return None,
+        TransferState::Active => {
+            let progress = transfer.progress().map_or_else(
+                || format!("{} so far", format::size(transfer.bytes)),
+                |progress| {
+                    format!(
+                        "{} of {}",
+                        format::percent(progress).trim_start(),
+                        format::size(transfer.total.unwrap_or_default())
+                    )
+                },
+            );
+            let speed = transfer
+                .smoothed_speed()
+                .map(|speed| format!(" at {}", format::speed(speed)))
+                .unwrap_or_default();
+            format!("{path}: {progress}{speed}")
+        }
+        TransferState::Completed => format!("{path}: done"),
+        TransferState::UpToDate => format!("{path}: already up to date"),
+        TransferState::Kept => format!("{path}: kept the existing file"),
+        TransferState::Paused => format!("{path}: paused"),
+        TransferState::Cancelled => format!("{path}: cancelled"),
+        TransferState::Failed(error) => format!("{path}: failed, {error}"),
+        TransferState::Corrupted => {
+            format!("{path}: arrived with other content than the host published")
+        }
+    })
+}
+
+/// The question the user has to answer before anything else happens, if any.
+fn question(app: &App) -> Option<String> {
+    if app.address_input.is_some() {
+        return Some("Type the host's address and press Enter:".to_string());
+    }
+    if let Some(diff) = &app.manifest_diff {
+        return Some(format!(
+            "Since this directory was last shared {} files were added, {} removed and {} \
+             changed. Share it? yes or no",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        ));
+    }
+    if let Some(prompt) = &app.conflict_prompt {
+        let paths: Vec<&str> = prompt
+            .conflicts
+            .iter()
+            .map(|conflict| conflict.relative_path.as_str())
+            .collect();
+        return Some(format!(
+            "These files already exist with other content: {}. Overwrite, skip or rename them?",
+            paths.join(", ")
+        ));
+    }
+    if !app.sensitive_pending.is_empty() {
+        let paths: Vec<String> = app
+            .sensitive_pending
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        return Some(format!(
+            "These may hold secrets: {}. Share them anyway? yes or no",
+            paths.join(", ")
+        ));
+    }
+    if app.confirming_download {
+        return Some(format!(
+            "Download {} of files? yes or no",
+            format::size(app.selected_download_size())
+        ));
+    }
+    None
+}
+
+/// Remembers what was printed, so each change is printed once.
+#[derive(Debug, Default)]
+pub struct Reporter {
+    connection: Option<String>,
+    addresses: usize,
+    share_code: bool,
+    notice: Option<String>,
+    question: Option<String>,
+    listing: Option<(PathBuf, usize)>,
+    /// The state of each transfer and the tenth of it last reported.
+    transfers: HashMap<String, (TransferState, u64)>,
+    uploads: usize,
+}
+
+impl Reporter {
+    /// What changed since the last call.
+    pub fn lines(&mut self, app: &App) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        let connection = connection(app);
+        if connection != self.connection {
+            match &connection {
+                Some(line) => lines.push(line.clone()),
+                None => lines.push("Disconnected".to_string()),
+            }
+            self.connection = connection;
+        }
+
+        if app.is_host {
+            lines.extend(
+                app.listening_addrs
+                    .iter()
+                    .skip(self.addresses)
+                    .map(|address| format!("Listening on {}", app.full_address(address))),
+            );
+            self.addresses = app.listening_addrs.len();
+            if let Some(code) = &app.share_code {
+                if app.share_code_published && !self.share_code {
+                    lines.push(format!(
+                        "Share code {code}, downloaders can use it with download --code {code}"
+                    ));
+                }
+            }
+            self.share_code = app.share_code_published;
+            let uploads = app.share_stats.active_uploads();
+            if uploads != self.uploads {
+                lines.push(format!("Sending {uploads} files"));
+                self.uploads = uploads;
+            }
+        }
+
+        let notice = app.warning.as_ref().map(|warning| match warning.severity {
+            Severity::Info => warning.message.clone(),
+            Severity::Warning => format!("Warning: {}", warning.message),
+            Severity::Error => format!("Error: {}", warning.message),
+        });
+        if notice != self.notice {
+            lines.extend(notice.clone());
+            self.notice = notice;
+        }
+
+        let listing = Some((app.current_path.clone(), app.directory_items.len()));
+        if listing != self.listing && !app.directory_items.is_empty() {
+            lines.extend(self::listing(app));
+            self.listing = listing;
+        }
+
+        for transfer in app.transfers.transfers() {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let tenth = (transfer.progress().unwrap_or_default() * 10.0) as u64;
+            let reported = (transfer.state.clone(), tenth);
+            if self.transfers.get(&transfer.path) != Some(&reported) {
+                lines.extend(transfer_line(transfer));
+                self.transfers.insert(transfer.path.clone(), reported);
+            }
+        }
+
+        let question = question(app);
+        if question != self.question {
+            lines.extend(question.clone());
+            self.question = question;
+        }
+        lines
+    }
+}
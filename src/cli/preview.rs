@@ -0,0 +1,360 @@
+//! Structured previews of data files.
+//!
+//! CSV and TSV show as aligned tables, JSON and YAML indented with deep nesting folded
+//! away, anything that isn't text as a hex dump. Previews only hold the start of a file,
+//! so every renderer copes with content cut off anywhere.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+};
+
+/// Rows of a table shown, the header included.
+const TABLE_ROWS: usize = 20;
+/// Wider cells are cut off with an ellipsis.
+const MAX_CELL_WIDTH: usize = 24;
+/// Objects and arrays nested deeper than this are shown as `{…}` and `[…]`.
+const FOLD_DEPTH: usize = 3;
+/// Bytes of a binary file shown in its hex dump.
+const HEX_DUMP_BYTES: usize = 512;
+const HEX_ROW: usize = 16;
+/// Starts the preview of a binary file, followed by its MIME type on the first line and
+/// the hex dump below. Text previews never contain it, NUL bytes make content binary.
+const BINARY_MARKER: char = '\0';
+
+/// MIME types recognized by the first bytes of a file.
+const SIGNATURES: [(&[u8], &str); 12] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF8", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\0asm", "application/wasm"),
+    (b"SQLite format 3\0", "application/vnd.sqlite3"),
+];
+
+/// The preview stored for a file starting with `bytes`: the text itself, or for binary
+/// content its MIME type and a hex dump.
+#[must_use]
+pub fn from_bytes(path: &Path, bytes: &[u8]) -> String {
+    if !is_binary(bytes) {
+        return String::from_utf8_lossy(bytes).to_string();
+    }
+    format!(
+        "{BINARY_MARKER}{}\n{}",
+        mime_type(path, bytes),
+        hex_dump(&bytes[..bytes.len().min(HEX_DUMP_BYTES)])
+    )
+}
+
+/// The MIME type of a binary preview, `None` for text.
+#[must_use]
+pub fn binary_mime(content: &str) -> Option<&str> {
+    content.strip_prefix(BINARY_MARKER)?.lines().next()
+}
+
+/// Whether `bytes` aren't text: they hold a NUL byte or aren't UTF-8, apart from a
+/// character cut off at the end.
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0) || std::str::from_utf8(bytes).is_err_and(|e| e.error_len().is_some())
+}
+
+/// Recognized by the content first, by the extension otherwise.
+fn mime_type(path: &Path, bytes: &[u8]) -> String {
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map(|(_, mime)| (*mime).to_string())
+        .or_else(|| mime_guess::from_path(path).first_raw().map(str::to_string))
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+/// Rows of offset, bytes in hex and the printable ones as ASCII, like `hexdump -C`.
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (row, chunk) in bytes.chunks(HEX_ROW).enumerate() {
+        let hex: Vec<String> = (0..HEX_ROW)
+            .map(|column| {
+                let byte = chunk
+                    .get(column)
+                    .map_or_else(|| "  ".to_string(), |byte| format!("{byte:02x}"));
+                // A wider gap halfway through the row
+                if column == HEX_ROW / 2 {
+                    format!(" {byte}")
+                } else {
+                    byte
+                }
+            })
+            .collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    char::from(byte)
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(dump, "{:08x}  {}  |{ascii}|", row * HEX_ROW, hex.join(" "));
+    }
+    dump
+}
+
+fn render_hex_dump(dump: &str) -> Vec<Line<'static>> {
+    dump.lines()
+        .map(|line| {
+            let (offset, rest) = line.split_at(line.len().min(8));
+            // The hex column has no '|', the first one starts the ASCII column
+            let (hex, ascii) = rest.split_once("  |").unwrap_or((rest, ""));
+            Line::from(vec![
+                Span::styled(offset.to_string(), Style::default().fg(Color::DarkGray)),
+                Span::raw(format!("{hex}  ")),
+                Span::styled(format!("|{ascii}"), Style::default().fg(Color::Cyan)),
+            ])
+        })
+        .collect()
+}
+
+/// The preview of `content`, the start of the file at `path`, rendered by its extension.
+/// Anything not understood is shown as it is.
+pub fn render(path: &Path, content: &str) -> Text<'static> {
+    if let Some(binary) = content.strip_prefix(BINARY_MARKER) {
+        let dump = binary.split_once('\n').map_or("", |(_, dump)| dump);
+        return Text::from(render_hex_dump(dump));
+    }
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let lines = match extension.as_deref() {
+        Some("csv") => table(content, ','),
+        Some("tsv" | "tab") => table(content, '\t'),
+        Some("json" | "geojson") => json(content),
+        Some("yaml" | "yml") => Some(yaml(content)),
+        _ => None,
+    };
+    lines.map_or_else(|| Text::raw(content.to_string()), Text::from)
+}
+
+/// Split a delimited line into fields, with quoted fields as in RFC 4180.
+fn split_row(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn fit(cell: &str, width: usize) -> String {
+    let length = cell.chars().count();
+    if length > width {
+        let cut: String = cell.chars().take(width.saturating_sub(1)).collect();
+        format!("{cut}…")
+    } else {
+        format!("{cell}{}", " ".repeat(width - length))
+    }
+}
+
+fn table(content: &str, delimiter: char) -> Option<Vec<Line<'static>>> {
+    let mut lines: Vec<&str> = content.lines().filter(|line| !line.is_empty()).collect();
+    // The last row is likely cut off unless the content ends where a line does
+    if lines.len() > 1 && !content.ends_with('\n') {
+        lines.pop();
+    }
+    let rows: Vec<Vec<String>> = lines
+        .iter()
+        .take(TABLE_ROWS)
+        .map(|line| split_row(line.trim_end_matches('\r'), delimiter))
+        .collect();
+    let columns = rows.iter().map(Vec::len).max()?;
+    if columns < 2 {
+        return None;
+    }
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+                .min(MAX_CELL_WIDTH)
+        })
+        .collect();
+
+    let render_row = |row: &[String], style: Style| {
+        let cells: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(column, &width)| fit(row.get(column).map_or("", String::as_str), width))
+            .collect();
+        Line::from(Span::styled(
+            cells.join(" │ ").trim_end().to_string(),
+            style,
+        ))
+    };
+    let mut output = vec![render_row(
+        &rows[0],
+        Style::default()
+            .fg(Color::Cyan)
+            .add_modifier(Modifier::BOLD),
+    )];
+    let separator: Vec<String> = widths.iter().map(|&width| "─".repeat(width)).collect();
+    output.push(Line::from(Span::styled(
+        separator.join("─┼─"),
+        Style::default().fg(Color::DarkGray),
+    )));
+    output.extend(
+        rows[1..]
+            .iter()
+            .map(|row| render_row(row, Style::default())),
+    );
+    if lines.len() > TABLE_ROWS {
+        output.push(Line::from(Span::styled(
+            "…",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    Some(output)
+}
+
+/// Re-indent JSON token by token rather than parsing it, so a cut off document still
+/// shows everything up to where it ends.
+fn json(content: &str) -> Option<Vec<Line<'static>>> {
+    let trimmed = content.trim_start();
+    if !trimmed.starts_with(['{', '[']) {
+        return None;
+    }
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut depth = 0usize;
+    // Depth of the container being folded, everything until it closes is skipped
+    let mut folded: Option<usize> = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    let new_line = |line: &mut String, lines: &mut Vec<String>, depth: usize| {
+        if !line.trim().is_empty() {
+            lines.push(std::mem::take(line));
+        }
+        *line = "  ".repeat(depth);
+    };
+
+    for c in trimmed.chars() {
+        if in_string {
+            if folded.is_none() {
+                line.push(c);
+            }
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                if folded.is_none() {
+                    line.push(c);
+                }
+            }
+            '{' | '[' => {
+                depth += 1;
+                if folded.is_some() {
+                    continue;
+                }
+                if depth > FOLD_DEPTH {
+                    folded = Some(depth);
+                    line.push_str(if c == '{' { "{…" } else { "[…" });
+                } else {
+                    line.push(c);
+                    new_line(&mut line, &mut lines, depth);
+                }
+            }
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                match folded {
+                    Some(at) if at == depth + 1 => {
+                        folded = None;
+                        line.push(c);
+                    }
+                    Some(_) => {}
+                    None => {
+                        new_line(&mut line, &mut lines, depth);
+                        line.push(c);
+                    }
+                }
+            }
+            ',' if folded.is_none() => {
+                line.push(c);
+                new_line(&mut line, &mut lines, depth);
+            }
+            ':' if folded.is_none() => line.push_str(": "),
+            c if c.is_whitespace() || folded.is_some() => {}
+            c => line.push(c),
+        }
+    }
+    if !line.trim().is_empty() {
+        lines.push(line);
+    }
+    Some(lines.into_iter().map(Line::from).collect())
+}
+
+/// Fold lines indented deeper than [`FOLD_DEPTH`] levels into a count of what was hidden.
+fn yaml(content: &str) -> Vec<Line<'static>> {
+    let indents: Vec<usize> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .filter(|&indent| indent > 0)
+        .collect();
+    // Indentation of one level, usually two spaces
+    let step = indents.iter().copied().min().unwrap_or(2);
+    let mut lines = Vec::new();
+    let mut hidden = 0;
+    let flush = |hidden: &mut usize, lines: &mut Vec<Line<'static>>, indent: usize| {
+        if *hidden > 0 {
+            lines.push(Line::from(Span::styled(
+                format!("{}… {hidden} more lines", " ".repeat(indent)),
+                Style::default().fg(Color::DarkGray),
+            )));
+            *hidden = 0;
+        }
+    };
+    for line in content.lines() {
+        let indent = line.len() - line.trim_start().len();
+        if !line.trim().is_empty() && indent >= step * FOLD_DEPTH {
+            hidden += 1;
+            continue;
+        }
+        flush(&mut hidden, &mut lines, step * FOLD_DEPTH);
+        let style = if line.trim_start().starts_with('#') {
+            Style::default().fg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+        lines.push(Line::from(Span::styled(line.to_string(), style)));
+    }
+    flush(&mut hidden, &mut lines, step * FOLD_DEPTH);
+    lines
+}
@@ -0,0 +1,194 @@
+//! Colours of the terminal UI: a preset picked under `[theme]` in the config file, with
+//! single colours replaced, or none at all when `NO_COLOR` is set.
+
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+
+/// Built-in sets of colours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// For dark terminal backgrounds.
+    #[default]
+    Dark,
+    /// For light terminal backgrounds, where yellow and white are hard to read.
+    Light,
+    /// No colours, the cursor and selection are still bold or reversed.
+    Mono,
+}
+
+/// `[theme]` in the config file. Colours are names like `yellow` or `light-blue`, or hex
+/// like `#ffaa00`, anything left out comes from the preset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub preset: Preset,
+    #[serde(deserialize_with = "color")]
+    pub accent: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub selection: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub success: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub warning: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub error: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub info: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub muted: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub text: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub directory: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub file: Option<Color>,
+    #[serde(deserialize_with = "color")]
+    pub matched: Option<Color>,
+}
+
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Color>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Color::from_str(&name).map(Some).map_err(|_| {
+        serde::de::Error::custom(format!("unknown colour '{name}', e.g. yellow or #ffaa00"))
+    })
+}
+
+/// What each part of the UI is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Keys in the title bar, the cursor, addresses and the focused pane.
+    pub accent: Color,
+    /// Rows selected for sharing or downloading.
+    pub selection: Color,
+    /// Connected, finished transfers and the share code.
+    pub success: Color,
+    pub warning: Color,
+    pub error: Color,
+    /// Titles, peer names and paused transfers.
+    pub info: Color,
+    /// Labels and details that matter less.
+    pub muted: Color,
+    pub text: Color,
+    pub directory: Color,
+    pub file: Color,
+    /// Characters of a name matching the search.
+    pub matched: Color,
+    /// Whatever the widgets chose, nothing is drawn in colour.
+    pub monochrome: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset(Preset::Dark)
+    }
+}
+
+impl Theme {
+    #[must_use]
+    pub const fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Dark => Self {
+                accent: Color::Yellow,
+                selection: Color::Green,
+                success: Color::Green,
+                warning: Color::Yellow,
+                error: Color::Red,
+                info: Color::Cyan,
+                muted: Color::DarkGray,
+                text: Color::White,
+                directory: Color::Reset,
+                file: Color::Reset,
+                matched: Color::Magenta,
+                monochrome: false,
+            },
+            Preset::Light => Self {
+                accent: Color::Blue,
+                selection: Color::Green,
+                success: Color::Green,
+                warning: Color::Magenta,
+                error: Color::Red,
+                info: Color::Blue,
+                muted: Color::Gray,
+                text: Color::Black,
+                directory: Color::Reset,
+                file: Color::Reset,
+                matched: Color::Red,
+                monochrome: false,
+            },
+            Preset::Mono => Self {
+                accent: Color::Reset,
+                selection: Color::Reset,
+                success: Color::Reset,
+                warning: Color::Reset,
+                error: Color::Reset,
+                info: Color::Reset,
+                muted: Color::Reset,
+                text: Color::Reset,
+                directory: Color::Reset,
+                file: Color::Reset,
+                matched: Color::Reset,
+                monochrome: true,
+            },
+        }
+    }
+
+    /// The theme `config` describes, or [`Preset::Mono`] if `no_color`.
+    #[must_use]
+    pub fn new(config: &ThemeConfig, no_color: bool) -> Self {
+        if no_color {
+            return Self::preset(Preset::Mono);
+        }
+        let preset = Self::preset(config.preset);
+        Self {
+            accent: config.accent.unwrap_or(preset.accent),
+            selection: config.selection.unwrap_or(preset.selection),
+            success: config.success.unwrap_or(preset.success),
+            warning: config.warning.unwrap_or(preset.warning),
+            error: config.error.unwrap_or(preset.error),
+            info: config.info.unwrap_or(preset.info),
+            muted: config.muted.unwrap_or(preset.muted),
+            text: config.text.unwrap_or(preset.text),
+            directory: config.directory.unwrap_or(preset.directory),
+            file: config.file.unwrap_or(preset.file),
+            matched: config.matched.unwrap_or(preset.matched),
+            monochrome: preset.monochrome,
+        }
+    }
+
+    /// Whether the user asked for no colours, see <https://no-color.org>.
+    #[must_use]
+    pub fn no_color() -> bool {
+        std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+    }
+
+    /// A key to press, e.g. in the title bar.
+    #[must_use]
+    pub fn key(&self) -> Style {
+        Style::default().fg(self.accent)
+    }
+
+    /// The highlighted row of a picker.
+    #[must_use]
+    pub fn picked(&self) -> Style {
+        if self.monochrome {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default().fg(Color::Black).bg(self.info)
+        }
+    }
+
+    /// Drop the colours of everything drawn into `buffer` if the theme has none, the
+    /// previews pick theirs by content.
+    pub fn apply(&self, buffer: &mut Buffer) {
+        if !self.monochrome {
+            return;
+        }
+        for cell in &mut buffer.content {
+            cell.set_fg(Color::Reset);
+            cell.set_bg(Color::Reset);
+        }
+    }
+}
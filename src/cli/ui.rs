@@ -1,64 +1,128 @@
-use std::path::PathBuf;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
-    text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    style::{Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame,
 };
 
-use crate::app::{App, AppState};
+use crate::app::{fuzzy_match, App, ConflictPrompt, ConnectionState};
+use crate::cli::preview;
+use crate::cli::theme::Theme;
+use crate::config::Severity;
+use crate::format;
+use crate::keys::Action;
+use crate::recent::RecentChoice;
+use crate::service::greeting::AuthRequirement;
+use crate::service::hashing::ManifestDiff;
+use crate::service::node::AddressKind;
+use crate::service::quality::MAX_SCORE;
+use crate::service::utils::ConflictPolicy;
+use crate::transfers::TransferState;
 
-pub fn render(frame: &mut Frame, app: &App) {
-    // Create main layout
-    let chunks = Layout::default()
+/// Files listed on the host's dashboard, by number of downloads.
+const TOP_FILES: usize = 5;
+/// Room for the totals, the heading and the top files, plus the borders.
+#[allow(clippy::cast_possible_truncation)]
+const DASHBOARD_HEIGHT: u16 = 4 + TOP_FILES as u16;
+/// Uploads listed at once, the rest are counted in the title.
+const OUTGOING_ROWS: usize = 4;
+#[allow(clippy::cast_possible_truncation)]
+const OUTGOING_HEIGHT: u16 = 2 + OUTGOING_ROWS as u16;
+/// Addresses listed at once while picking one to copy.
+const MAX_ADDRESS_ROWS: usize = 5;
+
+/// The columns of the screen and the rows of the left one.
+fn panels(area: Rect, app: &App) -> (Rc<[Rect]>, Rc<[Rect]>) {
+    // Split into left and right panels, plus the transfer queue when toggled on
+    let constraints = if app.show_transfers {
+        vec![
+            Constraint::Percentage(40),
+            Constraint::Percentage(25),
+            Constraint::Percentage(35),
+        ]
+    } else {
+        vec![Constraint::Percentage(50), Constraint::Percentage(50)]
+    };
+    let horizontal_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints(constraints)
+        .split(area);
+
+    // Left panel with file browser
+    let left_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),                          // Title
+            Constraint::Min(10),                            // File tree
+            Constraint::Length(3),                          // Status
+            Constraint::Length(connect_info_rows(app) + 2), // Connect info
+        ])
+        .split(horizontal_chunks[0]);
+    (horizontal_chunks, left_chunks)
+}
+
+fn main_area(area: Rect) -> Rect {
+    Layout::default()
         .direction(Direction::Vertical)
         .margin(2)
         .constraints([Constraint::Percentage(100)])
-        .split(frame.area());
+        .split(area)[0]
+}
 
+/// Rows of the connect info: one, more to pick an address from while it has the focus.
+fn connect_info_rows(app: &App) -> u16 {
+    if !app.addresses_focused {
+        return 1;
+    }
+    let entries = app.listening_addrs.len() + usize::from(app.share_code.is_some());
+    u16::try_from(entries.min(MAX_ADDRESS_ROWS)).unwrap_or(1)
+}
+
+/// Where the file list is drawn on a screen of `area`, its border included, to tell which
+/// row a mouse click landed on.
+#[must_use]
+pub fn file_tree_area(area: Rect, app: &App) -> Rect {
+    panels(main_area(area), app).1[1]
+}
+
+pub fn render(frame: &mut Frame, app: &App) {
     let main_block = Block::default()
         .title(format!(
-            "{} File Browser - PeerID: {}",
+            "{} File Browser{}{} - PeerID: {}",
             if app.is_host { "Host" } else { "Remote" },
+            app.display_name
+                .as_ref()
+                .map(|name| format!(" - {name}"))
+                .unwrap_or_default(),
+            app.share_label
+                .as_ref()
+                .map(|label| format!(" - {label}"))
+                .unwrap_or_default(),
             app.peer_id
         ))
         .borders(Borders::ALL);
     frame.render_widget(main_block, frame.area());
 
-    // Split into left and right panels
-    let horizontal_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .margin(1)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-        .split(chunks[0]);
-
-    // Left panel with file browser
-    let left_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(3), // Title
-            Constraint::Min(10),   // File tree
-            Constraint::Length(3), // Status
-            Constraint::Length(3), // Connect info
-        ])
-        .split(horizontal_chunks[0]);
+    let (horizontal_chunks, left_chunks) = panels(main_area(frame.area()), app);
 
-    render_title(frame, left_chunks[0], app.is_host);
+    render_title(frame, left_chunks[0], app);
 
     if app.is_loading {
         let loading_text = "Downloading files...";
         let loading = Paragraph::new(loading_text)
             .block(Block::default().title("Loading...").borders(Borders::ALL))
-            .style(Style::default().fg(Color::Yellow));
+            .style(app.theme.key());
         frame.render_widget(loading, left_chunks[1]);
     } else if app.is_warning() {
         tracing::warn!("Warning: {}", app.warning_message());
-        let warning = Paragraph::new(app.warning_message().to_string())
-            .style(Style::default().fg(Color::Yellow))
-            .alignment(Alignment::Center);
-        frame.render_widget(warning, left_chunks[1]);
+        render_notification(frame, app, left_chunks[1]);
     } else {
         render_file_tree(frame, app, left_chunks[1]);
     }
@@ -67,148 +131,663 @@ pub fn render(frame: &mut Frame, app: &App) {
     render_connect_info(frame, app, left_chunks[3]);
 
     // Right panel with preview
-    let preview_block = Block::default().title(" Preview ").borders(Borders::ALL);
-
-    let preview_content = app
+    let selected = app
         .selected_index
         .and_then(|index| app.directory_items.get(index))
-        .map_or("No file selected".to_string(), |item| item.preview.clone());
+        .map(|item| {
+            let preview = app.remote_previews.get(item).unwrap_or(&item.preview);
+            (&item.path, preview)
+        });
+    // Binary files are shown as a hex dump, with the type they were recognized as
+    let title = selected
+        .and_then(|(_, preview)| preview::binary_mime(preview))
+        .map_or_else(
+            || " Preview ".to_string(),
+            |mime| format!(" Preview ({mime}) "),
+        );
+    let preview_block = Block::default().title(title).borders(Borders::ALL);
+
+    let preview_content = selected.map_or_else(
+        || Text::raw("No file selected"),
+        |(path, preview)| preview::render(path, preview),
+    );
 
     let preview = Paragraph::new(preview_content)
         .block(preview_block)
-        .style(Style::default().fg(Color::White));
+        .style(Style::default().fg(app.theme.text));
+
+    // A host that is sharing gets its upload stats and running uploads below the preview
+    if app.is_host && !app.items_being_shared.is_empty() {
+        let right_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(5),
+                Constraint::Length(DASHBOARD_HEIGHT),
+                Constraint::Length(OUTGOING_HEIGHT),
+            ])
+            .split(horizontal_chunks[1]);
+        frame.render_widget(preview, right_chunks[0]);
+        render_dashboard(frame, app, right_chunks[1]);
+        render_outgoing(frame, app, right_chunks[2]);
+    } else {
+        frame.render_widget(preview, horizontal_chunks[1]);
+    }
+
+    if app.show_transfers {
+        render_transfers(frame, app, horizontal_chunks[2]);
+    }
+
+    if app.confirming_download {
+        render_download_confirmation(frame, app);
+    }
+    if let Some(input) = &app.address_input {
+        render_address_input(frame, input, &app.theme);
+    } else if let Some(prompt) = &app.conflict_prompt {
+        render_conflicts(frame, app, prompt);
+    } else if let Some(diff) = &app.manifest_diff {
+        render_manifest_diff(frame, diff, &app.theme);
+    } else if !app.sensitive_pending.is_empty() {
+        render_sensitive_warning(frame, &app.sensitive_pending, &app.theme);
+    } else if let Some(index) = app.bookmark_picker {
+        render_bookmarks(frame, app, index);
+    } else if app.show_peer_info {
+        render_peer_info(frame, app);
+    }
+    app.theme.apply(frame.buffer_mut());
+}
+
+/// Who we are connected to, with the full peer ID to check a display name against.
+fn render_peer_info(frame: &mut Frame, app: &App) {
+    let field = |name: &str, value: String| {
+        Line::from(vec![
+            Span::styled(format!("{name:<10}"), app.theme.key()),
+            Span::raw(value),
+        ])
+    };
+    #[allow(clippy::option_if_let_else)]
+    let mut text = match app.connected_peer_id {
+        None => vec![Line::from("Not connected")],
+        Some(peer_id) => {
+            let greeting = app.connected_greeting();
+            let mut text = vec![
+                field(
+                    "Name",
+                    greeting
+                        .and_then(|greeting| greeting.display_name.clone())
+                        .unwrap_or_else(|| "(none given)".to_string()),
+                ),
+                field("Peer ID", peer_id.to_string()),
+            ];
+            if let Some(agent) = app.peer_agents.get(&peer_id) {
+                text.push(field("Software", agent.clone()));
+            }
+            if let Some(greeting) = greeting {
+                if let Some(label) = &greeting.label {
+                    text.push(field("Share", label.clone()));
+                }
+                text.push(field("Features", greeting.features.join(", ")));
+                text.push(field(
+                    "Password",
+                    if greeting.auth == AuthRequirement::Password {
+                        "required".to_string()
+                    } else {
+                        "not required".to_string()
+                    },
+                ));
+            }
+            if let Some((score, rtt)) = app.connection_quality {
+                text.push(field(
+                    "Link",
+                    format!("{} {}ms", quality_dots(score), rtt.as_millis()),
+                ));
+            }
+            text
+        }
+    };
+    text.push(Line::from(""));
+    text.push(field(
+        "You",
+        app.display_name.as_ref().map_or_else(
+            || app.peer_id.to_string(),
+            |name| format!("{name} ({})", app.peer_id),
+        ),
+    ));
+    text.push(Line::from(vec![
+        Span::styled("Esc", app.theme.key()),
+        Span::raw(" Close"),
+    ]));
+
+    let height = u16::try_from(text.len() + 2).unwrap_or(u16::MAX);
+    let popup = centered_rect(frame.area(), 80, height);
+    let info = Paragraph::new(text)
+        .style(Style::default().fg(app.theme.text))
+        .block(Block::default().title(" Peer ").borders(Borders::ALL));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(info, popup);
+}
+
+fn render_bookmarks(frame: &mut Frame, app: &App, selected: usize) {
+    let mut text: Vec<Line> = app
+        .bookmarks
+        .iter()
+        .enumerate()
+        .map(|(index, (name, path))| {
+            let style = if index == selected {
+                app.theme.picked()
+            } else {
+                Style::default().fg(app.theme.text)
+            };
+            let key = if index < 9 {
+                format!("{} ", index + 1)
+            } else {
+                "  ".to_string()
+            };
+            Line::from(Span::styled(
+                format!("{key}@{name}  {}", path.display()),
+                style,
+            ))
+        })
+        .collect();
+    text.push(Line::from(""));
+    text.push(Line::from(vec![
+        Span::styled("Enter", app.theme.key()),
+        Span::raw(" Open | "),
+        Span::styled("Esc", app.theme.key()),
+        Span::raw(" Close"),
+    ]));
+
+    let height = u16::try_from(text.len() + 2).unwrap_or(u16::MAX);
+    let popup = centered_rect(frame.area(), 70, height);
+    let bookmarks = Paragraph::new(text)
+        .style(Style::default().fg(app.theme.text))
+        .block(Block::default().title(" Bookmarks ").borders(Borders::ALL));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(bookmarks, popup);
+}
+
+/// Shown by a bare `junkanoo`: recent shares and hosts, each one key press away.
+pub fn render_start_screen(
+    frame: &mut Frame,
+    choices: &[RecentChoice],
+    selected: usize,
+    theme: &Theme,
+) {
+    let mut text = vec![Line::from("Pick up where you left off:"), Line::from("")];
+    text.extend(choices.iter().enumerate().map(|(index, choice)| {
+        let style = if index == selected {
+            theme.picked()
+        } else {
+            Style::default().fg(theme.text)
+        };
+        let key = if index < 9 {
+            format!("{} ", index + 1)
+        } else {
+            "  ".to_string()
+        };
+        Line::from(Span::styled(format!("{key}{}", choice.describe()), style))
+    }));
+    text.push(Line::from(""));
+    text.push(Line::from(vec![
+        Span::styled("Enter", theme.key()),
+        Span::raw(" Start | "),
+        Span::styled("Esc", theme.key()),
+        Span::raw(" Quit"),
+    ]));
+
+    let height = u16::try_from(text.len() + 2).unwrap_or(u16::MAX);
+    let popup = centered_rect(frame.area(), 80, height);
+    let start = Paragraph::new(text)
+        .style(Style::default().fg(theme.text))
+        .block(Block::default().title(" junkanoo ").borders(Borders::ALL));
+    frame.render_widget(start, popup);
+    theme.apply(frame.buffer_mut());
+}
+
+fn render_notification(frame: &mut Frame, app: &App, area: Rect) {
+    let Some(warning) = &app.warning else {
+        return;
+    };
+    let color = match warning.severity {
+        Severity::Info => app.theme.info,
+        Severity::Warning => app.theme.warning,
+        Severity::Error => app.theme.error,
+    };
+    let mut text = vec![Line::from(warning.message.clone())];
+    if app.notifications.timeout(warning.severity).is_none() {
+        text.push(Line::from(Span::styled(
+            "Press any key to dismiss",
+            Style::default().fg(app.theme.muted),
+        )));
+    }
+    let notification = Paragraph::new(text)
+        .style(Style::default().fg(color))
+        .alignment(Alignment::Center);
+    frame.render_widget(notification, area);
+}
+
+fn render_address_input(frame: &mut Frame, input: &str, theme: &Theme) {
+    let popup = centered_rect(frame.area(), 100, 6);
+    // Keep the end of a long address in view, that's where the user is typing
+    let visible = usize::from(popup.width.saturating_sub(3));
+    let skipped = input.chars().count().saturating_sub(visible);
+    let shown: String = input.chars().skip(skipped).collect();
+    let text = vec![
+        Line::from("Address of the peer to download from:"),
+        Line::from(Span::styled(
+            format!("{shown}_"),
+            Style::default().fg(theme.info),
+        )),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("Enter", theme.key()),
+            Span::raw(" Connect | "),
+            Span::styled("V", theme.key()),
+            Span::raw(" Paste | "),
+            Span::styled("Esc", theme.key()),
+            Span::raw(" Quit"),
+        ]),
+    ];
+
+    let prompt = Paragraph::new(text)
+        .style(Style::default().fg(theme.text))
+        .block(Block::default().title(" Connect ").borders(Borders::ALL));
+    frame.render_widget(Clear, popup);
+    frame.render_widget(prompt, popup);
+}
+
+fn render_sensitive_warning(frame: &mut Frame, paths: &[PathBuf], theme: &Theme) {
+    let mut text = vec![Line::from("These files may hold secrets:")];
+    text.extend(paths.iter().map(|path| {
+        Line::from(Span::styled(
+            format!("! {}", path.display()),
+            Style::default().fg(theme.error),
+        ))
+    }));
+    text.push(Line::from(""));
+    text.push(Line::from(vec![
+        Span::styled("Y", theme.key()),
+        Span::raw(" Share anyway | "),
+        Span::styled("N", theme.key()),
+        Span::raw(" Unselect them"),
+    ]));
+
+    let height = u16::try_from(text.len() + 2).unwrap_or(u16::MAX);
+    let popup = centered_rect(frame.area(), 70, height);
+    let warning = Paragraph::new(text)
+        .style(Style::default().fg(theme.text))
+        .block(
+            Block::default()
+                .title(" Sensitive files ")
+                .borders(Borders::ALL),
+        );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(warning, popup);
+}
+
+fn render_manifest_diff(frame: &mut Frame, diff: &ManifestDiff, theme: &Theme) {
+    let mut text = vec![Line::from("Changes since this directory was last shared:")];
+    for (label, paths, color) in [
+        ("+", &diff.added, theme.success),
+        ("-", &diff.removed, theme.error),
+        ("~", &diff.changed, theme.warning),
+    ] {
+        text.extend(paths.iter().map(|path| {
+            Line::from(Span::styled(
+                format!("{label} {}", path.display()),
+                Style::default().fg(color),
+            ))
+        }));
+    }
+    text.push(Line::from(""));
+    text.push(Line::from(vec![
+        Span::styled("Y", theme.key()),
+        Span::raw(" Share | "),
+        Span::styled("N", theme.key()),
+        Span::raw(" Quit"),
+    ]));
+
+    let height = u16::try_from(text.len() + 2).unwrap_or(u16::MAX);
+    let popup = centered_rect(frame.area(), 70, height);
+    let diff = Paragraph::new(text)
+        .style(Style::default().fg(theme.text))
+        .block(
+            Block::default()
+                .title(" Review shared files ")
+                .borders(Borders::ALL),
+        );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(diff, popup);
+}
+
+/// Files of a download that already exist here, each with the decision taken so far and
+/// both versions' size and modification time to decide by.
+fn render_conflicts(frame: &mut Frame, app: &App, prompt: &ConflictPrompt) {
+    let modified =
+        |time: Option<SystemTime>| time.map_or_else(|| "unknown".to_string(), format::timestamp);
+    let mut text = vec![Line::from(
+        "These files already exist with other content:".to_string(),
+    )];
+    for (index, (conflict, decision)) in prompt.conflicts.iter().zip(&prompt.decisions).enumerate()
+    {
+        let remote_modified = app
+            .all_shared_items
+            .iter()
+            .find(|item| item.path.to_string_lossy() == conflict.path)
+            .and_then(|item| item.modified);
+        let (label, color) = match decision {
+            ConflictPolicy::Overwrite => ("overwrite", app.theme.error),
+            ConflictPolicy::Rename => ("rename", app.theme.info),
+            ConflictPolicy::Skip | ConflictPolicy::Ask => ("skip", app.theme.success),
+        };
+        let style = if index == prompt.selected {
+            Style::default().bg(app.theme.muted)
+        } else {
+            Style::default()
+        };
+        text.push(Line::from(vec![
+            Span::styled(format!("{label:<10}"), style.fg(color)),
+            Span::styled(conflict.relative_path.clone(), style),
+        ]));
+        text.push(Line::from(Span::styled(
+            format!(
+                "          here {} {} | host {} {}",
+                format::size(conflict.local_size),
+                modified(conflict.local_modified),
+                format::size(conflict.size),
+                modified(remote_modified),
+            ),
+            Style::default().fg(app.theme.muted),
+        )));
+    }
+    text.push(Line::from(""));
+    text.push(Line::from(vec![
+        Span::styled("Space", app.theme.key()),
+        Span::raw(" Change | "),
+        Span::styled("Enter", app.theme.key()),
+        Span::raw(" Confirm | "),
+        Span::styled("O", app.theme.key()),
+        Span::raw("/"),
+        Span::styled("S", app.theme.key()),
+        Span::raw("/"),
+        Span::styled("R", app.theme.key()),
+        Span::raw(" Overwrite/Skip/Rename all | "),
+        Span::styled("Esc", app.theme.key()),
+        Span::raw(" Skip all"),
+    ]));
+
+    let height = u16::try_from(text.len() + 2).unwrap_or(u16::MAX);
+    let popup = centered_rect(frame.area(), 90, height);
+    let conflicts = Paragraph::new(text)
+        .style(Style::default().fg(app.theme.text))
+        .block(
+            Block::default()
+                .title(format!(" {} files exist ", prompt.conflicts.len()))
+                .borders(Borders::ALL),
+        );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(conflicts, popup);
+}
+
+/// A popup of at most `width` by `height` in the middle of `area`.
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = area.width.min(width);
+    let height = area.height.min(height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn render_download_confirmation(frame: &mut Frame, app: &App) {
+    let size = app.selected_download_size();
+    #[allow(clippy::cast_precision_loss)]
+    let estimate = app.transfers.link_speed().map_or_else(
+        || "Time estimate unknown until a transfer ran".to_string(),
+        |speed| {
+            let seconds = size as f64 / speed;
+            format!(
+                "About {} at {}",
+                format::duration(Duration::from_secs_f64(seconds)),
+                format::speed(speed)
+            )
+        },
+    );
+    let text = vec![
+        Line::from(format!("Download {} of files?", format::size(size))),
+        Line::from(estimate),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("Y", app.theme.key()),
+            Span::raw(" Download | "),
+            Span::styled("N", app.theme.key()),
+            Span::raw(" Cancel"),
+        ]),
+    ];
 
-    frame.render_widget(preview, horizontal_chunks[1]);
+    let popup = centered_rect(frame.area(), 60, 6);
+    let confirmation = Paragraph::new(text)
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(app.theme.text))
+        .block(
+            Block::default()
+                .title(" Large download ")
+                .borders(Borders::ALL),
+        );
+    frame.render_widget(Clear, popup);
+    frame.render_widget(confirmation, popup);
 }
 
-fn render_title(frame: &mut Frame, area: Rect, is_host: bool) {
-    let title = Paragraph::new(Line::from(vec![
+/// The file browser's keys as bound, see [`KeyBindings`].
+fn render_title(frame: &mut Frame, area: Rect, app: &App) {
+    let (is_host, bindings, theme) = (app.is_host, &app.keys, &app.theme);
+    let navigate = bindings.label(Action::Up) + &bindings.label(Action::Down);
+    let mut shown = vec![
+        (navigate, " Navigate | "),
+        (bindings.label(Action::Open), " Open dir | "),
+        (bindings.label(Action::Select), " Select | "),
+        (bindings.label(Action::Unselect), " Unselect | "),
+        (bindings.label(Action::UnselectAll), " Unselect all | "),
+        (bindings.label(Action::Back), " Back | "),
+        (bindings.label(Action::Download), " Begin Download | "),
+        (bindings.label(Action::Preview), " Full preview | "),
+        (bindings.label(Action::Transfers), " Transfers | "),
+        (bindings.label(Action::Sort), " Sort | "),
+        (bindings.label(Action::Search), " Search | "),
+        (bindings.label(Action::Hidden), " Hidden | "),
+        (bindings.label(Action::Bookmarks), " Bookmarks | "),
+        (bindings.label(Action::PeerInfo), " Peer info | "),
+    ];
+    if !is_host {
+        shown.push((bindings.label(Action::Refresh), " Refresh | "));
+    }
+    let mut keys = vec![
         Span::styled(
             format!(" {} File Browser", if is_host { "Host" } else { "Remote" }),
-            Style::default().fg(Color::Cyan),
+            Style::default().fg(theme.info),
         ),
         Span::raw(" | "),
-        Span::styled("↑↓", Style::default().fg(Color::Yellow)),
-        Span::raw(" Navigate | "),
-        Span::styled("Enter", Style::default().fg(Color::Yellow)),
-        Span::raw(" Open dir | "),
-        Span::styled("Y", Style::default().fg(Color::Yellow)),
-        Span::raw(" Select | "),
-        Span::styled("N", Style::default().fg(Color::Yellow)),
-        Span::raw(" Unselect | "),
-        Span::styled("U", Style::default().fg(Color::Yellow)),
-        Span::raw(" Unselect all | "),
-        Span::styled("Backspace", Style::default().fg(Color::Yellow)),
-        Span::raw(" Back"),
-        Span::raw(" | "),
-        Span::styled("D", Style::default().fg(Color::Yellow)),
-        Span::raw(" Begin Download | "),
-    ]))
-    .block(Block::default().borders(Borders::ALL));
+    ];
+    for (key, description) in shown {
+        keys.push(Span::styled(key, theme.key()));
+        keys.push(Span::raw(description));
+    }
+    let title = Paragraph::new(Line::from(keys)).block(Block::default().borders(Borders::ALL));
     frame.render_widget(title, area);
 }
 
+// Columns next to the file names, the date reads like `2026-10-17 22:00` at most
+const SIZE_WIDTH: usize = 10;
+const MODIFIED_WIDTH: usize = 16;
+
 fn render_file_tree(frame: &mut Frame, app: &App, area: Rect) {
     if app.is_loading {
         let loading_text = "Downloading files...";
         let loading = Paragraph::new(loading_text)
             .block(Block::default().title("Status").borders(Borders::ALL))
-            .style(Style::default().fg(Color::Yellow));
+            .style(app.theme.key());
         frame.render_widget(loading, area);
     } else if app.is_warning() {
-        let warning = Paragraph::new(app.warning_message().to_string())
-            .style(Style::default().fg(Color::Yellow))
-            .alignment(Alignment::Center);
-        frame.render_widget(warning, area);
+        render_notification(frame, app, area);
     } else {
+        let visual_range = app.visual_range();
+        let now = SystemTime::now();
         let items: Vec<ListItem> = app
             .directory_items
             .iter()
             .map(|item| {
                 let indent = "  ".repeat(item.depth);
-                let selected = match app.state {
-                    AppState::Share => {
-                        #[allow(clippy::option_if_let_else)]
-                        if let Ok(rel_path) = item.path.strip_prefix(&app.current_path) {
-                            if app.items_to_share.contains(&rel_path.to_path_buf()) {
-                                "🔵 "
-                            } else {
-                                "  "
-                            }
-                        } else {
-                            "  "
-                        }
-                    }
-                    AppState::Download => {
-                        if app.items_to_download.contains(&item.path) {
-                            "🔵 "
-                        } else {
-                            "  "
-                        }
-                    }
-                };
+                let is_selected = app.is_selected(item);
+                let selected = if is_selected { "🔵 " } else { "  " };
                 let prefix = if item.is_dir { "📁 " } else { "📄 " };
 
-                let style = if app.selected_index.is_some_and(|idx| idx == item.index) {
+                let mut style = if app.selected_index.is_some_and(|idx| idx == item.index) {
                     Style::default()
-                        .fg(Color::Yellow)
+                        .fg(app.theme.accent)
                         .add_modifier(Modifier::BOLD)
-                } else if match app.state {
-                    AppState::Share => app.items_to_share.contains(
-                        &item
-                            .path
-                            .strip_prefix(&app.current_path)
-                            .unwrap_or(&PathBuf::new())
-                            .to_path_buf(),
-                    ),
-                    AppState::Download => app.items_to_download.contains(&item.path),
-                } {
-                    Style::default().fg(Color::Green)
+                } else if is_selected {
+                    Style::default().fg(app.theme.selection)
+                } else if item.is_dir {
+                    Style::default().fg(app.theme.directory)
                 } else {
-                    Style::default()
+                    Style::default().fg(app.theme.file)
                 };
+                if visual_range
+                    .as_ref()
+                    .is_some_and(|range| range.contains(&item.index))
+                {
+                    style = style.bg(app.theme.muted);
+                }
 
-                ListItem::new(Line::from(vec![
+                // Borders, selection marker, icon and the gaps between columns
+                let name_width = usize::from(area.width)
+                    .saturating_sub(indent.len() + 2 + 2 + 3 + SIZE_WIDTH + MODIFIED_WIDTH + 2);
+                let matches = fuzzy_match(&app.search_query, &item.name).unwrap_or_default();
+                let size = if item.is_dir {
+                    String::new()
+                } else {
+                    format::size(item.size)
+                };
+                let modified = item
+                    .modified
+                    .map_or_else(String::new, |modified| format::relative(modified, now));
+
+                let mut spans = vec![
                     Span::raw(indent),
                     Span::styled(selected, style),
-                    Span::styled(format!("{}{}", prefix, item.name), style),
-                ]))
+                    Span::styled(prefix, style),
+                ];
+                let name: Vec<char> = item.name.chars().take(name_width).collect();
+                spans.extend(name.iter().enumerate().map(|(position, c)| {
+                    if matches.contains(&position) {
+                        Span::styled(
+                            c.to_string(),
+                            style
+                                .fg(app.theme.matched)
+                                .add_modifier(Modifier::UNDERLINED),
+                        )
+                    } else {
+                        Span::styled(c.to_string(), style)
+                    }
+                }));
+                let padding = " ".repeat(name_width - name.len());
+                spans.push(Span::styled(
+                    format!("{padding} {size:>SIZE_WIDTH$} {modified:>MODIFIED_WIDTH$}"),
+                    style,
+                ));
+                ListItem::new(Line::from(spans))
             })
             .collect();
 
-        let current_path = format!(" {} ", app.current_path.display());
-        let files_list = List::new(items)
-            .block(Block::default().title(current_path).borders(Borders::ALL))
-            .highlight_style(
-                Style::default()
-                    .fg(Color::Yellow)
-                    .add_modifier(Modifier::BOLD),
-            );
+        let current_path = format!(
+            " {} | Sort: {} ",
+            app.current_path.display(),
+            app.sort_order.label()
+        );
+        let mut block = Block::default().title(current_path).borders(Borders::ALL);
+        if app.search_active || !app.search_query.is_empty() {
+            let cursor = if app.search_active { "▏" } else { "" };
+            block = block.title_bottom(format!(" /{}{cursor} ", app.search_query));
+        }
+        if let Some(range) = &visual_range {
+            block = block.title_bottom(format!(
+                " VISUAL {} rows | Y Select | N Unselect | Esc Cancel ",
+                range.end() - range.start() + 1
+            ));
+        }
+        let files_list = List::new(items).block(block).highlight_style(
+            Style::default()
+                .fg(app.theme.accent)
+                .add_modifier(Modifier::BOLD),
+        );
 
-        frame.render_widget(files_list, area);
+        frame.render_stateful_widget(
+            files_list,
+            area,
+            &mut ListState::default().with_offset(app.list_offset),
+        );
     }
 }
 
 fn render_status(frame: &mut Frame, app: &App, area: Rect) {
-    // Calculate total selected items
-    let total_selected = app.items_to_share.len() + app.items_to_download.len();
+    let selected = if app.is_host {
+        format!("{} items selected", app.items_to_share.len())
+    } else {
+        let (count, size) = app.selected_download();
+        format!("{count} items, {} selected", format::size(size))
+    };
 
     // Create status bar
-    let status = if app.is_connected() {
+    let mut status = if app.is_connected() {
+        let peer = match (app.connected_greeting(), app.connected_peer_id) {
+            (Some(greeting), Some(peer_id)) => greeting.describe(&peer_id),
+            (_, peer_id) => format!(
+                "Connected to peer: {}",
+                peer_id.map_or_else(|| "Unknown".to_string(), |id| id.to_string())
+            ),
+        };
         format!(
-            "Connected to peer: {} | Selected items: {}",
-            app.connected_peer_id
-                .map_or("Unknown".to_string(), |id| id.to_string()),
-            total_selected
+            "{}{}{} | {selected}",
+            peer,
+            app.connected_version()
+                .map(|version| format!(" ({version})"))
+                .unwrap_or_default(),
+            app.connection_quality
+                .map(|(score, rtt)| format!(" {} {}ms", quality_dots(score), rtt.as_millis()))
+                .unwrap_or_default(),
         )
+    } else if let ConnectionState::Reconnecting(attempt) = app.connection_state {
+        format!("Reconnecting (attempt {attempt}) | {selected}")
     } else {
-        format!("Disconnected | Selected items: {total_selected}")
+        format!("Disconnected | {selected}")
     };
+    if !app.items_being_shared.is_empty() {
+        let _ = write!(status, " | Sharing: {}", app.items_being_shared.len());
+    }
+    if let Some(expires_at) = app.share_expires_at {
+        let remaining = expires_at.saturating_duration_since(std::time::Instant::now());
+        let _ = write!(status, " | Expires in {}", format::duration(remaining));
+    }
+    if let Some(opens_at) = app
+        .share_opens_at
+        .filter(|opens_at| *opens_at > std::time::SystemTime::now())
+    {
+        let _ = write!(status, " | Opens at {}", format::time_of_day(opens_at));
+    }
+    if let Some(dht_peers) = app.dht_peers {
+        let _ = write!(status, " | DHT peers: {dht_peers}");
+    }
+    if let Some(note) = app.transport.note() {
+        let _ = write!(status, " | {note}");
+    }
 
     let status_style = if app.is_connected() {
-        Style::default().fg(Color::Green)
+        Style::default().fg(app.theme.success)
+    } else if matches!(app.connection_state, ConnectionState::Reconnecting(_)) {
+        Style::default().fg(app.theme.warning)
     } else {
-        Style::default().fg(Color::Red)
+        Style::default().fg(app.theme.error)
     };
 
     let status_widget = Paragraph::new(status)
@@ -218,19 +797,211 @@ fn render_status(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(status_widget, area);
 }
 
+/// Uptime, peers, bytes and the most downloaded files of the share.
+fn render_dashboard(frame: &mut Frame, app: &App, area: Rect) {
+    let stats = &app.share_stats;
+    let label = |text: &str| Span::styled(text.to_string(), Style::default().fg(app.theme.muted));
+    let mut lines = vec![
+        Line::from(vec![
+            label("Up "),
+            Span::raw(format::duration(stats.uptime())),
+            label(" | Peers served "),
+            Span::raw(stats.peers_served().to_string()),
+            label(" | Sent "),
+            Span::raw(format::size(stats.bytes_served())),
+            label(" | Active "),
+            Span::styled(
+                stats.active_uploads().to_string(),
+                Style::default().fg(if stats.active_uploads() > 0 {
+                    app.theme.accent
+                } else {
+                    app.theme.text
+                }),
+            ),
+        ]),
+        Line::from(label("Top files")),
+    ];
+    let top_files = stats.top_files(TOP_FILES);
+    if top_files.is_empty() {
+        lines.push(Line::from(label("  nothing downloaded yet")));
+    }
+    lines.extend(top_files.into_iter().map(|(path, downloads)| {
+        Line::from(vec![
+            Span::styled(
+                format!("  {downloads:>3}× "),
+                Style::default().fg(app.theme.info),
+            ),
+            Span::raw(path.to_string()),
+        ])
+    }));
+    let dashboard =
+        Paragraph::new(lines).block(Block::default().title(" Share ").borders(Borders::ALL));
+    frame.render_widget(dashboard, area);
+}
+
+/// Who is downloading what from us right now.
+fn render_outgoing(frame: &mut Frame, app: &App, area: Rect) {
+    let uploads = app.share_stats.uploads();
+    let items: Vec<ListItem> = if uploads.is_empty() {
+        vec![ListItem::new(Span::styled(
+            "No downloads running",
+            Style::default().fg(app.theme.muted),
+        ))]
+    } else {
+        uploads
+            .iter()
+            .take(OUTGOING_ROWS)
+            .map(|upload| {
+                let progress = upload
+                    .progress()
+                    .map_or_else(|| "starting".to_string(), format::percent);
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!("{} ", app.peer_name(&upload.peer_id)),
+                        Style::default().fg(app.theme.info),
+                    ),
+                    Span::raw(format!("{} ", upload.path)),
+                    Span::styled(progress, app.theme.key()),
+                ]))
+            })
+            .collect()
+    };
+    let title = match uploads.len().saturating_sub(OUTGOING_ROWS) {
+        0 => " Outgoing transfers ".to_string(),
+        more => format!(" Outgoing transfers, {more} more "),
+    };
+    let outgoing = List::new(items).block(Block::default().title(title).borders(Borders::ALL));
+    frame.render_widget(outgoing, area);
+}
+
+/// A quality score as filled and empty dots, e.g. ●●●○○.
+fn quality_dots(score: u8) -> String {
+    let filled = usize::from(score.min(MAX_SCORE));
+    "●".repeat(filled) + &"○".repeat(usize::from(MAX_SCORE) - filled)
+}
+
+fn render_transfers(frame: &mut Frame, app: &App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .transfers
+        .transfers()
+        .iter()
+        .map(|transfer| {
+            let name = Path::new(&transfer.path).file_name().map_or_else(
+                || transfer.path.clone(),
+                |name| name.to_string_lossy().to_string(),
+            );
+            let (details, color) = match &transfer.state {
+                TransferState::Queued => ("queued".to_string(), app.theme.muted),
+                TransferState::Active => {
+                    let mut details = transfer
+                        .progress()
+                        .map_or_else(String::new, format::percent);
+                    if let Some(speed) = transfer.smoothed_speed() {
+                        let _ = write!(details, " {}", format::speed(speed));
+                    }
+                    if let Some(eta) = transfer.eta() {
+                        let _ = write!(details, " ETA {}", format::duration(eta));
+                    }
+                    (details, app.theme.accent)
+                }
+                TransferState::Completed => {
+                    let mut details = "done".to_string();
+                    if let Some(speed) = transfer.record().and_then(|record| record.throughput()) {
+                        let _ = write!(details, " {}", format::speed(speed));
+                    }
+                    (details, app.theme.success)
+                }
+                TransferState::UpToDate => ("already up to date".to_string(), app.theme.success),
+                TransferState::Kept => ("kept the existing file".to_string(), app.theme.muted),
+                TransferState::Paused => {
+                    let details = transfer.progress().map_or_else(
+                        || "paused".to_string(),
+                        |progress| format!("{} paused", format::percent(progress)),
+                    );
+                    (details, app.theme.info)
+                }
+                TransferState::Cancelled => ("cancelled".to_string(), app.theme.muted),
+                TransferState::Failed(error) => (format!("failed: {error}"), app.theme.error),
+                TransferState::Corrupted => ("hash mismatch".to_string(), app.theme.error),
+            };
+            let mut line = vec![
+                Span::raw(format!("{name} ")),
+                Span::styled(details, Style::default().fg(color)),
+            ];
+            if let Some(note) = &transfer.note {
+                line.push(Span::styled(
+                    format!(", {note}"),
+                    Style::default().fg(app.theme.muted),
+                ));
+            }
+            // Throughput of the same file in an earlier session, for comparison
+            if let Some(previous) = app.transfer_history.previous(&transfer.path) {
+                if let Some(speed) = previous.throughput() {
+                    let from = previous
+                        .peer
+                        .as_ref()
+                        .map(|peer| format!(" from {peer}"))
+                        .unwrap_or_default();
+                    line.push(Span::styled(
+                        format!(" (last time {}{from})", format::speed(speed)),
+                        Style::default().fg(app.theme.muted),
+                    ));
+                }
+            }
+            ListItem::new(Line::from(line))
+        })
+        .collect();
+
+    let title = match app.transfers.totals() {
+        (_, 0) => " Transfers ".to_string(),
+        (bytes, total) => format!(
+            " Transfers {} of {} ",
+            format::size(bytes),
+            format::size(total)
+        ),
+    };
+    let mut block = Block::default().title(title).borders(Borders::ALL);
+    let mut state = ListState::default();
+    if app.transfers_focused {
+        block = block
+            .border_style(app.theme.key())
+            .title_bottom(" P Pause/resume | C Cancel | Tab Next ");
+        state.select(Some(app.selected_transfer));
+    }
+    let transfers = List::new(items)
+        .block(block)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(transfers, area, &mut state);
+}
+
 fn render_connect_info(frame: &mut Frame, app: &App, area: Rect) {
-    let items: Vec<ListItem> = if app.listening_addrs.is_empty() {
+    // The code comes first, it is what people read out to each other
+    let code = app.share_code.as_ref().map(|code| {
+        let mut line = vec![
+            Span::raw("🔑 Code: "),
+            Span::styled(
+                code.clone(),
+                Style::default()
+                    .fg(app.theme.success)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        ];
+        if !app.share_code_published {
+            line.push(Span::styled(
+                " (publishing…)",
+                Style::default().fg(app.theme.muted),
+            ));
+        }
+        ListItem::new(Line::from(line))
+    });
+    let addresses: Vec<ListItem> = if app.listening_addrs.is_empty() {
         vec![ListItem::new("No listening addresses available")]
     } else {
         app.listening_addrs
             .iter()
-            .map(|addr| {
-                let addr_str = if addr.to_string().contains("/p2p/") {
-                    addr.to_string()
-                } else {
-                    format!("{}/p2p/{}", addr, app.peer_id)
-                };
-                let icon = if app.clipboard_success {
+            .enumerate()
+            .map(|(index, addr)| {
+                let icon = if app.clipboard_success() && index == app.selected_address {
                     "✅ " // Checkmark icon
                 } else {
                     "📋 " // Clipboard icon
@@ -238,9 +1009,13 @@ fn render_connect_info(frame: &mut Frame, app: &App, area: Rect) {
                 ListItem::new(Line::from(vec![
                     Span::raw(icon),
                     Span::styled(
-                        addr_str,
+                        format!("{:<9}", AddressKind::of(addr).label()),
+                        Style::default().fg(app.theme.muted),
+                    ),
+                    Span::styled(
+                        app.full_address(addr),
                         Style::default()
-                            .fg(Color::Yellow)
+                            .fg(app.theme.accent)
                             .add_modifier(Modifier::UNDERLINED),
                     ),
                 ]))
@@ -248,11 +1023,21 @@ fn render_connect_info(frame: &mut Frame, app: &App, area: Rect) {
             .collect()
     };
 
-    let connect_widget = List::new(items).block(
-        Block::default()
-            .title(" Addresses (Press X to Copy the address) ")
-            .borders(Borders::ALL),
-    );
+    let has_code = app.share_code.is_some();
+    let items: Vec<ListItem> = code.into_iter().chain(addresses).collect();
+    let mut block = Block::default()
+        .title(" Addresses (X to copy, Tab to pick another) ")
+        .borders(Borders::ALL);
+    let mut state = ListState::default();
+    if app.addresses_focused {
+        block = block
+            .border_style(app.theme.key())
+            .title_bottom(" ↑↓ Select | X Copy | Tab Files ");
+        state.select(Some(app.selected_address + usize::from(has_code)));
+    }
+    let connect_widget = List::new(items)
+        .block(block)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
 
-    frame.render_widget(connect_widget, area);
+    frame.render_stateful_widget(connect_widget, area, &mut state);
 }
@@ -0,0 +1,175 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::cli::theme::ThemeConfig;
+use crate::keys::KeyBindings;
+use crate::service::limiter::BandwidthSchedule;
+
+/// Settings read from `config.toml` in the user's config directory. Every field is
+/// optional, anything left out keeps its default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name peers see instead of the peer ID, the machine's host name if left out.
+    pub display_name: Option<String>,
+    pub notifications: NotificationConfig,
+    pub navigation: NavigationConfig,
+    /// Named directories, jumped to with `b` or shared with `junkanoo share @name`.
+    pub bookmarks: BTreeMap<String, PathBuf>,
+    /// Transfer rate limits by time of day, `--max-upload` and `--max-download` replace
+    /// the limits outside of its windows.
+    pub bandwidth: BandwidthSchedule,
+    pub keys: KeyBindings,
+    pub theme: ThemeConfig,
+}
+
+/// How important a message shown to the user is, which decides how long it stays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Display durations of notifications per [`Severity`], in seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(clippy::struct_field_names)]
+pub struct NotificationConfig {
+    pub info_seconds: u64,
+    pub warning_seconds: u64,
+    /// Errors stay until a key is pressed unless this is set.
+    pub error_seconds: Option<u64>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            info_seconds: 2,
+            warning_seconds: 2,
+            error_seconds: None,
+        }
+    }
+}
+
+impl NotificationConfig {
+    /// How long a notification stays on screen, `None` until it is dismissed.
+    pub fn timeout(&self, severity: Severity) -> Option<Duration> {
+        match severity {
+            Severity::Info => Some(self.info_seconds),
+            Severity::Warning => Some(self.warning_seconds),
+            Severity::Error => self.error_seconds,
+        }
+        .map(Duration::from_secs)
+    }
+}
+
+/// How holding Up or Down speeds up the cursor in long listings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NavigationConfig {
+    /// Presses of the same key closer together than this count as the key being held.
+    pub repeat_interval_ms: u64,
+    /// Repeats of a held key before the cursor starts skipping rows.
+    pub accelerate_after: u32,
+    /// Rows moved per repeat once the cursor sped up.
+    pub accelerated_rows: usize,
+}
+
+impl Default for NavigationConfig {
+    fn default() -> Self {
+        Self {
+            repeat_interval_ms: 150,
+            accelerate_after: 8,
+            accelerated_rows: 5,
+        }
+    }
+}
+
+impl NavigationConfig {
+    #[must_use]
+    pub const fn repeat_interval(&self) -> Duration {
+        Duration::from_millis(self.repeat_interval_ms)
+    }
+}
+
+/// Where the config file is looked for.
+#[must_use]
+pub fn config_path() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|dir| dir.join("junkanoo").join("config.toml"))
+}
+
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keep state in `dir` instead of the user's data directory, set once from `--state-dir`.
+pub fn set_state_dir(dir: PathBuf) {
+    let _ = STATE_DIR.set(dir);
+}
+
+/// Where transfer history, recent shares and hash caches are kept between sessions.
+pub fn state_dir() -> Option<PathBuf> {
+    STATE_DIR
+        .get()
+        .cloned()
+        .or_else(|| dirs_next::data_local_dir().map(|dir| dir.join("junkanoo")))
+}
+
+impl Config {
+    /// Read the config file, a missing file means the defaults.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read or is not valid config.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Parse the contents of a config file.
+    ///
+    /// # Errors
+    ///
+    /// If `contents` is not valid TOML or has unknown settings.
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut config: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        for path in config.bookmarks.values_mut() {
+            *path = expand_home(path);
+        }
+        Ok(config)
+    }
+
+    /// Directory of the bookmark called `name`.
+    ///
+    /// # Errors
+    ///
+    /// If no bookmark is called `name`.
+    pub fn bookmark(&self, name: &str) -> Result<&Path, String> {
+        self.bookmarks
+            .get(name)
+            .map(PathBuf::as_path)
+            .ok_or_else(|| {
+                let known: Vec<&str> = self.bookmarks.keys().map(String::as_str).collect();
+                if known.is_empty() {
+                    format!(
+                        "no bookmark named '{name}', add one under [bookmarks] in the config file"
+                    )
+                } else {
+                    format!("no bookmark named '{name}', known: {}", known.join(", "))
+                }
+            })
+    }
+}
+
+/// Resolve a leading `~` to the home directory.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs_next::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
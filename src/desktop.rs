@@ -0,0 +1,65 @@
+//! Desktop notifications for `--notify`, so the end of a transfer left running in a
+//! background terminal doesn't go unnoticed.
+
+use crate::format;
+
+/// A notification, built from what happened so its text can be checked without a desktop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notice {
+    pub summary: String,
+    pub body: String,
+}
+
+impl Notice {
+    /// `files` arrived from the host, named `peer` if it greeted with a name.
+    #[must_use]
+    pub fn downloaded(files: &[String], peer: Option<&str>) -> Self {
+        Self {
+            summary: "Download finished".to_string(),
+            body: peer.map_or_else(
+                || describe(files),
+                |peer| format!("{} from {peer}", describe(files)),
+            ),
+        }
+    }
+
+    #[must_use]
+    pub fn download_failed(files: &[String]) -> Self {
+        Self {
+            summary: "Download failed".to_string(),
+            body: describe(files),
+        }
+    }
+
+    /// `peer` stopped fetching from the share after `files` files of `bytes` in total.
+    #[must_use]
+    pub fn served(peer: &str, files: usize, bytes: u64) -> Self {
+        Self {
+            summary: format!("{peer} finished downloading"),
+            body: format!("{files} files, {}", format::size(bytes)),
+        }
+    }
+
+    /// Show it from a thread of its own, the notification daemon may take a while to
+    /// answer. Failures, e.g. without a desktop session, are only logged.
+    pub fn show(self) {
+        std::thread::spawn(move || {
+            if let Err(e) = notify_rust::Notification::new()
+                .appname("junkanoo")
+                .summary(&self.summary)
+                .body(&self.body)
+                .show()
+            {
+                tracing::warn!("Failed to show a desktop notification: {}", e);
+            }
+        });
+    }
+}
+
+/// The file for a single one, otherwise how many.
+fn describe(files: &[String]) -> String {
+    match files {
+        [file] => file.clone(),
+        files => format!("{} files", files.len()),
+    }
+}
@@ -0,0 +1,159 @@
+//! How sizes, times and durations are shown to people.
+//!
+//! Used in the file tree, the status bar, summaries and the display fields of `--json`
+//! output. Numbers follow the decimal separator of the user's locale, taken from `LC_ALL`,
+//! `LC_NUMERIC` or `LANG`.
+
+use chrono::{DateTime, Local};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+/// Languages that write `1,5` rather than `1.5`.
+const DECIMAL_COMMA: [&str; 27] = [
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "pl", "pt", "ro", "ru", "sv",
+];
+
+/// Number conventions of a locale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub decimal_separator: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::ENGLISH
+    }
+}
+
+impl Locale {
+    pub const ENGLISH: Self = Self {
+        decimal_separator: '.',
+    };
+
+    /// The locale named like `de_DE.UTF-8`, only the language matters.
+    #[must_use]
+    pub fn from_name(name: &str) -> Self {
+        let language = name
+            .split(['_', '.', '@', '-'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        if DECIMAL_COMMA.contains(&language.as_str()) {
+            Self {
+                decimal_separator: ',',
+            }
+        } else {
+            Self::ENGLISH
+        }
+    }
+
+    /// The locale of the environment, read once.
+    pub fn current() -> Self {
+        static CURRENT: OnceLock<Locale> = OnceLock::new();
+        *CURRENT.get_or_init(|| {
+            ["LC_ALL", "LC_NUMERIC", "LANG"]
+                .iter()
+                .filter_map(|variable| std::env::var(variable).ok())
+                .find(|name| !name.is_empty())
+                .map_or_else(Self::default, |name| Self::from_name(&name))
+        })
+    }
+
+    /// A number with one decimal, e.g. `1.5` or `1,5`.
+    fn decimal(self, value: f64) -> String {
+        let formatted = format!("{value:.1}");
+        if self.decimal_separator == '.' {
+            formatted
+        } else {
+            formatted.replace('.', &self.decimal_separator.to_string())
+        }
+    }
+
+    /// Human-readable size in binary units, e.g. `1.5 MiB`.
+    #[must_use]
+    pub fn size(self, bytes: u64) -> String {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        #[allow(clippy::cast_precision_loss)]
+        let mut size = bytes as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{bytes} B")
+        } else {
+            format!("{} {}", self.decimal(size), UNITS[unit])
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    #[must_use]
+    pub fn speed(self, bytes_per_second: f64) -> String {
+        format!("{}/s", self.size(bytes_per_second as u64))
+    }
+}
+
+/// Human-readable size in the user's locale, e.g. `1.5 MiB`.
+#[must_use]
+pub fn size(bytes: u64) -> String {
+    Locale::current().size(bytes)
+}
+
+/// Transfer rate in the user's locale, e.g. `1.5 MiB/s`.
+#[must_use]
+pub fn speed(bytes_per_second: f64) -> String {
+    Locale::current().speed(bytes_per_second)
+}
+
+/// A countdown or elapsed time as `MM:SS`, with hours as `H:MM:SS`.
+#[must_use]
+pub fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes:02}:{seconds:02}")
+    }
+}
+
+/// Progress between 0 and 1 as a whole percentage, padded to line up, e.g. ` 42%`.
+#[must_use]
+pub fn percent(progress: f64) -> String {
+    format!("{:>3.0}%", progress * 100.0)
+}
+
+/// A point in time as a local `HH:MM`, as shown for scheduled shares.
+#[must_use]
+pub fn time_of_day(time: SystemTime) -> String {
+    DateTime::<Local>::from(time).format("%H:%M").to_string()
+}
+
+/// A point in time as a local date and time, e.g. `2024-05-01 14:02`.
+#[must_use]
+pub fn timestamp(time: SystemTime) -> String {
+    DateTime::<Local>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// How long before `now` the point in time was, e.g. `5 min ago` or `yesterday`. Older
+/// than a week it is the date, times in the future are shown as they are.
+#[must_use]
+pub fn relative(time: SystemTime, now: SystemTime) -> String {
+    let Ok(ago) = now.duration_since(time) else {
+        return timestamp(time);
+    };
+    let minutes = ago.as_secs() / 60;
+    let (hours, days) = (minutes / 60, minutes / 60 / 24);
+    match (minutes, hours, days) {
+        (0, _, _) => "just now".to_string(),
+        (minutes, 0, _) => format!("{minutes} min ago"),
+        (_, hours, 0) => format!("{hours} h ago"),
+        (_, _, 1) => "yesterday".to_string(),
+        (_, _, days) if days < 7 => format!("{days} days ago"),
+        _ => DateTime::<Local>::from(time).format("%Y-%m-%d").to_string(),
+    }
+}
@@ -0,0 +1,171 @@
+//! `junkanoo get`, downloading named paths from a host without starting the UI, like `scp`
+//! for quick one-offs.
+
+use libp2p::Multiaddr;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::app::DirectoryItem;
+use crate::format;
+use crate::service::archive::ArchiveFormat;
+use crate::service::error::JunkanooError;
+use crate::service::node::Event;
+use crate::service::secret::Secret;
+use crate::{JunkanooNode, NodeConfig};
+
+/// Characters the bar itself is wide.
+const BAR_WIDTH: usize = 24;
+
+/// Connect to the host at `address` and download `paths` of its listing.
+///
+/// Directories come with everything below them, into `directory`, then the connection is
+/// closed again. With `archive` directories arrive as one archive each. Every network event
+/// is passed to `on_event` while the files arrive. Resolves with the saved paths.
+///
+/// # Errors
+///
+/// If the host can't be reached or refuses, or a download fails.
+pub async fn fetch(
+    config: NodeConfig,
+    address: Multiaddr,
+    password: Option<Secret>,
+    paths: &[PathBuf],
+    directory: Option<PathBuf>,
+    archive: Option<ArchiveFormat>,
+    mut on_event: impl FnMut(&Event),
+) -> Result<Vec<String>, JunkanooError> {
+    let node = JunkanooNode::start(config).await?;
+    let mut download = node.download(address, password).await?;
+    let result = if let Some(path) = missing(download.items(), paths) {
+        Err(JunkanooError::other(format!(
+            "{} is not shared by the host",
+            path.display()
+        )))
+    } else {
+        let (archives, files): (Vec<PathBuf>, Vec<PathBuf>) = paths
+            .iter()
+            .cloned()
+            .partition(|path| archive.is_some() && is_directory(download.items(), path));
+        let mut events = download.subscribe();
+        let request = async {
+            let mut saved = Vec::new();
+            for path in &archives {
+                let format = archive.unwrap_or_default();
+                saved.push(
+                    download
+                        .download_archive(path, directory.clone(), format)
+                        .await?,
+                );
+            }
+            if !files.is_empty() {
+                saved.extend(download.download(&files, directory).await?);
+            }
+            Ok(saved)
+        };
+        tokio::pin!(request);
+        let result = loop {
+            tokio::select! {
+                result = &mut request => break result,
+                Ok(event) = events.recv() => on_event(&event),
+            }
+        };
+        // Events of the last files are already queued when the request resolves
+        while let Ok(event) = events.try_recv() {
+            on_event(&event);
+        }
+        result
+    };
+    download.close().await?;
+    result
+}
+
+/// The first of `paths` that is neither an item of the listing nor a directory of one.
+#[must_use]
+pub fn missing<'a>(items: &[DirectoryItem], paths: &'a [PathBuf]) -> Option<&'a PathBuf> {
+    paths.iter().find(|path| {
+        !items
+            .iter()
+            .any(|item| item.display_path.starts_with(path) || item.path.starts_with(path))
+    })
+}
+
+/// Whether `path` is a directory of the listing.
+fn is_directory(items: &[DirectoryItem], path: &PathBuf) -> bool {
+    items
+        .iter()
+        .any(|item| item.is_dir && (item.display_path == *path || item.path == *path))
+}
+
+/// `[#######-----]  42%  1.2 MiB of 2.9 MiB  path`
+#[must_use]
+pub fn bar(path: &str, bytes: u64, total: u64) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let progress = if total == 0 {
+        1.0
+    } else {
+        (bytes as f64 / total as f64).min(1.0)
+    };
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let filled = (progress * BAR_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}] {}  {} of {}  {path}",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        format::percent(progress),
+        format::size(bytes),
+        format::size(total)
+    )
+}
+
+/// Progress of the files arriving on stderr, the one moving redrawn in place and a line
+/// kept for each that ended.
+#[derive(Debug, Default)]
+pub struct ProgressBars {
+    totals: HashMap<String, u64>,
+    /// Characters of the line drawn last, to blank out what a shorter one leaves over.
+    drawn: usize,
+}
+
+impl ProgressBars {
+    /// The line to show for `event`, if any, and whether it stays.
+    pub fn line(&mut self, event: &Event) -> Option<(String, bool)> {
+        Some(match event {
+            Event::TransferProgress { path, bytes, total } => {
+                self.totals.insert(path.clone(), *total);
+                (bar(path, *bytes, *total), false)
+            }
+            Event::TransferCompleted(path) => {
+                let total = self.totals.get(path).copied().unwrap_or_default();
+                (bar(path, total, total), true)
+            }
+            Event::TransferUpToDate(path) => (format!("{path}: already up to date"), true),
+            Event::TransferFailed { path, error } => (format!("{path}: failed, {error}"), true),
+            Event::TransferCorrupted(path) => (
+                format!("{path}: arrived with other content than the host published"),
+                true,
+            ),
+            _ => return None,
+        })
+    }
+
+    pub fn print(&mut self, event: &Event) {
+        let Some((line, stays)) = self.line(event) else {
+            return;
+        };
+        let width = self.drawn.max(line.chars().count());
+        let mut stderr = std::io::stderr().lock();
+        let _ = if stays {
+            self.drawn = 0;
+            writeln!(stderr, "\r{line:<width$}")
+        } else {
+            self.drawn = line.chars().count();
+            write!(stderr, "\r{line:<width$}")
+        };
+        let _ = stderr.flush();
+    }
+}
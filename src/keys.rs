@@ -0,0 +1,198 @@
+use crossterm::event::KeyCode;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashSet};
+
+/// Something a key does in the file browser. Esc and Ctrl-C always close or quit and
+/// can't be bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Up,
+    Down,
+    Open,
+    Back,
+    Select,
+    Unselect,
+    UnselectAll,
+    Visual,
+    Download,
+    Preview,
+    Refresh,
+    CopyAddress,
+    Disconnect,
+    Transfers,
+    Focus,
+    PeerInfo,
+    Sort,
+    Hidden,
+    Search,
+    Bookmarks,
+}
+
+impl Action {
+    const fn default_key(self) -> KeyCode {
+        match self {
+            Self::Up => KeyCode::Up,
+            Self::Down => KeyCode::Down,
+            Self::Open => KeyCode::Enter,
+            Self::Back => KeyCode::Backspace,
+            Self::Select => KeyCode::Char('y'),
+            Self::Unselect => KeyCode::Char('n'),
+            Self::UnselectAll => KeyCode::Char('u'),
+            Self::Visual => KeyCode::Char('V'),
+            Self::Download => KeyCode::Char('d'),
+            Self::Preview => KeyCode::Char('p'),
+            Self::Refresh => KeyCode::Char('r'),
+            Self::CopyAddress => KeyCode::Char('x'),
+            Self::Disconnect => KeyCode::Char('q'),
+            Self::Transfers => KeyCode::Char('t'),
+            Self::Focus => KeyCode::Tab,
+            Self::PeerInfo => KeyCode::Char('i'),
+            Self::Sort => KeyCode::Char('s'),
+            Self::Hidden => KeyCode::Char('.'),
+            Self::Search => KeyCode::Char('/'),
+            Self::Bookmarks => KeyCode::Char('b'),
+        }
+    }
+}
+
+const ACTIONS: [Action; 20] = [
+    Action::Up,
+    Action::Down,
+    Action::Open,
+    Action::Back,
+    Action::Select,
+    Action::Unselect,
+    Action::UnselectAll,
+    Action::Visual,
+    Action::Download,
+    Action::Preview,
+    Action::Refresh,
+    Action::CopyAddress,
+    Action::Disconnect,
+    Action::Transfers,
+    Action::Focus,
+    Action::PeerInfo,
+    Action::Sort,
+    Action::Hidden,
+    Action::Search,
+    Action::Bookmarks,
+];
+
+/// The key for each [`Action`], `[keys]` in the config file replaces some of them, e.g.
+/// `down = "j"` or `select = "space"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    keys: BTreeMap<Action, KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: ACTIONS
+                .into_iter()
+                .map(|action| (action, action.default_key()))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The defaults with the keys in `bindings` replaced. Fails on keys that don't exist
+    /// or are bound to two actions.
+    ///
+    /// # Errors
+    ///
+    /// If a key doesn't exist or is bound to two actions.
+    pub fn with(bindings: &BTreeMap<Action, String>) -> Result<Self, String> {
+        let mut keys = Self::default().keys;
+        for (action, key) in bindings {
+            keys.insert(*action, parse_key(key)?);
+        }
+        let mut seen = HashSet::new();
+        for key in keys.values() {
+            if !seen.insert(*key) {
+                return Err(format!(
+                    "{} is bound to two actions, give one of them another key",
+                    key_label(*key)
+                ));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// What `code` does, if it's bound.
+    #[must_use]
+    pub fn action(&self, code: KeyCode) -> Option<Action> {
+        self.keys
+            .iter()
+            .find(|(_, key)| **key == code)
+            .map(|(action, _)| *action)
+    }
+
+    /// The key for `action` as shown in the title bar, e.g. `Y` or `Enter`.
+    #[must_use]
+    pub fn label(&self, action: Action) -> String {
+        key_label(self.keys[&action])
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bindings = BTreeMap::<Action, String>::deserialize(deserializer)?;
+        Self::with(&bindings).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parse a key such as `j`, `space`, `enter` or `f2`, letters are case-sensitive.
+///
+/// # Errors
+///
+/// If `input` names no key.
+pub fn parse_key(input: &str) -> Result<KeyCode, String> {
+    let mut chars = input.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_whitespace() || c.is_control() {
+            return Err(format!("unknown key '{input}'"));
+        }
+        return Ok(KeyCode::Char(c));
+    }
+    let lower = input.to_ascii_lowercase();
+    Ok(match lower.as_str() {
+        "space" => KeyCode::Char(' '),
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => match lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n @ 1..=12) => KeyCode::F(n),
+            _ => return Err(format!("unknown key '{input}'")),
+        },
+    })
+}
+
+/// How a key is shown to the user. Letters are shown upper-case, like on the keyboard.
+#[must_use]
+pub fn key_label(key: KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_ascii_uppercase().to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::PageUp => "PgUp".to_string(),
+        KeyCode::PageDown => "PgDn".to_string(),
+        KeyCode::F(n) => format!("F{n}"),
+        key => format!("{key:?}"),
+    }
+}
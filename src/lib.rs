@@ -0,0 +1,44 @@
+//! Peer-to-peer file sharing over libp2p.
+//!
+//! The `junkanoo` binary is one consumer of this library, other programs can embed sharing
+//! and downloading without the terminal UI through [`JunkanooNode`], [`ShareSession`] and
+//! [`DownloadSession`]:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), junkanoo::JunkanooError> {
+//! use junkanoo::{JunkanooNode, NodeConfig};
+//!
+//! let node = JunkanooNode::start(NodeConfig::default()).await?;
+//! let mut share = node.share(vec!["photos".into()]).await?;
+//! println!("Download with {:?}", share.addresses().await?);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod app;
+pub mod bench;
+pub mod cli;
+pub mod config;
+pub mod desktop;
+pub mod format;
+pub mod get;
+pub mod keys;
+pub mod ls;
+pub mod pipe;
+pub mod plan;
+pub mod recent;
+pub mod report;
+mod run;
+pub mod sensitive;
+pub mod service;
+mod session;
+mod tests;
+pub mod transfers;
+
+pub use service::error::JunkanooError;
+pub use service::node::NodeConfig;
+pub use session::{shared_items, DownloadSession, JunkanooNode, ShareSession};
+
+/// What the `junkanoo` binary runs, its exit code.
+#[doc(hidden)]
+pub use run::run;
@@ -0,0 +1,91 @@
+//! What a host shares, printed by `junkanoo ls` without starting the UI, to check an offer
+//! before downloading from it.
+
+use libp2p::Multiaddr;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::app::DirectoryItem;
+use crate::format;
+use crate::service::error::JunkanooError;
+use crate::service::protocol::DisplayResponse;
+use crate::service::secret::Secret;
+use crate::{JunkanooNode, NodeConfig};
+
+/// Connect to the host at `address`, fetch everything it shares and disconnect again.
+///
+/// # Errors
+///
+/// If the host can't be reached or refuses to list its share.
+pub async fn fetch(
+    config: NodeConfig,
+    address: Multiaddr,
+    password: Option<Secret>,
+) -> Result<DisplayResponse, JunkanooError> {
+    let node = JunkanooNode::start(config).await?;
+    let download = node.download(address, password).await?;
+    let listing = download.listing().clone();
+    download.close().await?;
+    Ok(listing)
+}
+
+/// The listing as an indented tree, each directory followed by what is in it and files
+/// with their size, then the totals.
+#[must_use]
+pub fn tree(listing: &DisplayResponse) -> String {
+    let mut items: Vec<&DirectoryItem> = listing
+        .items
+        .iter()
+        .filter(|item| !item.display_path.as_os_str().is_empty())
+        .collect();
+    items.sort_by(|a, b| a.display_path.cmp(&b.display_path));
+    let rows: Vec<(String, Option<u64>)> = items
+        .iter()
+        .map(|item| {
+            let depth = item.display_path.components().count().saturating_sub(1);
+            let name = display_name(&item.display_path);
+            let indent = "  ".repeat(depth);
+            if item.is_dir {
+                (format!("{indent}{name}/"), None)
+            } else {
+                (format!("{indent}{name}"), Some(item.size))
+            }
+        })
+        .collect();
+    let width = rows.iter().map(|(name, _)| name.chars().count()).max();
+
+    let mut lines = Vec::new();
+    if let Some(label) = &listing.label {
+        lines.push(format!("Share '{label}'"));
+    }
+    if let Some(opens_at) = listing.opens_at {
+        let opens_at = UNIX_EPOCH + Duration::from_secs(opens_at);
+        lines.push(format!("Opens at {}", format::time_of_day(opens_at)));
+    }
+    lines.extend(rows.iter().map(|(name, size)| {
+        size.map_or_else(
+            || name.clone(),
+            |size| {
+                format!(
+                    "{name:<width$}  {:>10}",
+                    format::size(size),
+                    width = width.unwrap_or_default()
+                )
+            },
+        )
+    }));
+    let files: Vec<u64> = rows.iter().filter_map(|(_, size)| *size).collect();
+    lines.push(format!(
+        "{} files, {} in total",
+        files.len(),
+        format::size(files.iter().sum())
+    ));
+    lines.join("\n")
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,62 @@
+//! Pipe mode, for sharing from stdin and downloading to stdout.
+//!
+//! `junkanoo share -` shares what arrives on stdin as a single file and
+//! `junkanoo get <addr> <path> -O -` writes the file received to stdout, e.g.
+//! `junkanoo get … | tar x`. Both pass through a scratch file, the listing needs a size and
+//! hash before anything is offered and a download only counts once its hash was checked.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::service::error::JunkanooError;
+
+/// What stdin is shared as unless `share --as` names it.
+pub const STDIN_NAME: &str = "stdin";
+
+/// A directory of this process under the system's temporary one, for `purpose`. Not
+/// created yet, and removed by whoever uses it.
+#[must_use]
+pub fn scratch_directory(purpose: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("junkanoo-{purpose}-{}", std::process::id()))
+}
+
+/// Copy everything `input` has into a new file `name` in `directory`, and return its path.
+///
+/// # Errors
+///
+/// If `input` can't be read or the file can't be written.
+pub fn spool(
+    input: &mut impl Read,
+    directory: &Path,
+    name: &str,
+) -> Result<PathBuf, JunkanooError> {
+    if Path::new(name).file_name() != Some(name.as_ref()) {
+        return Err(JunkanooError::other(format!(
+            "'{name}' is not a file name, leave out any directories"
+        )));
+    }
+    fs::create_dir_all(directory)?;
+    let path = directory.join(name);
+    io::copy(input, &mut File::create(&path)?)?;
+    Ok(path)
+}
+
+/// Copy the file at `path` to `output`, or to stdout if that is `-`. A reader on stdout
+/// that stopped early, like `head`, is not an error.
+///
+/// # Errors
+///
+/// If `path` can't be read or `output` can't be written.
+pub fn deliver(path: &Path, output: &Path) -> Result<(), JunkanooError> {
+    let mut file = File::open(path)?;
+    if output != Path::new("-") {
+        io::copy(&mut file, &mut File::create(output)?)?;
+        return Ok(());
+    }
+    let mut stdout = io::stdout().lock();
+    match io::copy(&mut file, &mut stdout).and_then(|_| stdout.flush()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+        _ => Ok(()),
+    }
+}
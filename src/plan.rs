@@ -0,0 +1,192 @@
+//! What a download would do, worked out without transferring anything, see `--dry-run`.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::app::DirectoryItem;
+use crate::format;
+use crate::service::hashing::hash_file;
+
+/// What happens to one file of the selection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", content = "reason", rename_all = "snake_case")]
+pub enum PlannedAction {
+    Create,
+    /// A different file is in the way and would be replaced.
+    Overwrite,
+    /// The destination already matches the host's hash, nothing is sent.
+    UpToDate,
+    /// The file can't be saved, e.g. because a directory is in the way.
+    Blocked(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedFile {
+    /// Path of the file in the share.
+    pub path: PathBuf,
+    pub destination: PathBuf,
+    pub size: u64,
+    /// The size as shown to people, e.g. `1.5 MiB`.
+    pub size_display: String,
+    #[serde(flatten)]
+    pub action: PlannedAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DownloadPlan {
+    /// Directory the files are saved below.
+    pub directory: PathBuf,
+    pub files: Vec<PlannedFile>,
+    /// Free space on the destination's filesystem, if it could be found out.
+    pub free_space: Option<u64>,
+    pub free_space_display: Option<String>,
+}
+
+impl DownloadPlan {
+    /// Check each `(path in share, item)` against what is already below `directory`.
+    pub fn new<'a>(
+        directory: &Path,
+        items: impl IntoIterator<Item = (PathBuf, &'a DirectoryItem)>,
+    ) -> Self {
+        let mut files: Vec<PlannedFile> = items
+            .into_iter()
+            .map(|(path, item)| {
+                let destination = directory.join(&path);
+                PlannedFile {
+                    action: plan_file(&destination, item),
+                    path,
+                    destination,
+                    size: item.size,
+                    size_display: format::size(item.size),
+                }
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let free_space = available_space(directory);
+        Self {
+            directory: directory.to_path_buf(),
+            files,
+            free_space,
+            free_space_display: free_space.map(format::size),
+        }
+    }
+
+    /// Bytes that would actually be sent, up to date and blocked files don't count.
+    #[must_use]
+    pub fn transfer_bytes(&self) -> u64 {
+        self.files
+            .iter()
+            .filter(|file| {
+                matches!(
+                    file.action,
+                    PlannedAction::Create | PlannedAction::Overwrite
+                )
+            })
+            .map(|file| file.size)
+            .sum()
+    }
+
+    /// Whether the files fit, assumed when the free space is unknown.
+    #[must_use]
+    pub fn has_room(&self) -> bool {
+        self.free_space
+            .is_none_or(|free_space| free_space >= self.transfer_bytes())
+    }
+
+    /// Whether the download would go through without problems.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.has_room()
+            && !self
+                .files
+                .iter()
+                .any(|file| matches!(file.action, PlannedAction::Blocked(_)))
+    }
+
+    /// The plan as shown on the terminal.
+    #[must_use]
+    pub fn report(&self) -> String {
+        let mut lines = vec![format!(
+            "Dry run, nothing was transferred. Files would be saved below {}:",
+            self.directory.display()
+        )];
+        for file in &self.files {
+            let action = match &file.action {
+                PlannedAction::Create => "create".to_string(),
+                PlannedAction::Overwrite => "overwrite".to_string(),
+                PlannedAction::UpToDate => "up to date, skip".to_string(),
+                PlannedAction::Blocked(reason) => format!("blocked: {reason}"),
+            };
+            lines.push(format!(
+                "  {:>10}  {}  ({action})",
+                file.size_display,
+                file.destination.display()
+            ));
+        }
+        let count = |wanted: fn(&PlannedAction) -> bool| {
+            self.files
+                .iter()
+                .filter(|file| wanted(&file.action))
+                .count()
+        };
+        lines.push(format!(
+            "{} to transfer in {} files, {} overwritten, {} up to date, {} blocked",
+            format::size(self.transfer_bytes()),
+            count(|action| matches!(action, PlannedAction::Create | PlannedAction::Overwrite)),
+            count(|action| *action == PlannedAction::Overwrite),
+            count(|action| *action == PlannedAction::UpToDate),
+            count(|action| matches!(action, PlannedAction::Blocked(_))),
+        ));
+        match self.free_space {
+            Some(free_space) if !self.has_room() => lines.push(format!(
+                "Not enough space: only {} free",
+                format::size(free_space)
+            )),
+            Some(free_space) => lines.push(format!("{} free", format::size(free_space))),
+            None => lines.push("Free space unknown".to_string()),
+        }
+        lines.join("\n")
+    }
+}
+
+fn plan_file(destination: &Path, item: &DirectoryItem) -> PlannedAction {
+    // A file where a directory of the path should be stops the download as well
+    if let Some(blocker) = destination
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_file())
+    {
+        return PlannedAction::Blocked(format!("{} is a file", blocker.display()));
+    }
+    match std::fs::metadata(destination) {
+        Err(_) => PlannedAction::Create,
+        Ok(metadata) if metadata.is_dir() => {
+            PlannedAction::Blocked("a directory is in the way".to_string())
+        }
+        Ok(metadata) => {
+            let same = metadata.len() == item.size
+                && item
+                    .hash
+                    .as_ref()
+                    .is_some_and(|hash| hash_file(destination).is_ok_and(|local| &local == hash));
+            if same {
+                PlannedAction::UpToDate
+            } else {
+                PlannedAction::Overwrite
+            }
+        }
+    }
+}
+
+/// Free space on the filesystem `path` is on, or would be created on.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|ancestor| ancestor.exists())?;
+    let existing = std::fs::canonicalize(existing).ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| existing.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(sysinfo::Disk::available_space)
+}
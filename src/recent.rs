@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries kept per kind, older ones drop off.
+const MAX_RECENT: usize = 5;
+
+/// A directory shared in an earlier session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentShare {
+    pub path: PathBuf,
+    pub label: Option<String>,
+    /// Unix timestamp of the last time it was shared.
+    pub at: u64,
+}
+
+/// A host downloaded from in an earlier session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentPeer {
+    pub address: String,
+    /// Display name of the host, or the label of its share if it gave no name.
+    pub label: Option<String>,
+    pub at: u64,
+}
+
+/// Something the start screen offers to do again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecentChoice {
+    Share(RecentShare),
+    Connect(RecentPeer),
+}
+
+impl RecentChoice {
+    /// One line for the start screen, e.g. "Share ~/Documents/reports again".
+    #[must_use]
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Share(share) => {
+                let path = display_path(&share.path);
+                share.label.as_ref().map_or_else(
+                    || format!("Share {path} again"),
+                    |label| format!("Share {path} again as '{label}'"),
+                )
+            }
+            Self::Connect(peer) => peer.label.as_ref().map_or_else(
+                || format!("Reconnect to {}", peer.address),
+                |label| format!("Reconnect to {label}"),
+            ),
+        }
+    }
+
+    /// Command line arguments that repeat the choice, after the global options.
+    #[must_use]
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Self::Share(share) => {
+                let mut args = vec!["share".to_string(), share.path.display().to_string()];
+                if let Some(label) = &share.label {
+                    args.extend(["--label".to_string(), label.clone()]);
+                }
+                args
+            }
+            Self::Connect(peer) => vec!["download".to_string(), peer.address.clone()],
+        }
+    }
+}
+
+/// Shorten the home directory to `~`.
+fn display_path(path: &Path) -> String {
+    match dirs_next::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf))
+    {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Where recent shares and peers are kept.
+#[must_use]
+pub fn recent_path() -> Option<PathBuf> {
+    crate::config::state_dir().map(|dir| dir.join("recent.json"))
+}
+
+/// Recently shared directories and recently contacted hosts, newest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Recent {
+    pub shares: Vec<RecentShare>,
+    pub peers: Vec<RecentPeer>,
+}
+
+impl Recent {
+    /// Read the store, a missing or unreadable file is empty.
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Keep the store for the next session.
+    ///
+    /// # Errors
+    ///
+    /// If `path` or its directory can't be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn add_share(&mut self, path: PathBuf, label: Option<String>, at: u64) {
+        self.shares.retain(|share| share.path != path);
+        self.shares.insert(0, RecentShare { path, label, at });
+        self.shares.truncate(MAX_RECENT);
+    }
+
+    pub fn add_peer(&mut self, address: String, label: Option<String>, at: u64) {
+        self.peers.retain(|peer| peer.address != address);
+        self.peers.insert(0, RecentPeer { address, label, at });
+        self.peers.truncate(MAX_RECENT);
+    }
+
+    /// Everything to offer, the newest first whether share or peer.
+    #[must_use]
+    pub fn choices(&self) -> Vec<RecentChoice> {
+        let mut choices: Vec<(u64, RecentChoice)> = self
+            .shares
+            .iter()
+            .map(|share| (share.at, RecentChoice::Share(share.clone())))
+            .chain(
+                self.peers
+                    .iter()
+                    .map(|peer| (peer.at, RecentChoice::Connect(peer.clone()))),
+            )
+            .collect();
+        choices.sort_by_key(|(at, _)| std::cmp::Reverse(*at));
+        choices.into_iter().map(|(_, choice)| choice).collect()
+    }
+}
+
+/// Add an entry to the store on disk, failures only cost the quick start entry.
+pub fn remember(update: impl FnOnce(&mut Recent, u64)) {
+    let Some(path) = recent_path() else {
+        return;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let mut recent = Recent::load(&path);
+    update(&mut recent, now);
+    if let Err(e) = recent.save(&path) {
+        tracing::warn!("Failed to save recent shares and peers: {}", e);
+    }
+}
@@ -0,0 +1,137 @@
+//! Summary of the files a session delivered, written with `--report` as a single HTML page
+//! without external resources, to attach to a ticket or an email as proof of delivery.
+
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use crate::format::Locale;
+
+/// A file that arrived completely, on either side of the transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveredFile {
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 the host published for the file.
+    pub hash: Option<String>,
+    /// The other side: the host for downloads, the downloader for uploads.
+    pub peer_id: PeerId,
+    /// Display name the peer greeted with, if any.
+    pub peer: Option<String>,
+    pub finished_at: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionReport {
+    pub started_at: SystemTime,
+    pub files: Vec<DeliveredFile>,
+}
+
+impl Default for SessionReport {
+    fn default() -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            files: Vec::new(),
+        }
+    }
+}
+
+impl SessionReport {
+    pub fn add(&mut self, file: DeliveredFile) {
+        self.files.push(file);
+    }
+
+    /// How many files went to or came from `peer_id` and their size in total.
+    #[must_use]
+    pub fn delivered(&self, peer_id: &PeerId) -> (usize, u64) {
+        self.files
+            .iter()
+            .filter(|file| file.peer_id == *peer_id)
+            .fold((0, 0), |(files, bytes), file| {
+                (files + 1, bytes + file.size)
+            })
+    }
+
+    /// The report as a complete HTML document. `sent` says whether we were the host,
+    /// `label` is the share's name if it has one.
+    pub fn to_html(
+        &self,
+        peer_id: &PeerId,
+        sent: bool,
+        label: Option<&str>,
+        finished_at: SystemTime,
+    ) -> String {
+        let title = label.map_or_else(
+            || "junkanoo delivery report".to_string(),
+            |label| format!("junkanoo delivery report: {}", escape(label)),
+        );
+        let total: u64 = self.files.iter().map(|file| file.size).sum();
+        // The page is in English, its numbers are written the same way wherever it was made
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+             <dl>\n<dt>{role}</dt><dd><code>{peer_id}</code></dd>\n\
+             <dt>Session</dt><dd>{} to {}</dd>\n<dt>{verb}</dt><dd>{} files, {}</dd>\n</dl>\n",
+            timestamp(self.started_at),
+            timestamp(finished_at),
+            self.files.len(),
+            Locale::ENGLISH.size(total),
+            role = if sent { "Host" } else { "Downloader" },
+            verb = if sent { "Sent" } else { "Received" },
+        );
+        html.push_str(
+            "<table>\n<thead><tr><th>File</th><th>Size</th><th>SHA-256</th><th>Peer</th>\
+             <th>Completed</th></tr></thead>\n<tbody>\n",
+        );
+        for file in &self.files {
+            let peer = file.peer.as_ref().map_or_else(
+                || format!("<code>{}</code>", file.peer_id),
+                |name| format!("{}<br><code>{}</code>", escape(name), file.peer_id),
+            );
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td title=\"{} bytes\">{}</td><td><code>{}</code></td>\
+                 <td>{peer}</td><td>{}</td></tr>",
+                escape(&file.path),
+                file.size,
+                Locale::ENGLISH.size(file.size),
+                file.hash
+                    .as_deref()
+                    .map_or_else(|| "unknown".to_string(), escape),
+                timestamp(file.finished_at),
+            );
+        }
+        html.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+    table{border-collapse:collapse}th,td{border:1px solid #ccc;padding:.3em .6em;\
+    text-align:left;vertical-align:top}code{font-size:.85em;word-break:break-all}\
+    dt{font-weight:bold}";
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y-%m-%d %H:%M:%S UTC")
+        .to_string()
+}
+
+/// Escape text for HTML element content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
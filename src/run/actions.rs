@@ -0,0 +1,215 @@
+//! What both the terminal UI and `--plain` do on the user's behalf: downloads, previews,
+//! listings and transfer controls.
+
+use parking_lot::Mutex;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::app::App;
+use crate::cli::preview;
+use crate::format;
+use crate::service::error::JunkanooError;
+use crate::transfers::TransferState;
+
+/// How much of a remote file `p` loads into the preview pane.
+pub const REMOTE_PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// How much of a remote file is loaded into the preview pane when it is highlighted.
+const HIGHLIGHT_PREVIEW_BYTES: u64 = 16 * 1024;
+
+pub enum TransferAction {
+    TogglePause,
+    Cancel,
+}
+
+/// Pause, resume or cancel the transfer highlighted in the transfer list. The list shows
+/// the new state once the network took the signal.
+pub fn control_transfer(app: &App, app_handle: Arc<Mutex<App>>, action: TransferAction) {
+    let Some(transfer) = app.selected_transfer() else {
+        return;
+    };
+    let paused = match transfer.state {
+        TransferState::Queued | TransferState::Active => true,
+        TransferState::Paused => false,
+        // Nothing left to pause or cancel
+        _ => return,
+    };
+    let Some(client) = app.client.clone() else {
+        return;
+    };
+    let path = transfer.path.clone();
+    tokio::spawn(async move {
+        let result = match action {
+            TransferAction::TogglePause => client.pause_transfer(path.clone(), paused).await,
+            TransferAction::Cancel => client.cancel_transfer(path.clone()).await,
+        };
+        let mut app = app_handle.lock();
+        match result {
+            Ok(()) => match action {
+                TransferAction::TogglePause => app.transfers.pause(&path, paused),
+                TransferAction::Cancel => app.transfers.cancel(&path),
+            },
+            Err(e) => tracing::warn!("Failed to control the transfer of {}: {}", path, e),
+        }
+        if let Some(tx) = app.refresh_sender() {
+            let _ = tx.try_send(());
+        }
+    });
+}
+
+/// Fetch the preview of the highlighted remote file once it stayed highlighted a moment,
+/// the listing itself only carries a short preview.
+pub fn preview_highlighted(app_handle: &Arc<Mutex<App>>) {
+    let mut app = app_handle.lock();
+    if app.is_host || !app.is_connected() {
+        return;
+    }
+    let item = app
+        .selected_index
+        .and_then(|index| app.directory_items.get(index))
+        .cloned();
+    if app.remote_previews.due(item.as_ref(), Instant::now()) {
+        load_remote_preview(&mut app, Arc::clone(app_handle), HIGHLIGHT_PREVIEW_BYTES);
+    }
+}
+
+/// Load the first `bytes` of the highlighted remote file into the preview pane.
+pub fn load_remote_preview(app: &mut App, app_handle: Arc<Mutex<App>>, bytes: u64) {
+    let Some(item) = app
+        .selected_index
+        .and_then(|index| app.directory_items.get(index))
+        .filter(|item| !item.is_dir)
+    else {
+        return;
+    };
+    let (Some(client), Some(peer_id)) = (app.client.clone(), app.connected_peer_id) else {
+        return;
+    };
+    let (path, hash) = (item.path.clone(), item.hash.clone());
+    app.remote_previews.start(&path);
+    tokio::spawn(async move {
+        let preview = match client
+            .request_file_range(peer_id, path.to_string_lossy().to_string(), 0..bytes)
+            .await
+        {
+            Ok(bytes) => preview::from_bytes(&path, &bytes),
+            Err(e) => format!("Unable to load a preview: {}", e.explain()),
+        };
+        let mut app = app_handle.lock();
+        app.remote_previews.insert(path, hash, preview);
+        if let Some(tx) = app.refresh_sender() {
+            let _ = tx.try_send(());
+        }
+    });
+}
+
+/// Ask the host for the remote directories the downloader needs next, see
+/// [`App::due_listings`].
+pub fn list_due_directories(app_handle: &Arc<Mutex<App>>) {
+    let mut app = app_handle.lock();
+    if app.is_host || !app.is_connected() {
+        return;
+    }
+    let (Some(client), Some(peer_id)) = (app.client.clone(), app.connected_peer_id) else {
+        return;
+    };
+    for (directory, recursive) in app.due_listings() {
+        let client = Arc::clone(&client);
+        let app_handle = Arc::clone(app_handle);
+        tokio::spawn(async move {
+            let path = directory.to_string_lossy().to_string();
+            let listing = client.list_directory(peer_id, path, recursive).await;
+            let mut app = app_handle.lock();
+            match listing {
+                Ok(listing) => {
+                    app.apply_listing(directory, recursive, listing.items);
+                    start_sync(&mut app);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to list {:?}: {}", directory, e);
+                    app.set_warning(match e {
+                        JunkanooError::Timeout(_) => format!(
+                            "The host took too long to list {}, press r to ask again",
+                            directory.display()
+                        ),
+                        e => format!("Failed to list {}: {}", directory.display(), e.explain()),
+                    });
+                    app.remote_listing.fail(directory);
+                }
+            }
+            if let Some(tx) = app.refresh_sender() {
+                let _ = tx.try_send(());
+            }
+        });
+    }
+}
+
+/// With `sync`, download everything the host shares as soon as the listing is known.
+pub fn start_sync(app: &mut App) {
+    if !app.sync
+        || app.is_loading
+        || !app.items_being_downloaded.is_empty()
+        || app.all_shared_items.is_empty()
+        || !app.remote_listing.is_expanded(Path::new(""))
+    {
+        return;
+    }
+    app.items_to_download = app
+        .all_shared_items
+        .iter()
+        .map(|item| item.path.clone())
+        .collect();
+    begin_download(app);
+}
+
+/// Share the selection as the host, or download it after checking it can be, asking first
+/// if it is large.
+pub fn request_download(app: &mut App) {
+    if app.is_host {
+        app.start_share();
+        return;
+    }
+    // Check if any files are selected before spawning the task
+    if app.items_to_download.is_empty() {
+        app.set_warning("No files selected for download. Please select files first.".to_string());
+        // Notify UI to refresh
+        if let Some(refresh_sender) = app.refresh_sender() {
+            let _ = refresh_sender.try_send(());
+        }
+    } else if app.remote_listing.is_expanding() {
+        app.set_warning(
+            "Still listing the selected directories, try again in a moment".to_string(),
+        );
+    } else if let Some(free) = app.download_space_shortfall() {
+        let message = format!(
+            "Not enough space: {} selected, only {} free",
+            format::size(app.selected_download_size()),
+            format::size(free)
+        );
+        app.set_warning(message);
+    } else if app.needs_download_confirmation() && !app.dry_run {
+        app.confirming_download = true;
+    } else {
+        begin_download(app);
+    }
+}
+
+pub fn begin_download(app: &mut App) {
+    if app.dry_run {
+        app.download_plan = Some(tokio::task::block_in_place(|| app.plan_download()));
+        app.should_quit = true;
+        return;
+    }
+    app.is_loading = true;
+    // Clone the app before dropping the lock
+    let mut app_clone = app.clone();
+    tracing::debug!(
+        "Starting download with {:#?} items selected",
+        app.items_to_download
+    );
+    // Start the download in a new task
+    tokio::spawn(async move {
+        app_clone.start_download().await;
+    });
+}
@@ -0,0 +1,206 @@
+//! The subcommands that run without the UI: `ls`, `get` and `bench`.
+
+use libp2p::Multiaddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::bench;
+use crate::cli::output;
+use crate::get;
+use crate::ls;
+use crate::pipe;
+use crate::service::archive::ArchiveFormat;
+use crate::service::error::JunkanooError;
+use crate::service::node::{Event as NetworkEvent, NodeConfig};
+use crate::service::secret::Secret;
+
+/// Print what the host shares, as a tree or a `listing` event with `--json`. Returns the
+/// exit code.
+pub async fn run_ls(matches: &clap::ArgMatches, sub_matches: &clap::ArgMatches) -> i32 {
+    let Some(address) = sub_matches
+        .get_one::<Multiaddr>("PEER_ADDR_IDENTIFIER")
+        .cloned()
+    else {
+        return 1;
+    };
+    let config = NodeConfig {
+        lan_only: matches.get_flag("lan-only"),
+        request_timeout: matches.get_one::<Duration>("request-timeout").copied(),
+        ..NodeConfig::default()
+    };
+    let password = sub_matches.get_one::<Secret>("password").cloned();
+    match ls::fetch(config, address, password).await {
+        Ok(listing) if output::is_json() => {
+            output::emit(&output::JsonEvent::Listing {
+                items: listing
+                    .items
+                    .iter()
+                    .map(output::ListingEntry::from)
+                    .collect(),
+            });
+            0
+        }
+        Ok(listing) => {
+            println!("{}", ls::tree(&listing));
+            0
+        }
+        Err(e) => {
+            output::error(&format!("Failed to list the share: {}", e.explain()));
+            1
+        }
+    }
+}
+
+/// Download the paths named on the command line with progress bars, or progress events
+/// with `--json`. Returns the exit code.
+pub async fn run_get(matches: &clap::ArgMatches, sub_matches: &clap::ArgMatches) -> i32 {
+    let Some(address) = sub_matches
+        .get_one::<Multiaddr>("PEER_ADDR_IDENTIFIER")
+        .cloned()
+    else {
+        return 1;
+    };
+    let paths: Vec<PathBuf> = sub_matches
+        .get_many::<PathBuf>("PATH")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let config = NodeConfig {
+        lan_only: matches.get_flag("lan-only"),
+        no_compress: matches.get_flag("no-compress"),
+        max_download: matches.get_one::<u64>("max-download").copied(),
+        parallel_downloads: matches.get_one::<usize>("parallel").copied(),
+        request_timeout: matches.get_one::<Duration>("request-timeout").copied(),
+        ..NodeConfig::default()
+    };
+    let password = sub_matches.get_one::<Secret>("password").cloned();
+    let document = sub_matches.get_one::<PathBuf>("output-document");
+    if document.is_some() && paths.len() > 1 {
+        output::error("-O takes a single file, leave it out to download several");
+        return 1;
+    }
+    if output::is_json() && document.is_some_and(|document| document == Path::new("-")) {
+        output::error("--json and -O - would both write to stdout");
+        return 1;
+    }
+    // Downloads for -O land in a scratch directory first, their hash has to be checked
+    let scratch = document.map(|_| pipe::scratch_directory("get"));
+    let directory = scratch
+        .clone()
+        .or_else(|| sub_matches.get_one::<PathBuf>("output").cloned());
+
+    let json = output::is_json();
+    let mut bars = get::ProgressBars::default();
+    let mut failed = Vec::new();
+    let archive = sub_matches.get_one::<ArchiveFormat>("archive").copied();
+    let result = get::fetch(
+        config,
+        address,
+        password,
+        &paths,
+        directory,
+        archive,
+        |event| {
+            match event {
+                NetworkEvent::TransferFailed { path, .. }
+                | NetworkEvent::TransferCorrupted(path) => {
+                    failed.push(path.clone());
+                }
+                NetworkEvent::TransferProgress { path, bytes, total } if json => {
+                    output::emit(&output::JsonEvent::Progress {
+                        path: path.clone(),
+                        bytes: *bytes,
+                        total: *total,
+                    });
+                }
+                _ => {}
+            }
+            if !json {
+                bars.print(event);
+            }
+        },
+    )
+    .await;
+    let result = match (result, document, &scratch) {
+        (Ok(files), Some(document), Some(scratch)) => match files.as_slice() {
+            [file] => pipe::deliver(&scratch.join(file), document).map(|()| files),
+            _ => Err(JunkanooError::other(format!(
+                "-O takes a single file, but {} has {} in it",
+                paths[0].display(),
+                files.len()
+            ))),
+        },
+        (result, ..) => result,
+    };
+    if let Some(scratch) = &scratch {
+        let _ = std::fs::remove_dir_all(scratch);
+    }
+    report_get(result, document, json, failed)
+}
+
+/// Print how a download went, `failed` naming the files that didn't arrive. Returns the
+/// exit code.
+fn report_get(
+    result: Result<Vec<String>, JunkanooError>,
+    document: Option<&PathBuf>,
+    json: bool,
+    failed: Vec<String>,
+) -> i32 {
+    match result {
+        Ok(_) if document.is_some_and(|document| document == Path::new("-")) => 0,
+        Ok(files) if json => {
+            output::emit(&output::JsonEvent::Completed { files });
+            0
+        }
+        Ok(files) => {
+            println!("Downloaded {} files", files.len());
+            0
+        }
+        Err(e) if json && !failed.is_empty() => {
+            output::emit(&output::JsonEvent::Failed {
+                files: failed,
+                error: e.explain(),
+            });
+            1
+        }
+        Err(e) => {
+            output::error(&format!("Download failed: {}", e.explain()));
+            1
+        }
+    }
+}
+
+/// Download everything the host shares into a scratch directory, removed again afterwards,
+/// and print how fast each file arrived. Returns the exit code.
+pub async fn run_bench(matches: &clap::ArgMatches, sub_matches: &clap::ArgMatches) -> i32 {
+    let Some(address) = sub_matches
+        .get_one::<Multiaddr>("PEER_ADDR_IDENTIFIER")
+        .cloned()
+    else {
+        return 1;
+    };
+    let config = NodeConfig {
+        lan_only: matches.get_flag("lan-only"),
+        no_compress: matches.get_flag("no-compress"),
+        max_download: matches.get_one::<u64>("max-download").copied(),
+        request_timeout: matches.get_one::<Duration>("request-timeout").copied(),
+        ..NodeConfig::default()
+    };
+    let scratch = std::env::temp_dir().join(format!("junkanoo-bench-{}", std::process::id()));
+    let result = bench::download_all(config, address, &scratch).await;
+    let _ = std::fs::remove_dir_all(&scratch);
+    match result {
+        Ok(samples) if samples.is_empty() => {
+            output::error("The host shares no files to measure");
+            1
+        }
+        Ok(samples) => {
+            println!("{}", bench::report(&samples));
+            0
+        }
+        Err(e) => {
+            output::error(&format!("Benchmark failed: {}", e.explain()));
+            1
+        }
+    }
+}
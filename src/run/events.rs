@@ -0,0 +1,323 @@
+//! Keeping the app up to date with what the node reports.
+
+use futures::{Stream, StreamExt};
+use libp2p::PeerId;
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::spawn;
+
+use super::actions::start_sync;
+use super::network::{apply_shared_items, finish_pushed};
+use crate::app::{App, ConflictPrompt, ConnectionState, DirectoryItem};
+use crate::config::Severity;
+use crate::desktop::Notice;
+use crate::report::DeliveredFile;
+use crate::service::node::{Event as NetworkEvent, RequestedFile};
+use crate::service::utils::transfer_path;
+use crate::transfers::{self, TransferHistory, TransferSummary};
+
+/// Sum up the downloads that just ended into the session's summary and the history.
+fn summarize_downloads(app: &mut App) -> Option<TransferSummary> {
+    let summary = app.transfers.take_summary()?;
+    if let Some(history) = transfers::history_path() {
+        if let Err(e) = TransferHistory::append(&history, &summary) {
+            tracing::warn!("Failed to record the download summary: {}", e);
+        }
+    }
+    app.download_summary
+        .get_or_insert_with(TransferSummary::default)
+        .merge(&summary);
+    Some(summary)
+}
+
+/// How long a downloader may take to ask for its next file before it counts as done.
+const SERVED_SETTLE: Duration = Duration::from_secs(3);
+
+/// Tell the desktop that `peer_id` finished downloading, unless it fetches more within
+/// [`SERVED_SETTLE`] of the file that just completed.
+async fn notify_when_served(app: Arc<Mutex<App>>, peer_id: PeerId) {
+    let before = app.lock().session_report.delivered(&peer_id);
+    tokio::time::sleep(SERVED_SETTLE).await;
+    let app = app.lock();
+    let (files, bytes) = app.session_report.delivered(&peer_id);
+    let uploading = app
+        .share_stats
+        .uploads()
+        .iter()
+        .any(|upload| upload.peer_id == peer_id);
+    if (files, bytes) == before && !uploading {
+        Notice::served(&app.peer_name(&peer_id), files, bytes).show();
+    }
+}
+
+pub async fn handle_network_events(
+    mut event_stream: impl Stream<Item = NetworkEvent> + Unpin,
+    app: Arc<Mutex<App>>,
+) {
+    while let Some(event) = event_stream.next().await {
+        let mut guard = app.lock();
+        apply_event(&mut guard, &app, event);
+        // Notify the UI to refresh
+        if let Some(tx) = guard.refresh_sender() {
+            let _ = tx.try_send(());
+        }
+    }
+}
+
+fn apply_event(app: &mut App, app_handle: &Arc<Mutex<App>>, event: NetworkEvent) {
+    match event {
+        NetworkEvent::NewListenAddr(addr) => {
+            if !app.listening_addrs.contains(&addr) {
+                app.listening_addrs.push(addr);
+            }
+        }
+        NetworkEvent::PeerConnected(peer_id) => peer_connected(app, peer_id),
+        NetworkEvent::PeerGreeted { peer_id, greeting } => {
+            app.notify(Severity::Info, greeting.describe(&peer_id));
+            app.peer_greeting = Some((peer_id, greeting));
+        }
+        NetworkEvent::PeerIdentified {
+            peer_id,
+            agent_version,
+        } => {
+            app.peer_agents.insert(peer_id, agent_version);
+        }
+        NetworkEvent::Reconnecting { peer_id, attempt } => {
+            tracing::info!("Reconnecting to {peer_id}, attempt {attempt}");
+            app.connection_state = ConnectionState::Reconnecting(attempt);
+        }
+        NetworkEvent::PeerDisconnected() => peer_disconnected(app),
+        NetworkEvent::DownloadCompleted(file_names) => download_completed(app, &file_names),
+        NetworkEvent::DownloadFailed(file_names) => download_failed(app, &file_names),
+        NetworkEvent::PushAccepted {
+            peer_id,
+            files,
+            directory,
+        } => push_accepted(app, peer_id, files, directory),
+        NetworkEvent::ShareUpdated(items) => share_changed(app, |_| items),
+        NetworkEvent::ShareChanged(update) => share_changed(app, |mut items| {
+            update.apply(&mut items);
+            items
+        }),
+        NetworkEvent::ConnectionQuality {
+            peer_id,
+            score,
+            rtt,
+        } => {
+            if app.connected_peer_id == Some(peer_id) {
+                app.connection_quality = Some((score, rtt));
+            }
+        }
+        NetworkEvent::DhtStatus { routing_table_size } => {
+            app.dht_peers = Some(routing_table_size);
+        }
+        NetworkEvent::ShareCodePublished => app.share_code_published = true,
+        NetworkEvent::ShareCompleted => {
+            tracing::info!("Share completed");
+            app.should_quit = true;
+        }
+        NetworkEvent::TransfersQueued(paths) => app.transfers.queue(paths),
+        NetworkEvent::TransfersSized(sizes) => {
+            for (path, size) in sizes {
+                app.transfers.expect(&path, size);
+            }
+        }
+        NetworkEvent::TransferProgress { path, bytes, total } => {
+            app.transfers.progress(&path, bytes, total);
+        }
+        NetworkEvent::TransferCompleted(path) => transfer_completed(app, &path),
+        NetworkEvent::TransferUpToDate(path) => app.transfers.up_to_date(&path),
+        NetworkEvent::TransferConflict { path, conflict } => {
+            app.transfers.conflict(&path, &conflict);
+        }
+        NetworkEvent::ConflictsFound(conflicts) => {
+            app.conflict_prompt = Some(ConflictPrompt::new(conflicts));
+        }
+        NetworkEvent::TransferCancelled(path) => app.transfers.cancel(&path),
+        NetworkEvent::TransferFailed { path, error } => app.transfers.fail(&path, error),
+        NetworkEvent::TransferCorrupted(path) => app.transfers.corrupted(&path),
+        NetworkEvent::UploadStarted { peer_id, path } => {
+            app.share_stats.upload_started(peer_id, path);
+        }
+        NetworkEvent::UploadProgress {
+            peer_id,
+            path,
+            bytes,
+            total,
+        } => {
+            app.share_stats
+                .upload_progress(peer_id, &path, bytes, total);
+        }
+        NetworkEvent::UploadCompleted {
+            peer_id,
+            path,
+            bytes,
+            hash,
+        } => upload_completed(app, app_handle, peer_id, path, bytes, hash),
+        NetworkEvent::UploadFailed {
+            peer_id,
+            path,
+            bytes,
+        } => {
+            finish_pushed(app, &path, false);
+            app.share_stats.upload_failed(peer_id, &path, bytes);
+        }
+    }
+}
+
+fn peer_connected(app: &mut App, peer_id: PeerId) {
+    if matches!(app.connection_state, ConnectionState::Reconnecting(_)) {
+        app.notify(Severity::Info, "Reconnected".to_string());
+    }
+    app.connection_state = ConnectionState::Connected;
+    app.connected_peer_id = Some(peer_id);
+}
+
+fn peer_disconnected(app: &mut App) {
+    if app
+        .pushing
+        .as_ref()
+        .is_some_and(|pushing| !pushing.is_empty())
+    {
+        app.notify(
+            Severity::Error,
+            "The host left before it fetched every pushed file".to_string(),
+        );
+        app.exit_code = 1;
+        app.should_quit = true;
+    }
+    app.connection_state = ConnectionState::Disconnected;
+    app.connected_peer_id = None;
+    app.connection_quality = None;
+}
+
+fn download_completed(app: &mut App, file_names: &[String]) {
+    tracing::info!("Download completed: {:?}", file_names);
+    app.is_loading = false;
+    let message = summarize_downloads(app).map_or_else(
+        || format!("Downloaded {} files", file_names.len()),
+        |summary| summary.headline(),
+    );
+    app.notify(Severity::Info, message);
+    if app.desktop_notifications {
+        Notice::downloaded(file_names, app.connected_peer_name().as_deref()).show();
+    }
+    // A host only downloads what was pushed to it, archives end one by one
+    let finished = app
+        .transfers
+        .transfers()
+        .iter()
+        .all(|transfer| transfer.state.is_finished());
+    if app.exit_on_complete && !app.is_host && finished {
+        app.should_quit = true;
+    }
+}
+
+fn download_failed(app: &mut App, file_names: &[String]) {
+    tracing::error!("Download failed: {:?}", file_names);
+    app.is_loading = false;
+    summarize_downloads(app);
+    app.notify(
+        Severity::Error,
+        format!("Failed to download: {}", file_names.join(", ")),
+    );
+    if app.desktop_notifications {
+        Notice::download_failed(file_names).show();
+    }
+    if app.exit_on_complete {
+        app.exit_code = 1;
+        app.should_quit = true;
+    }
+}
+
+fn push_accepted(app: &mut App, peer_id: PeerId, files: Vec<RequestedFile>, directory: PathBuf) {
+    let name = app
+        .greeted_name(&peer_id)
+        .unwrap_or_else(|| peer_id.to_string());
+    app.notify(
+        Severity::Info,
+        format!("{name} pushed {} new or changed files", files.len()),
+    );
+    if let Some(client) = app.client.clone() {
+        spawn(async move {
+            if let Err(e) = client
+                .request_files(peer_id, files, Some(directory), true)
+                .await
+            {
+                tracing::error!("Failed to fetch pushed files: {}", e);
+            }
+        });
+    }
+}
+
+/// The host's listing changed, `update` turns the one we have into the new one.
+fn share_changed(app: &mut App, update: impl FnOnce(Vec<DirectoryItem>) -> Vec<DirectoryItem>) {
+    if app.remote_listing.on_demand {
+        // The directories needed are listed again
+        app.remote_listing.invalidate();
+    } else {
+        let items = update(app.all_shared_items.clone());
+        apply_shared_items(app, items);
+    }
+    tracing::info!("Updated directory items: {:?}", app.directory_items);
+    start_sync(app);
+}
+
+fn transfer_completed(app: &mut App, path: &str) {
+    let record = app.transfers.complete(path);
+    if let (Some(mut record), Some(history)) = (record.clone(), transfers::history_path()) {
+        record.peer = app.connected_peer_name();
+        record.peer_id = app.connected_peer_id.map(|id| id.to_string());
+        if let Err(e) = TransferHistory::append(&history, &record) {
+            tracing::warn!("Failed to record the transfer: {}", e);
+        }
+    }
+    if let (Some(record), Some(peer_id)) = (record, app.connected_peer_id) {
+        let item = app
+            .all_shared_items
+            .iter()
+            .find(|item| item.path == Path::new(path));
+        let delivered = DeliveredFile {
+            path: item.map_or_else(
+                || path.to_string(),
+                |item| item.display_path.to_string_lossy().to_string(),
+            ),
+            size: record.bytes,
+            hash: item.and_then(|item| item.hash.clone()),
+            peer_id,
+            peer: app.greeted_name(&peer_id),
+            finished_at: SystemTime::now(),
+        };
+        app.session_report.add(delivered);
+    }
+}
+
+/// Record the delivery in the session report and let the desktop know once the peer has
+/// everything.
+fn upload_completed(
+    app: &mut App,
+    app_handle: &Arc<Mutex<App>>,
+    peer_id: PeerId,
+    path: String,
+    bytes: u64,
+    hash: Option<String>,
+) {
+    let delivered = DeliveredFile {
+        path: transfer_path(Path::new(&path))
+            .to_string_lossy()
+            .to_string(),
+        size: bytes,
+        hash,
+        peer_id,
+        peer: app.greeted_name(&peer_id),
+        finished_at: SystemTime::now(),
+    };
+    app.session_report.add(delivered);
+    finish_pushed(app, &path, true);
+    app.share_stats.upload_completed(peer_id, path, bytes);
+    if app.desktop_notifications {
+        spawn(notify_when_served(Arc::clone(app_handle), peer_id));
+    }
+}
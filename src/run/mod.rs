@@ -0,0 +1,503 @@
+//! What the `junkanoo` binary runs: the command line is parsed, the app set up for the
+//! subcommand, and the terminal UI or `--plain` runs next to the network until quit.
+
+mod actions;
+mod commands;
+mod events;
+pub mod network;
+mod plain;
+mod terminal;
+
+use chrono::NaiveTime;
+use clap::ArgMatches;
+use human_panic::{setup_panic, Metadata};
+use libp2p::Multiaddr;
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::spawn;
+use tokio::sync::watch;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::rolling;
+use tracing_subscriber::EnvFilter;
+
+use crate::app::{self, App};
+use crate::cli::{self, output, theme::Theme};
+use crate::config::{self, Config};
+use crate::pipe;
+use crate::recent::{self, Recent};
+use crate::service::archive::ArchiveFormat;
+use crate::service::greeting;
+use crate::service::identity;
+use crate::service::secret::Secret;
+use crate::service::systemd;
+use crate::service::utils::SymlinkPolicy;
+use crate::transfers::{self, TransferHistory};
+
+/// How long quitting waits for downloads to flush and peers to be told goodbye.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run the command line of the process, resolves with its exit code.
+pub async fn run() -> i32 {
+    setup_panic_handler();
+    let Some(matches) = parse_args() else {
+        return 0;
+    };
+
+    #[cfg(debug_assertions)]
+    setup_logger(is_read_only(&matches));
+    output::set_json(matches.get_flag("json"));
+
+    match matches.subcommand() {
+        Some(("bench", sub_matches)) => return commands::run_bench(&matches, sub_matches).await,
+        Some(("ls", sub_matches)) => return commands::run_ls(&matches, sub_matches).await,
+        Some(("get", sub_matches)) => return commands::run_get(&matches, sub_matches).await,
+        _ => {}
+    }
+
+    let config = load_config();
+    let mut app = new_app(&matches, &config);
+    // Where `share -` keeps what arrived on stdin, removed on exit
+    let mut stdin_scratch = None;
+    let mut target_peer_addrs = Vec::new();
+    let setup = match matches.subcommand() {
+        Some(("share", sub_matches)) => {
+            setup_share(&mut app, &matches, sub_matches, &config).map(|scratch| {
+                stdin_scratch = scratch;
+            })
+        }
+        Some((command @ ("download" | "sync"), sub_matches)) => {
+            setup_download(&mut app, &matches, command, sub_matches).map(|addrs| {
+                target_peer_addrs = addrs;
+            })
+        }
+        _ => {
+            tracing::error!("Unknown subcommand");
+            Ok(())
+        }
+    };
+    if let Err(e) = setup {
+        output::error(&e);
+        return 1;
+    }
+
+    let app = Arc::new(Mutex::new(app));
+    run_session(&app, &matches, target_peer_addrs).await;
+    print_results(&app, &matches);
+
+    if let Some(directory) = stdin_scratch.as_deref().and_then(Path::parent) {
+        let _ = std::fs::remove_dir_all(directory);
+    }
+    let exit_code = app.lock().exit_code;
+    exit_code
+}
+
+/// The command line, or the one of the recent share or host picked on the start screen
+/// when it names no subcommand. `None` to quit right away.
+fn parse_args() -> Option<ArgMatches> {
+    let matches = cli::commands::get_args().get_matches();
+    if let Some(dir) = matches.get_one::<PathBuf>("state-dir") {
+        config::set_state_dir(dir.clone());
+    }
+    if matches.subcommand().is_some() {
+        return Some(matches);
+    }
+    let choices = recent::recent_path()
+        .map(|path| Recent::load(&path).choices())
+        .unwrap_or_default();
+    if choices.is_empty() {
+        let _ = cli::commands::get_args().print_help();
+        return None;
+    }
+    let choice = if matches.get_flag("plain") {
+        plain::pick_recent(&choices)
+    } else {
+        terminal::pick_recent(&choices)
+    }?;
+    // Global options given on the command line still apply
+    let args = std::env::args().chain(choice.args());
+    Some(cli::commands::get_args().get_matches_from(args))
+}
+
+/// The config file, its defaults if there is none or it can't be read.
+fn load_config() -> Config {
+    config::config_path()
+        .map(|path| {
+            Config::load(&path).unwrap_or_else(|e| {
+                output::error(&format!("Ignoring {}: {e}", path.display()));
+                Config::default()
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// The app with the settings of the config file and the global options.
+fn new_app(matches: &ArgMatches, config: &Config) -> App {
+    let mut app = App::new();
+    app.notifications = config.notifications.clone();
+    app.navigation = config.navigation.clone();
+    app.keys = config.keys.clone();
+    app.theme = Theme::new(&config.theme, Theme::no_color());
+    app.bookmarks = config.bookmarks.clone();
+    app.bandwidth = config.bandwidth.clone();
+    app.display_name = matches
+        .get_one::<String>("name")
+        .cloned()
+        .or_else(|| config.display_name.clone())
+        .or_else(greeting::default_display_name);
+    app.exit_on_complete = matches.get_flag("exit-on-complete");
+    app.desktop_notifications = matches.get_flag("notify");
+    app.show_hidden = matches.get_flag("show-hidden");
+    if let Some(threshold) = matches.get_one::<u64>("confirm-above") {
+        app.confirm_threshold = *threshold;
+    }
+    app
+}
+
+/// Set the app up to host what `share` names. Resolves with the scratch directory holding
+/// what arrived on stdin for `share -`.
+fn setup_share(
+    app: &mut App,
+    matches: &ArgMatches,
+    sub_matches: &ArgMatches,
+    config: &Config,
+) -> Result<Option<PathBuf>, String> {
+    app.state = app::AppState::Share;
+    app.is_host = true;
+    let mut stdin_scratch = None;
+    // Selections are keyed by absolute path, so start from one
+    let path = match sub_matches.get_one::<String>("FILE_PATH") {
+        Some(path) if path == "-" => {
+            if matches.get_flag("plain") {
+                return Err("--plain reads commands from stdin, share a file instead".to_string());
+            }
+            let name = sub_matches
+                .get_one::<String>("as")
+                .map_or(pipe::STDIN_NAME, String::as_str);
+            let directory = pipe::scratch_directory("share");
+            let spooled = pipe::spool(&mut std::io::stdin().lock(), &directory, name);
+            match spooled {
+                Ok(file) => stdin_scratch = Some(file),
+                Err(e) => {
+                    let _ = std::fs::remove_dir_all(&directory);
+                    return Err(format!("Failed to read stdin: {}", e.explain()));
+                }
+            }
+            directory
+        }
+        Some(path) => match path.strip_prefix('@') {
+            Some(name) => config.bookmark(name)?.to_path_buf(),
+            None => PathBuf::from(path),
+        },
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+    app.current_path = std::fs::canonicalize(&path).unwrap_or(path);
+    app.symlinks = symlink_policy(matches);
+    app.populate_directory_items();
+    // Nothing else is in the scratch directory, so stdin is offered straight away
+    if let Some(file) = &stdin_scratch {
+        let file = std::fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+        app.items_to_share.insert(file);
+    }
+    app.share_label = sub_matches.get_one::<String>("label").cloned();
+    if !sub_matches.get_flag("read-only") && stdin_scratch.is_none() {
+        let (path, label) = (app.current_path.clone(), app.share_label.clone());
+        recent::remember(|recent, now| recent.add_share(path, label, now));
+    }
+    if let Some(patterns) = sub_matches.get_many::<String>("sensitive") {
+        app.sensitive_patterns.extend(patterns.cloned());
+    }
+    app.share_opens_at = sub_matches
+        .get_one::<NaiveTime>("start-at")
+        .map(|time| next_occurrence(*time));
+    // The window only starts counting down once a scheduled share opened
+    let until_open = app
+        .share_opens_at
+        .and_then(|opens_at| opens_at.duration_since(SystemTime::now()).ok())
+        .unwrap_or_default();
+    // `--expires` and `--window` conflict, at most one of them is given
+    app.share_expires_at = sub_matches
+        .get_one::<Duration>("expires")
+        .map(|ttl| Instant::now() + *ttl)
+        .or_else(|| {
+            sub_matches
+                .get_one::<Duration>("window")
+                .map(|window| Instant::now() + until_open + *window)
+        });
+    Ok(stdin_scratch)
+}
+
+/// Set the app up for `download` or `sync`, the latter pushing the directory with `--push`.
+/// Resolves with the host addresses given.
+fn setup_download(
+    app: &mut App,
+    matches: &ArgMatches,
+    command: &str,
+    sub_matches: &ArgMatches,
+) -> Result<Vec<Multiaddr>, String> {
+    app.state = app::AppState::Download;
+    app.is_host = false;
+    if command == "sync" {
+        app.sync = true;
+        app.sync_directory = sub_matches.get_one::<PathBuf>("DIR").cloned();
+        if sub_matches.get_flag("push") {
+            // Pushing shares the directory, the host downloads from us
+            app.state = app::AppState::Share;
+            app.is_host = true;
+            let path = app.sync_directory.clone().unwrap_or_default();
+            app.current_path = std::fs::canonicalize(&path).unwrap_or(path);
+            app.symlinks = symlink_policy(matches);
+            app.populate_directory_items();
+        } else {
+            app.exit_on_complete = true;
+        }
+    }
+    app.dry_run = sub_matches.get_flag("dry-run");
+    if command == "download" {
+        app.archive = sub_matches.get_one::<ArchiveFormat>("archive").copied();
+    }
+    if let Some(history) = transfers::history_path() {
+        app.transfer_history = TransferHistory::load(&history);
+    }
+    sub_matches
+        .get_many::<String>("PEER_ADDR_IDENTIFIER")
+        .into_iter()
+        .flatten()
+        .map(|address| {
+            address
+                .parse::<Multiaddr>()
+                .map_err(|e| format!("Invalid peer address format: {e}"))
+        })
+        .collect()
+}
+
+/// Run the UI, or `--plain`, until quit with the network next to it, then wind the network
+/// down.
+async fn run_session(
+    app: &Arc<Mutex<App>>,
+    matches: &ArgMatches,
+    target_peer_addrs: Vec<Multiaddr>,
+) {
+    forward_refreshes(app);
+
+    // The network winds down once the UI loop ended
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let network = {
+        let (app, matches) = (Arc::clone(app), matches.clone());
+        tokio::spawn(async move {
+            let result =
+                network::start_network(Arc::clone(&app), &matches, target_peer_addrs, shutdown)
+                    .await;
+            if result.is_err() {
+                // Ends the UI loop, so the terminal is restored before the error is shown
+                app.lock().should_quit = true;
+            }
+            result
+        })
+    };
+
+    quit_on_terminate(Arc::clone(app));
+    if let Some(interval) = systemd::watchdog_interval() {
+        spawn_watchdog(Arc::clone(app), interval);
+    }
+
+    // The UI runs on the main thread
+    if matches.get_flag("plain") {
+        plain::plain_loop(app);
+    } else {
+        let mut terminal = terminal::setup_terminal();
+        terminal::render_loop(&mut terminal, app);
+        terminal::cleanup_terminal();
+    }
+
+    systemd::notify("STOPPING=1");
+    let _ = shutdown_sender.send(true);
+    match tokio::time::timeout(SHUTDOWN_TIMEOUT, network).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => {
+            output::error(&format!("Network error: {e}"));
+            app.lock().exit_code = 1;
+        }
+        Ok(Err(e)) => tracing::error!("Network task failed: {}", e),
+        Err(_) => tracing::warn!("Network did not shut down in time"),
+    }
+}
+
+/// Pass the refreshes asked for through the app's refresh channel on to the UI.
+fn forward_refreshes(app: &Arc<Mutex<App>>) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    app.lock().refresh_sender = Some(tx);
+    let app = Arc::clone(app);
+    tokio::spawn(async move {
+        while (rx.recv().await).is_some() {
+            // Force a UI refresh
+            if let Some(tx) = app.lock().refresh_sender() {
+                let _ = tx.try_send(());
+            }
+        }
+    });
+}
+
+/// Write the `--report`, and print the plan of a dry run and the summary of the downloads.
+fn print_results(app: &Arc<Mutex<App>>, matches: &ArgMatches) {
+    if let Some(path) = matches.get_one::<PathBuf>("report") {
+        let html = {
+            let app = app.lock();
+            app.session_report.to_html(
+                &app.peer_id,
+                app.is_host,
+                app.share_label.as_deref(),
+                SystemTime::now(),
+            )
+        };
+        if let Err(e) = std::fs::write(path, html) {
+            output::error(&format!(
+                "Failed to write the report to {}: {e}",
+                path.display()
+            ));
+        }
+    }
+
+    let plan = app.lock().download_plan.take();
+    if let Some(plan) = plan {
+        if output::is_json() {
+            output::emit(&output::JsonEvent::Plan(plan.clone()));
+        } else {
+            println!("{}", plan.report());
+        }
+        if !plan.is_ok() {
+            app.lock().exit_code = 1;
+        }
+    }
+
+    let summary = app.lock().download_summary.take();
+    if let Some(summary) = summary {
+        if output::is_json() {
+            output::emit(&output::JsonEvent::Summary(summary));
+        } else {
+            println!("{}", summary.table());
+        }
+    }
+}
+
+fn setup_panic_handler() {
+    setup_panic!(
+        Metadata::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+        .authors("Chad Nehemiah <chad@nehemiah94@gmail.com>")
+        .homepage("https://maschad.codes")
+        .support("- Open a support request via GitHub Issues: https://github.com/maschad/junkanoo/issues")
+    );
+
+    // Hand the terminal back before the report is printed, from whichever thread panicked,
+    // otherwise it lands on the alternate screen and the shell is left in raw mode
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if terminal::is_active() {
+            terminal::cleanup_terminal();
+        }
+        report(info);
+    }));
+}
+
+fn setup_logger(read_only: bool) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::DEBUG.into())
+        .from_env()
+        .unwrap();
+
+    // A read-only session must not write anything, not even logs
+    if read_only {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_env_filter(filter)
+            .init();
+        return;
+    }
+
+    // Initialize logging to file and terminal
+    let file_appender = rolling::minutely("logs", "p2p-file-share");
+
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr) // Write to terminal
+        .with_writer(file_appender) // Also write to file
+        .with_env_filter(filter)
+        .init();
+}
+
+/// The next time the local clock shows `time`, later today or tomorrow.
+fn next_occurrence(time: NaiveTime) -> SystemTime {
+    let now = chrono::Local::now();
+    let today = now.date_naive().and_time(time);
+    let next = if today > now.naive_local() {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    };
+    next.and_local_timezone(chrono::Local)
+        .earliest()
+        .map_or_else(SystemTime::now, SystemTime::from)
+}
+
+/// The identity seed to start the node with. A share publishing a code needs its peer ID
+/// before the node starts, its code is derived from it, so it gets a new seed unless
+/// `--identity-seed` gave one.
+pub fn identity_seed(matches: &clap::ArgMatches) -> Option<Secret> {
+    let seed = matches.get_one::<Secret>("identity-seed").cloned();
+    seed.or_else(|| wants_share_code(matches).then(identity::new_phrase))
+}
+
+/// Whether to publish a share code. Codes are looked up in the DHT, which a LAN-only share
+/// stays out of, or at a rendezvous point.
+pub fn wants_share_code(matches: &clap::ArgMatches) -> bool {
+    matches.subcommand_matches("share").is_some()
+        && (!matches.get_flag("lan-only") || matches.contains_id("rendezvous"))
+}
+
+/// Whether the host asked for a read-only session, see `share --read-only`.
+pub fn is_read_only(matches: &clap::ArgMatches) -> bool {
+    matches
+        .subcommand_matches("share")
+        .is_some_and(|share| share.get_flag("read-only"))
+}
+
+/// How the host asked for symlinks to be shared, see `share --copy-links`.
+pub fn symlink_policy(matches: &clap::ArgMatches) -> SymlinkPolicy {
+    match matches.subcommand_matches("share") {
+        Some(share) if share.get_flag("skip-symlinks") => SymlinkPolicy::Skip,
+        Some(share) if share.get_flag("copy-links") => SymlinkPolicy::Copy,
+        _ => SymlinkPolicy::Follow,
+    }
+}
+
+/// End the UI loop on SIGTERM, e.g. from `systemctl stop`, so the shutdown is as graceful
+/// as quitting with `q`.
+fn quit_on_terminate(app: Arc<Mutex<App>>) {
+    #[cfg(unix)]
+    spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                tracing::warn!("Failed to handle SIGTERM: {}", e);
+                return;
+            }
+        };
+        if terminate.recv().await.is_some() {
+            tracing::info!("Received SIGTERM, shutting down");
+            app.lock().should_quit = true;
+        }
+    });
+    #[cfg(not(unix))]
+    drop(app);
+}
+
+/// Keep systemd's watchdog fed while the app state can still be locked, a deadlock gets
+/// the service restarted.
+fn spawn_watchdog(app: Arc<Mutex<App>>, interval: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        drop(app.lock());
+        systemd::notify("WATCHDOG=1");
+    });
+}
@@ -0,0 +1,651 @@
+//! The node behind a session: starting it, and hosting, pushing or downloading with it.
+
+use clap::ArgMatches;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use parking_lot::Mutex;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::spawn;
+use tokio::sync::watch;
+
+use super::actions::start_sync;
+use super::events::handle_network_events;
+use super::{identity_seed, is_read_only, symlink_policy, wants_share_code};
+use crate::app::{App, DirectoryItem};
+use crate::config::Severity;
+use crate::recent;
+use crate::service;
+use crate::service::client::NetworkClient;
+use crate::service::dial::{self, DialPolicy};
+use crate::service::hashing::HashCache;
+use crate::service::identity;
+use crate::service::node::NodeConfig;
+use crate::service::probe::TransportChoice;
+use crate::service::protocol::DisplayResponse;
+use crate::service::secret::Secret;
+use crate::service::share_code;
+use crate::service::systemd;
+use crate::service::utils::ConflictPolicy;
+
+/// How often the host looks at its shared files again, e.g. for one edited on disk.
+const SHARE_RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a downloader asks the host for its listing, less often from hosts that push
+/// changes to it.
+const DIRECTORY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const PUSHED_DIRECTORY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+async fn handle_host_mode(
+    client: &dyn NetworkClient,
+    app: Arc<Mutex<App>>,
+    save_history: bool,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    // Hashes from earlier sessions show what changed since this directory was last shared
+    let (root, symlinks) = {
+        let app = app.lock();
+        (app.current_path.clone(), app.symlinks)
+    };
+    let history = service::hashing::history_path(&root);
+    let mut hash_cache = history.as_deref().map(HashCache::load).unwrap_or_default();
+    let diff = tokio::task::block_in_place(|| hash_cache.diff(&root));
+    if !diff.is_empty() {
+        let mut app = app.lock();
+        app.manifest_diff = Some(diff);
+        if let Some(tx) = app.refresh_sender() {
+            let _ = tx.try_send(());
+        }
+    }
+
+    // Selection changes are pushed, rescans only catch files edited while they are shared
+    let (share_sender, mut share_changes) = tokio::sync::mpsc::channel(1);
+    app.lock().share_sender = Some(share_sender);
+    let mut published = Vec::new();
+    loop {
+        let directory_items = {
+            let mut app = app.lock();
+            // Nothing is published until the host confirmed the changes
+            let all_paths: Vec<_> = if app.manifest_diff.is_some() {
+                Vec::new()
+            } else {
+                // Likely secrets stay on this machine until the host confirmed them
+                let sensitive = app.unconfirmed_sensitive_items();
+                if app.sensitive_pending != sensitive {
+                    app.sensitive_pending.clone_from(&sensitive);
+                    if let Some(tx) = app.refresh_sender() {
+                        let _ = tx.try_send(());
+                    }
+                }
+                app.items_to_share
+                    .iter()
+                    .filter(|path| !sensitive.contains(path))
+                    .cloned()
+                    .collect()
+            };
+            drop(app); // Release the lock early
+
+            // Hashing a new or changed file reads all of it
+            tokio::task::block_in_place(|| {
+                crate::shared_items(&all_paths, symlinks, &mut hash_cache)
+            })
+        };
+
+        // Only send updates if there are changes, each one is a new manifest version
+        if directory_items != published {
+            if save_history {
+                if let Some(history) = &history {
+                    if let Err(e) = hash_cache.save(history) {
+                        tracing::warn!("Failed to save the hash cache: {}", e);
+                    }
+                }
+            }
+            tracing::info!("Publishing {} shared items", directory_items.len());
+            if let Err(e) = client.update_directory_items(directory_items.clone()).await {
+                tracing::error!("Failed to send directory items: {}", e);
+                break;
+            }
+            published = directory_items;
+        }
+
+        tokio::select! {
+            _ = share_changes.recv() => {}
+            () = tokio::time::sleep(SHARE_RESCAN_INTERVAL) => {}
+            () = shutdown_requested(&mut shutdown) => break,
+        }
+    }
+}
+
+/// Resolves once the UI asked everything to stop.
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    // A dropped sender means the UI is gone as well
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+/// The peer all of `addresses` lead to, they may only differ in how to get there.
+fn target_peer_id(addresses: &[Multiaddr]) -> Result<PeerId, String> {
+    let mut peer_ids = addresses.iter().map(|address| {
+        address
+            .iter()
+            .find_map(|p| match p {
+                Protocol::P2p(peer_id) => Some(peer_id),
+                _ => None,
+            })
+            .ok_or("Peer address must contain a peer ID component (/p2p/...)")
+    });
+    let first = peer_ids.next().ok_or("No peer address provided")??;
+    for peer_id in peer_ids {
+        if peer_id? != first {
+            return Err("All peer addresses must be of the same peer".to_string());
+        }
+    }
+    Ok(first)
+}
+
+/// Dial the peer at the first of `target_peer_addrs` that answers and introduce ourselves
+/// with `password`. Returns the peer and the address it was reached at.
+async fn connect(
+    client: &dyn NetworkClient,
+    target_peer_addrs: Vec<Multiaddr>,
+    password: Option<Secret>,
+    app: &Arc<Mutex<App>>,
+) -> Result<(PeerId, Multiaddr), String> {
+    let target_peer_id = target_peer_id(&target_peer_addrs)?;
+    if app.lock().transport == TransportChoice::Tcp && target_peer_addrs.iter().all(is_udp) {
+        tracing::warn!(
+            "Dialing a QUIC address although UDP looks blocked, ask the host for a TCP one"
+        );
+    }
+
+    let target_peer_addr = dial::dial_any(
+        client,
+        target_peer_id,
+        &target_peer_addrs,
+        DialPolicy::default(),
+    )
+    .await
+    .map_err(|e| e.explain())?;
+    if target_peer_addrs.len() > 1 {
+        app.lock().notify(
+            Severity::Info,
+            format!("Reached the host at {target_peer_addr}"),
+        );
+    }
+
+    // Introduce ourselves before asking for anything, hosts of older releases don't answer
+    let sent_password = password.is_some();
+    match client.greet(target_peer_id, password).await {
+        Ok(welcome) if !welcome.authorized => {
+            return Err(if sent_password {
+                "Wrong password for the share"
+            } else {
+                "The share requires a password, pass it with --password"
+            }
+            .to_string());
+        }
+        Ok(welcome) => {
+            let mut app = app.lock();
+            app.notify(Severity::Info, welcome.greeting.describe(&target_peer_id));
+            if welcome.greeting.label.is_some() {
+                app.share_label.clone_from(&welcome.greeting.label);
+            }
+            app.peer_greeting = Some((target_peer_id, welcome.greeting));
+        }
+        Err(e) => tracing::warn!("The host didn't answer the greeting: {}", e),
+    }
+    Ok((target_peer_id, target_peer_addr))
+}
+
+/// `sync --push`: offer the files of the directory to the host, then serve the ones it
+/// asks for until it fetched them all.
+async fn handle_push_mode(
+    client: &dyn NetworkClient,
+    target_peer_addrs: Vec<Multiaddr>,
+    password: Option<Secret>,
+    app: Arc<Mutex<App>>,
+) -> Result<(), String> {
+    let (root, symlinks) = {
+        let app = app.lock();
+        (app.current_path.clone(), app.symlinks)
+    };
+    // Hashes from earlier shares of the directory spare reading unchanged files
+    let mut hash_cache = service::hashing::history_path(&root)
+        .as_deref()
+        .map(HashCache::load)
+        .unwrap_or_default();
+    let items = tokio::task::block_in_place(|| {
+        let files: Vec<PathBuf> = service::utils::walk(&root, symlinks)
+            .filter(|path| !path.is_dir())
+            .collect();
+        crate::shared_items(&files, symlinks, &mut hash_cache)
+    });
+    let paths: Vec<String> = items
+        .iter()
+        .map(|item| item.path.to_string_lossy().to_string())
+        .collect();
+    client
+        .update_directory_items(items)
+        .await
+        .map_err(|e| format!("Failed to publish the files to push: {e}"))?;
+
+    let (target_peer_id, _) = connect(client, target_peer_addrs, password, &app).await?;
+    let offers_push = app
+        .lock()
+        .peer_greeting
+        .as_ref()
+        .is_some_and(|(_, greeting)| greeting.supports("push"));
+    if !offers_push {
+        return Err("The host runs a release that doesn't take pushes".to_string());
+    }
+    let response = client.push(target_peer_id, paths).await.map_err(|e| {
+        tracing::error!("Failed to push: {}", e);
+        "The host didn't take the push, it may run an older release".to_string()
+    })?;
+    if let Some(rejection) = response.rejection {
+        return Err(format!("The host turned the push down: {rejection}"));
+    }
+
+    let mut app = app.lock();
+    if response.requested.is_empty() {
+        tracing::info!("The host already has every file");
+        app.should_quit = true;
+    } else {
+        app.notify(
+            Severity::Info,
+            format!("The host is fetching {} files", response.requested.len()),
+        );
+        app.pushing = Some(response.requested.into_iter().collect());
+    }
+    if let Some(tx) = app.refresh_sender() {
+        let _ = tx.try_send(());
+    }
+    drop(app);
+    Ok(())
+}
+
+/// A pushed file was uploaded or failed, quit once the host has them all.
+pub fn finish_pushed(app: &mut App, path: &str, completed: bool) {
+    let Some(pushing) = &mut app.pushing else {
+        return;
+    };
+    if !pushing.remove(path) {
+        return;
+    }
+    if !completed {
+        app.exit_code = 1;
+    }
+    if pushing.is_empty() {
+        app.should_quit = true;
+    }
+}
+
+async fn handle_download_mode(
+    client: &Arc<dyn NetworkClient>,
+    target_peer_addrs: Vec<Multiaddr>,
+    password: Option<Secret>,
+    app: Arc<Mutex<App>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), String> {
+    let (target_peer_id, target_peer_addr) =
+        connect(client.as_ref(), target_peer_addrs, password, &app).await?;
+    let address = target_peer_addr.to_string();
+
+    // Initial directory request
+    match client.request_directory(target_peer_id).await {
+        Ok(display_response) => {
+            let name = app
+                .lock()
+                .peer_greeting
+                .as_ref()
+                .and_then(|(_, greeting)| greeting.display_name.clone());
+            let label = name.or_else(|| display_response.label.clone());
+            recent::remember(|recent, now| recent.add_peer(address, label, now));
+            apply_first_listing(&mut app.lock(), display_response, target_peer_id);
+            // Polls of such hosts only catch up on missed changes, the label and the opening
+            let poll_interval =
+                if app
+                    .lock()
+                    .peer_greeting
+                    .as_ref()
+                    .is_some_and(|(peer_id, greeting)| {
+                        *peer_id == target_peer_id && greeting.supports("updates")
+                    })
+                {
+                    PUSHED_DIRECTORY_POLL_INTERVAL
+                } else {
+                    DIRECTORY_POLL_INTERVAL
+                };
+
+            // Start a background task to handle directory updates
+            let client_clone = Arc::clone(client);
+            let app_clone = app.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        () = tokio::time::sleep(poll_interval) => {}
+                        () = shutdown_requested(&mut shutdown) => break,
+                    }
+                    // Nothing to ask while the host is being redialed
+                    if !app_clone.lock().is_connected() {
+                        continue;
+                    }
+                    match client_clone.request_directory(target_peer_id).await {
+                        Ok(display_response) => {
+                            let opens_at = share_opens_at(&display_response);
+                            let label = display_response.label.clone();
+                            // Listing changes arrive as `ShareUpdated` events
+                            let mut app = app_clone.lock();
+                            if app.share_opens_at != opens_at || app.share_label != label {
+                                app.share_opens_at = opens_at;
+                                app.share_label = label;
+                                if let Some(refresh_sender) = &app.refresh_sender {
+                                    let _ = refresh_sender.try_send(());
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            // Usually the connection dropped, polling resumes once it's redialed
+                            tracing::warn!("Failed to request directory: {}", e);
+                            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                        }
+                    }
+                }
+            });
+
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!("Failed to request directory: {}", e);
+            Err(format!("Failed to request directory: {}", e.explain()))
+        }
+    }
+}
+
+/// Start the node, listen, and then run it as a host, a pusher or a downloader until quit.
+pub async fn start_network(
+    app: Arc<Mutex<App>>,
+    matches: &ArgMatches,
+    target_peer_addrs: Vec<Multiaddr>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), String> {
+    let identity_seed = identity_seed(matches);
+    if let Some(seed) = identity_seed.as_ref().filter(|_| wants_share_code(matches)) {
+        let peer_id = identity::keypair(seed)?.public().to_peer_id();
+        app.lock().share_code = Some(share_code::of(&peer_id));
+    }
+    let config = node_config(&app, matches, identity_seed);
+
+    let (client, event_stream, event_loop, peer_id) =
+        service::node::new(&config).map_err(|_| "Failed to create node")?;
+    let client: Arc<dyn NetworkClient> = Arc::new(client);
+
+    {
+        let mut app = app.lock();
+        app.peer_id = peer_id;
+        app.set_client(Arc::clone(&client));
+    }
+
+    spawn(event_loop.run());
+    spawn(handle_network_events(event_stream, Arc::clone(&app)));
+
+    // Given listen addresses are used as they are, and the LAN doesn't need the internet
+    let transport = if matches.contains_id("listen") || matches.get_flag("lan-only") {
+        TransportChoice::Quic
+    } else {
+        service::probe::choose_transport().await
+    };
+    app.lock().transport = transport;
+
+    start_listening(client.as_ref(), listen_addrs(matches, transport)).await?;
+    let listening_addrs: Vec<Multiaddr> = client.get_listening_addrs().await.map_err(|e| {
+        tracing::error!("Failed to get listening addresses: {}", e);
+        "Could not get listening addresses. Please try again.".to_string()
+    })?;
+    app.lock().listening_addrs = listening_addrs;
+    systemd::notify("READY=1");
+
+    let password = matches
+        .subcommand()
+        .and_then(|(_, download)| download.get_one::<Secret>("password").cloned());
+    let pushing = {
+        let app = app.lock();
+        app.is_host && app.sync
+    };
+    if pushing {
+        handle_push_mode(client.as_ref(), target_peer_addrs, password, app).await?;
+    } else if app.lock().is_host {
+        let expires_at = app.lock().share_expires_at;
+        if let Some(expires_at) = expires_at {
+            spawn(expire_share(
+                Arc::clone(&client),
+                expires_at,
+                Arc::clone(&app),
+            ));
+        }
+        handle_host_mode(
+            client.as_ref(),
+            app,
+            !is_read_only(matches),
+            shutdown.clone(),
+        )
+        .await;
+    } else {
+        let code = matches
+            .subcommand_matches("download")
+            .and_then(|download| download.get_one::<String>("code").cloned());
+        let target_peer_addrs = match (target_peer_addrs.is_empty(), code) {
+            (false, _) => Some(target_peer_addrs),
+            (true, Some(code)) => tokio::select! {
+                addrs = resolve_share_code(client.as_ref(), code, &app) => Some(addrs?),
+                () = shutdown_requested(&mut shutdown) => None,
+            },
+            (true, None) => tokio::select! {
+                addr = ask_peer_address(&app) => {
+                    Some(vec![addr.ok_or("No peer address provided")?])
+                }
+                // Quit before an address was entered
+                () = shutdown_requested(&mut shutdown) => None,
+            },
+        };
+        if let Some(target_peer_addrs) = target_peer_addrs {
+            handle_download_mode(&client, target_peer_addrs, password, app, shutdown.clone())
+                .await?;
+        }
+    }
+
+    shutdown_requested(&mut shutdown).await;
+    client
+        .shutdown()
+        .await
+        .map_err(|e| format!("Failed to shut down the network: {e}"))
+}
+
+/// The node's settings from the command line and what the session already set up.
+fn node_config(
+    app: &Arc<Mutex<App>>,
+    matches: &ArgMatches,
+    identity_seed: Option<Secret>,
+) -> NodeConfig {
+    let app = app.lock();
+    let share = matches.subcommand_matches("share");
+    let download = matches.subcommand_matches("download");
+    NodeConfig {
+        max_upload: matches.get_one::<u64>("max-upload").copied(),
+        max_download: matches.get_one::<u64>("max-download").copied(),
+        bandwidth: app.bandwidth.clone(),
+        max_connections: matches.get_one::<u32>("max-connections").copied(),
+        lan_only: matches.get_flag("lan-only"),
+        bootnodes: Vec::new(),
+        no_compress: matches.get_flag("no-compress"),
+        parallel_downloads: matches.get_one::<usize>("parallel").copied(),
+        once: share.is_some_and(|share| share.get_flag("once")),
+        read_only: is_read_only(matches),
+        opens_at: app.share_opens_at,
+        label: app.share_label.clone(),
+        display_name: app.display_name.clone(),
+        password: share.and_then(|share| share.get_one::<Secret>("password").cloned()),
+        exit_on_complete: app.is_host && app.exit_on_complete,
+        preserve: ["download", "sync"].into_iter().any(|command| {
+            matches
+                .subcommand_matches(command)
+                .is_some_and(|download| download.get_flag("preserve"))
+        }),
+        on_conflict: download
+            .and_then(|download| download.get_one::<ConflictPolicy>("on-conflict").copied())
+            .unwrap_or_default(),
+        symlinks: symlink_policy(matches),
+        sync_root: (app.is_host && app.sync).then(|| app.current_path.clone()),
+        push_directory: share
+            .is_some_and(|share| share.get_flag("accept-push"))
+            .then(|| {
+                // Into the shared directory, next to a shared file
+                if app.current_path.is_dir() {
+                    app.current_path.clone()
+                } else {
+                    app.current_path
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .unwrap_or_default()
+                }
+            }),
+        share_code: app.share_code.clone(),
+        identity_seed,
+        rendezvous: matches.get_one::<Multiaddr>("rendezvous").cloned(),
+        request_timeout: matches.get_one::<Duration>("request-timeout").copied(),
+    }
+}
+
+/// The addresses to listen on, the given ones or every interface on the given port.
+fn listen_addrs(matches: &ArgMatches, transport: TransportChoice) -> Vec<Multiaddr> {
+    let address = matches.get_one::<IpAddr>("address").copied();
+    let addrs: Vec<Multiaddr> = matches.get_many::<Multiaddr>("listen").map_or_else(
+        || {
+            service::node::listen_addrs(
+                address,
+                matches.get_one::<u16>("port").copied().unwrap_or(0),
+            )
+        },
+        |listen| listen.cloned().collect(),
+    );
+    // On a port of its own, QUIC already holds the given one
+    #[cfg(feature = "webrtc")]
+    let addrs = if matches.contains_id("listen") {
+        addrs
+    } else {
+        let webrtc = service::node::listen_addrs(address, 0)
+            .into_iter()
+            .filter(is_udp)
+            .map(|addr| {
+                addr.replace(2, |_| Some(Protocol::WebRTCDirect))
+                    .unwrap_or(addr)
+            });
+        addrs.into_iter().chain(webrtc).collect()
+    };
+    // Nothing reaches UDP listeners when UDP is blocked
+    if transport == TransportChoice::Tcp {
+        addrs.into_iter().filter(|addr| !is_udp(addr)).collect()
+    } else {
+        addrs
+    }
+}
+
+fn is_udp(addr: &Multiaddr) -> bool {
+    addr.iter()
+        .any(|protocol| matches!(protocol, Protocol::Udp(_)))
+}
+
+/// Listen on `addrs`. A machine without IPv6 still shares over IPv4, only all of them
+/// failing is fatal.
+async fn start_listening(client: &dyn NetworkClient, addrs: Vec<Multiaddr>) -> Result<(), String> {
+    let mut listening = 0;
+    for addr in addrs {
+        match client.start_listening(addr.clone()).await {
+            Ok(()) => listening += 1,
+            Err(e) => tracing::warn!("Failed to listen on {}: {}", addr, e),
+        }
+    }
+    if listening == 0 {
+        tracing::error!("Failed to listen on any address");
+        return Err(
+            "Could not start listening on the specified address. The port might be in use."
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+/// The addresses the host published under `code`, the one most likely reachable from here
+/// first.
+async fn resolve_share_code(
+    client: &dyn NetworkClient,
+    code: String,
+    app: &Arc<Mutex<App>>,
+) -> Result<Vec<Multiaddr>, String> {
+    app.lock()
+        .notify(Severity::Info, format!("Looking up the share code {code}"));
+    let addresses = client.resolve_code(code).await.map_err(|e| e.explain())?;
+    tracing::info!("Share code resolved to {addresses:?}");
+    if addresses.is_empty() {
+        return Err("No share found under this code, check it with the host".to_string());
+    }
+    Ok(addresses)
+}
+
+/// Show the address input box and wait until the user entered a valid address.
+async fn ask_peer_address(app: &Arc<Mutex<App>>) -> Option<Multiaddr> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    {
+        let mut app = app.lock();
+        app.address_input = Some(String::new());
+        app.address_sender = Some(tx);
+        if let Some(refresh_sender) = app.refresh_sender() {
+            let _ = refresh_sender.try_send(());
+        }
+    }
+    rx.recv().await
+}
+
+/// Show what the host sent when we connected, unless it lists directories on demand.
+fn apply_first_listing(app: &mut App, display_response: DisplayResponse, host: PeerId) {
+    app.share_opens_at = share_opens_at(&display_response);
+    app.share_label.clone_from(&display_response.label);
+    app.current_path = PathBuf::new();
+    // Such hosts only send the directories entered, see `list_due_directories`
+    app.remote_listing.on_demand = app
+        .peer_greeting
+        .as_ref()
+        .is_some_and(|(peer_id, greeting)| *peer_id == host && greeting.supports("list"));
+    if !app.remote_listing.on_demand {
+        apply_shared_items(app, display_response.items);
+        tracing::info!("Initial directory items: {:?}", app.directory_items);
+        start_sync(app);
+    }
+}
+
+/// Show the whole listing published by a host that doesn't list directories on demand.
+/// Selected downloads the host no longer offers are dropped.
+pub fn apply_shared_items(app: &mut App, items: Vec<DirectoryItem>) {
+    app.apply_listing(PathBuf::new(), true, items);
+}
+
+/// When the host's scheduled share opens, if it hasn't yet.
+fn share_opens_at(display_response: &DisplayResponse) -> Option<SystemTime> {
+    display_response
+        .opens_at
+        .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Close the share once its time to live ran out, then quit.
+pub async fn expire_share(
+    client: Arc<dyn NetworkClient>,
+    expires_at: Instant,
+    app: Arc<Mutex<App>>,
+) {
+    tokio::time::sleep_until(expires_at.into()).await;
+    tracing::info!("Share expired");
+    if let Err(e) = client.close_share().await {
+        tracing::error!("Failed to close the share: {}", e);
+    }
+    app.lock().should_quit = true;
+}
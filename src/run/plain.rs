@@ -0,0 +1,189 @@
+//! The loop of `--plain`, printing what [`crate::cli::plain`] reports and running the
+//! commands typed on stdin.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::spawn;
+
+use super::actions::{begin_download, list_due_directories, request_download};
+use crate::app::App;
+use crate::cli::plain;
+use crate::config::Severity;
+use crate::recent::RecentChoice;
+use crate::service::utils::ConflictPolicy;
+
+/// The start screen for `--plain`: the recent shares and hosts numbered, one is picked by
+/// typing its number.
+pub fn pick_recent(choices: &[RecentChoice]) -> Option<RecentChoice> {
+    println!("Pick up where you left off:");
+    for (index, choice) in choices.iter().enumerate() {
+        println!("{} {}", index + 1, choice.describe());
+    }
+    println!("Type a number and press Enter, or just Enter to quit:");
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).ok()?;
+    let index = line.trim().parse::<usize>().ok()?;
+    choices.get(index.checked_sub(1)?).cloned()
+}
+
+/// `--plain`: print what changes as lines of text and run the commands typed on stdin,
+/// until quit. Without stdin, e.g. as a service, it only prints.
+pub fn plain_loop(app: &Arc<Mutex<App>>) {
+    let (sender, commands) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let quit = Arc::clone(app);
+    spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            quit.lock().should_quit = true;
+        }
+    });
+
+    println!("Type help for the commands");
+    let mut reporter = plain::Reporter::default();
+    let mut stdin_open = true;
+    loop {
+        list_due_directories(app);
+        {
+            let mut app = app.lock();
+            if app.should_quit {
+                break;
+            }
+            if app.warning_expired() {
+                app.clear_warning();
+            }
+            for line in reporter.lines(&app) {
+                println!("{line}");
+            }
+            // Printed once, so it doesn't hold back the messages after it
+            if app
+                .warning
+                .as_ref()
+                .is_some_and(|warning| warning.severity == Severity::Error)
+            {
+                app.clear_warning();
+            }
+        }
+        if !stdin_open {
+            std::thread::sleep(PLAIN_POLL);
+            continue;
+        }
+        match commands.recv_timeout(PLAIN_POLL) {
+            Ok(line) => run_plain_command(app, &line),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => stdin_open = false,
+        }
+    }
+}
+
+/// How often `--plain` looks for changes to print.
+const PLAIN_POLL: Duration = Duration::from_millis(100);
+
+fn run_plain_command(app_handle: &Arc<Mutex<App>>, line: &str) {
+    let mut app = app_handle.lock();
+    if app.address_input.is_some() {
+        app.address_input = Some(line.trim().to_string());
+        app.submit_address();
+        return;
+    }
+    if line.trim().is_empty() {
+        return;
+    }
+    let command = match plain::Command::parse(line) {
+        Ok(command) => command,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+    let select = |app: &mut App, name: &str, select: bool| {
+        let indices: Vec<usize> = if name == "*" {
+            (0..app.directory_items.len()).collect()
+        } else if let Some(index) = plain::find_item(app, name) {
+            vec![index]
+        } else {
+            println!("There is no {name} here, type ls for what is");
+            return;
+        };
+        for index in indices {
+            app.selected_index = Some(index);
+            if select {
+                app.select_item();
+            } else {
+                app.unselect_item();
+            }
+        }
+        println!(
+            "{} selected",
+            if app.is_host {
+                app.items_to_share.len()
+            } else {
+                app.items_to_download.len()
+            }
+        );
+    };
+    match command {
+        plain::Command::List => plain::listing(&app)
+            .iter()
+            .for_each(|line| println!("{line}")),
+        plain::Command::Open(name) => {
+            app.selected_index = plain::find_item(&app, &name);
+            if !app.enter_directory() {
+                println!("There is no directory {name} here, type ls for what is");
+            }
+        }
+        plain::Command::Back => app.go_up_previous_directory(),
+        plain::Command::Select(name) => select(&mut app, &name, true),
+        plain::Command::Unselect(name) => select(&mut app, &name, false),
+        plain::Command::Download if app.is_host && !app.is_connected() => {
+            println!("Nobody is connected yet, wait for a peer to connect first");
+        }
+        plain::Command::Download => request_download(&mut app),
+        plain::Command::Refresh if !app.is_host => app.refresh_remote_directory(),
+        plain::Command::Refresh => app.populate_directory_items(),
+        plain::Command::Status => plain::status(&app)
+            .iter()
+            .for_each(|line| println!("{line}")),
+        plain::Command::Answer(answer) => answer_plain_question(&mut app, &answer),
+        plain::Command::Help => println!("{}", plain::HELP),
+        plain::Command::Quit => app.should_quit = true,
+    }
+}
+
+/// Apply `answer` to whichever question [`plain::Reporter`] asked last.
+fn answer_plain_question(app: &mut App, answer: &str) {
+    let yes = matches!(answer, "y" | "yes");
+    if app.manifest_diff.is_some() {
+        if yes {
+            app.confirm_manifest_diff();
+        } else {
+            app.should_quit = true;
+        }
+    } else if let Some(prompt) = &mut app.conflict_prompt {
+        prompt.decide_all(match answer {
+            "overwrite" => ConflictPolicy::Overwrite,
+            "rename" => ConflictPolicy::Rename,
+            _ => ConflictPolicy::Skip,
+        });
+        app.resolve_conflicts();
+    } else if !app.sensitive_pending.is_empty() {
+        if yes {
+            app.confirm_sensitive_items();
+        } else {
+            app.unselect_sensitive_items();
+        }
+    } else if app.confirming_download {
+        app.confirming_download = false;
+        if yes {
+            begin_download(app);
+        }
+    } else {
+        println!("There is no question to answer");
+    }
+}
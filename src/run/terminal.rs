@@ -0,0 +1,450 @@
+//! The full-screen terminal UI: drawing it and acting on keys and the mouse.
+
+use arboard::Clipboard;
+use crossterm::{
+    cursor::Show,
+    event::{
+        poll, read, DisableMouseCapture, EnableMouseCapture, Event as CrosstermEvent, KeyCode,
+        KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use parking_lot::Mutex;
+use ratatui::{
+    layout::{Margin, Position, Rect},
+    prelude::CrosstermBackend,
+    Terminal,
+};
+use std::io::Stdout;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::actions::{
+    begin_download, control_transfer, list_due_directories, load_remote_preview,
+    preview_highlighted, request_download, TransferAction, REMOTE_PREVIEW_BYTES,
+};
+use crate::app::App;
+use crate::cli::theme::{Theme, ThemeConfig};
+use crate::cli::ui;
+use crate::config::Severity;
+use crate::keys::Action;
+use crate::recent::RecentChoice;
+use crate::service::utils::ConflictPolicy;
+
+/// Whether the UI holds the terminal in raw mode on the alternate screen.
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Whether the terminal has to be handed back, e.g. before a panic is reported.
+pub fn is_active() -> bool {
+    TERMINAL_ACTIVE.load(Ordering::SeqCst)
+}
+
+pub fn setup_terminal() -> Terminal<CrosstermBackend<Stdout>> {
+    // Setup terminal
+    let terminal = {
+        let backend = ratatui::backend::CrosstermBackend::new(std::io::stdout());
+        ratatui::Terminal::new(backend).expect("Failed to create terminal")
+    };
+
+    TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
+    enable_raw_mode().expect("Failed to enable raw mode");
+    execute!(std::io::stdout(), EnterAlternateScreen, EnableMouseCapture)
+        .expect("Failed to setup terminal");
+
+    terminal
+}
+
+pub fn cleanup_terminal() {
+    // Also runs from the panic hook, so failures are ignored rather than panicking again
+    TERMINAL_ACTIVE.store(false, Ordering::SeqCst);
+    let _ = disable_raw_mode();
+    let _ = execute!(
+        std::io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        Show
+    );
+}
+
+/// Show the start screen until a recent share or host was picked, `None` to quit.
+pub fn pick_recent(choices: &[RecentChoice]) -> Option<RecentChoice> {
+    let mut terminal = setup_terminal();
+    let mut selected = 0;
+    let theme = Theme::new(&ThemeConfig::default(), Theme::no_color());
+    let choice = loop {
+        terminal
+            .draw(|frame| ui::render_start_screen(frame, choices, selected, &theme))
+            .expect("Failed to draw");
+        let CrosstermEvent::Key(key) = read().expect("Failed to read event") else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char(c @ '1'..='9') => {
+                if let Some(choice) = choices.get(c as usize - '1' as usize) {
+                    break Some(choice.clone());
+                }
+            }
+            KeyCode::Down => selected = (selected + 1).min(choices.len() - 1),
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Enter => break choices.get(selected).cloned(),
+            KeyCode::Esc | KeyCode::Char('q') => break None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break None,
+            _ => {}
+        }
+    };
+    cleanup_terminal();
+    choice
+}
+
+pub fn render_loop(terminal: &mut Terminal<CrosstermBackend<Stdout>>, app: &Arc<Mutex<App>>) {
+    let mut last_click = None;
+    while !app.lock().should_quit {
+        // Check warning timer before rendering
+        {
+            let mut app = app.lock();
+            if app.warning_expired() {
+                app.clear_warning();
+                // Notify UI to refresh
+                if let Some(refresh_sender) = app.refresh_sender() {
+                    let _ = refresh_sender.try_send(());
+                }
+            }
+        }
+
+        let size = terminal.size().expect("Failed to read the terminal size");
+        let screen = Rect::new(0, 0, size.width, size.height);
+        let tree_area = ui::file_tree_area(screen, &app.lock());
+        app.lock()
+            .scroll_to_cursor(usize::from(tree_area.height.saturating_sub(2)));
+        preview_highlighted(app);
+        list_due_directories(app);
+        terminal
+            .draw(|frame| ui::render(frame, &app.lock()))
+            .expect("Failed to draw");
+
+        if !poll(Duration::from_millis(16)).expect("Failed to poll events") {
+            continue;
+        }
+        match read().expect("Failed to read event") {
+            CrosstermEvent::Mouse(mouse) => {
+                handle_mouse(&mut app.lock(), mouse, tree_area, &mut last_click);
+            }
+            CrosstermEvent::Key(key)
+                if key.kind == KeyEventKind::Press
+                    && handle_key(&mut app.lock(), app, key).is_break() =>
+            {
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Act on a key press, `Break` quits.
+fn handle_key(app: &mut App, app_handle: &Arc<Mutex<App>>, key: KeyEvent) -> ControlFlow<()> {
+    // Errors stay until dismissed, the key press does nothing else
+    if app
+        .warning
+        .as_ref()
+        .is_some_and(|warning| warning.severity == Severity::Error)
+    {
+        app.clear_warning();
+        return ControlFlow::Continue(());
+    }
+    if let Some(flow) = handle_modal_key(app, key) {
+        return flow;
+    }
+    if app.addresses_focused && handle_addresses_key(app, key.code) {
+        return ControlFlow::Continue(());
+    }
+    if app.transfers_focused && handle_transfers_key(app, app_handle, key) {
+        return ControlFlow::Continue(());
+    }
+    if is_ctrl_c(key) {
+        return ControlFlow::Break(());
+    }
+    if key.code == KeyCode::Esc {
+        if app.show_peer_info {
+            app.show_peer_info = false;
+        } else if !app.search_query.is_empty() {
+            app.clear_search();
+        } else {
+            return ControlFlow::Break(());
+        }
+        return ControlFlow::Continue(());
+    }
+    if let Some(action) = app.keys.action(key.code) {
+        handle_action(app, app_handle, action);
+    }
+    ControlFlow::Continue(())
+}
+
+fn is_ctrl_c(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+/// Keys for whichever input, question or mode is open, it takes them all. `None` if none is.
+fn handle_modal_key(app: &mut App, key: KeyEvent) -> Option<ControlFlow<()>> {
+    if app.search_active {
+        handle_search_key(app, key.code);
+    } else if app.address_input.is_some() {
+        return Some(handle_address_key(app, key));
+    } else if app.manifest_diff.is_some() {
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Enter => app.confirm_manifest_diff(),
+            KeyCode::Char('n') | KeyCode::Esc => app.should_quit = true,
+            _ => {}
+        }
+    } else if app.conflict_prompt.is_some() {
+        handle_conflict_key(app, key.code);
+    } else if !app.sensitive_pending.is_empty() {
+        match key.code {
+            KeyCode::Char('y') => app.confirm_sensitive_items(),
+            KeyCode::Char('n') | KeyCode::Esc => app.unselect_sensitive_items(),
+            _ => {}
+        }
+    } else if let Some(index) = app.bookmark_picker {
+        match key.code {
+            KeyCode::Down => app.navigate_bookmarks(true),
+            KeyCode::Up => app.navigate_bookmarks(false),
+            KeyCode::Enter => app.jump_to_bookmark(index),
+            KeyCode::Char(c @ '1'..='9') => app.jump_to_bookmark(c as usize - '1' as usize),
+            KeyCode::Esc | KeyCode::Char('b') => app.bookmark_picker = None,
+            _ => {}
+        }
+    } else if app.confirming_download {
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Enter => {
+                app.confirming_download = false;
+                begin_download(app);
+            }
+            KeyCode::Char('n') | KeyCode::Esc => app.confirming_download = false,
+            _ => {}
+        }
+    } else if app.selection_anchor.is_some() {
+        return Some(handle_visual_key(app, key));
+    } else {
+        return None;
+    }
+    Some(ControlFlow::Continue(()))
+}
+
+fn handle_search_key(app: &mut App, code: KeyCode) {
+    match code {
+        KeyCode::Esc => app.clear_search(),
+        KeyCode::Enter => app.search_active = false,
+        KeyCode::Backspace => app.pop_search_char(),
+        KeyCode::Down => app.navigate(true, Instant::now()),
+        KeyCode::Up => app.navigate(false, Instant::now()),
+        KeyCode::Char(c) => app.push_search_char(c),
+        _ => {}
+    }
+}
+
+fn handle_address_key(app: &mut App, key: KeyEvent) -> ControlFlow<()> {
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            return ControlFlow::Break(());
+        }
+        KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.paste_address();
+        }
+        // A bare 'v' can be part of an address, e.g. quic-v1
+        KeyCode::Char('v') if app.address_input.as_deref() == Some("") => app.paste_address(),
+        KeyCode::Char(c) => app.address_input.get_or_insert_default().push(c),
+        KeyCode::Backspace => {
+            app.address_input.get_or_insert_default().pop();
+        }
+        KeyCode::Enter => app.submit_address(),
+        KeyCode::Esc => return ControlFlow::Break(()),
+        _ => {}
+    }
+    ControlFlow::Continue(())
+}
+
+fn handle_conflict_key(app: &mut App, code: KeyCode) {
+    let Some(prompt) = &mut app.conflict_prompt else {
+        return;
+    };
+    match code {
+        KeyCode::Down => prompt.navigate(true),
+        KeyCode::Up => prompt.navigate(false),
+        KeyCode::Char(' ') | KeyCode::Tab => prompt.cycle(),
+        KeyCode::Enter => app.resolve_conflicts(),
+        KeyCode::Char(c @ ('o' | 's' | 'r')) => {
+            prompt.decide_all(match c {
+                'o' => ConflictPolicy::Overwrite,
+                's' => ConflictPolicy::Skip,
+                _ => ConflictPolicy::Rename,
+            });
+            app.resolve_conflicts();
+        }
+        KeyCode::Esc => {
+            prompt.decide_all(ConflictPolicy::Skip);
+            app.resolve_conflicts();
+        }
+        _ => {}
+    }
+}
+
+/// Visual mode only moves the end of the range and applies it to the rows.
+fn handle_visual_key(app: &mut App, key: KeyEvent) -> ControlFlow<()> {
+    match key.code {
+        KeyCode::Esc => app.toggle_visual(),
+        _ if is_ctrl_c(key) => return ControlFlow::Break(()),
+        code => match app.keys.action(code) {
+            Some(Action::Down) => app.navigate(true, Instant::now()),
+            Some(Action::Up) => app.navigate(false, Instant::now()),
+            Some(Action::Select) => app.select_item(),
+            Some(Action::Unselect) => app.unselect_item(),
+            Some(Action::Visual) => app.toggle_visual(),
+            _ => {}
+        },
+    }
+    ControlFlow::Continue(())
+}
+
+/// Keys for the focused address list, whether it took the key.
+fn handle_addresses_key(app: &mut App, code: KeyCode) -> bool {
+    match (code, app.keys.action(code)) {
+        (_, Some(Action::Down)) => app.navigate_addresses(true),
+        (_, Some(Action::Up)) => app.navigate_addresses(false),
+        (_, Some(Action::CopyAddress | Action::Open)) => copy_selected_address(app),
+        (_, Some(Action::Focus)) => app.cycle_focus(),
+        (KeyCode::Esc, _) => app.addresses_focused = false,
+        _ => return false,
+    }
+    true
+}
+
+/// Keys for the focused transfer list, whether it took the key.
+fn handle_transfers_key(app: &mut App, app_handle: &Arc<Mutex<App>>, key: KeyEvent) -> bool {
+    match (key.code, app.keys.action(key.code)) {
+        (_, Some(Action::Down)) => app.navigate_transfers(true),
+        (_, Some(Action::Up)) => app.navigate_transfers(false),
+        (KeyCode::Char('p'), _) => {
+            control_transfer(app, Arc::clone(app_handle), TransferAction::TogglePause);
+        }
+        (KeyCode::Char('c'), _) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+            control_transfer(app, Arc::clone(app_handle), TransferAction::Cancel);
+        }
+        (_, Some(Action::Focus)) => app.cycle_focus(),
+        (KeyCode::Esc, _) => app.transfers_focused = false,
+        _ => return false,
+    }
+    true
+}
+
+/// What a key bound to `action` does on the file list.
+fn handle_action(app: &mut App, app_handle: &Arc<Mutex<App>>, action: Action) {
+    match action {
+        Action::CopyAddress => copy_selected_address(app),
+        Action::Disconnect => {
+            app.disconnect();
+        }
+        Action::Preview if !app.is_host => {
+            load_remote_preview(app, Arc::clone(app_handle), REMOTE_PREVIEW_BYTES);
+        }
+        Action::Refresh if !app.is_host => app.refresh_remote_directory(),
+        Action::UnselectAll => {
+            app.unselect_all();
+        }
+        Action::Bookmarks if app.is_host => app.open_bookmarks(),
+        Action::Transfers => app.toggle_transfers(),
+        Action::Focus => app.cycle_focus(),
+        Action::PeerInfo => app.show_peer_info = !app.show_peer_info,
+        Action::Sort => app.cycle_sort(),
+        Action::Hidden => app.toggle_hidden(),
+        Action::Search => app.start_search(),
+        Action::Down => app.navigate(true, Instant::now()),
+        Action::Up => app.navigate(false, Instant::now()),
+        Action::Open => {
+            app.enter_directory();
+        }
+        Action::Back => app.go_up_previous_directory(),
+        Action::Select => app.select_item(),
+        Action::Unselect => app.unselect_item(),
+        Action::Visual => app.toggle_visual(),
+        Action::Download => request_download(app),
+        _ => {}
+    }
+}
+
+/// Clicks on the same row closer together than this count as a double-click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Clicks and the scroll wheel on the file list, `tree_area` with its border. A click moves
+/// the cursor, a double-click enters a directory, a right-click or a click on the selection
+/// marker selects or unselects the row. Popups take no mouse input.
+fn handle_mouse(
+    app: &mut App,
+    mouse: MouseEvent,
+    tree_area: Rect,
+    last_click: &mut Option<(Instant, usize)>,
+) {
+    if app.is_loading
+        || app.is_warning()
+        || app.address_input.is_some()
+        || app.conflict_prompt.is_some()
+        || app.manifest_diff.is_some()
+        || !app.sensitive_pending.is_empty()
+        || app.bookmark_picker.is_some()
+        || app.confirming_download
+    {
+        return;
+    }
+    let inner = tree_area.inner(Margin::new(1, 1));
+    if !inner.contains(Position::new(mouse.column, mouse.row)) {
+        return;
+    }
+    match mouse.kind {
+        MouseEventKind::ScrollDown => app.navigate_next_file(),
+        MouseEventKind::ScrollUp => app.navigate_previous_file(),
+        MouseEventKind::Down(button) => {
+            let Some(index) = app.item_at_row(usize::from(mouse.row - inner.y)) else {
+                return;
+            };
+            app.selected_index = Some(index);
+            let column = usize::from(mouse.column - inner.x);
+            // The marker follows the indentation of nested items
+            let marker = app.directory_items[index].depth * 2;
+            match button {
+                MouseButton::Right => app.toggle_selection(),
+                MouseButton::Left if (marker..marker + 3).contains(&column) => {
+                    app.toggle_selection();
+                }
+                MouseButton::Left => {
+                    let now = Instant::now();
+                    let double = last_click.is_some_and(|(at, clicked)| {
+                        clicked == index && now.duration_since(at) < DOUBLE_CLICK
+                    });
+                    if double {
+                        app.enter_directory();
+                        *last_click = None;
+                    } else {
+                        *last_click = Some((now, index));
+                    }
+                }
+                MouseButton::Middle => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Copy the highlighted listening address, the first one unless another was picked.
+fn copy_selected_address(app: &mut App) {
+    let Some(address) = app.selected_address() else {
+        return;
+    };
+    match Clipboard::new().and_then(|mut clipboard| clipboard.set_text(address)) {
+        Ok(()) => app.copied_address(),
+        Err(e) => tracing::error!("Failed to copy address to clipboard: {}", e),
+    }
+}
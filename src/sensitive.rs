@@ -0,0 +1,59 @@
+use std::path::Path;
+
+/// Names that usually hold secrets, matched against every component of a path so whole
+/// directories such as `.ssh` or a browser profile are covered too.
+pub const DEFAULT_PATTERNS: [&str; 22] = [
+    ".env",
+    ".env.*",
+    "id_rsa*",
+    "id_dsa*",
+    "id_ecdsa*",
+    "id_ed25519*",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "*.keychain",
+    "*.keychain-db",
+    ".ssh",
+    ".gnupg",
+    ".aws",
+    ".netrc",
+    ".git-credentials",
+    ".mozilla",
+    "Cookies",
+    "Login Data",
+    "logins.json",
+    "key4.db",
+];
+
+/// Whether any component of `path` matches one of the patterns.
+#[must_use]
+pub fn is_sensitive(path: &Path, patterns: &[String]) -> bool {
+    path.components().any(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        patterns.iter().any(|pattern| glob_match(pattern, &name))
+    })
+}
+
+/// Match `name` against a pattern where `*` stands for any run of characters.
+#[must_use]
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
@@ -0,0 +1,311 @@
+//! Shared directories sent as a single tar stream, see `download --archive`. A tree of
+//! thousands of small files otherwise costs a stream and a manifest entry for each.
+//!
+//! The host writes the archive while it reads the files, nothing is staged on disk. The
+//! size is known from the file sizes up front, so the archive travels like any other file:
+//! behind the usual response header, zstd compressed when the downloader offered it. The
+//! downloader gzips it while writing if it asked for `tar.gz`.
+
+use async_compression::futures::write::ZstdEncoder;
+use futures::io::{AsyncWrite, AsyncWriteExt};
+use libp2p::PeerId;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tar::{Builder, EntryType, Header};
+use tokio::io::AsyncReadExt;
+
+use super::error::JunkanooError;
+use super::fairness::FairScheduler;
+use super::limiter::RateLimiter;
+use super::protocol::{
+    write_response, FileAttributes, ResponseHeader, COMPRESSION_NONE, COMPRESSION_ZSTD,
+};
+use super::utils::{escaping_link, ProgressCallback, CHUNK_SIZE};
+
+/// Tar works in blocks of this many bytes, file contents are padded to a multiple.
+const BLOCK: usize = 512;
+
+/// Two empty blocks end an archive.
+const END: [u8; 2 * BLOCK] = [0; 2 * BLOCK];
+
+/// How a downloaded archive is stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    #[default]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// Parse `tar` or `tar.gz`, also `tgz`.
+///
+/// # Errors
+///
+/// If `input` names no format.
+pub fn parse_format(input: &str) -> Result<ArchiveFormat, String> {
+    match input.to_ascii_lowercase().as_str() {
+        "tar" => Ok(ArchiveFormat::Tar),
+        "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+        _ => Err(format!(
+            "unknown archive format '{input}', use tar or tar.gz"
+        )),
+    }
+}
+
+/// Something to put into an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveSource {
+    /// Path inside the archive, components separated by `/`.
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Where the link points to, for symlinks shared as links.
+    pub link_target: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The header blocks, with long name entries before them.
+    header: Vec<u8>,
+    /// The file whose contents follow, `None` for directories and links.
+    source: Option<PathBuf>,
+    size: u64,
+}
+
+/// A tar archive to be written, with the metadata read when it was put together.
+#[derive(Debug)]
+pub struct Archive {
+    entries: Vec<Entry>,
+    size: u64,
+}
+
+impl Archive {
+    /// Read the metadata of `sources`, in the order given. Their sizes are what the
+    /// archive is announced with, so this blocks until all of them were read. Links
+    /// pointing out of the archived directory are left out, like a download refuses them.
+    ///
+    /// # Errors
+    ///
+    /// If the metadata of a source can't be read.
+    pub fn new(sources: &[ArchiveSource]) -> io::Result<Self> {
+        // Only writes the headers, with GNU long name entries before those that need them
+        let mut builder = Builder::new(Vec::new());
+        let mut entries = Vec::with_capacity(sources.len());
+        for source in sources {
+            if let Some(target) = &source.link_target {
+                // The first component of every name is the archived directory
+                let inside: PathBuf = Path::new(&source.name).components().skip(1).collect();
+                if let Some(reason) = escaping_link(&inside, target) {
+                    tracing::warn!("Leaving {:?} out of the archive, {}", source.path, reason);
+                    continue;
+                }
+            }
+            let metadata = if source.link_target.is_some() {
+                std::fs::symlink_metadata(&source.path)?
+            } else {
+                std::fs::metadata(&source.path)?
+            };
+            let attributes = FileAttributes::from_metadata(&metadata);
+            let (kind, size) = match &source.link_target {
+                Some(_) => (EntryType::Symlink, 0),
+                None if source.is_dir => (EntryType::Directory, 0),
+                None => (EntryType::Regular, metadata.len()),
+            };
+            let mut header = Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(size);
+            header.set_mode(attributes.mode);
+            header.set_mtime(
+                attributes
+                    .modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |since_epoch| since_epoch.as_secs()),
+            );
+            match &source.link_target {
+                Some(target) => builder.append_link(&mut header, &source.name, target)?,
+                None => builder.append_data(&mut header, &source.name, io::empty())?,
+            }
+            entries.push(Entry {
+                header: std::mem::take(builder.get_mut()),
+                source: (kind == EntryType::Regular).then(|| source.path.clone()),
+                size,
+            });
+        }
+        let size = entries
+            .iter()
+            .map(|entry| entry.header.len() as u64 + padded(entry.size))
+            .sum::<u64>()
+            + END.len() as u64;
+        Ok(Self { entries, size })
+    }
+
+    /// Files whose contents are in the archive.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.source.as_ref())
+    }
+}
+
+/// `size` rounded up to whole blocks.
+const fn padded(size: u64) -> u64 {
+    size.div_ceil(BLOCK as u64) * BLOCK as u64
+}
+
+/// Sends an [`Archive`] in answer to an archive request, like
+/// [`FileTransfer`](super::utils::FileTransfer) sends a file.
+pub struct ArchiveTransfer {
+    /// The path the archive is sent under, e.g. `photos.tar`.
+    path: String,
+    archive: Archive,
+    rate_limit: Option<Arc<RateLimiter>>,
+    fair_share: Option<(Arc<FairScheduler>, PeerId)>,
+    compression: bool,
+    sent: AtomicU64,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl ArchiveTransfer {
+    #[must_use]
+    pub fn new(path: String, archive: Archive) -> Self {
+        Self {
+            path,
+            archive,
+            rate_limit: None,
+            fair_share: None,
+            compression: false,
+            sent: AtomicU64::new(0),
+            on_progress: None,
+        }
+    }
+
+    /// Throttle the upload with a limiter shared across all outgoing transfers.
+    #[must_use]
+    pub fn with_rate_limit(mut self, rate_limit: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Send each chunk in `peer`'s turn, shared fairly with uploads to other peers.
+    #[must_use]
+    pub fn with_fair_share(mut self, scheduler: Arc<FairScheduler>, peer: PeerId) -> Self {
+        self.fair_share = Some((scheduler, peer));
+        self
+    }
+
+    /// Compress the body with zstd, if the peer supports it.
+    #[must_use]
+    pub const fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Report progress after every chunk sent.
+    #[must_use]
+    pub fn with_progress(mut self, on_progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Bytes of the archive sent so far, before compression.
+    pub fn bytes_sent(&self) -> u64 {
+        self.sent.load(Ordering::SeqCst)
+    }
+
+    /// Write the archive to `stream`, reading the files as it goes.
+    ///
+    /// # Errors
+    ///
+    /// If a file can't be read or the stream breaks.
+    pub async fn stream_archive<S>(&self, stream: &mut S) -> Result<(), JunkanooError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let header = ResponseHeader {
+            path: self.path.clone(),
+            size: usize::try_from(self.archive.size)?,
+            compression: if self.compression {
+                COMPRESSION_ZSTD
+            } else {
+                COMPRESSION_NONE
+            },
+            attributes: None,
+        };
+        write_response(stream, &header).await?;
+        if self.compression {
+            let mut encoder = ZstdEncoder::new(&mut *stream);
+            self.write_body(&mut encoder).await?;
+            // Closing writes the end of the zstd frame
+            encoder.close().await?;
+            return Ok(());
+        }
+        self.write_body(stream).await?;
+        stream.flush().await.map_err(JunkanooError::from)
+    }
+
+    async fn write_body<W>(&self, writer: &mut W) -> Result<(), JunkanooError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        for entry in &self.archive.entries {
+            self.send(writer, &entry.header).await?;
+            let Some(source) = &entry.source else {
+                continue;
+            };
+            let mut file = tokio::fs::File::open(source).await?.take(entry.size);
+            let mut read = 0;
+            loop {
+                let bytes_read = file.read(&mut buffer).await?;
+                if bytes_read == 0 {
+                    break;
+                }
+                self.send(writer, &buffer[..bytes_read]).await?;
+                read += bytes_read as u64;
+            }
+            // The size went out in the header, a file that changed since can't be sent
+            if read != entry.size || tokio::fs::metadata(source).await?.len() != entry.size {
+                tracing::warn!("{:?} changed while it was being archived", source);
+                return Err(JunkanooError::SourceChanged);
+            }
+            #[allow(clippy::cast_possible_truncation)]
+            let padding = (padded(entry.size) - entry.size) as usize;
+            self.send(writer, &END[..padding]).await?;
+        }
+        self.send(writer, &END).await
+    }
+
+    async fn send<W>(&self, writer: &mut W, bytes: &[u8]) -> Result<(), JunkanooError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let _turn = match &self.fair_share {
+            Some((scheduler, peer)) => Some(scheduler.turn(*peer, bytes.len()).await),
+            None => None,
+        };
+        if let Some(limiter) = &self.rate_limit {
+            limiter.acquire(bytes.len()).await;
+        }
+        writer.write_all(bytes).await?;
+        let sent = self.sent.fetch_add(bytes.len() as u64, Ordering::SeqCst) + bytes.len() as u64;
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(sent, self.archive.size);
+        }
+        Ok(())
+    }
+}
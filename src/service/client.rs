@@ -0,0 +1,371 @@
+//! What the application asks of the network, behind a trait so the flows using it can run
+//! against [`MockClient`] in tests instead of real swarms. [`Client`] is the real thing.
+
+use futures::future::BoxFuture;
+use libp2p::{Multiaddr, PeerId};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use super::archive::ArchiveFormat;
+use super::error::JunkanooError;
+use super::greeting::Welcome;
+use super::node::{Client, RequestedFile};
+use super::protocol::{DisplayResponse, ListDirectoryResponse, PushResponse};
+use super::secret::Secret;
+use super::utils::ConflictPolicy;
+use crate::app::DirectoryItem;
+
+type Reply<T> = BoxFuture<'static, Result<T, JunkanooError>>;
+
+/// The requests of [`Client`], see there for what each does. Replies don't borrow the
+/// client, so they can be awaited from spawned tasks.
+pub trait NetworkClient: Send + Sync {
+    fn start_listening(&self, addr: Multiaddr) -> Reply<()>;
+    fn get_listening_addrs(&self) -> Reply<Vec<Multiaddr>>;
+    fn dial(&self, peer_id: PeerId, peer_addr: Multiaddr) -> Reply<()>;
+    fn close_share(&self) -> Reply<()>;
+    fn shutdown(&self) -> Reply<()>;
+    fn disconnect(&self, peer_id: PeerId) -> Reply<()>;
+    fn pause_transfer(&self, path: String, paused: bool) -> Reply<()>;
+    fn cancel_transfer(&self, path: String) -> Reply<()>;
+    fn resolve_conflicts(&self, decisions: Vec<ConflictPolicy>) -> Reply<()>;
+    fn greet(&self, peer_id: PeerId, password: Option<Secret>) -> Reply<Welcome>;
+    fn push(&self, peer_id: PeerId, paths: Vec<String>) -> Reply<PushResponse>;
+    fn request_directory(&self, peer_id: PeerId) -> Reply<DisplayResponse>;
+    fn list_directory(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        recursive: bool,
+    ) -> Reply<ListDirectoryResponse>;
+    fn resolve_code(&self, code: String) -> Reply<Vec<Multiaddr>>;
+    fn update_directory_items(&self, directory_items: Vec<DirectoryItem>) -> Reply<()>;
+    fn request_file_range(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        range: Range<u64>,
+    ) -> Reply<Vec<u8>>;
+    fn request_files(
+        &self,
+        peer_id: PeerId,
+        files: Vec<RequestedFile>,
+        directory: Option<PathBuf>,
+        delta: bool,
+    ) -> Reply<Vec<u8>>;
+    fn request_archive(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        directory: Option<PathBuf>,
+        format: ArchiveFormat,
+    ) -> Reply<()>;
+}
+
+impl NetworkClient for Client {
+    fn start_listening(&self, addr: Multiaddr) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::start_listening(&mut client, addr).await })
+    }
+
+    fn get_listening_addrs(&self) -> Reply<Vec<Multiaddr>> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::get_listening_addrs(&mut client).await })
+    }
+
+    fn dial(&self, peer_id: PeerId, peer_addr: Multiaddr) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::dial(&mut client, peer_id, peer_addr).await })
+    }
+
+    fn close_share(&self) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::close_share(&mut client).await })
+    }
+
+    fn shutdown(&self) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::shutdown(&mut client).await })
+    }
+
+    fn disconnect(&self, peer_id: PeerId) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::disconnect(&mut client, peer_id).await })
+    }
+
+    fn pause_transfer(&self, path: String, paused: bool) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::pause_transfer(&mut client, path, paused).await })
+    }
+
+    fn cancel_transfer(&self, path: String) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::cancel_transfer(&mut client, path).await })
+    }
+
+    fn resolve_conflicts(&self, decisions: Vec<ConflictPolicy>) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::resolve_conflicts(&mut client, decisions).await })
+    }
+
+    fn greet(&self, peer_id: PeerId, password: Option<Secret>) -> Reply<Welcome> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::greet(&mut client, peer_id, password).await })
+    }
+
+    fn push(&self, peer_id: PeerId, paths: Vec<String>) -> Reply<PushResponse> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::push(&mut client, peer_id, paths).await })
+    }
+
+    fn request_directory(&self, peer_id: PeerId) -> Reply<DisplayResponse> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::request_directory(&mut client, peer_id).await })
+    }
+
+    fn list_directory(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        recursive: bool,
+    ) -> Reply<ListDirectoryResponse> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::list_directory(&mut client, peer_id, path, recursive).await })
+    }
+
+    fn resolve_code(&self, code: String) -> Reply<Vec<Multiaddr>> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::resolve_code(&mut client, code).await })
+    }
+
+    fn update_directory_items(&self, directory_items: Vec<DirectoryItem>) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::update_directory_items(&mut client, directory_items).await })
+    }
+
+    fn request_file_range(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        range: Range<u64>,
+    ) -> Reply<Vec<u8>> {
+        let mut client = self.clone();
+        Box::pin(async move { Self::request_file_range(&mut client, peer_id, path, range).await })
+    }
+
+    fn request_files(
+        &self,
+        peer_id: PeerId,
+        files: Vec<RequestedFile>,
+        directory: Option<PathBuf>,
+        delta: bool,
+    ) -> Reply<Vec<u8>> {
+        let mut client = self.clone();
+        Box::pin(
+            async move { Self::request_files(&mut client, peer_id, files, directory, delta).await },
+        )
+    }
+
+    fn request_archive(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        directory: Option<PathBuf>,
+        format: ArchiveFormat,
+    ) -> Reply<()> {
+        let mut client = self.clone();
+        Box::pin(async move {
+            Self::request_archive(&mut client, peer_id, path, directory, format).await
+        })
+    }
+}
+
+/// A request made of a [`MockClient`], with its arguments.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    StartListening(Multiaddr),
+    GetListeningAddrs,
+    Dial(PeerId, Multiaddr),
+    CloseShare,
+    Shutdown,
+    Disconnect(PeerId),
+    PauseTransfer(String, bool),
+    CancelTransfer(String),
+    ResolveConflicts(Vec<ConflictPolicy>),
+    Greet(PeerId),
+    Push(PeerId, Vec<String>),
+    RequestDirectory(PeerId),
+    ListDirectory(PeerId, String, bool),
+    ResolveCode(String),
+    UpdateDirectoryItems(Vec<DirectoryItem>),
+    RequestFileRange(PeerId, String, Range<u64>),
+    RequestFiles(PeerId, Vec<String>, Option<PathBuf>, bool),
+    RequestArchive(PeerId, String, Option<PathBuf>, ArchiveFormat),
+}
+
+#[cfg(test)]
+impl Call {
+    /// Which request this is, what responses are scripted by.
+    fn request(&self) -> std::mem::Discriminant<Self> {
+        std::mem::discriminant(self)
+    }
+}
+
+/// A [`NetworkClient`] answering with scripted responses and remembering every call.
+///
+/// Responses come in the order they were scripted for each kind of request. Requests
+/// without a reply fail when nothing is scripted for them, the others succeed.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockClient {
+    calls: parking_lot::Mutex<Vec<Call>>,
+    #[allow(clippy::type_complexity)]
+    responses: parking_lot::Mutex<
+        std::collections::HashMap<
+            std::mem::Discriminant<Call>,
+            std::collections::VecDeque<Box<dyn std::any::Any + Send>>,
+        >,
+    >,
+}
+
+#[cfg(test)]
+impl MockClient {
+    /// Answer the next request like `call` with `response`, the arguments of `call` don't
+    /// matter.
+    pub fn respond<T: Send + 'static>(&self, call: &Call, response: Result<T, String>) {
+        self.responses
+            .lock()
+            .entry(call.request())
+            .or_default()
+            .push_back(Box::new(response));
+    }
+
+    /// The requests made so far.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().clone()
+    }
+
+    fn answer<T: Send + 'static>(&self, call: Call) -> Reply<T> {
+        let scripted = self
+            .responses
+            .lock()
+            .get_mut(&call.request())
+            .and_then(std::collections::VecDeque::pop_front);
+        let name = format!("{call:?}");
+        self.calls.lock().push(call);
+        let response = scripted.map_or_else(
+            || {
+                (Box::new(()) as Box<dyn std::any::Any>)
+                    .downcast::<T>()
+                    .map(|unit| *unit)
+                    .map_err(|_| format!("no response scripted for {name}"))
+            },
+            |response| {
+                response.downcast::<Result<T, String>>().map_or_else(
+                    |_| Err(format!("scripted the wrong response for {name}")),
+                    |response| *response,
+                )
+            },
+        );
+        Box::pin(async move { response.map_err(JunkanooError::Other) })
+    }
+}
+
+#[cfg(test)]
+impl NetworkClient for MockClient {
+    fn start_listening(&self, addr: Multiaddr) -> Reply<()> {
+        self.answer(Call::StartListening(addr))
+    }
+
+    fn get_listening_addrs(&self) -> Reply<Vec<Multiaddr>> {
+        self.answer(Call::GetListeningAddrs)
+    }
+
+    fn dial(&self, peer_id: PeerId, peer_addr: Multiaddr) -> Reply<()> {
+        self.answer(Call::Dial(peer_id, peer_addr))
+    }
+
+    fn close_share(&self) -> Reply<()> {
+        self.answer(Call::CloseShare)
+    }
+
+    fn shutdown(&self) -> Reply<()> {
+        self.answer(Call::Shutdown)
+    }
+
+    fn disconnect(&self, peer_id: PeerId) -> Reply<()> {
+        self.answer(Call::Disconnect(peer_id))
+    }
+
+    fn pause_transfer(&self, path: String, paused: bool) -> Reply<()> {
+        self.answer(Call::PauseTransfer(path, paused))
+    }
+
+    fn cancel_transfer(&self, path: String) -> Reply<()> {
+        self.answer(Call::CancelTransfer(path))
+    }
+
+    fn resolve_conflicts(&self, decisions: Vec<ConflictPolicy>) -> Reply<()> {
+        self.answer(Call::ResolveConflicts(decisions))
+    }
+
+    fn greet(&self, peer_id: PeerId, _password: Option<Secret>) -> Reply<Welcome> {
+        self.answer(Call::Greet(peer_id))
+    }
+
+    fn push(&self, peer_id: PeerId, paths: Vec<String>) -> Reply<PushResponse> {
+        self.answer(Call::Push(peer_id, paths))
+    }
+
+    fn request_directory(&self, peer_id: PeerId) -> Reply<DisplayResponse> {
+        self.answer(Call::RequestDirectory(peer_id))
+    }
+
+    fn list_directory(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        recursive: bool,
+    ) -> Reply<ListDirectoryResponse> {
+        self.answer(Call::ListDirectory(peer_id, path, recursive))
+    }
+
+    fn resolve_code(&self, code: String) -> Reply<Vec<Multiaddr>> {
+        self.answer(Call::ResolveCode(code))
+    }
+
+    fn update_directory_items(&self, directory_items: Vec<DirectoryItem>) -> Reply<()> {
+        self.answer(Call::UpdateDirectoryItems(directory_items))
+    }
+
+    fn request_file_range(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        range: Range<u64>,
+    ) -> Reply<Vec<u8>> {
+        self.answer(Call::RequestFileRange(peer_id, path, range))
+    }
+
+    fn request_files(
+        &self,
+        peer_id: PeerId,
+        files: Vec<RequestedFile>,
+        directory: Option<PathBuf>,
+        delta: bool,
+    ) -> Reply<Vec<u8>> {
+        let paths = files.into_iter().map(|file| file.path).collect();
+        self.answer(Call::RequestFiles(peer_id, paths, directory, delta))
+    }
+
+    fn request_archive(
+        &self,
+        peer_id: PeerId,
+        path: String,
+        directory: Option<PathBuf>,
+        format: ArchiveFormat,
+    ) -> Reply<()> {
+        self.answer(Call::RequestArchive(peer_id, path, directory, format))
+    }
+}
@@ -0,0 +1,304 @@
+//! rsync-style delta transfers: the downloader describes the blocks of the copy it already
+//! has, the host answers with the blocks to reuse and the bytes that changed.
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+
+use super::error::JunkanooError;
+
+/// Size of the blocks files are compared in.
+pub const BLOCK_SIZE: usize = 64 * 1024;
+/// Largest block size a host accepts from a downloader.
+const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+/// Most signatures sent for a file, enough for 64 GiB at the default block size. Blocks past
+/// this are simply sent again.
+const MAX_SIGNATURES: usize = 1 << 20;
+/// Changed bytes are sent in pieces of at most this size.
+const MAX_LITERAL: usize = 1024 * 1024;
+
+const OP_COPY: u8 = 0;
+const OP_DATA: u8 = 1;
+const OP_END: u8 = 2;
+
+/// Checksums of one block of the downloader's copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: [u8; 32],
+}
+
+/// One step of rebuilding the host's file from the downloader's copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Reuse this block of the downloader's copy.
+    Copy(u32),
+    /// Bytes the downloader doesn't have.
+    Data(Vec<u8>),
+}
+
+/// Adler-32 style checksum that slides over the data one byte at a time.
+#[derive(Debug, Default, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Self {
+        let mut rolling = Self::default();
+        for &byte in block {
+            rolling.push(byte);
+        }
+        rolling
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.a = self.a.wrapping_add(u32::from(byte));
+        self.b = self.b.wrapping_add(self.a);
+        self.len += 1;
+    }
+
+    /// Drop the first byte of the window.
+    fn pop(&mut self, byte: u8) {
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(u32::from(byte)));
+        self.a = self.a.wrapping_sub(u32::from(byte));
+        self.len -= 1;
+    }
+
+    const fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong_hash(block: &[u8]) -> [u8; 32] {
+    Sha256::digest(block).into()
+}
+
+/// Read until `buffer` is full or the reader is exhausted.
+fn read_block(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Signatures of the blocks of an existing copy, the last one may be shorter.
+///
+/// # Errors
+///
+/// If `reader` fails.
+pub fn signatures(reader: &mut impl Read, block_size: usize) -> io::Result<Vec<BlockSignature>> {
+    let mut block = vec![0u8; block_size];
+    let mut signatures = Vec::new();
+    while signatures.len() < MAX_SIGNATURES {
+        let read = read_block(reader, &mut block)?;
+        if read == 0 {
+            break;
+        }
+        signatures.push(BlockSignature {
+            weak: Rolling::new(&block[..read]).digest(),
+            strong: strong_hash(&block[..read]),
+        });
+        if read < block_size {
+            break;
+        }
+    }
+    Ok(signatures)
+}
+
+/// Describe `reader` in terms of the blocks in `signatures`, handing each step to `emit`.
+///
+/// # Errors
+///
+/// If `reader` or `emit` fails.
+///
+/// # Panics
+///
+/// Never, a byte is only taken from a window that holds one.
+pub fn compute_delta(
+    reader: &mut impl Read,
+    signatures: &[BlockSignature],
+    block_size: usize,
+    mut emit: impl FnMut(DeltaOp) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut by_weak: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (index, signature) in (0u32..).zip(signatures) {
+        by_weak.entry(signature.weak).or_default().push(index);
+    }
+
+    let mut bytes = io::BufReader::with_capacity(MAX_LITERAL, reader).bytes();
+    let mut window = VecDeque::with_capacity(block_size);
+    let mut literal = Vec::new();
+    let fill = |window: &mut VecDeque<u8>, bytes: &mut io::Bytes<_>| -> io::Result<Rolling> {
+        for byte in bytes.by_ref().take(block_size) {
+            window.push_back(byte?);
+        }
+        Ok(Rolling::new(window.make_contiguous()))
+    };
+    let mut rolling = fill(&mut window, &mut bytes)?;
+
+    while !window.is_empty() {
+        let matched = by_weak.get(&rolling.digest()).and_then(|candidates| {
+            let strong = strong_hash(window.make_contiguous());
+            candidates
+                .iter()
+                .find(|&&index| signatures[index as usize].strong == strong)
+        });
+        if let Some(&index) = matched {
+            if !literal.is_empty() {
+                emit(DeltaOp::Data(std::mem::take(&mut literal)))?;
+            }
+            emit(DeltaOp::Copy(index))?;
+            window.clear();
+            rolling = fill(&mut window, &mut bytes)?;
+            continue;
+        }
+
+        // No block starts here, the byte goes out as is and the window slides on
+        let window_full = window.len() == block_size;
+        let first = window.pop_front().expect("window is not empty");
+        rolling.pop(first);
+        literal.push(first);
+        if literal.len() >= MAX_LITERAL {
+            emit(DeltaOp::Data(std::mem::take(&mut literal)))?;
+        }
+        if window_full {
+            if let Some(byte) = bytes.next() {
+                let byte = byte?;
+                window.push_back(byte);
+                rolling.push(byte);
+            }
+        }
+    }
+    if !literal.is_empty() {
+        emit(DeltaOp::Data(literal))?;
+    }
+    Ok(())
+}
+
+/// Send the block size and the signatures of the downloader's copy.
+///
+/// # Errors
+///
+/// If the stream breaks.
+pub async fn write_signatures<S>(
+    stream: &mut S,
+    block_size: usize,
+    signatures: &[BlockSignature],
+) -> Result<(), JunkanooError>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut frame = Vec::with_capacity(8 + signatures.len() * 36);
+    let block_size = u32::try_from(block_size)?;
+    let count = u32::try_from(signatures.len())?;
+    frame.extend_from_slice(&block_size.to_le_bytes());
+    frame.extend_from_slice(&count.to_le_bytes());
+    for signature in signatures {
+        frame.extend_from_slice(&signature.weak.to_le_bytes());
+        frame.extend_from_slice(&signature.strong);
+    }
+    stream.write_all(&frame).await?;
+    stream.flush().await.map_err(JunkanooError::from)
+}
+
+/// Read what [`write_signatures`] sent.
+///
+/// # Errors
+///
+/// If the stream breaks or the signatures are malformed.
+pub async fn read_signatures<S>(
+    stream: &mut S,
+) -> Result<(usize, Vec<BlockSignature>), JunkanooError>
+where
+    S: AsyncRead + Unpin,
+{
+    let block_size = read_u32(stream).await? as usize;
+    let count = read_u32(stream).await? as usize;
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE || count > MAX_SIGNATURES {
+        return Err(JunkanooError::Protocol(format!(
+            "{count} signatures of {block_size} byte blocks"
+        )));
+    }
+    let mut signatures = Vec::with_capacity(count);
+    for _ in 0..count {
+        let weak = read_u32(stream).await?;
+        let mut strong = [0u8; 32];
+        stream.read_exact(&mut strong).await?;
+        signatures.push(BlockSignature { weak, strong });
+    }
+    Ok((block_size, signatures))
+}
+
+/// Write one step, `None` marks the end of the file.
+///
+/// # Errors
+///
+/// If the stream breaks.
+pub async fn write_op<S>(stream: &mut S, op: Option<&DeltaOp>) -> Result<(), JunkanooError>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut frame = Vec::new();
+    match op {
+        Some(DeltaOp::Copy(index)) => {
+            frame.push(OP_COPY);
+            frame.extend_from_slice(&index.to_le_bytes());
+        }
+        Some(DeltaOp::Data(bytes)) => {
+            let len = u32::try_from(bytes.len())?;
+            frame.push(OP_DATA);
+            frame.extend_from_slice(&len.to_le_bytes());
+            frame.extend_from_slice(bytes);
+        }
+        None => frame.push(OP_END),
+    }
+    stream.write_all(&frame).await.map_err(JunkanooError::from)
+}
+
+/// Read one step, `None` once the host reached the end of the file.
+///
+/// # Errors
+///
+/// If the stream breaks or the step is malformed.
+pub async fn read_op<S>(stream: &mut S) -> Result<Option<DeltaOp>, JunkanooError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut tag = [0u8; 1];
+    stream.read_exact(&mut tag).await?;
+    match tag[0] {
+        OP_COPY => Ok(Some(DeltaOp::Copy(read_u32(stream).await?))),
+        OP_DATA => {
+            let len = read_u32(stream).await? as usize;
+            if len > MAX_LITERAL {
+                return Err(JunkanooError::Protocol(format!(
+                    "{len} bytes of data at once"
+                )));
+            }
+            let mut bytes = vec![0u8; len];
+            stream.read_exact(&mut bytes).await?;
+            Ok(Some(DeltaOp::Data(bytes)))
+        }
+        OP_END => Ok(None),
+        other => Err(JunkanooError::Protocol(format!(
+            "unknown delta step {other}"
+        ))),
+    }
+}
+
+async fn read_u32<S>(stream: &mut S) -> Result<u32, JunkanooError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut bytes = [0u8; 4];
+    stream.read_exact(&mut bytes).await?;
+    Ok(u32::from_le_bytes(bytes))
+}
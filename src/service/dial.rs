@@ -0,0 +1,66 @@
+//! Reaching a host that may be known under several addresses, say a LAN, a public and a
+//! relayed one, of which only some work from here.
+
+use libp2p::{Multiaddr, PeerId};
+use std::time::Duration;
+
+use super::client::NetworkClient;
+use super::error::JunkanooError;
+use super::reconnect;
+
+/// How hard to try before giving up on a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DialPolicy {
+    /// How long one address may take to connect, an unreachable one otherwise only fails
+    /// once the transport gives up.
+    pub attempt_timeout: Duration,
+    /// How often to go through all addresses, waiting [`reconnect::backoff`] in between.
+    pub rounds: u32,
+}
+
+impl Default for DialPolicy {
+    fn default() -> Self {
+        Self {
+            attempt_timeout: Duration::from_secs(10),
+            rounds: 3,
+        }
+    }
+}
+
+/// Dial `addresses` of `peer_id` one after the other, in the order given, until one
+/// connects, and return that one. Fails with the error of the last attempt.
+///
+/// The addresses are tried in sequence rather than all at once so the host isn't left with
+/// several connections to us, and the first address listed is the one used when it works.
+///
+/// # Errors
+///
+/// If no address connects.
+pub async fn dial_any(
+    client: &dyn NetworkClient,
+    peer_id: PeerId,
+    addresses: &[Multiaddr],
+    policy: DialPolicy,
+) -> Result<Multiaddr, JunkanooError> {
+    let mut last_error = JunkanooError::Dial("no address to dial".to_string());
+    for round in 1..=policy.rounds {
+        if round > 1 {
+            tokio::time::sleep(reconnect::backoff(round - 1)).await;
+        }
+        for address in addresses {
+            let attempt = client.dial(peer_id, address.clone());
+            match tokio::time::timeout(policy.attempt_timeout, attempt).await {
+                Ok(Ok(())) => return Ok(address.clone()),
+                Ok(Err(e)) => {
+                    tracing::info!("Dialing {address} failed: {e}");
+                    last_error = e;
+                }
+                Err(_) => {
+                    tracing::info!("Dialing {address} timed out");
+                    last_error = JunkanooError::Timeout(format!("dialing {address}"));
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
@@ -0,0 +1,181 @@
+//! How talking to peers and moving files fails, so callers and the UI can tell a host that
+//! can't be reached from one that refused, and say what to do about it.
+
+use libp2p::request_response::OutboundFailure;
+use libp2p::swarm::DialError;
+use std::convert::Infallible;
+use std::io;
+
+use super::protocol::PROTOCOL_VERSION;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JunkanooError {
+    /// The peer couldn't be reached, or the connection to it went away.
+    #[error("Could not reach the peer: {0}")]
+    Dial(String),
+    /// The peer didn't answer in time.
+    #[error("The peer didn't answer in time: {0}")]
+    Timeout(String),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    /// The peer sent something this release doesn't understand.
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    /// The downloader speaks a newer [`PROTOCOL_VERSION`] than we do.
+    #[error("Unsupported protocol version {0}, this release speaks {PROTOCOL_VERSION}")]
+    UnsupportedVersion(u8),
+    #[error("Unsupported compression: {0}")]
+    UnsupportedCompression(u8),
+    /// The peer refused the request, e.g. for a file it doesn't share.
+    #[error("Request rejected by host: {0}")]
+    Rejected(String),
+    /// Stopped on request, by cancelling the transfer or shutting down.
+    #[error("transfer cancelled")]
+    Cancelled,
+    /// The shared file was modified or deleted while it was being sent.
+    #[error("source changed during transfer")]
+    SourceChanged,
+    /// A received file hashes to something else than the host published for it.
+    #[error("content differs from what the host published")]
+    HashMismatch,
+    /// The network task is gone, e.g. after shutting down.
+    #[error("The network stopped")]
+    Shutdown,
+    /// The network couldn't be set up, e.g. a transport or the identity.
+    #[error("Could not set up the network: {0}")]
+    Setup(String),
+    /// Anything else, described for the user.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl JunkanooError {
+    pub fn other(message: impl Into<String>) -> Self {
+        Self::Other(message.into())
+    }
+
+    /// What the user can do about it, where there is something.
+    #[must_use]
+    pub const fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Dial(_) => Some("Check the address and that the host is still sharing"),
+            Self::Timeout(_) => Some("The peer may be busy or on a slow network, try again"),
+            Self::Protocol(_) | Self::UnsupportedVersion(_) | Self::UnsupportedCompression(_) => {
+                Some("The peer runs another release of junkanoo, update both to the latest")
+            }
+            Self::SourceChanged => Some("The host changed the file meanwhile, download it again"),
+            Self::HashMismatch => Some("The file was damaged on the way, download it again"),
+            _ => None,
+        }
+    }
+
+    /// The message followed by the hint, for showing to the user.
+    #[must_use]
+    pub fn explain(&self) -> String {
+        self.hint()
+            .map_or_else(|| self.to_string(), |hint| format!("{self}. {hint}"))
+    }
+
+    /// Whether the connection to the peer went away, so it's worth trying again once the
+    /// peer is redialed.
+    #[must_use]
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Self::Dial(_) => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<std::string::FromUtf8Error> for JunkanooError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Self::Protocol(e.to_string())
+    }
+}
+
+impl From<std::num::TryFromIntError> for JunkanooError {
+    fn from(e: std::num::TryFromIntError) -> Self {
+        Self::Protocol(e.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for JunkanooError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Self::Other(e.to_string())
+    }
+}
+
+impl From<OutboundFailure> for JunkanooError {
+    fn from(failure: OutboundFailure) -> Self {
+        match failure {
+            OutboundFailure::DialFailure | OutboundFailure::ConnectionClosed => {
+                Self::Dial(failure.to_string())
+            }
+            OutboundFailure::Timeout => Self::Timeout(failure.to_string()),
+            OutboundFailure::UnsupportedProtocols => Self::Protocol(failure.to_string()),
+            OutboundFailure::Io(e) => Self::Io(e),
+        }
+    }
+}
+
+impl From<libp2p::noise::Error> for JunkanooError {
+    fn from(e: libp2p::noise::Error) -> Self {
+        Self::Setup(e.to_string())
+    }
+}
+
+#[cfg(feature = "webrtc")]
+impl From<libp2p_webrtc::tokio::certificate::Error> for JunkanooError {
+    fn from(e: libp2p_webrtc::tokio::certificate::Error) -> Self {
+        Self::Setup(e.to_string())
+    }
+}
+
+impl From<libp2p::multiaddr::Error> for JunkanooError {
+    fn from(e: libp2p::multiaddr::Error) -> Self {
+        Self::Setup(e.to_string())
+    }
+}
+
+impl From<Infallible> for JunkanooError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+impl From<DialError> for JunkanooError {
+    fn from(e: DialError) -> Self {
+        Self::Dial(e.to_string())
+    }
+}
+
+impl From<libp2p_stream::OpenStreamError> for JunkanooError {
+    fn from(e: libp2p_stream::OpenStreamError) -> Self {
+        match e {
+            libp2p_stream::OpenStreamError::UnsupportedProtocol(_) => Self::Protocol(e.to_string()),
+            // Streams only fail to open on a connection that is gone
+            _ => Self::Dial(e.to_string()),
+        }
+    }
+}
+
+impl From<futures::channel::mpsc::SendError> for JunkanooError {
+    fn from(_: futures::channel::mpsc::SendError) -> Self {
+        Self::Shutdown
+    }
+}
+
+impl From<futures::channel::oneshot::Canceled> for JunkanooError {
+    fn from(_: futures::channel::oneshot::Canceled) -> Self {
+        Self::Shutdown
+    }
+}
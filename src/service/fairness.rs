@@ -0,0 +1,248 @@
+//! Fair sharing of the upload among downloaders.
+//!
+//! Upload streams are admitted and their chunks sent in turns across peers, so one peer
+//! pulling a huge file, or many files at once, can't starve another pulling a few small
+//! documents.
+
+use libp2p::PeerId;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// Bytes a peer of weight 1 may send per round, one chunk of a file.
+pub const QUANTUM: usize = 1024 * 1024;
+/// Chunks written at the same time across all uploads.
+pub const CHUNKS_IN_FLIGHT: usize = 4;
+
+/// Weighted round-robin over the peers downloading from us.
+///
+/// Streams beyond `streams` wait for a slot, which goes to the waiting peer with the fewest
+/// running uploads. A peer with none is admitted right away, so a busy host never makes
+/// a new downloader wait for someone else's big file. Chunks take turns by deficit
+/// round-robin: each round a peer may send [`QUANTUM`] bytes times its weight.
+#[derive(Debug)]
+pub struct FairScheduler {
+    streams: usize,
+    weights: HashMap<PeerId, usize>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Running uploads by peer.
+    active: HashMap<PeerId, usize>,
+    admissions: VecDeque<(PeerId, oneshot::Sender<Admission>)>,
+    in_flight: usize,
+    /// Peers with chunks waiting, the first one's turn is now.
+    rounds: VecDeque<PeerId>,
+    queues: HashMap<PeerId, ChunkQueue>,
+}
+
+#[derive(Debug, Default)]
+struct ChunkQueue {
+    /// Bytes the peer may still send this round.
+    deficit: usize,
+    /// Whether this round's quantum was added to the deficit.
+    credited: bool,
+    waiting: VecDeque<(usize, oneshot::Sender<Turn>)>,
+}
+
+/// A running upload, the slot is freed when dropped.
+#[derive(Debug)]
+pub struct Admission {
+    scheduler: Arc<FairScheduler>,
+    peer: PeerId,
+}
+
+/// Permission to write one chunk, the next one is handed out when dropped.
+#[derive(Debug)]
+pub struct Turn {
+    scheduler: Arc<FairScheduler>,
+}
+
+/// Grants decided under the lock, handed out after it was released. A grant nobody waits
+/// for anymore is dropped, which frees it again.
+#[derive(Default)]
+struct Grants {
+    admissions: Vec<(oneshot::Sender<Admission>, Admission)>,
+    turns: Vec<(oneshot::Sender<Turn>, Turn)>,
+}
+
+impl Grants {
+    fn hand_out(self) {
+        for (sender, admission) in self.admissions {
+            let _ = sender.send(admission);
+        }
+        for (sender, turn) in self.turns {
+            let _ = sender.send(turn);
+        }
+    }
+}
+
+impl FairScheduler {
+    /// A scheduler running up to `streams` uploads, more only for peers without one.
+    /// `weights` are by peer ID, peers left out or given 0 have weight 1.
+    #[must_use]
+    pub fn new(streams: usize, weights: &BTreeMap<String, usize>) -> Self {
+        let weights = weights
+            .iter()
+            .filter_map(|(peer, &weight)| match PeerId::from_str(peer) {
+                Ok(peer) => Some((peer, weight.max(1))),
+                Err(e) => {
+                    tracing::warn!("Ignoring the upload weight of '{}': {}", peer, e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            streams,
+            weights,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn weight(&self, peer: &PeerId) -> usize {
+        self.weights.get(peer).copied().unwrap_or(1)
+    }
+
+    /// Wait until an upload to `peer` may start.
+    ///
+    /// # Panics
+    ///
+    /// If the scheduler is dropped while waiting.
+    pub async fn admit(self: &Arc<Self>, peer: PeerId) -> Admission {
+        let receiver = {
+            let mut state = self.state.lock();
+            let (sender, receiver) = oneshot::channel();
+            state.admissions.push_back((peer, sender));
+            let grants = self.dispatch(&mut state);
+            drop(state);
+            grants.hand_out();
+            receiver
+        };
+        receiver
+            .await
+            .expect("the scheduler to outlive its waiters")
+    }
+
+    /// Wait until `peer` may write a chunk of `bytes`.
+    ///
+    /// # Panics
+    ///
+    /// If the scheduler is dropped while waiting.
+    pub async fn turn(self: &Arc<Self>, peer: PeerId, bytes: usize) -> Turn {
+        let receiver = {
+            let mut state = self.state.lock();
+            let (sender, receiver) = oneshot::channel();
+            if !state.rounds.contains(&peer) {
+                state.rounds.push_back(peer);
+            }
+            state
+                .queues
+                .entry(peer)
+                .or_default()
+                .waiting
+                .push_back((bytes, sender));
+            let grants = self.dispatch(&mut state);
+            drop(state);
+            grants.hand_out();
+            receiver
+        };
+        receiver
+            .await
+            .expect("the scheduler to outlive its waiters")
+    }
+
+    /// Decide who goes next, as far as there are free slots.
+    fn dispatch(self: &Arc<Self>, state: &mut State) -> Grants {
+        let mut grants = Grants::default();
+
+        let running: usize = state.active.values().sum();
+        let mut free = self.streams.saturating_sub(running);
+        loop {
+            let fewest = state
+                .admissions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (peer, _))| state.active.get(peer).copied().unwrap_or(0))
+                .map(|(index, (peer, _))| (index, state.active.contains_key(peer)));
+            let index = match fewest {
+                Some((index, running)) if !running || free > 0 => index,
+                _ => break,
+            };
+            free = free.saturating_sub(1);
+            let Some((peer, sender)) = state.admissions.remove(index) else {
+                break;
+            };
+            *state.active.entry(peer).or_default() += 1;
+            let admission = Admission {
+                scheduler: Arc::clone(self),
+                peer,
+            };
+            grants.admissions.push((sender, admission));
+        }
+
+        while state.in_flight < CHUNKS_IN_FLIGHT {
+            let Some(&peer) = state.rounds.front() else {
+                break;
+            };
+            let weight = self.weight(&peer);
+            let queue = state.queues.entry(peer).or_default();
+            if !queue.credited {
+                queue.deficit += QUANTUM * weight;
+                queue.credited = true;
+            }
+            match queue.waiting.front() {
+                Some(&(bytes, _)) if bytes <= queue.deficit => {
+                    queue.deficit -= bytes;
+                    let (_, sender) = queue.waiting.pop_front().expect("a waiting chunk");
+                    state.in_flight += 1;
+                    let turn = Turn {
+                        scheduler: Arc::clone(self),
+                    };
+                    grants.turns.push((sender, turn));
+                }
+                Some(_) => {
+                    // Out of bytes for this round, the next peer's turn
+                    queue.credited = false;
+                    state.rounds.rotate_left(1);
+                }
+                None => {
+                    // Nothing left to send, unused bytes don't carry over
+                    state.queues.remove(&peer);
+                    state.rounds.pop_front();
+                }
+            }
+        }
+        grants
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let scheduler = &self.scheduler;
+        let mut state = scheduler.state.lock();
+        if let Some(running) = state.active.get_mut(&self.peer) {
+            *running -= 1;
+            if *running == 0 {
+                state.active.remove(&self.peer);
+            }
+        }
+        let grants = scheduler.dispatch(&mut state);
+        drop(state);
+        grants.hand_out();
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let scheduler = &self.scheduler;
+        let mut state = scheduler.state.lock();
+        state.in_flight -= 1;
+        let grants = scheduler.dispatch(&mut state);
+        drop(state);
+        grants.hand_out();
+    }
+}
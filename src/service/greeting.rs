@@ -0,0 +1,149 @@
+//! Application handshake: right after connecting, before asking for the listing, the
+//! downloader introduces itself and the host answers with what it shares and what it needs.
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+use super::protocol::{COMPRESSION_NONE, COMPRESSION_ZSTD, PROTOCOL_VERSION};
+use super::secret::Secret;
+
+/// Optional protocol features of this release. Names a peer doesn't know are ignored, a
+/// session only uses the features both sides offered, see [`Greeting::negotiate`].
+///
+/// - `zstd`: file bodies may be compressed
+/// - `delta`: only the blocks that differ from the downloader's copy are sent
+/// - `range`: parts of a file can be fetched, e.g. for previews
+/// - `resume`: interrupted downloads continue from the offset they reached
+/// - `hash`: the listing carries SHA-256 hashes to check downloads against
+/// - `manifest`: sizes and hashes of requested files are sent before their streams
+/// - `attributes`: file headers may carry permissions and the modification time
+/// - `push`: files can be offered to the host with `sync --push`
+/// - `list`: directories are listed one at a time as the downloader enters them
+/// - `updates`: the host pushes changes of its listing instead of waiting to be asked
+/// - `archive`: a shared directory can be fetched as one tar archive
+pub const FEATURES: [&str; 11] = [
+    "zstd",
+    "delta",
+    "range",
+    "resume",
+    "hash",
+    "manifest",
+    "attributes",
+    "push",
+    "list",
+    "updates",
+    "archive",
+];
+
+/// Protocol name advertised through libp2p identify, e.g. `junkanoo/1`.
+#[must_use]
+pub fn identify_protocol() -> String {
+    format!("junkanoo/{PROTOCOL_VERSION}")
+}
+
+/// What a peer says about itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Greeting {
+    /// Name to show instead of the peer ID.
+    pub display_name: Option<String>,
+    /// Name the host gave the share, always `None` for downloaders.
+    pub label: Option<String>,
+    pub features: Vec<String>,
+    pub auth: AuthRequirement,
+    /// [`PROTOCOL_VERSION`] of the peer, 0 for releases from before it was sent.
+    pub protocol_version: u8,
+}
+
+/// What a downloader has to prove before the host serves it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthRequirement {
+    #[default]
+    None,
+    Password,
+}
+
+/// The downloader's half of the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub greeting: Greeting,
+    pub password: Option<Secret>,
+}
+
+/// The host's half of the handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Welcome {
+    pub greeting: Greeting,
+    /// Whether the host serves the downloader, false after a missing or wrong password.
+    pub authorized: bool,
+}
+
+impl Greeting {
+    pub fn new(display_name: Option<String>, label: Option<String>, auth: AuthRequirement) -> Self {
+        Self {
+            display_name,
+            label,
+            features: FEATURES.map(String::from).to_vec(),
+            auth,
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Stop offering `feature`, e.g. `zstd` with `--no-compress`.
+    #[must_use]
+    pub fn without(mut self, feature: &str) -> Self {
+        self.features.retain(|offered| offered != feature);
+        self
+    }
+
+    /// The peer's greeting with only the features `ours` offered as well, what the session
+    /// with the peer uses. Older peers lack newer features, so both sides fall back to
+    /// what the older one speaks.
+    #[must_use]
+    pub fn negotiate(mut self, ours: &Self) -> Self {
+        self.features.retain(|feature| ours.supports(feature));
+        self
+    }
+
+    /// Whether the peer offered a feature from [`FEATURES`].
+    #[must_use]
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|offered| offered == feature)
+    }
+
+    /// Compression to ask for in file requests, zstd only if the peer offered it.
+    #[must_use]
+    pub fn compression(&self) -> u8 {
+        if self.supports("zstd") {
+            COMPRESSION_ZSTD
+        } else {
+            COMPRESSION_NONE
+        }
+    }
+
+    /// e.g. "Connected to Chad's laptop — share 'holiday-photos' (password required)".
+    #[must_use]
+    pub fn describe(&self, peer_id: &PeerId) -> String {
+        let mut description = self.display_name.as_ref().map_or_else(
+            || format!("Connected to {peer_id}"),
+            |name| format!("Connected to {name}"),
+        );
+        if let Some(label) = &self.label {
+            let _ = write!(description, " — share '{label}'");
+        }
+        if self.auth == AuthRequirement::Password {
+            description.push_str(" (password required)");
+        }
+        description
+    }
+}
+
+/// Name of this machine, what peers see unless a display name is configured.
+#[must_use]
+pub fn default_display_name() -> Option<String> {
+    gethostname::gethostname()
+        .into_string()
+        .ok()
+        .filter(|name| !name.is_empty())
+}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use walkdir::WalkDir;
+
+/// SHA-256 of a file's contents as lowercase hex.
+///
+/// # Errors
+///
+/// If the file can't be read.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    size: u64,
+    modified: Option<SystemTime>,
+    hash: String,
+}
+
+/// Hashes of shared files, only recomputed when a file's size or modification time
+/// changes.
+///
+/// The cache is kept between sessions per shared directory, which doubles as a record of
+/// what was shared from it last time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    entries: HashMap<PathBuf, CachedHash>,
+}
+
+/// What changed in a shared directory since the files it had were last shared, paths
+/// are relative to the directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+}
+
+impl ManifestDiff {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Where the hash cache of a shared directory is kept between sessions.
+#[must_use]
+pub fn history_path(root: &Path) -> Option<PathBuf> {
+    let mut hasher = Sha256::new();
+    hasher.update(root.to_string_lossy().as_bytes());
+    let name = hex(&hasher.finalize());
+    crate::config::state_dir().map(|dir| dir.join("hash-cache").join(format!("{name}.json")))
+}
+
+impl HashCache {
+    /// Load a cache saved by an earlier session, empty if there is none.
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Keep the cache for the next session.
+    ///
+    /// # Errors
+    ///
+    /// If `path` or its directory can't be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec(self)?)
+    }
+
+    /// Compare the files under `root` with the ones hashed in an earlier session. Empty
+    /// when nothing under `root` was shared before.
+    pub fn diff(&mut self, root: &Path) -> ManifestDiff {
+        let mut previous: Vec<PathBuf> = self
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(root))
+            .cloned()
+            .collect();
+        if previous.is_empty() {
+            return ManifestDiff::default();
+        }
+        previous.sort();
+
+        let current: HashSet<PathBuf> = WalkDir::new(root)
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.file_type().is_file())
+            .map(walkdir::DirEntry::into_path)
+            .collect();
+        let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+
+        let mut diff = ManifestDiff::default();
+        for path in previous {
+            let Some(metadata) = current
+                .contains(&path)
+                .then(|| std::fs::metadata(&path).ok())
+                .flatten()
+            else {
+                self.entries.remove(&path);
+                diff.removed.push(relative(&path));
+                continue;
+            };
+            let previous_hash = self.entries[&path].hash.clone();
+            let hash = self.hash(&path, metadata.len(), metadata.modified().ok());
+            if hash.as_ref() != Some(&previous_hash) {
+                diff.changed.push(relative(&path));
+            }
+        }
+        diff.added = current
+            .iter()
+            .filter(|path| !self.entries.contains_key(*path))
+            .map(|path| relative(path))
+            .collect();
+        diff.added.sort();
+        diff
+    }
+
+    pub fn hash(&mut self, path: &Path, size: u64, modified: Option<SystemTime>) -> Option<String> {
+        if let Some(cached) = self.entries.get(path) {
+            if cached.size == size && cached.modified == modified {
+                return Some(cached.hash.clone());
+            }
+        }
+
+        match hash_file(path) {
+            Ok(hash) => {
+                self.entries.insert(
+                    path.to_path_buf(),
+                    CachedHash {
+                        size,
+                        modified,
+                        hash: hash.clone(),
+                    },
+                );
+                Some(hash)
+            }
+            Err(e) => {
+                tracing::warn!("Cannot hash {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Identities derived from a BIP39 phrase with `--identity-seed`.
+//!
+//! The same phrase gives the same peer ID on every machine, so a share's address can be
+//! handed out before it starts. Without one every run has a new random identity.
+
+use bip39::Mnemonic;
+use libp2p::identity::Keypair;
+
+use super::secret::Secret;
+
+/// Keeps keys derived here apart from other uses of the same phrase, e.g. a wallet.
+const PASSPHRASE: &str = "junkanoo identity";
+
+/// Value parser for `--identity-seed`: a BIP39 phrase of 12 to 24 words. Freely chosen
+/// phrases are turned away, they are too easy to guess for a key anyone may use.
+///
+/// # Errors
+///
+/// If `value` is not a BIP39 phrase.
+pub fn parse_seed(value: &str) -> Result<Secret, String> {
+    match Mnemonic::parse(value) {
+        Ok(_) => Ok(Secret::from(value)),
+        Err(e) => Err(format!(
+            "not a BIP39 phrase of 12 to 24 words ({e}), e.g. this new one: {}",
+            new_phrase().expose()
+        )),
+    }
+}
+
+/// A random phrase of 12 words.
+///
+/// # Panics
+///
+/// Never, 16 bytes are valid entropy.
+#[must_use]
+pub fn new_phrase() -> Secret {
+    let entropy: [u8; 16] = rand::random();
+    let mnemonic = Mnemonic::from_entropy(&entropy).expect("16 bytes to be valid entropy");
+    Secret::new(mnemonic.to_string())
+}
+
+/// The ed25519 keypair of `seed`, a phrase accepted by [`parse_seed`].
+///
+/// # Errors
+///
+/// If `seed` is not a BIP39 phrase.
+pub fn keypair(seed: &Secret) -> Result<Keypair, String> {
+    let mnemonic = Mnemonic::parse(seed.expose()).map_err(|e| e.to_string())?;
+    let mut secret = mnemonic.to_seed(PASSPHRASE);
+    let keypair = Keypair::ed25519_from_bytes(&mut secret[..32]).map_err(|e| e.to_string());
+    secret.fill(0);
+    keypair
+}
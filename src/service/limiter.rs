@@ -0,0 +1,250 @@
+use chrono::NaiveTime;
+use parking_lot::Mutex;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Token-bucket limiter shared by every transfer going in one direction.
+///
+/// The bucket holds at most one second worth of tokens. A chunk larger than what is
+/// available puts the bucket into debt and the caller sleeps until it is paid back, so
+/// chunk size does not matter for the long-term rate. The rate can change while transfers
+/// are running, 0 lets everything through.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: AtomicU64::new(bytes_per_second),
+            bucket: Mutex::new(Bucket {
+                #[allow(clippy::cast_precision_loss)]
+                tokens: bytes_per_second as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn with_limit(limit: Limit) -> Self {
+        Self::new(limit.rate().unwrap_or(0))
+    }
+
+    /// Change the rate, transfers waiting on the limiter pick it up with their next chunk.
+    pub fn set_limit(&self, limit: Limit) {
+        self.bytes_per_second
+            .store(limit.rate().unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn limit(&self) -> Limit {
+        match self.bytes_per_second.load(Ordering::Relaxed) {
+            0 => Limit::Unlimited,
+            rate => Limit::Rate(rate),
+        }
+    }
+
+    /// Wait until `bytes` may be sent or written.
+    pub async fn acquire(&self, bytes: usize) {
+        let Limit::Rate(rate) = self.limit() else {
+            return;
+        };
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            #[allow(clippy::cast_precision_loss)]
+            let rate = rate as f64;
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = elapsed.mul_add(rate, bucket.tokens).min(rate);
+            bucket.last_refill = now;
+            #[allow(clippy::cast_precision_loss)]
+            let bytes = bytes as f64;
+            bucket.tokens -= bytes;
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// A transfer rate limit, written as a rate or `unlimited` in the config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Limit {
+    #[default]
+    Unlimited,
+    /// Bytes per second.
+    Rate(u64),
+}
+
+impl Limit {
+    #[must_use]
+    pub const fn rate(self) -> Option<u64> {
+        match self {
+            Self::Unlimited => None,
+            Self::Rate(rate) => Some(rate),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value.trim().eq_ignore_ascii_case("unlimited") {
+            return Ok(Self::Unlimited);
+        }
+        parse_rate(&value)
+            .map(Self::Rate)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Upload and download limits by time of day, the `[bandwidth]` section of the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthSchedule {
+    /// Limits outside of every window.
+    pub upload: Limit,
+    pub download: Limit,
+    /// Times of day with other limits, the first window covering a time applies.
+    pub windows: Vec<BandwidthWindow>,
+    /// Shares of the upload by peer ID while several peers download, 1 for peers left out.
+    pub weights: BTreeMap<String, usize>,
+}
+
+/// Limits from `from` until `to`, passing midnight if `to` is the earlier time. A
+/// direction left out keeps the limit outside the window.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BandwidthWindow {
+    #[serde(deserialize_with = "time_of_day")]
+    pub from: NaiveTime,
+    #[serde(deserialize_with = "time_of_day")]
+    pub to: NaiveTime,
+    #[serde(default)]
+    pub upload: Option<Limit>,
+    #[serde(default)]
+    pub download: Option<Limit>,
+}
+
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").map_err(|_| {
+        serde::de::Error::custom(format!("invalid time '{value}', expected e.g. 22:00"))
+    })
+}
+
+impl BandwidthWindow {
+    fn covers(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+impl BandwidthSchedule {
+    /// Upload and download limits at a time of day.
+    #[must_use]
+    pub fn limits_at(&self, time: NaiveTime) -> (Limit, Limit) {
+        let window = self.windows.iter().find(|window| window.covers(time));
+        (
+            window
+                .and_then(|window| window.upload)
+                .unwrap_or(self.upload),
+            window
+                .and_then(|window| window.download)
+                .unwrap_or(self.download),
+        )
+    }
+
+    /// Whether uploads are limited at any time of day, so a limiter is needed.
+    #[must_use]
+    pub fn limits_upload(&self) -> bool {
+        self.upload != Limit::Unlimited
+            || self
+                .windows
+                .iter()
+                .any(|window| window.upload.is_some_and(|limit| limit != Limit::Unlimited))
+    }
+
+    #[must_use]
+    pub fn limits_download(&self) -> bool {
+        self.download != Limit::Unlimited
+            || self.windows.iter().any(|window| {
+                window
+                    .download
+                    .is_some_and(|limit| limit != Limit::Unlimited)
+            })
+    }
+}
+
+/// Parse a transfer rate such as `5MiB/s`, `500KB` or `1048576`.
+///
+/// Units are the ones of [`parse_size`], the trailing `/s` is optional.
+///
+/// # Errors
+///
+/// If `input` is not a rate.
+pub fn parse_rate(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+    let value = trimmed.strip_suffix("/s").unwrap_or(trimmed);
+    let rate =
+        parse_size(value).map_err(|_| format!("invalid rate '{input}', expected e.g. 5MiB/s"))?;
+    if rate < 1 {
+        return Err(format!("rate '{input}' must be at least 1 byte per second"));
+    }
+    Ok(rate)
+}
+
+/// Parse an amount of bytes such as `5GB`, `500KiB` or `1048576`.
+///
+/// Decimal suffixes (`K`, `KB`, `M`, ...) are powers of 1000, binary suffixes (`KiB`,
+/// `MiB`, ...) powers of 1024.
+///
+/// # Errors
+///
+/// If `input` is not an amount or too large.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let value = input.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{input}', expected e.g. 5GB"))?;
+    let multiplier: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "kib" => 1024.0,
+        "m" | "mb" => 1e6,
+        "mib" => 1024.0 * 1024.0,
+        "g" | "gb" => 1e9,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        "t" | "tb" => 1e12,
+        "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        other => return Err(format!("unknown size unit '{other}' in '{input}'")),
+    };
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok((number * multiplier).round() as u64)
+}
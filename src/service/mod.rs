@@ -1,2 +1,22 @@
+pub mod archive;
+pub mod client;
+pub mod delta;
+pub mod dial;
+pub mod error;
+pub mod fairness;
+pub mod greeting;
+pub mod hashing;
+pub mod identity;
+pub mod limiter;
 pub mod node;
+pub mod probe;
+pub mod protocol;
+pub mod push;
+pub mod quality;
+pub mod reconnect;
+pub mod registry;
+pub mod sampling;
+pub mod secret;
+pub mod share_code;
+pub mod systemd;
 pub mod utils;
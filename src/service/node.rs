@@ -3,30 +3,74 @@ use futures::{
     prelude::*,
 };
 use libp2p::{
-    kad,
+    connection_limits::{self, ConnectionLimits},
+    identify, kad,
     multiaddr::{Multiaddr, Protocol},
-    noise,
+    noise, ping, rendezvous,
     request_response::{self, OutboundRequestId, ProtocolSupport},
-    swarm::{NetworkBehaviour, Swarm, SwarmEvent},
-    tcp, yamux, PeerId, StreamProtocol, SwarmBuilder,
+    swarm::{dial_opts::DialOpts, ConnectionId, DialError, NetworkBehaviour, Swarm, SwarmEvent},
+    tcp, yamux, PeerId, SwarmBuilder,
 };
 use libp2p_stream as stream;
-use serde::{Deserialize, Serialize};
 use std::{
-    collections::{hash_map, HashMap},
-    error::Error,
-    path::PathBuf,
-    sync::LazyLock,
-    time::Duration,
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::Semaphore;
+use tokio::sync::watch;
 
 use crate::app::DirectoryItem;
+use crate::format;
 
-use super::utils::{FileReceiver, FileTransfer};
+use super::archive::{Archive, ArchiveFormat, ArchiveSource, ArchiveTransfer};
+use super::error::JunkanooError;
+use super::fairness::FairScheduler;
+use super::greeting::{self, AuthRequirement, Greeting, Hello, Welcome};
+use super::identity;
+use super::limiter::{BandwidthSchedule, Limit, RateLimiter};
+use super::protocol::{
+    reject_request, DisplayRequest, DisplayResponse, FileRequest, ListDirectoryRequest,
+    ListDirectoryResponse, Manifest, ManifestEntry, ManifestRequest, PushRequest, PushResponse,
+    ShareUpdate, ShareUpdateAck, COMPRESSION_ZSTD, JUNKANOO_FILE_PROTOCOL,
+    JUNKANOO_GREETING_PROTOCOL, JUNKANOO_LIST_PROTOCOL, JUNKANOO_MANIFEST_PROTOCOL,
+    JUNKANOO_PUSH_PROTOCOL, JUNKANOO_REQUEST_RESPONSE_PROTOCOL, JUNKANOO_UPDATES_PROTOCOL,
+};
+use super::push;
+use super::quality::LinkQuality;
+use super::reconnect::{self, Attempt, ReconnectManager};
+use super::registry::{ShareEntry, ShareRegistry, SharedRegistry};
+use super::sampling::LogSampler;
+use super::secret::Secret;
+use super::share_code;
+use super::utils::{
+    proceed, Conflict, ConflictPolicy, FileReceiver, FileTransfer, ReceivedFile, SymlinkPolicy,
+    TransferControl,
+};
 // 10 minutes
 const CONNECTION_TIMEOUT: u64 = 600;
 
+// How often connected peers are pinged to judge the link quality
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+// How often a shutting down event loop checks whether downloads and connections are done
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Swarm events without a handler of their own are logged at most this often
+const EVENT_LOG_INTERVAL: Duration = Duration::from_secs(1);
+const EVENT_LOG_BURST: u32 = 20;
+
+// How often the routing table is refreshed once the first bootstrap ran
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// How often the bandwidth schedule is checked for a window starting or ending
+const BANDWIDTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 // Amino Bootnode https://docs.ipfs.tech/concepts/public-utilities/#amino-dht-bootstrappers
 const BOOTNODES: [&str; 5] = [
     "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
@@ -36,14 +80,207 @@ const BOOTNODES: [&str; 5] = [
     "12D3KooWKnDdG3iXw9eTFijk3EWSunZcFi54Zka4wmtqtt6rPxc",
 ];
 
-const JUNKANOO_REQUEST_RESPONSE_PROTOCOL: StreamProtocol =
-    StreamProtocol::new("/junkanoo/request-response");
+// Room for bulk requests to queue up without blocking callers on every send
+const COMMAND_CHANNEL_CAPACITY: usize = 32;
+// Control commands are rare but must never wait behind bulk requests
+const CONTROL_CHANNEL_CAPACITY: usize = 8;
 
-const JUNKANOO_FILE_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/stream");
+// Cap on half-open connections in either direction, so scanners can't exhaust file descriptors
+const MAX_PENDING_CONNECTIONS: u32 = 16;
+// A downloader needs a single connection, a few more allow for simultaneous dials
+const MAX_CONNECTIONS_PER_PEER: u32 = 4;
+// Total established connections, including DHT traffic
+const DEFAULT_MAX_CONNECTIONS: u32 = 64;
 
 // Limit concurrent transfers to prevent resource exhaustion
 // This can be tuned based on system capabilities and requirements
-static TRANSFER_SEMAPHORE: LazyLock<Semaphore> = LazyLock::new(|| Semaphore::new(4));
+const MAX_INCOMING_TRANSFERS: usize = 4;
+
+// Files fetched at the same time by a downloader unless `--parallel` says otherwise
+const DEFAULT_PARALLEL_DOWNLOADS: usize = 4;
+
+// A file that keeps changing on the host, e.g. a log, is given up on after this many tries
+const MAX_SOURCE_CHANGES: u32 = 2;
+
+// Time a host has to answer a request unless `--request-timeout` says otherwise, recursive
+// listings of big shares take a while
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Options for the network layer, set from the command line.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct NodeConfig {
+    /// Maximum upload rate in bytes per second across all outgoing transfers.
+    pub max_upload: Option<u64>,
+    /// Maximum download rate in bytes per second across all incoming transfers.
+    pub max_download: Option<u64>,
+    /// Limits by time of day, `max_upload` and `max_download` replace its defaults.
+    pub bandwidth: BandwidthSchedule,
+    /// Maximum number of established connections, defaults to [`DEFAULT_MAX_CONNECTIONS`].
+    pub max_connections: Option<u32>,
+    /// Skip the public DHT entirely, only direct dials on the local network are used.
+    pub lan_only: bool,
+    /// DHT nodes to join through, each ending in its peer ID, instead of the public
+    /// bootstrappers.
+    pub bootnodes: Vec<Multiaddr>,
+    /// Never compress file streams, neither when sending nor when receiving.
+    pub no_compress: bool,
+    /// Files downloaded in parallel, capped by what the host advertises.
+    pub parallel_downloads: Option<usize>,
+    /// Serve a single downloader, then close the share.
+    pub once: bool,
+    /// Open shared files read-only when they are published and only serve from those.
+    pub read_only: bool,
+    /// Scheduled shares only answer requests from this point on.
+    pub opens_at: Option<SystemTime>,
+    /// Close the share once the first downloader finished, without turning others away.
+    pub exit_on_complete: bool,
+    /// Name the host gave the share, sent along with the listing.
+    pub label: Option<String>,
+    /// Name peers see instead of our peer ID.
+    pub display_name: Option<String>,
+    /// Only serve downloaders that greeted with this password.
+    pub password: Option<Secret>,
+    /// Give downloaded files the permissions and modification times they have on the host.
+    pub preserve: bool,
+    /// What downloads do with files that already exist with other content.
+    pub on_conflict: ConflictPolicy,
+    /// How symlinks in shared directories are handled.
+    pub symlinks: SymlinkPolicy,
+    /// Shared files are sent relative to this directory, set when pushing it with `sync`.
+    pub sync_root: Option<PathBuf>,
+    /// Take files pushed with `sync --push` and save them here.
+    pub push_directory: Option<PathBuf>,
+    /// Announce ourselves in the DHT as the host of this code, see [`share_code`].
+    pub share_code: Option<String>,
+    /// Derive our identity from this phrase instead of making up a new one, see [`identity`].
+    pub identity_seed: Option<Secret>,
+    /// Rendezvous point to register the share code at and look codes up from instead of the
+    /// DHT. Carries the point's peer ID.
+    pub rendezvous: Option<Multiaddr>,
+    /// How long a peer has to answer a request or take a file stream before it fails with
+    /// [`JunkanooError::Timeout`], defaults to [`DEFAULT_REQUEST_TIMEOUT`].
+    pub request_timeout: Option<Duration>,
+}
+
+impl NodeConfig {
+    fn request_timeout(&self) -> Duration {
+        self.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Requests are timed out by their behaviour, which fails them with
+    /// [`request_response::OutboundFailure::Timeout`] under their request ID.
+    fn request_response_config(&self) -> request_response::Config {
+        request_response::Config::default().with_request_timeout(self.request_timeout())
+    }
+
+    fn connection_limits(&self) -> ConnectionLimits {
+        let max_connections = self.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        ConnectionLimits::default()
+            .with_max_pending_incoming(Some(MAX_PENDING_CONNECTIONS))
+            .with_max_pending_outgoing(Some(MAX_PENDING_CONNECTIONS))
+            .with_max_established(Some(max_connections))
+            .with_max_established_per_peer(Some(MAX_CONNECTIONS_PER_PEER))
+    }
+
+    /// The rendezvous point's peer ID and address, if `--rendezvous` names one.
+    fn rendezvous_point(&self) -> Option<(PeerId, Multiaddr)> {
+        self.rendezvous.as_ref().and_then(|address| {
+            address.iter().find_map(|protocol| match protocol {
+                Protocol::P2p(peer_id) => Some((peer_id, address.clone())),
+                _ => None,
+            })
+        })
+    }
+
+    /// What this node tells peers about itself when they connect.
+    fn greeting(&self) -> Greeting {
+        let greeting = Greeting::new(
+            self.display_name.clone(),
+            self.label.clone(),
+            if self.password.is_some() {
+                AuthRequirement::Password
+            } else {
+                AuthRequirement::None
+            },
+        );
+        if self.no_compress {
+            greeting.without("zstd")
+        } else {
+            greeting
+        }
+    }
+}
+
+/// Addresses to listen on when `--listen` isn't given: QUIC and TCP on `port`, on `address`
+/// or else on every IPv4 and IPv6 interface. Port 0 lets the system pick.
+#[must_use]
+pub fn listen_addrs(address: Option<IpAddr>, port: u16) -> Vec<Multiaddr> {
+    let addresses = address.map_or_else(
+        || {
+            vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ]
+        },
+        |address| vec![address],
+    );
+    addresses
+        .into_iter()
+        .flat_map(|address| {
+            let ip = Multiaddr::empty().with(Protocol::from(address));
+            [
+                ip.clone().with(Protocol::Udp(port)).with(Protocol::QuicV1),
+                ip.with(Protocol::Tcp(port)),
+            ]
+        })
+        .collect()
+}
+
+/// Where a listening address can be reached from, shown next to it in the UI. Ordered
+/// from reachable by anyone to only by this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AddressKind {
+    Public,
+    Relayed,
+    Lan,
+    Loopback,
+}
+
+impl AddressKind {
+    #[must_use]
+    pub fn of(address: &Multiaddr) -> Self {
+        if address
+            .iter()
+            .any(|protocol| protocol == Protocol::P2pCircuit)
+        {
+            return Self::Relayed;
+        }
+        let ip = address.iter().find_map(|protocol| match protocol {
+            Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+            Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        });
+        match ip {
+            Some(ip) if ip.is_loopback() => Self::Loopback,
+            // Unspecified addresses stand for every interface of the machine
+            Some(ip) if ip.is_unspecified() => Self::Lan,
+            Some(IpAddr::V4(ip)) if ip.is_private() || ip.is_link_local() => Self::Lan,
+            Some(IpAddr::V6(ip)) if ip.is_unique_local() || ip.is_unicast_link_local() => Self::Lan,
+            _ => Self::Public,
+        }
+    }
+
+    #[must_use]
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::Relayed => "relayed",
+            Self::Lan => "LAN",
+            Self::Loopback => "loopback",
+        }
+    }
+}
 
 /// Creates the network components, namely:
 ///
@@ -52,31 +289,44 @@ static TRANSFER_SEMAPHORE: LazyLock<Semaphore> = LazyLock::new(|| Semaphore::new
 /// - The network event stream, e.g. for incoming requests.
 ///
 /// - The network task driving the network itself.
-pub fn new() -> Result<(Client, impl Stream<Item = Event>, EventLoop, PeerId), Box<dyn Error>> {
-    // Create a public/private key pair, either random or based on a seed.
-    // let id_keys = Keypair::generate_ed25519();
-    // let peer_id = id_keys.public().to_peer_id();
-    let peer_id = PeerId::random();
-
-    let mut swarm = SwarmBuilder::with_new_identity()
+///
+/// # Errors
+///
+/// With [`JunkanooError::Setup`] if the transport can't be set up or `identity_seed` is not
+/// a phrase.
+///
+/// # Panics
+///
+/// If the file transfer protocol is accepted twice.
+pub fn new(
+    config: &NodeConfig,
+) -> Result<(Client, impl Stream<Item = Event>, EventLoop, PeerId), JunkanooError> {
+    let builder = match &config.identity_seed {
+        Some(seed) => SwarmBuilder::with_existing_identity(
+            identity::keypair(seed).map_err(JunkanooError::Setup)?,
+        ),
+        None => SwarmBuilder::with_new_identity(),
+    };
+    let builder = builder
         .with_tokio()
         .with_tcp(
             tcp::Config::default(),
             noise::Config::new,
             yamux::Config::default,
         )?
-        .with_quic()
-        .with_behaviour(|key| Behaviour {
-            kademlia: kad::Behaviour::new(
-                peer_id,
-                kad::store::MemoryStore::new(key.public().to_peer_id()),
-            ),
-            request_response: request_response::cbor::Behaviour::new(
-                [(JUNKANOO_REQUEST_RESPONSE_PROTOCOL, ProtocolSupport::Full)],
-                request_response::Config::default(),
-            ),
-            file_stream: stream::Behaviour::new(),
+        .with_quic();
+    // Browsers can dial WebRTC directly, the listen addresses carry the certificate hash
+    #[cfg(feature = "webrtc")]
+    let builder = {
+        let certificate = libp2p_webrtc::tokio::Certificate::generate(&mut rand08::thread_rng())?;
+        builder.with_other_transport(|key| {
+            libp2p_webrtc::tokio::Transport::new(key.clone(), certificate)
         })?
+    };
+    let mut swarm = builder
+        .with_dns()
+        .map_err(|e| JunkanooError::Setup(e.to_string()))?
+        .with_behaviour(|key| Behaviour::new(key, config))?
         .with_swarm_config(|c| {
             c.with_idle_connection_timeout(Duration::from_secs(CONNECTION_TIMEOUT))
         })
@@ -100,17 +350,29 @@ pub fn new() -> Result<(Client, impl Stream<Item = Event>, EventLoop, PeerId), B
         .kademlia
         .set_mode(Some(kad::Mode::Server));
 
-    // Then add the bootnodes
-    for peer in &BOOTNODES {
-        if let Ok(peer_id) = peer.parse() {
-            swarm
-                .behaviour_mut()
-                .kademlia
-                .add_address(&peer_id, "/dnsaddr/bootstrap.libp2p.io".parse()?);
+    // Then add the bootnodes, unless the DHT is not wanted at all
+    if !config.lan_only && config.bootnodes.is_empty() {
+        for peer in &BOOTNODES {
+            if let Ok(peer_id) = peer.parse() {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, "/dnsaddr/bootstrap.libp2p.io".parse()?);
+            }
+        }
+    } else if !config.lan_only {
+        for address in &config.bootnodes {
+            if let Some(Protocol::P2p(peer_id)) = address.iter().last() {
+                swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, address.clone());
+            }
         }
     }
 
-    let (command_sender, command_receiver) = mpsc::channel(0);
+    let (command_sender, command_receiver) = mpsc::channel(COMMAND_CHANNEL_CAPACITY);
+    let (control_sender, control_receiver) = mpsc::channel(CONTROL_CHANNEL_CAPACITY);
     let (event_sender, event_receiver) = mpsc::channel(0);
 
     let local_peer_id = *swarm.local_peer_id();
@@ -118,9 +380,17 @@ pub fn new() -> Result<(Client, impl Stream<Item = Event>, EventLoop, PeerId), B
     Ok((
         Client {
             sender: command_sender,
+            control_sender,
         },
         event_receiver,
-        EventLoop::new(swarm, command_receiver, event_sender, incoming_streams),
+        EventLoop::new(
+            swarm,
+            command_receiver,
+            control_receiver,
+            event_sender,
+            incoming_streams,
+            config,
+        ),
         local_peer_id,
     ))
 }
@@ -128,192 +398,643 @@ pub fn new() -> Result<(Client, impl Stream<Item = Event>, EventLoop, PeerId), B
 #[derive(Clone)]
 pub struct Client {
     sender: mpsc::Sender<Command>,
+    control_sender: mpsc::Sender<Command>,
 }
 
 impl Client {
-    /// Listen for incoming connections on the given address.
-    pub(crate) async fn start_listening(
+    /// Send a command to the event loop and wait for its reply.
+    ///
+    /// Control commands go through their own channel so they are never stuck behind bulk
+    /// requests. Returns an error instead of panicking if the event loop has shut down.
+    async fn send_command<T>(
         &mut self,
-        addr: Multiaddr,
-    ) -> Result<(), Box<dyn Error + Send>> {
+        command: impl FnOnce(oneshot::Sender<Result<T, JunkanooError>>) -> Command,
+    ) -> Result<T, JunkanooError> {
         let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::StartListening { addr, sender })
+        let command = command(sender);
+        let channel = if command.is_control() {
+            &mut self.control_sender
+        } else {
+            &mut self.sender
+        };
+        channel.send(command).await?;
+        receiver.await?
+    }
+
+    /// Listen for incoming connections on the given address.
+    ///
+    /// # Errors
+    ///
+    /// If the address can't be listened on. If the network has shut down.
+    pub async fn start_listening(&mut self, addr: Multiaddr) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::StartListening { addr, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
     }
 
-    pub(crate) async fn get_listening_addrs(
-        &mut self,
-    ) -> Result<Vec<Multiaddr>, Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::GetListeningAddrs { sender })
+    /// Addresses the node listens on.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn get_listening_addrs(&mut self) -> Result<Vec<Multiaddr>, JunkanooError> {
+        self.send_command(|sender| Command::GetListeningAddrs { sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
     }
 
     /// Dial the given peer at the given address.
-    pub(crate) async fn dial(
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached. If the network has shut down.
+    pub async fn dial(
         &mut self,
         peer_id: PeerId,
         peer_addr: Multiaddr,
-    ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::Dial {
-                peer_id,
-                peer_addr,
-                sender,
-            })
+    ) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::Dial {
+            peer_id,
+            peer_addr,
+            sender,
+        })
+        .await
+    }
+
+    /// Stop answering requests for the share and drop every connection.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn close_share(&mut self) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::CloseShare { sender })
+            .await
+    }
+
+    /// Cancel running downloads, say goodbye to every peer and stop the event loop. Returns
+    /// once downloads flushed what they received and the connections are closed.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn shutdown(&mut self) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::Shutdown { sender })
+            .await
+    }
+
+    /// Close all connections to the given peer. Takes priority over queued bulk requests.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn disconnect(&mut self, peer_id: PeerId) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::Disconnect { peer_id, sender })
+            .await
+    }
+
+    /// Hold the download of a requested file between chunks, or let it go on again.
+    ///
+    /// # Errors
+    ///
+    /// If no such file is being downloaded. If the network has shut down.
+    pub async fn pause_transfer(
+        &mut self,
+        path: String,
+        paused: bool,
+    ) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::PauseTransfer {
+            path,
+            paused,
+            sender,
+        })
+        .await
+    }
+
+    /// Stop the download of a requested file, what arrived so far is kept.
+    ///
+    /// # Errors
+    ///
+    /// If no such file is being downloaded. If the network has shut down.
+    pub async fn cancel_transfer(&mut self, path: String) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::CancelTransfer { path, sender })
+            .await
+    }
+
+    /// Answer [`Event::ConflictsFound`] with a decision for each file, in the same order.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn resolve_conflicts(
+        &mut self,
+        decisions: Vec<ConflictPolicy>,
+    ) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::ResolveConflicts { decisions, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Introduce ourselves to a host, with the password it may ask for. Comes before any
+    /// other request.
+    ///
+    /// # Errors
+    ///
+    /// If the host refuses, e.g. for a wrong password. If the network has shut down.
+    pub async fn greet(
+        &mut self,
+        peer_id: PeerId,
+        password: Option<Secret>,
+    ) -> Result<Welcome, JunkanooError> {
+        self.send_command(|sender| Command::Greet {
+            peer_id,
+            password,
+            sender,
+        })
+        .await
+    }
+
+    /// Offer the given peer the shared `paths`, it downloads the ones it doesn't have.
+    /// Answers with the paths it is about to request.
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached or refuses. If the network has shut down.
+    pub async fn push(
+        &mut self,
+        peer_id: PeerId,
+        paths: Vec<String>,
+    ) -> Result<PushResponse, JunkanooError> {
+        self.send_command(|sender| Command::Push {
+            peer_id,
+            paths,
+            sender,
+        })
+        .await
     }
 
     /// Request the directory items from the given peer.
-    pub(crate) async fn request_directory(
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached or refuses. If the network has shut down.
+    pub async fn request_directory(
         &mut self,
         peer_id: PeerId,
-    ) -> Result<DisplayResponse, Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::RequestDisplay { peer_id, sender })
+    ) -> Result<DisplayResponse, JunkanooError> {
+        self.send_command(|sender| Command::RequestDisplay { peer_id, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
     }
 
-    /// Send the current directory items to the given peer.
-    pub(crate) async fn insert_directory_items(
+    /// List one directory of the given peer's share, `path` as the host listed it and
+    /// empty for the top of the share. With `recursive` everything below it is listed.
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached or refuses. If the network has shut down.
+    pub async fn list_directory(
         &mut self,
         peer_id: PeerId,
-        directory_items: Vec<DirectoryItem>,
-    ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::InsertDirectoryItems {
-                peer_id,
-                directory_items,
-                sender,
-            })
+        path: String,
+        recursive: bool,
+    ) -> Result<ListDirectoryResponse, JunkanooError> {
+        self.send_command(|sender| Command::ListDirectory {
+            peer_id,
+            path,
+            recursive,
+            sender,
+        })
+        .await
+    }
+
+    /// Look up the addresses a host published under a share code in the DHT, those
+    /// reachable from further away first. Waits for the first bootstrap if needed.
+    ///
+    /// # Errors
+    ///
+    /// If no host or several answer for the code. If the network has shut down.
+    pub async fn resolve_code(&mut self, code: String) -> Result<Vec<Multiaddr>, JunkanooError> {
+        self.send_command(|sender| Command::ResolveCode { code, sender })
             .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
     }
 
-    /// Request files from the given peer.
-    pub(crate) async fn request_files(
+    /// Publish the items offered to downloaders.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn update_directory_items(
+        &mut self,
+        directory_items: Vec<DirectoryItem>,
+    ) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::UpdateDirectoryItems {
+            directory_items,
+            sender,
+        })
+        .await
+    }
+
+    /// Fetch part of a shared file into memory, e.g. to preview the start of a huge file
+    /// without downloading it.
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached or refuses. If the network has shut down.
+    pub async fn request_file_range(
         &mut self,
         peer_id: PeerId,
-        file_names: Vec<String>,
-    ) -> Result<Vec<u8>, Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::RequestFiles {
-                peer_id,
-                file_names,
-                sender,
-            })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+        path: String,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, JunkanooError> {
+        self.send_command(|sender| Command::RequestFileRange {
+            peer_id,
+            path,
+            range,
+            sender,
+        })
+        .await
+    }
+
+    /// Request files from the given peer, with `delta` only the blocks that differ from
+    /// existing copies are transferred.
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached or a file fails to arrive. If the network has shut down.
+    pub async fn request_files(
+        &mut self,
+        peer_id: PeerId,
+        files: Vec<RequestedFile>,
+        directory: Option<PathBuf>,
+        delta: bool,
+    ) -> Result<Vec<u8>, JunkanooError> {
+        self.send_command(|sender| Command::RequestFiles {
+            peer_id,
+            files,
+            directory,
+            delta,
+            sender,
+        })
+        .await
+    }
+
+    /// Request a shared directory from the given peer as a single archive, saved as
+    /// `<name>.tar` or `<name>.tar.gz` below `directory`.
+    ///
+    /// # Errors
+    ///
+    /// If the peer can't be reached or the archive fails to arrive. If the network has shut down.
+    pub async fn request_archive(
+        &mut self,
+        peer_id: PeerId,
+        path: String,
+        directory: Option<PathBuf>,
+        format: ArchiveFormat,
+    ) -> Result<(), JunkanooError> {
+        self.send_command(|sender| Command::RequestArchive {
+            peer_id,
+            path,
+            directory,
+            format,
+            sender,
+        })
+        .await
     }
 }
 
+/// A file to download, with the hash the host published for it if any.
+#[derive(Debug, Clone)]
+pub struct RequestedFile {
+    pub path: String,
+    pub hash: Option<String>,
+}
+
+/// A file to download that exists locally with other content, asked about with
+/// [`ConflictPolicy::Ask`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    /// The file as requested from the host.
+    pub path: String,
+    /// Where it is saved, relative to the download directory.
+    pub relative_path: String,
+    /// Size of the host's file.
+    pub size: u64,
+    pub local_size: u64,
+    pub local_modified: Option<SystemTime>,
+}
+
+/// Answer a share code lookup with the addresses found, none means there is no such share.
+fn answer_code_lookup(sender: PendingCodeSender, addresses: Vec<Multiaddr>) {
+    let _ = sender.send(if addresses.is_empty() {
+        Err(JunkanooError::other(
+            "No share found under this code, check it with the host",
+        ))
+    } else {
+        Ok(addresses)
+    });
+}
+
+/// Where a download waits for the answer to [`Event::ConflictsFound`].
+type PendingConflicts = Arc<parking_lot::Mutex<Option<oneshot::Sender<Vec<ConflictPolicy>>>>>;
+
 // Add these type aliases before the EventLoop struct
-type PendingDialSender = oneshot::Sender<Result<(), Box<dyn Error + Send>>>;
-type PendingFileSender = oneshot::Sender<Result<Vec<u8>, Box<dyn Error + Send>>>;
-type PendingDisplaySender = oneshot::Sender<Result<DisplayResponse, Box<dyn Error + Send>>>;
+type PendingDialSender = oneshot::Sender<Result<(), JunkanooError>>;
+type PendingDisplaySender = oneshot::Sender<Result<DisplayResponse, JunkanooError>>;
+type PendingGreetingSender = oneshot::Sender<Result<Welcome, JunkanooError>>;
+type PendingManifestSender = oneshot::Sender<Result<Manifest, JunkanooError>>;
+type PendingPushSender = oneshot::Sender<Result<PushResponse, JunkanooError>>;
+type PendingListSender = oneshot::Sender<Result<ListDirectoryResponse, JunkanooError>>;
+type PendingCodeSender = oneshot::Sender<Result<Vec<Multiaddr>, JunkanooError>>;
+/// Manifest asked for by a running download, sent from the event loop.
+type ManifestQuery = (PeerId, Vec<String>, PendingManifestSender);
+/// A push whose files were compared, to be answered from the event loop.
+type PushAnswer = (
+    request_response::ResponseChannel<PushResponse>,
+    PeerId,
+    Vec<RequestedFile>,
+);
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct EventLoop {
     swarm: Swarm<Behaviour>,
     command_receiver: mpsc::Receiver<Command>,
+    control_receiver: mpsc::Receiver<Command>,
     event_sender: mpsc::Sender<Event>,
-    pending_dial: HashMap<PeerId, PendingDialSender>,
-    pending_request_file: HashMap<OutboundRequestId, PendingFileSender>,
+    /// Keyed by connection rather than peer, an attempt given up on mustn't hold up the next.
+    pending_dial: HashMap<ConnectionId, PendingDialSender>,
     pending_request_display: HashMap<OutboundRequestId, PendingDisplaySender>,
-    pending_directory_items: HashMap<PeerId, Vec<DirectoryItem>>,
+    pending_greetings: HashMap<OutboundRequestId, PendingGreetingSender>,
+    pending_manifests: HashMap<OutboundRequestId, PendingManifestSender>,
+    pending_pushes: HashMap<OutboundRequestId, PendingPushSender>,
+    pending_listings: HashMap<OutboundRequestId, PendingListSender>,
+    /// Lookups in the DHT of who provides a code's key.
+    pending_code_lookups: HashMap<kad::QueryId, CodeLookup>,
+    /// Lookups in the DHT of where the host a code belongs to is.
+    pending_host_lookups: HashMap<kad::QueryId, (PeerId, PendingCodeSender)>,
+    /// Lookups at the rendezvous point with the code looked up, by namespace.
+    pending_rendezvous_lookups: HashMap<String, (String, PendingCodeSender)>,
+    /// Lookups asked for before the routing table had any peers or the rendezvous point
+    /// was connected, started once it has or is.
+    deferred_code_lookups: Vec<(String, PendingCodeSender)>,
+    /// What each peer said in its greeting, only the features it offered are used with it.
+    peer_greetings: HashMap<PeerId, Greeting>,
+    registry: SharedRegistry,
     incoming_streams: stream::IncomingStreams,
+    upload_limit: Option<Arc<RateLimiter>>,
+    download_limit: Option<Arc<RateLimiter>>,
+    /// Takes turns between the peers we upload to.
+    fair_share: Arc<FairScheduler>,
+    bandwidth: BandwidthSchedule,
+    dht_enabled: bool,
+    bootstrapped: bool,
+    /// We provide its key in the DHT after every bootstrap.
+    share_code: Option<String>,
+    /// Where share codes are registered and looked up instead of the DHT, if anywhere.
+    rendezvous: Option<(PeerId, Multiaddr)>,
+    /// Whether the rendezvous point was dialed, it is once we listen.
+    rendezvous_dialed: bool,
+    compression: bool,
+    preserve: bool,
+    conflict: ConflictPolicy,
+    parallel_downloads: usize,
+    request_timeout: Duration,
+    host_transfer_limits: HashMap<PeerId, usize>,
+    /// Recent pings of each connected peer.
+    link_quality: HashMap<PeerId, LinkQuality>,
+    /// Listing version last seen from each host.
+    share_versions: HashMap<PeerId, u64>,
+    reconnects: ReconnectManager,
+    /// Running downloads, waited for on shutdown.
+    downloads: Vec<tokio::task::JoinHandle<()>>,
+    /// Set on shutdown, downloads stop after flushing what they received.
+    cancel_downloads: Arc<AtomicBool>,
+    /// Pause and cancel signals of the files being downloaded, by requested path.
+    transfer_controls: Arc<parking_lot::Mutex<HashMap<String, watch::Sender<TransferControl>>>>,
+    /// Set while a download asks what to do about files that already exist.
+    pending_conflicts: PendingConflicts,
+    /// Answered once the event loop finished shutting down.
+    shutdown: Option<oneshot::Sender<Result<(), JunkanooError>>>,
+    share_open: bool,
+    upload_sender: mpsc::UnboundedSender<(PeerId, PathBuf)>,
+    upload_receiver: mpsc::UnboundedReceiver<(PeerId, PathBuf)>,
+    manifest_sender: mpsc::UnboundedSender<ManifestQuery>,
+    manifest_receiver: mpsc::UnboundedReceiver<ManifestQuery>,
+    /// Where pushed files are saved, `None` unless pushes are accepted.
+    push_directory: Option<PathBuf>,
+    push_sender: mpsc::UnboundedSender<PushAnswer>,
+    push_receiver: mpsc::UnboundedReceiver<PushAnswer>,
+    once: Option<OnceShare>,
+    opens_at: Option<SystemTime>,
+    label: Option<String>,
+    /// What we tell peers about ourselves in the handshake.
+    greeting: Greeting,
+    password: Option<Secret>,
+    /// Downloaders that gave the right password, kept across reconnects.
+    authorized: HashSet<PeerId>,
+    /// Samples the debug log of swarm events nothing else handles.
+    event_log: LogSampler,
+}
+
+/// Progress of a share that closes after its first downloader. For `--once` shares the
+/// first peer to ask claims it exclusively, otherwise the first peer served is followed.
+#[derive(Debug, Default)]
+struct OnceShare {
+    peer: Option<PeerId>,
+    served: HashSet<PathBuf>,
+    exclusive: bool,
+}
+
+/// A share code being looked up in the DHT, the providers of its key found so far.
+struct CodeLookup {
+    code: String,
+    sender: PendingCodeSender,
+    providers: HashSet<PeerId>,
 }
 
 impl EventLoop {
     fn new(
         swarm: Swarm<Behaviour>,
         command_receiver: mpsc::Receiver<Command>,
+        control_receiver: mpsc::Receiver<Command>,
         event_sender: mpsc::Sender<Event>,
         incoming_streams: stream::IncomingStreams,
+        config: &NodeConfig,
     ) -> Self {
+        let (upload_sender, upload_receiver) = mpsc::unbounded();
+        let (push_sender, push_receiver) = mpsc::unbounded();
+        let (manifest_sender, manifest_receiver) = mpsc::unbounded();
+        let registry = ShareRegistry::shared();
+        let mut bandwidth = config.bandwidth.clone();
+        if let Some(rate) = config.max_upload {
+            bandwidth.upload = Limit::Rate(rate);
+        }
+        if let Some(rate) = config.max_download {
+            bandwidth.download = Limit::Rate(rate);
+        }
+        let (upload, download) = bandwidth.limits_at(chrono::Local::now().time());
+        registry.write().set_read_only(config.read_only);
+        registry.write().set_root(config.sync_root.clone());
         Self {
             swarm,
             command_receiver,
+            control_receiver,
             event_sender,
             pending_dial: HashMap::default(),
-            pending_request_file: HashMap::default(),
             pending_request_display: HashMap::default(),
-            pending_directory_items: HashMap::default(),
+            pending_greetings: HashMap::default(),
+            pending_manifests: HashMap::default(),
+            pending_pushes: HashMap::default(),
+            pending_listings: HashMap::default(),
+            pending_code_lookups: HashMap::default(),
+            pending_host_lookups: HashMap::default(),
+            pending_rendezvous_lookups: HashMap::default(),
+            deferred_code_lookups: Vec::new(),
+            peer_greetings: HashMap::default(),
+            registry,
             incoming_streams,
+            upload_limit: bandwidth
+                .limits_upload()
+                .then(|| Arc::new(RateLimiter::with_limit(upload))),
+            download_limit: bandwidth
+                .limits_download()
+                .then(|| Arc::new(RateLimiter::with_limit(download))),
+            fair_share: Arc::new(FairScheduler::new(
+                MAX_INCOMING_TRANSFERS,
+                &bandwidth.weights,
+            )),
+            bandwidth,
+            dht_enabled: !config.lan_only,
+            bootstrapped: false,
+            share_code: config.share_code.clone(),
+            rendezvous: config.rendezvous_point(),
+            rendezvous_dialed: false,
+            compression: !config.no_compress,
+            preserve: config.preserve,
+            conflict: config.on_conflict,
+            parallel_downloads: config
+                .parallel_downloads
+                .unwrap_or(DEFAULT_PARALLEL_DOWNLOADS),
+            request_timeout: config.request_timeout(),
+            host_transfer_limits: HashMap::default(),
+            share_versions: HashMap::default(),
+            reconnects: ReconnectManager::default(),
+            downloads: Vec::new(),
+            cancel_downloads: Arc::new(AtomicBool::new(false)),
+            transfer_controls: Arc::default(),
+            pending_conflicts: Arc::default(),
+            shutdown: None,
+            link_quality: HashMap::default(),
+            share_open: true,
+            upload_sender,
+            upload_receiver,
+            manifest_sender,
+            manifest_receiver,
+            push_directory: config.push_directory.clone(),
+            push_sender,
+            push_receiver,
+            once: (config.once || config.exit_on_complete).then(|| OnceShare {
+                exclusive: config.once,
+                ..OnceShare::default()
+            }),
+            opens_at: config.opens_at,
+            label: config.label.clone(),
+            greeting: config.greeting(),
+            password: config.password.clone(),
+            authorized: HashSet::new(),
+            event_log: LogSampler::new(EVENT_LOG_INTERVAL, EVENT_LOG_BURST),
         }
     }
 
-    pub(crate) async fn run(mut self) {
+    pub async fn run(mut self) {
+        let mut bootstrap_timer = tokio::time::interval_at(
+            tokio::time::Instant::now() + BOOTSTRAP_INTERVAL,
+            BOOTSTRAP_INTERVAL,
+        );
+        let mut shutdown_timer = tokio::time::interval(SHUTDOWN_POLL_INTERVAL);
+        let mut bandwidth_timer = tokio::time::interval(BANDWIDTH_CHECK_INTERVAL);
         loop {
+            if self.shutdown.is_some()
+                && self.swarm.connected_peers().next().is_none()
+                && self
+                    .downloads
+                    .iter()
+                    .all(tokio::task::JoinHandle::is_finished)
+            {
+                tracing::info!("Network shut down");
+                if let Some(sender) = self.shutdown.take() {
+                    let _ = sender.send(Ok(()));
+                }
+                return;
+            }
+            let next_redial = self.reconnects.next_due();
             tokio::select! {
+                // Poll in declaration order so control commands always win over bulk requests.
+                biased;
+                command = self.control_receiver.next() => match command {
+                    Some(c) => self.handle_command(c),
+                    None => return,
+                },
                 event = self.swarm.select_next_some() => self.handle_event(event).await,
                 command = self.command_receiver.next() => match command {
                     Some(c) => self.handle_command(c),
                     // Command channel closed, thus shutting down the network event loop.
                     None=>  return,
                 },
+                _ = bootstrap_timer.tick(), if self.dht_enabled => self.bootstrap(),
+                // Only wakes the loop up to check whether the shutdown finished
+                _ = shutdown_timer.tick(), if self.shutdown.is_some() => {}
+                _ = bandwidth_timer.tick(), if !self.bandwidth.windows.is_empty() => {
+                    self.apply_bandwidth_schedule(chrono::Local::now().time());
+                }
+                () = tokio::time::sleep_until(
+                    next_redial.unwrap_or_else(tokio::time::Instant::now),
+                ), if next_redial.is_some() => self.redial_due().await,
+                Some((peer, path)) = self.upload_receiver.next() => {
+                    self.handle_upload_completed(peer, &path).await;
+                }
+                Some((peer, paths, sender)) = self.manifest_receiver.next() => {
+                    let request_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .manifest
+                        .send_request(&peer, ManifestRequest { paths });
+                    self.pending_manifests.insert(request_id, sender);
+                }
+                Some((channel, peer, files)) = self.push_receiver.next() => {
+                    self.answer_push(channel, peer, files).await;
+                }
                 stream = self.incoming_streams.next() => {
-                    if let Some((peer, mut stream)) = stream {
+                    if let Some((peer, stream)) = stream {
                         tracing::info!("Received file transfer stream from peer {}", peer);
 
                         // Spawn a task to handle the file transfer
-                        let permit = TRANSFER_SEMAPHORE.acquire().await.unwrap();
+                        let registry = self.registry.clone();
+                        let upload_limit = self.upload_limit.clone();
+                        let fair_share = Arc::clone(&self.fair_share);
+                        let compression = self.compression;
+                        let attributes = self
+                            .peer_greetings
+                            .get(&peer)
+                            .is_some_and(|greeting| greeting.supports("attributes"));
+                        let rejection = self.stream_rejection(peer);
+                        let upload_sender = self.upload_sender.clone();
+                        let event_sender = self.event_sender.clone();
                         tokio::spawn(async move {
-                            // Read the file path request from the stream
-                            let mut path_len_bytes = [0u8; 8];
-                            if let Err(e) = stream.read_exact(&mut path_len_bytes).await {
-                                tracing::error!("Failed to read path length from peer {}: {}", peer, e);
-                                drop(permit);
-                                return;
-                            }
-                            let path_len = usize::try_from(u64::from_le_bytes(path_len_bytes))
-                                .map_err(|e| Box::new(e) as Box<dyn Error + Send>);
-
-                            // Read the requested file path
-                            let mut path_bytes = vec![0u8; path_len.unwrap()];
-                            if let Err(e) = stream.read_exact(&mut path_bytes).await {
-                                tracing::error!("Failed to read path from peer {}: {}", peer, e);
-                                drop(permit);
-                                return;
-                            }
-                            let file_path = match String::from_utf8(path_bytes) {
-                                Ok(path) => path,
-                                Err(e) => {
-                                    tracing::error!("Invalid UTF-8 in path from peer {}: {}", peer, e);
-                                    drop(permit);
-                                    return;
-                                }
-                            };
-
-                            tracing::info!("Received file request for '{}' from peer {}", file_path, peer);
-
-                            // Send the file
-                            let transfer = FileTransfer::new(&PathBuf::from(&file_path));
-                            match transfer.stream_file(&mut stream).await {
-                                Ok(()) => {
-                                    tracing::info!("Successfully sent file '{}' to peer {}", file_path, peer);
-                                }
-                                Err(e) => {
-                                    tracing::error!("Failed to send file '{}' to peer {}: {}", file_path, peer, e);
-                                }
+                            // Wait for a slot inside the task, so extra streams queue up
+                            // without stalling the event loop
+                            let admission = fair_share.admit(peer).await;
+                            for path in serve_file_request(
+                                peer,
+                                stream,
+                                &registry,
+                                upload_limit,
+                                fair_share,
+                                compression,
+                                attributes,
+                                rejection.as_deref(),
+                                event_sender,
+                            )
+                            .await
+                            {
+                                let _ = upload_sender.unbounded_send((peer, path));
                             }
-
-                            drop(permit);
+                            drop(admission);
                         });
                     }
                 }
@@ -321,271 +1042,1714 @@ impl EventLoop {
         }
     }
 
-    async fn handle_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
-        match event {
-            SwarmEvent::NewExternalAddrOfPeer { peer_id, address } => {
-                tracing::info!("New external address of peer {peer_id}: {address}");
+    /// Dial the lost peers whose backoff ran out.
+    async fn redial_due(&mut self) {
+        let now = tokio::time::Instant::now();
+        for (peer_id, address) in self.reconnects.take_due(now) {
+            tracing::info!("Redialing {peer_id} at {address}");
+            if let Err(e) = self.swarm.dial(address) {
+                tracing::warn!("Failed to redial {peer_id}: {e}");
+                if let Some(attempt) = self.reconnects.dial_failed(peer_id, now) {
+                    self.report_reconnect(peer_id, attempt).await;
+                }
             }
-            SwarmEvent::NewListenAddr { address, .. } => {
-                let local_peer_id = *self.swarm.local_peer_id();
-                let addr_with_peer = address.with(Protocol::P2p(local_peer_id));
-                tracing::info!("Local node is listening on {:?}", addr_with_peer);
+        }
+    }
 
-                self.event_sender
-                    .send(Event::NewListenAddr(addr_with_peer))
-                    .await
-                    .expect("Event receiver not to be dropped.");
+    /// Tell the application about a scheduled redial, or that the peer is gone for good.
+    async fn report_reconnect(&mut self, peer_id: PeerId, attempt: Attempt) {
+        let event = match attempt {
+            Attempt::Scheduled(attempt) => {
+                tracing::info!(
+                    "Reconnecting to {peer_id} in {:?} (attempt {attempt})",
+                    reconnect::backoff(attempt)
+                );
+                Event::Reconnecting { peer_id, attempt }
             }
-            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
-                request_response::Event::Message { message, .. },
-            )) => match message {
-                request_response::Message::Request { channel, .. } => {
-                    // When receiving a directory request, respond with items from pending_directory_items
-                    let items = self
-                        .pending_directory_items
-                        .get(self.swarm.local_peer_id())
-                        .cloned()
-                        .unwrap_or_default();
+            Attempt::GaveUp => {
+                tracing::warn!("Giving up reconnecting to {peer_id}");
+                Event::PeerDisconnected()
+            }
+        };
+        self.event_sender
+            .send(event)
+            .await
+            .expect("Event receiver not to be dropped.");
+    }
 
-                    let response = DisplayResponse { items };
+    /// Why a file stream from the peer is turned away, if it is.
+    fn stream_rejection(&mut self, peer: PeerId) -> Option<String> {
+        if let Some(opens_at) = self.pending_opening() {
+            return Some(format!(
+                "the share opens at {}",
+                format::time_of_day(opens_at)
+            ));
+        }
+        if !self.is_authorized(peer) {
+            return Some("the share requires a password".to_string());
+        }
+        (!self.accepts_peer(peer))
+            .then(|| "the share was already claimed by another peer".to_string())
+    }
 
-                    self.swarm
-                        .behaviour_mut()
-                        .request_response
-                        .send_response(channel, response)
-                        .expect("Response channel to be valid");
-                }
-                request_response::Message::Response {
-                    request_id,
-                    response,
-                } => {
-                    if let Some(sender) = self.pending_request_display.remove(&request_id) {
-                        let _ = sender.send(Ok(response));
-                    }
-                }
-            },
-            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
-                request_response::Event::OutboundFailure {
-                    request_id, error, ..
-                },
-            )) => {
-                // Try both maps since we don't know which type of request failed
-                if let Some(sender) = self.pending_request_display.remove(&request_id) {
-                    let _ = sender.send(Err(Box::new(error)));
-                } else if let Some(sender) = self.pending_request_file.remove(&request_id) {
-                    let _ = sender.send(Err(Box::new(error)));
-                } else {
-                    tracing::warn!("Received failure for unknown request ID: {:?}", request_id);
-                }
-            }
-            SwarmEvent::ConnectionEstablished {
-                peer_id, endpoint, ..
-            } => {
-                tracing::info!("Connected to {peer_id}");
+    /// Whether the peer may use the share. Only matters for `--once` shares, where the
+    /// first peer to ask claims it.
+    fn accepts_peer(&mut self, peer: PeerId) -> bool {
+        match &mut self.once {
+            Some(once) if once.exclusive => *once.peer.get_or_insert(peer) == peer,
+            _ => true,
+        }
+    }
 
-                if endpoint.is_dialer() {
-                    if let Some(sender) = self.pending_dial.remove(&peer_id) {
-                        let _ = sender.send(Ok(()));
-                    }
-                }
-                self.event_sender
-                    .send(Event::PeerConnected(peer_id))
-                    .await
-                    .expect("Event receiver not to be dropped.");
-            }
-            SwarmEvent::ConnectionClosed {
-                peer_id,
-                connection_id,
-                num_established,
-                ..
-            } => {
-                tracing::debug!("Connection closed: {peer_id} {connection_id} {num_established}");
-                self.event_sender
-                    .send(Event::PeerDisconnected())
-                    .await
-                    .expect("Event receiver not to be dropped.");
-            }
-            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                if let Some(peer_id) = peer_id {
-                    if let Some(sender) = self.pending_dial.remove(&peer_id) {
-                        let _ = sender.send(Err(Box::new(error)));
-                    }
-                }
-            }
-            SwarmEvent::IncomingConnectionError { .. } => {}
-            SwarmEvent::Dialing {
-                peer_id: Some(peer_id),
-                ..
-            } => tracing::debug!("Dialing {peer_id}"),
-            e => tracing::debug!("{e:?}"),
+    /// Whether the peer may use the share, without claiming a `--once` share for it.
+    fn may_use(&self, peer: PeerId) -> bool {
+        match &self.once {
+            Some(once) if once.exclusive => once.peer.is_none_or(|claimed| claimed == peer),
+            _ => true,
         }
     }
 
-    #[allow(clippy::too_many_lines)]
-    fn handle_command(&mut self, command: Command) {
-        match command {
-            Command::StartListening { addr, sender } => {
-                let _ = match self.swarm.listen_on(addr) {
-                    Ok(_) => sender.send(Ok(())),
-                    Err(e) => sender.send(Err(Box::new(e))),
-                };
-            }
-            Command::Dial {
-                peer_id,
-                peer_addr,
-                sender,
-            } => {
-                if let hash_map::Entry::Vacant(e) = self.pending_dial.entry(peer_id) {
-                    self.swarm
-                        .behaviour_mut()
-                        .kademlia
-                        .add_address(&peer_id, peer_addr.clone());
-                    match self.swarm.dial(peer_addr) {
-                        Ok(()) => {
-                            e.insert(sender);
+    /// Whether the peer greeted with the share's password, always true without one.
+    fn is_authorized(&self, peer: PeerId) -> bool {
+        self.password.is_none() || self.authorized.contains(&peer)
+    }
+
+    /// Whether both sides offered `feature` in the greeting. Hosts that didn't greet predate
+    /// negotiation and are asked for whatever we offer.
+    fn peer_supports(&self, peer: &PeerId, feature: &str) -> bool {
+        self.peer_greetings.get(peer).map_or_else(
+            || self.greeting.supports(feature),
+            |greeting| greeting.supports(feature),
+        )
+    }
+
+    /// Compression to ask the peer for, see [`Greeting::compression`].
+    fn peer_compression(&self, peer: &PeerId) -> u8 {
+        self.peer_greetings
+            .get(peer)
+            .unwrap_or(&self.greeting)
+            .compression()
+    }
+
+    /// Whether the peer lists directories as it enters them rather than getting all of
+    /// them with every poll.
+    fn lists_on_demand(&self, peer: PeerId) -> bool {
+        self.peer_greetings
+            .get(&peer)
+            .is_some_and(|greeting| greeting.supports("list"))
+    }
+
+    /// Tell connected downloaders that negotiated `updates` what changed in the listing.
+    fn push_share_update(&mut self, update: &ShareUpdate) {
+        if !self.share_open || self.pending_opening().is_some() {
+            return;
+        }
+        let peers: Vec<PeerId> = self
+            .peer_greetings
+            .iter()
+            .filter(|(_, greeting)| greeting.supports("updates"))
+            .map(|(peer, _)| *peer)
+            .filter(|peer| {
+                self.swarm.is_connected(peer) && self.is_authorized(*peer) && self.may_use(*peer)
+            })
+            .collect();
+        for peer in peers {
+            self.swarm
+                .behaviour_mut()
+                .updates
+                .send_request(&peer, update.clone());
+        }
+    }
+
+    /// When a scheduled share opens, `None` once it is open.
+    fn pending_opening(&self) -> Option<SystemTime> {
+        self.opens_at
+            .filter(|opens_at| *opens_at > SystemTime::now())
+    }
+
+    async fn handle_upload_completed(&mut self, peer: PeerId, path: &Path) {
+        let Some(once) = &mut self.once else {
+            return;
+        };
+        if *once.peer.get_or_insert(peer) != peer {
+            return;
+        }
+        once.served.insert(path.to_path_buf());
+
+        // Done once the downloader has every shared file
+        let all_served = self
+            .registry
+            .read()
+            .items()
+            .iter()
+            .filter(|item| !item.is_dir)
+            .all(|item| once.served.contains(&item.path));
+        if all_served {
+            tracing::info!("Peer {peer} downloaded everything, closing the one-shot share");
+            self.finish_once_share().await;
+        }
+    }
+
+    /// Tell the pushing peer which files are about to be requested, then let the
+    /// application download them.
+    async fn answer_push(
+        &mut self,
+        channel: request_response::ResponseChannel<PushResponse>,
+        peer: PeerId,
+        files: Vec<RequestedFile>,
+    ) {
+        let response = PushResponse {
+            requested: files.iter().map(|file| file.path.clone()).collect(),
+            rejection: None,
+        };
+        if self
+            .swarm
+            .behaviour_mut()
+            .push
+            .send_response(channel, response)
+            .is_err()
+        {
+            tracing::debug!("Peer {peer} left before the push was answered");
+            return;
+        }
+        let Some(directory) = self.push_directory.clone() else {
+            return;
+        };
+        if files.is_empty() {
+            tracing::info!("Everything peer {peer} pushed is up to date");
+            return;
+        }
+        self.event_sender
+            .send(Event::PushAccepted {
+                peer_id: peer,
+                files,
+                directory,
+            })
+            .await
+            .expect("Event receiver not to be dropped.");
+    }
+
+    /// Close a one-shot share and let the application know it is done.
+    async fn finish_once_share(&mut self) {
+        if !self.share_open {
+            return;
+        }
+        self.close_share();
+        self.event_sender
+            .send(Event::ShareCompleted)
+            .await
+            .expect("Event receiver not to be dropped.");
+    }
+
+    /// Send `control` to the running download of `path`. A cancelled one stays cancelled.
+    fn signal_transfer(&self, path: &str, control: TransferControl) -> Result<(), JunkanooError> {
+        self.transfer_controls
+            .lock()
+            .get(path)
+            .ok_or_else(|| JunkanooError::other(format!("No download of {path} is running")))?
+            .send_if_modified(|current| {
+                let changed = *current != control && *current != TransferControl::Cancel;
+                if changed {
+                    *current = control;
+                }
+                changed
+            });
+        Ok(())
+    }
+
+    /// Stop answering requests and drop every connection.
+    fn close_share(&mut self) {
+        tracing::info!("Closing the share");
+        self.share_open = false;
+        self.registry.write().replace(Vec::new());
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer_id in peers {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+    }
+
+    /// Set the limits the bandwidth schedule has for `time`, running transfers slow down or
+    /// speed up with their next chunk.
+    fn apply_bandwidth_schedule(&self, time: chrono::NaiveTime) {
+        let (upload, download) = self.bandwidth.limits_at(time);
+        for (direction, limiter, limit) in [
+            ("upload", &self.upload_limit, upload),
+            ("download", &self.download_limit, download),
+        ] {
+            if let Some(limiter) = limiter.as_ref().filter(|limiter| limiter.limit() != limit) {
+                tracing::info!("Bandwidth schedule sets the {direction} limit to {limit:?}");
+                limiter.set_limit(limit);
+            }
+        }
+    }
+
+    /// Start a Kademlia bootstrap to populate and refresh the routing table.
+    fn bootstrap(&mut self) {
+        match self.swarm.behaviour_mut().kademlia.bootstrap() {
+            Ok(query_id) => tracing::debug!("Started DHT bootstrap {query_id:?}"),
+            Err(e) => tracing::warn!("Cannot bootstrap the DHT: {e}"),
+        }
+        self.bootstrapped = true;
+    }
+
+    /// Provide the share code's key, again after every bootstrap so the provider record
+    /// stays around. Downloaders find where we are through the DHT.
+    fn publish_share_code(&mut self) {
+        let Some(code) = self
+            .share_code
+            .as_ref()
+            .filter(|_| self.rendezvous.is_none())
+        else {
+            return;
+        };
+        if !share_code::belongs_to(code, self.swarm.local_peer_id()) {
+            tracing::warn!("Downloaders will refuse the share code {code}, it isn't ours");
+        }
+        match self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .start_providing(share_code::provider_key(code))
+        {
+            Ok(query_id) => tracing::debug!("Publishing the share code {query_id:?}"),
+            Err(e) => tracing::warn!("Cannot publish the share code: {e}"),
+        }
+    }
+
+    /// Register the share code at the rendezvous point. It only hands out addresses we
+    /// know to be external, without `AutoNAT` there are none, so our listen addresses and
+    /// the address the point sees us at stand in for them. The latter only over QUIC, TCP
+    /// connections come from a port nobody can dial.
+    fn register_share_code(&mut self, observed: Multiaddr) {
+        let (Some(code), Some((point, _))) = (&self.share_code, &self.rendezvous) else {
+            return;
+        };
+        let (namespace, point) = (share_code::namespace(code), *point);
+        let listeners: Vec<Multiaddr> = self.swarm.listeners().cloned().collect();
+        let observed = Some(observed).filter(|address| {
+            address
+                .iter()
+                .any(|protocol| matches!(protocol, Protocol::QuicV1))
+        });
+        for address in listeners.into_iter().chain(observed) {
+            self.swarm.add_external_address(address);
+        }
+        let result = rendezvous::Namespace::new(namespace)
+            .map_err(|e| e.to_string())
+            .and_then(|namespace| {
+                self.swarm
+                    .behaviour_mut()
+                    .rendezvous
+                    .register(namespace, point, None)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            tracing::warn!("Cannot register the share code at the rendezvous point: {e}");
+        }
+    }
+
+    /// Start looking up the addresses of the host of `code`, at the rendezvous point if
+    /// there is one and in the DHT otherwise.
+    fn look_up_code(&mut self, code: &str, sender: PendingCodeSender) {
+        let Some((point, _)) = self.rendezvous else {
+            let query_id = self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .get_providers(share_code::provider_key(code));
+            self.pending_code_lookups.insert(
+                query_id,
+                CodeLookup {
+                    code: code.to_string(),
+                    sender,
+                    providers: HashSet::new(),
+                },
+            );
+            return;
+        };
+        let namespace = share_code::namespace(code);
+        match rendezvous::Namespace::new(namespace.clone()) {
+            Ok(name) => {
+                self.swarm
+                    .behaviour_mut()
+                    .rendezvous
+                    .discover(Some(name), None, None, point);
+                self.pending_rendezvous_lookups
+                    .insert(namespace, (code.to_string(), sender));
+            }
+            Err(e) => {
+                let _ = sender.send(Err(JunkanooError::other(e.to_string())));
+            }
+        }
+    }
+
+    fn is_rendezvous_point(&self, peer_id: &PeerId) -> bool {
+        self.rendezvous
+            .as_ref()
+            .is_some_and(|(point, _)| point == peer_id)
+    }
+
+    /// Fail the lookups waiting for the rendezvous point, it can't be reached.
+    fn fail_deferred_codes(&mut self, error: &str) {
+        for (_, sender) in std::mem::take(&mut self.deferred_code_lookups) {
+            let _ = sender.send(Err(JunkanooError::Dial(format!(
+                "Cannot reach the rendezvous point: {error}"
+            ))));
+        }
+    }
+
+    async fn handle_rendezvous_event(&mut self, event: rendezvous::client::Event) {
+        match event {
+            rendezvous::client::Event::Registered { namespace, .. } => {
+                tracing::info!("Registered as {namespace} at the rendezvous point");
+                self.event_sender
+                    .send(Event::ShareCodePublished)
+                    .await
+                    .expect("Event receiver not to be dropped.");
+            }
+            rendezvous::client::Event::RegisterFailed {
+                namespace, error, ..
+            } => tracing::warn!("Failed to register as {namespace}: {error:?}"),
+            rendezvous::client::Event::Discovered {
+                registrations,
+                cookie,
+                ..
+            } => {
+                let Some((code, sender)) = cookie.namespace().and_then(|namespace| {
+                    self.pending_rendezvous_lookups
+                        .remove(&namespace.to_string())
+                }) else {
+                    return;
+                };
+                // Registrations are signed, but anyone may register any code
+                let registrants = registrations
+                    .iter()
+                    .map(|registration| registration.record.peer_id());
+                let addresses = match share_code::host(&code, registrants) {
+                    Ok(Some(host)) => {
+                        let registered: Vec<Multiaddr> = registrations
+                            .iter()
+                            .filter(|registration| registration.record.peer_id() == host)
+                            .flat_map(|registration| registration.record.addresses().to_vec())
+                            .collect();
+                        share_code::addresses(host, &registered)
+                    }
+                    Ok(None) => Vec::new(),
+                    Err(e) => {
+                        let _ = sender.send(Err(JunkanooError::other(e)));
+                        return;
+                    }
+                };
+                answer_code_lookup(sender, addresses);
+            }
+            rendezvous::client::Event::DiscoverFailed {
+                namespace, error, ..
+            } => {
+                tracing::warn!("Lookup at the rendezvous point failed: {error:?}");
+                if let Some((_, sender)) = namespace.and_then(|namespace| {
+                    self.pending_rendezvous_lookups
+                        .remove(&namespace.to_string())
+                }) {
+                    answer_code_lookup(sender, Vec::new());
+                }
+            }
+            rendezvous::client::Event::Expired { .. } => {}
+        }
+    }
+
+    fn look_up_deferred_codes(&mut self) {
+        for (code, sender) in std::mem::take(&mut self.deferred_code_lookups) {
+            self.look_up_code(&code, sender);
+        }
+    }
+
+    /// Whether code lookups can start: the rendezvous point is connected, or without one
+    /// the routing table has peers to ask.
+    fn code_lookups_ready(&mut self) -> bool {
+        match self.rendezvous {
+            Some((point, _)) => self.swarm.is_connected(&point),
+            None => self.routing_table_size() > 0,
+        }
+    }
+
+    fn routing_table_size(&mut self) -> usize {
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .kbuckets()
+            .map(|bucket| bucket.num_entries())
+            .sum()
+    }
+
+    async fn handle_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::NewExternalAddrOfPeer { peer_id, address } => {
+                tracing::info!("New external address of peer {peer_id}: {address}");
+            }
+            SwarmEvent::NewListenAddr { address, .. } => {
+                self.on_new_listen_addr(address).await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(event)) => {
+                self.handle_kademlia_event(event).await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Rendezvous(event)) => {
+                self.handle_rendezvous_event(event).await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(event)) => {
+                self.handle_directory_event(event).await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Greeting(event)) => {
+                self.handle_greeting_event(event).await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Manifest(event)) => {
+                self.handle_manifest_event(event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::List(event)) => {
+                self.handle_list_event(event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Push(event)) => {
+                self.handle_push_event(event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Updates(event)) => {
+                self.handle_updates_event(event).await;
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                tracing::info!("Connected to {peer_id}");
+                self.link_quality.entry(peer_id).or_default().relayed = endpoint
+                    .get_remote_address()
+                    .iter()
+                    .any(|protocol| protocol == Protocol::P2pCircuit);
+                self.reconnects.connected(&peer_id);
+
+                if self.is_rendezvous_point(&peer_id) {
+                    // Not a peer to share with, the UI doesn't hear about it
+                    self.look_up_deferred_codes();
+                    return;
+                }
+                if let Some(sender) = self.pending_dial.remove(&connection_id) {
+                    let _ = sender.send(Ok(()));
+                }
+                self.event_sender
+                    .send(Event::PeerConnected(peer_id))
+                    .await
+                    .expect("Event receiver not to be dropped.");
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => {
+                self.on_connection_closed(peer_id, connection_id, num_established)
+                    .await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => {
+                self.handle_identify_event(event).await;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
+                self.handle_ping_event(event).await;
+            }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id,
+                connection_id,
+                error,
+            } => {
+                self.on_dial_failed(peer_id, connection_id, error).await;
+            }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => {
+                // Mostly connections denied by the connection limits
+                tracing::debug!("Incoming connection from {send_back_addr} failed: {error}");
+            }
+            SwarmEvent::Dialing {
+                peer_id: Some(peer_id),
+                ..
+            } => tracing::debug!("Dialing {peer_id}"),
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    /// Tell the UI where we listen and get onto the DHT and the rendezvous point.
+    async fn on_new_listen_addr(&mut self, address: Multiaddr) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        let addr_with_peer = address.with(Protocol::P2p(local_peer_id));
+        tracing::info!("Local node is listening on {:?}", addr_with_peer);
+
+        self.event_sender
+            .send(Event::NewListenAddr(addr_with_peer))
+            .await
+            .expect("Event receiver not to be dropped.");
+
+        // Bootstrap once we are reachable, the timer takes over from there
+        if self.dht_enabled && !self.bootstrapped {
+            self.bootstrap();
+        }
+        if let Some((point, address)) = self.rendezvous.clone().filter(|_| !self.rendezvous_dialed)
+        {
+            self.rendezvous_dialed = true;
+            self.swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(&point, address.clone());
+            if let Err(e) = self.swarm.dial(address) {
+                tracing::warn!("Cannot dial the rendezvous point: {e}");
+                self.fail_deferred_codes(&e.to_string());
+            }
+        }
+    }
+
+    /// Close the one-shot share once its downloader leaves and schedule a reconnect.
+    async fn on_connection_closed(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        num_established: u32,
+    ) {
+        tracing::debug!("Connection closed: {peer_id} {connection_id} {num_established}");
+        if self.is_rendezvous_point(&peer_id) {
+            return;
+        }
+
+        // The one-shot downloader left after getting at least part of the share
+        let downloader_left = self
+            .once
+            .as_ref()
+            .is_some_and(|once| once.peer == Some(peer_id) && !once.served.is_empty());
+        if downloader_left && num_established == 0 {
+            tracing::info!("Peer {peer_id} finished, closing the one-shot share");
+            self.finish_once_share().await;
+        }
+        if num_established == 0 {
+            self.link_quality.remove(&peer_id);
+        }
+
+        self.event_sender
+            .send(Event::PeerDisconnected())
+            .await
+            .expect("Event receiver not to be dropped.");
+
+        if num_established == 0 {
+            let lost = self
+                .reconnects
+                .connection_lost(peer_id, tokio::time::Instant::now());
+            if let Some(attempt) = lost {
+                self.report_reconnect(peer_id, attempt).await;
+            }
+        }
+    }
+
+    /// Fail the dial waiting on this connection, or count it against the peer's redial.
+    async fn on_dial_failed(
+        &mut self,
+        peer_id: Option<PeerId>,
+        connection_id: ConnectionId,
+        error: DialError,
+    ) {
+        if let Some(sender) = self.pending_dial.remove(&connection_id) {
+            let _ = sender.send(Err(error.into()));
+        } else if let Some(peer_id) = peer_id {
+            if self.is_rendezvous_point(&peer_id) {
+                tracing::warn!("Cannot reach the rendezvous point: {error}");
+                self.fail_deferred_codes(&error.to_string());
+            } else if let Some(attempt) = self
+                .reconnects
+                .dial_failed(peer_id, tokio::time::Instant::now())
+            {
+                tracing::debug!("Redial of {peer_id} failed: {error}");
+                self.report_reconnect(peer_id, attempt).await;
+            }
+        }
+    }
+
+    /// Log an event nothing else handles, sampled so busy swarms don't flood the log.
+    fn log_unhandled(&mut self, event: &impl std::fmt::Debug) {
+        if let Some(skipped) = self.event_log.sample() {
+            tracing::debug!("{event:?} ({skipped} events not logged)");
+        }
+    }
+
+    async fn handle_kademlia_event(&mut self, event: kad::Event) {
+        match event {
+            kad::Event::OutboundQueryProgressed {
+                result: kad::QueryResult::Bootstrap(result),
+                step,
+                ..
+            } => {
+                match result {
+                    Ok(kad::BootstrapOk {
+                        peer,
+                        num_remaining,
+                    }) => tracing::debug!("Bootstrapped with {peer}, {num_remaining} remaining"),
+                    Err(e) => tracing::warn!("DHT bootstrap failed: {e}"),
+                }
+
+                if step.last {
+                    let routing_table_size = self.routing_table_size();
+                    tracing::info!("DHT routing table holds {routing_table_size} peers");
+                    self.publish_share_code();
+                    if self.rendezvous.is_none() {
+                        self.look_up_deferred_codes();
+                    }
+                    self.event_sender
+                        .send(Event::DhtStatus { routing_table_size })
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                }
+            }
+            kad::Event::OutboundQueryProgressed {
+                result: kad::QueryResult::StartProviding(result),
+                ..
+            } => match result {
+                Ok(_) => {
+                    tracing::info!("Published the share code");
+                    self.event_sender
+                        .send(Event::ShareCodePublished)
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                }
+                Err(e) => tracing::warn!("Failed to publish the share code: {e}"),
+            },
+            kad::Event::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::GetProviders(result),
+                step,
+                ..
+            } => self.providers_found(id, result, step.last),
+            kad::Event::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::GetClosestPeers(result),
+                ..
+            } => {
+                let Some((host, sender)) = self.pending_host_lookups.remove(&id) else {
+                    return;
+                };
+                let peers = match result {
+                    Ok(kad::GetClosestPeersOk { peers, .. })
+                    | Err(kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                };
+                let addresses = peers
+                    .into_iter()
+                    .find(|peer| peer.peer_id == host)
+                    .map(|peer| share_code::addresses(host, &peer.addrs))
+                    .unwrap_or_default();
+                answer_code_lookup(sender, addresses);
+            }
+            kad::Event::InboundRequest {
+                request:
+                    kad::InboundRequest::AddProvider {
+                        record: Some(record),
+                    },
+            } => {
+                // The behaviour made sure the provider is the peer that sent the record
+                let store = self.swarm.behaviour_mut().kademlia.store_mut();
+                if let Err(e) = kad::store::RecordStore::add_provider(store, record) {
+                    tracing::debug!("Not storing a provider record, {e}");
+                }
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    /// Collect the providers a share code lookup heard of, once it finished find the
+    /// addresses of the host the code belongs to.
+    fn providers_found(&mut self, id: kad::QueryId, result: kad::GetProvidersResult, last: bool) {
+        let Some(lookup) = self.pending_code_lookups.get_mut(&id) else {
+            return;
+        };
+        // Every provider is heard, a second one the code belongs to fails the lookup
+        if let Ok(kad::GetProvidersOk::FoundProviders { providers, .. }) = result {
+            lookup.providers.extend(providers);
+            if !last {
+                return;
+            }
+        }
+        let Some(lookup) = self.pending_code_lookups.remove(&id) else {
+            return;
+        };
+        match share_code::host(&lookup.code, lookup.providers) {
+            Ok(Some(host)) => {
+                tracing::info!("Share code belongs to {host}, looking for its addresses");
+                let query_id = self.swarm.behaviour_mut().kademlia.get_closest_peers(host);
+                self.pending_host_lookups
+                    .insert(query_id, (host, lookup.sender));
+            }
+            Ok(None) => answer_code_lookup(lookup.sender, Vec::new()),
+            Err(e) => {
+                let _ = lookup.sender.send(Err(JunkanooError::other(e)));
+            }
+        }
+    }
+
+    async fn handle_directory_event(
+        &mut self,
+        event: request_response::Event<DisplayRequest, DisplayResponse>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                request_response::Message::Request { .. } if !self.share_open => {
+                    // Dropping the channel fails the request on the downloader's side
+                    tracing::info!("Ignoring directory request from {peer}, the share is closed");
+                }
+                request_response::Message::Request { .. } if !self.is_authorized(peer) => {
+                    tracing::info!("Ignoring directory request from {peer}, no password given");
+                }
+                request_response::Message::Request { .. } if !self.accepts_peer(peer) => {
+                    tracing::info!("Ignoring directory request from {peer}, the share is claimed");
+                }
+                request_response::Message::Request { channel, .. } => {
+                    // When receiving a directory request, respond with the shared items.
+                    // Early downloaders of a scheduled share only learn when it opens and
+                    // keep polling until then.
+                    // Peers that list directories on demand only poll for the version
+                    let opens_at = self.pending_opening();
+                    let items = if opens_at.is_some() {
+                        tracing::info!("Peer {peer} asked before the share opened");
+                        Vec::new()
+                    } else if self.lists_on_demand(peer) {
+                        Vec::new()
+                    } else {
+                        self.registry.read().items().to_vec()
+                    };
+
+                    let response = DisplayResponse {
+                        items,
+                        max_concurrent_transfers: Some(MAX_INCOMING_TRANSFERS),
+                        opens_at: opens_at.map(|opens_at| {
+                            opens_at
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |since_epoch| since_epoch.as_secs())
+                        }),
+                        label: self.label.clone(),
+                        version: if opens_at.is_some() {
+                            0
+                        } else {
+                            self.registry.read().version()
+                        },
+                    };
+
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        tracing::debug!("Peer {peer} left before the listing was sent");
+                    }
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(limit) = response.max_concurrent_transfers {
+                        self.host_transfer_limits.insert(peer, limit);
+                    }
+                    let previous = self.share_versions.insert(peer, response.version);
+                    if previous.is_some_and(|previous| previous != response.version) {
+                        tracing::info!("{peer} updated the share to version {}", response.version);
+                        self.event_sender
+                            .send(Event::ShareUpdated(response.items.clone()))
+                            .await
+                            .expect("Event receiver not to be dropped.");
+                    }
+                    if let Some(sender) = self.pending_request_display.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                if let Some(sender) = self.pending_request_display.remove(&request_id) {
+                    let _ = sender.send(Err(error.into()));
+                } else {
+                    tracing::warn!("Received failure for unknown request ID: {:?}", request_id);
+                }
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    async fn handle_greeting_event(&mut self, event: request_response::Event<Hello, Welcome>) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                request_response::Message::Request { .. } if !self.share_open => {
+                    tracing::info!("Ignoring greeting from {peer}, the share is closed");
+                }
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let authorized = match &self.password {
+                        Some(password) => request.password.as_ref() == Some(password),
+                        None => true,
+                    };
+                    if authorized {
+                        self.authorized.insert(peer);
+                    } else {
+                        tracing::warn!("Peer {peer} greeted without the right password");
+                    }
+                    tracing::info!(
+                        "Peer {peer} greeted as {:?}, protocol {}, features {:?}",
+                        request.greeting.display_name,
+                        request.greeting.protocol_version,
+                        request.greeting.features
+                    );
+                    let greeting = request.greeting.negotiate(&self.greeting);
+                    self.peer_greetings.insert(peer, greeting.clone());
+                    let welcome = Welcome {
+                        greeting: self.greeting.clone(),
+                        authorized,
+                    };
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .greeting
+                        .send_response(channel, welcome)
+                        .is_err()
+                    {
+                        tracing::debug!("Peer {peer} left before the greeting was answered");
+                    }
+                    self.event_sender
+                        .send(Event::PeerGreeted {
+                            peer_id: peer,
+                            greeting,
+                        })
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    tracing::info!(
+                        "Host {peer} greeted as {:?}, protocol {}, features {:?}",
+                        response.greeting.display_name,
+                        response.greeting.protocol_version,
+                        response.greeting.features
+                    );
+                    let response = Welcome {
+                        greeting: response.greeting.negotiate(&self.greeting),
+                        ..response
+                    };
+                    self.peer_greetings.insert(peer, response.greeting.clone());
+                    if let Some(sender) = self.pending_greetings.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                if let Some(sender) = self.pending_greetings.remove(&request_id) {
+                    let _ = sender.send(Err(error.into()));
+                }
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    fn handle_manifest_event(&mut self, event: request_response::Event<ManifestRequest, Manifest>) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                // Claiming a `--once` share comes last, requests turned away don't claim it
+                request_response::Message::Request { .. }
+                    if !self.share_open
+                        || self.pending_opening().is_some()
+                        || !self.is_authorized(peer)
+                        || !self.accepts_peer(peer) =>
+                {
+                    tracing::info!("Ignoring manifest request from {peer}");
+                }
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let manifest = self.registry.read().manifest(&request.paths);
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .manifest
+                        .send_response(channel, manifest)
+                        .is_err()
+                    {
+                        tracing::debug!("Peer {peer} left before the manifest was sent");
+                    }
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(sender) = self.pending_manifests.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                if let Some(sender) = self.pending_manifests.remove(&request_id) {
+                    let _ = sender.send(Err(error.into()));
+                }
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    fn handle_list_event(
+        &mut self,
+        event: request_response::Event<ListDirectoryRequest, ListDirectoryResponse>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                request_response::Message::Request { .. }
+                    if !self.share_open
+                        || !self.is_authorized(peer)
+                        || !self.accepts_peer(peer) =>
+                {
+                    tracing::info!("Ignoring listing request from {peer}");
+                }
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let response = if self.pending_opening().is_some() {
+                        ListDirectoryResponse::default()
+                    } else {
+                        let registry = self.registry.read();
+                        ListDirectoryResponse {
+                            items: registry.list(Path::new(&request.path), request.recursive),
+                            version: registry.version(),
                         }
-                        Err(e) => {
-                            let _ = sender.send(Err(Box::new(e)));
+                    };
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .list
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        tracing::debug!("Peer {peer} left before the listing was sent");
+                    }
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(sender) = self.pending_listings.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                if let Some(sender) = self.pending_listings.remove(&request_id) {
+                    let _ = sender.send(Err(error.into()));
+                }
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    fn handle_push_event(&mut self, event: request_response::Event<PushRequest, PushResponse>) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let rejection = if !self.share_open {
+                        Some("the share is closed")
+                    } else if !self.is_authorized(peer) {
+                        Some("the share requires a password")
+                    } else if self.push_directory.is_none() {
+                        Some("the host doesn't accept pushes")
+                    } else {
+                        None
+                    };
+                    if let (Some(directory), None) = (self.push_directory.clone(), rejection) {
+                        tracing::info!(
+                            "Peer {peer} offered {} files, comparing them",
+                            request.entries.len()
+                        );
+                        let push_sender = self.push_sender.clone();
+                        tokio::task::spawn_blocking(move || {
+                            let files = push::wanted(&directory, &request.entries)
+                                .into_iter()
+                                .map(|entry| RequestedFile {
+                                    path: entry.path,
+                                    hash: entry.hash,
+                                })
+                                .collect();
+                            let _ = push_sender.unbounded_send((channel, peer, files));
+                        });
+                    } else {
+                        let rejection = rejection.map(str::to_string);
+                        tracing::info!("Turning down the push of {peer}: {rejection:?}");
+                        let response = PushResponse {
+                            requested: Vec::new(),
+                            rejection,
+                        };
+                        if self
+                            .swarm
+                            .behaviour_mut()
+                            .push
+                            .send_response(channel, response)
+                            .is_err()
+                        {
+                            tracing::debug!("Peer {peer} left before the push was answered");
                         }
                     }
                 }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(sender) = self.pending_pushes.remove(&request_id) {
+                        let _ = sender.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                if let Some(sender) = self.pending_pushes.remove(&request_id) {
+                    let _ = sender.send(Err(error.into()));
+                }
             }
-            Command::RequestFiles {
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    async fn handle_updates_event(
+        &mut self,
+        event: request_response::Event<ShareUpdate, ShareUpdateAck>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            } => {
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .updates
+                    .send_response(channel, ShareUpdateAck)
+                    .is_err()
+                {
+                    tracing::debug!("Host {peer} left before its update was acknowledged");
+                }
+                // Changes to another version than ours would leave a wrong listing, the
+                // next poll gets the whole one instead
+                match self.share_versions.get_mut(&peer) {
+                    Some(version) if *version == request.base => {
+                        tracing::info!("{peer} updated the share to version {}", request.version);
+                        *version = request.version;
+                        self.event_sender
+                            .send(Event::ShareChanged(request))
+                            .await
+                            .expect("Event receiver not to be dropped.");
+                    }
+                    _ => tracing::debug!("Ignoring update {} from {peer}", request.version),
+                }
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                tracing::debug!("Failed to push the share update to {peer}: {error}");
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    async fn handle_identify_event(&mut self, event: identify::Event) {
+        match event {
+            identify::Event::Received { peer_id, info, .. } => {
+                if self.is_rendezvous_point(&peer_id) {
+                    self.register_share_code(info.observed_addr.clone());
+                }
+                // Where else the peer listens, e.g. for the DHT to find it after an address change
+                for address in &info.listen_addrs {
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, address.clone());
+                }
+                if info.protocol_version == greeting::identify_protocol() {
+                    tracing::debug!("{peer_id} runs {}", info.agent_version);
+                } else {
+                    tracing::warn!(
+                        "{peer_id} runs {} speaking {}, we speak {}, only shared features are used",
+                        info.agent_version,
+                        info.protocol_version,
+                        greeting::identify_protocol()
+                    );
+                }
+                self.event_sender
+                    .send(Event::PeerIdentified {
+                        peer_id,
+                        agent_version: info.agent_version,
+                    })
+                    .await
+                    .expect("Event receiver not to be dropped.");
+            }
+            e => self.log_unhandled(&e),
+        }
+    }
+
+    async fn handle_ping_event(&mut self, event: ping::Event) {
+        let ping::Event { peer, result, .. } = event;
+        let quality = self.link_quality.entry(peer).or_default();
+        match result {
+            Ok(rtt) => quality.record_rtt(rtt),
+            Err(e) => {
+                tracing::debug!("Ping to {peer} failed: {e}");
+                quality.record_stall();
+            }
+        }
+        if let (Some(score), Some(rtt)) = (quality.score(), quality.rtt()) {
+            self.event_sender
+                .send(Event::ConnectionQuality {
+                    peer_id: peer,
+                    score,
+                    rtt,
+                })
+                .await
+                .expect("Event receiver not to be dropped.");
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::StartListening { addr, sender } => {
+                let _ = match self.swarm.listen_on(addr) {
+                    Ok(_) => sender.send(Ok(())),
+                    Err(e) => sender.send(Err(JunkanooError::other(e.to_string()))),
+                };
+            }
+            Command::Dial {
                 peer_id,
-                file_names,
+                peer_addr,
+                sender,
+            } => {
+                self.reconnects.remember(peer_id, peer_addr.clone());
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, peer_addr.clone());
+                let opts = DialOpts::from(peer_addr);
+                let connection_id = opts.connection_id();
+                match self.swarm.dial(opts) {
+                    Ok(()) => {
+                        self.pending_dial.insert(connection_id, sender);
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into()));
+                    }
+                }
+            }
+            Command::Greet {
+                peer_id,
+                password,
                 sender,
             } => {
+                let hello = Hello {
+                    greeting: self.greeting.clone(),
+                    password,
+                };
                 let request_id = self
                     .swarm
                     .behaviour_mut()
-                    .request_response
-                    .send_request(&peer_id, DisplayRequest);
+                    .greeting
+                    .send_request(&peer_id, hello);
+                self.pending_greetings.insert(request_id, sender);
+            }
+            Command::Push {
+                peer_id,
+                paths,
+                sender,
+            } => {
+                let entries = self.registry.read().manifest(&paths).entries;
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .push
+                    .send_request(&peer_id, PushRequest { entries });
+                self.pending_pushes.insert(request_id, sender);
+            }
+            Command::RequestFiles {
+                peer_id,
+                files,
+                directory,
+                delta,
+                sender,
+            } => {
+                let stream_control = self.swarm.behaviour().file_stream.new_control();
+                let mut event_sender = self.event_sender.clone();
+                let download_limit = self.download_limit.clone();
+                let preserve = self.preserve;
+                let request_timeout = self.request_timeout;
+                let conflict = self.conflict;
+                let cancel = self.cancel_downloads.clone();
+                let transfer_controls = self.transfer_controls.clone();
+                let pending_conflicts = self.pending_conflicts.clone();
+                let manifest_requests = self.manifest_sender.clone();
+                let controls: HashMap<String, watch::Receiver<TransferControl>> = {
+                    let mut transfer_controls = transfer_controls.lock();
+                    files
+                        .iter()
+                        .map(|file| {
+                            let (sender, receiver) = watch::channel(TransferControl::Run);
+                            transfer_controls.insert(file.path.clone(), sender);
+                            (file.path.clone(), receiver)
+                        })
+                        .collect()
+                };
+                let supports = |feature| self.peer_supports(&peer_id, feature);
+                let compression = self.peer_compression(&peer_id);
+                let delta = delta && supports("delta");
+                // Broken off downloads continue as a delta against the part received
+                let resume = supports("resume") && supports("delta");
+                let hashes = supports("hash");
+                let files: Vec<RequestedFile> = if hashes {
+                    files
+                } else {
+                    files
+                        .into_iter()
+                        .map(|file| RequestedFile { hash: None, ..file })
+                        .collect()
+                };
+                // Sizes and hashes of the files as they are now, before any stream opens
+                let manifest = supports("manifest").then(|| {
+                    let (manifest_sender, manifest_receiver) = oneshot::channel();
+                    let paths = files.iter().map(|file| file.path.clone()).collect();
+                    let request_id = self
+                        .swarm
+                        .behaviour_mut()
+                        .manifest
+                        .send_request(&peer_id, ManifestRequest { paths });
+                    self.pending_manifests.insert(request_id, manifest_sender);
+                    manifest_receiver
+                });
 
-                // Remove the sender from pending_request_file since we're moving it into the task
-                self.pending_request_file.remove(&request_id);
+                // Opening more streams than the host serves at once only queues them there
+                let parallel = self
+                    .host_transfer_limits
+                    .get(&peer_id)
+                    .map_or(self.parallel_downloads, |&limit| {
+                        self.parallel_downloads.min(limit)
+                    })
+                    .max(1);
+                tracing::info!("Downloading {} files, {parallel} at a time", files.len());
 
-                let mut stream_control = self.swarm.behaviour().file_stream.new_control();
-                let file_names_clone = file_names;
-                let mut event_sender = self.event_sender.clone();
+                let download = tokio::spawn(async move {
+                    let mut successful_transfers = Vec::new();
+                    let mut failed_transfers = Vec::new();
 
-                tokio::spawn(async move {
-                    // Open a new stream to the peer instead of waiting for an incoming stream
-                    match stream_control
-                        .open_stream(peer_id, JUNKANOO_FILE_PROTOCOL)
+                    let file_names = files.iter().map(|file| file.path.clone()).collect();
+                    event_sender
+                        .send(Event::TransfersQueued(file_names))
                         .await
-                    {
-                        Ok(_) => {
-                            let mut successful_transfers = Vec::new();
-                            let mut failed_transfers = Vec::new();
-
-                            for file_name in file_names_clone {
-                                // Open a new stream for each file
-                                match stream_control
-                                    .open_stream(peer_id, JUNKANOO_FILE_PROTOCOL)
-                                    .await
-                                {
-                                    Ok(mut stream) => {
-                                        tracing::info!(
-                                            "Creating FileTransfer with path: {}",
-                                            file_name
-                                        );
+                        .expect("Event receiver not to be dropped.");
 
-                                        // First send the file path request
-                                        let path_bytes = file_name.as_bytes();
-                                        if let Err(e) = stream
-                                            .write_all(&(path_bytes.len() as u64).to_le_bytes())
-                                            .await
-                                        {
-                                            tracing::error!("Failed to send path length: {}", e);
-                                            failed_transfers.push(file_name);
-                                            continue;
+                    let manifest = match manifest {
+                        Some(manifest) => match manifest.await {
+                            Ok(Ok(manifest)) => Some(manifest),
+                            Ok(Err(e)) => {
+                                tracing::warn!("Host sent no manifest, downloading without: {}", e);
+                                None
+                            }
+                            Err(_) => None,
+                        },
+                        None => None,
+                    };
+                    let files = match &manifest {
+                        Some(manifest) => {
+                            apply_manifest(
+                                manifest,
+                                files,
+                                hashes,
+                                conflict,
+                                &pending_conflicts,
+                                directory.as_ref(),
+                                &mut event_sender,
+                                &mut successful_transfers,
+                                &mut failed_transfers,
+                            )
+                            .await
+                        }
+                        None => files.into_iter().map(|file| (file, None)).collect(),
+                    };
+
+                    let progress_sender = event_sender.clone();
+                    let mut downloads = futures::stream::iter(files)
+                        .map(|(mut file, save_as)| {
+                            let mut entry = manifest
+                                .as_ref()
+                                .and_then(|manifest| manifest.entry(&file.path))
+                                .cloned();
+                            let manifest_requests = manifest_requests.clone();
+                            let mut stream_control = stream_control.clone();
+                            let download_limit = download_limit.clone();
+                            let progress_sender = progress_sender.clone();
+                            let directory = directory.clone();
+                            let cancel = cancel.clone();
+                            let mut control = controls.get(&file.path).cloned();
+                            async move {
+                                let mut request = FileRequest {
+                                    path: file.path.clone(),
+                                    offset: 0,
+                                    length: None,
+                                    delta,
+                                    compression,
+                                    archive: false,
+                                };
+                                let mut attempt = 0;
+                                let mut changes = 0;
+                                loop {
+                                    // Paused while still queued, no stream is opened yet
+                                    if let Some(control) = &mut control {
+                                        if !proceed(control).await {
+                                            break (file.path, Err(JunkanooError::Cancelled));
                                         }
-                                        if let Err(e) = stream.write_all(path_bytes).await {
-                                            tracing::error!("Failed to send path: {}", e);
-                                            failed_transfers.push(file_name);
-                                            continue;
+                                    }
+                                    let result = download_file(
+                                        &mut stream_control,
+                                        peer_id,
+                                        &request,
+                                        request_timeout,
+                                        file.hash.clone(),
+                                        entry.as_ref().map(|entry| entry.size),
+                                        directory.clone(),
+                                        preserve,
+                                        conflict,
+                                        save_as.clone(),
+                                        download_limit.clone(),
+                                        cancel.clone(),
+                                        control.clone(),
+                                        progress_sender.clone(),
+                                    )
+                                    .await;
+                                    let e = match result {
+                                        Err(e) if !matches!(e, JunkanooError::Cancelled) => e,
+                                        result => break (file.path, result),
+                                    };
+                                    // A host stops sending a file that changes under it, the
+                                    // stream breaks off like a lost connection
+                                    let update = match &entry {
+                                        Some(entry) => {
+                                            source_update(&manifest_requests, peer_id, entry).await
                                         }
-                                        if let Err(e) = stream.flush().await {
-                                            tracing::error!("Failed to flush stream: {}", e);
-                                            failed_transfers.push(file_name);
+                                        None => SourceUpdate::Unchanged,
+                                    };
+                                    match update {
+                                        SourceUpdate::Changed(current)
+                                            if changes < MAX_SOURCE_CHANGES =>
+                                        {
+                                            changes += 1;
+                                            tracing::warn!(
+                                                "'{}' changed on the host during the transfer, downloading it again",
+                                                file.path
+                                            );
+                                            if hashes {
+                                                file.hash.clone_from(&current.hash);
+                                            }
+                                            let _ = progress_sender
+                                                .clone()
+                                                .send(Event::TransfersSized(vec![(
+                                                    file.path.clone(),
+                                                    current.size,
+                                                )]))
+                                                .await;
+                                            entry = Some(current);
+                                            request.delta |= resume;
                                             continue;
                                         }
-
-                                        // Now receive the file
-                                        let receiver = FileReceiver::new();
-                                        match receiver.receive_file(&mut stream).await {
-                                            Ok(file_name) => {
-                                                tracing::info!(
-                                                    "Successfully received file '{}' from peer {}",
-                                                    file_name,
-                                                    peer_id
-                                                );
-                                                successful_transfers.push(file_name);
-                                            }
-                                            Err(e) => {
-                                                tracing::error!(
-                                                    "Transfer failed for file '{}' with error: {}",
-                                                    file_name,
-                                                    e
-                                                );
-                                                failed_transfers.push(file_name);
-                                            }
+                                        SourceUpdate::Changed(_) | SourceUpdate::Removed => {
+                                            let error = JunkanooError::SourceChanged;
+                                            break (file.path, Err(error));
                                         }
+                                        SourceUpdate::Unchanged => {}
                                     }
-                                    Err(e) => {
-                                        tracing::error!(
-                                            "Failed to open stream for file '{}': {}",
-                                            file_name,
+                                    if attempt < reconnect::MAX_ATTEMPTS
+                                        && !cancel.load(Ordering::SeqCst)
+                                        && e.is_connection_error()
+                                    {
+                                        attempt += 1;
+                                        tracing::warn!(
+                                            "Lost the connection while downloading '{}', retrying: {}",
+                                            file.path,
                                             e
                                         );
-                                        failed_transfers.push(file_name);
+                                        tokio::time::sleep(reconnect::backoff(attempt)).await;
+                                        // With `resume` the partial file is the base of a
+                                        // delta, so only the missing part is sent again
+                                        request.delta |= resume;
+                                        continue;
                                     }
+                                    break (file.path, Err(e));
                                 }
                             }
-
-                            if !successful_transfers.is_empty() {
+                        })
+                        .buffer_unordered(parallel);
+
+                    while let Some((file_name, result)) = downloads.next().await {
+                        if let Ok(ReceivedFile {
+                            conflict: Some(conflict),
+                            ..
+                        }) = &result
+                        {
+                            event_sender
+                                .send(Event::TransferConflict {
+                                    path: file_name.clone(),
+                                    conflict: conflict.clone(),
+                                })
+                                .await
+                                .expect("Event receiver not to be dropped.");
+                        }
+                        match result {
+                            Ok(received) if received.conflict == Some(Conflict::Skipped) => {
+                                tracing::info!("Kept the existing '{}'", received.path);
+                                successful_transfers.push(received.path);
+                            }
+                            Ok(received) if received.up_to_date => {
+                                tracing::info!("'{}' is already up to date", received.path);
                                 event_sender
-                                    .send(Event::DownloadCompleted(successful_transfers))
+                                    .send(Event::TransferUpToDate(file_name))
                                     .await
                                     .expect("Event receiver not to be dropped.");
+                                successful_transfers.push(received.path);
                             }
-
-                            if failed_transfers.is_empty() {
-                                let _ = sender.send(Ok(Vec::new()));
-                            } else {
-                                let failed_files = failed_transfers.join(", ");
+                            Ok(received) => {
+                                tracing::info!(
+                                    "Successfully received file '{}' from peer {}",
+                                    received.path,
+                                    peer_id
+                                );
                                 event_sender
-                                    .send(Event::DownloadFailed(failed_transfers))
+                                    .send(Event::TransferCompleted(file_name.clone()))
                                     .await
                                     .expect("Event receiver not to be dropped.");
-
-                                let _ = sender.send(Err(Box::new(std::io::Error::other(format!(
-                                    "Failed to transfer files: {failed_files}"
-                                )))
-                                    as Box<dyn Error + Send>));
+                                successful_transfers.push(received.path);
+                            }
+                            Err(JunkanooError::Cancelled) => {
+                                tracing::info!("Cancelled the transfer of '{}'", file_name);
+                                event_sender
+                                    .send(Event::TransferCancelled(file_name.clone()))
+                                    .await
+                                    .expect("Event receiver not to be dropped.");
+                                failed_transfers.push(file_name);
+                            }
+                            Err(JunkanooError::HashMismatch) => {
+                                tracing::error!("'{}' arrived with other content", file_name);
+                                event_sender
+                                    .send(Event::TransferCorrupted(file_name.clone()))
+                                    .await
+                                    .expect("Event receiver not to be dropped.");
+                                failed_transfers.push(file_name);
+                            }
+                            Err(e) => {
+                                tracing::error!(
+                                    "Transfer failed for file '{}' with error: {}",
+                                    file_name,
+                                    e
+                                );
+                                event_sender
+                                    .send(Event::TransferFailed {
+                                        path: file_name.clone(),
+                                        error: e.to_string(),
+                                    })
+                                    .await
+                                    .expect("Event receiver not to be dropped.");
+                                failed_transfers.push(file_name);
                             }
                         }
-                        Err(e) => {
-                            tracing::error!("Failed to open stream: {}", e);
-                            event_sender
-                                .send(Event::DownloadFailed(file_names_clone))
-                                .await
-                                .expect("Event receiver not to be dropped.");
-                            let _ = sender.send(Err(Box::new(e) as Box<dyn Error + Send>));
+                    }
+
+                    drop(downloads);
+                    {
+                        let mut transfer_controls = transfer_controls.lock();
+                        for path in controls.keys() {
+                            transfer_controls.remove(path);
                         }
                     }
+
+                    // Failures go out first, so whoever quits on completion has seen them
+                    let failed_files = failed_transfers.join(", ");
+                    if !failed_transfers.is_empty() {
+                        event_sender
+                            .send(Event::DownloadFailed(failed_transfers))
+                            .await
+                            .expect("Event receiver not to be dropped.");
+                    }
+
+                    if !successful_transfers.is_empty() {
+                        event_sender
+                            .send(Event::DownloadCompleted(successful_transfers))
+                            .await
+                            .expect("Event receiver not to be dropped.");
+                    }
+
+                    if failed_files.is_empty() {
+                        let _ = sender.send(Ok(Vec::new()));
+                    } else {
+                        let _ = sender.send(Err(JunkanooError::other(format!(
+                            "Failed to transfer files: {failed_files}"
+                        ))));
+                    }
                 });
+                self.downloads.retain(|download| !download.is_finished());
+                self.downloads.push(download);
             }
-            Command::InsertDirectoryItems {
+            Command::RequestArchive {
                 peer_id,
+                path,
+                directory,
+                format,
+                sender,
+            } => {
+                if !self.peer_supports(&peer_id, "archive") {
+                    let _ = sender.send(Err(JunkanooError::other(
+                        "the host doesn't send directories as archives",
+                    )));
+                    return;
+                }
+                let request = FileRequest {
+                    path,
+                    offset: 0,
+                    length: None,
+                    delta: false,
+                    compression: self.peer_compression(&peer_id),
+                    archive: true,
+                };
+                let mut stream_control = self.swarm.behaviour().file_stream.new_control();
+                let mut event_sender = self.event_sender.clone();
+                let download_limit = self.download_limit.clone();
+                let request_timeout = self.request_timeout;
+                let conflict = self.conflict;
+                let cancel = self.cancel_downloads.clone();
+                let download = tokio::spawn(async move {
+                    let path = request.path.clone();
+                    event_sender
+                        .send(Event::TransfersQueued(vec![path.clone()]))
+                        .await
+                        .expect("Event receiver not to be dropped.");
+                    let result = download_archive(
+                        &mut stream_control,
+                        peer_id,
+                        &request,
+                        request_timeout,
+                        format,
+                        directory,
+                        conflict,
+                        download_limit,
+                        cancel,
+                        event_sender.clone(),
+                    )
+                    .await;
+                    let (events, reply) = match result {
+                        Ok(received) => {
+                            tracing::info!("Received '{}' as '{}'", path, received.path);
+                            (
+                                [
+                                    Event::TransferCompleted(path),
+                                    Event::DownloadCompleted(vec![received.path]),
+                                ],
+                                Ok(()),
+                            )
+                        }
+                        Err(e) => {
+                            tracing::error!("Archive download of '{}' failed: {}", path, e);
+                            let event = if matches!(e, JunkanooError::Cancelled) {
+                                Event::TransferCancelled(path.clone())
+                            } else {
+                                Event::TransferFailed {
+                                    path: path.clone(),
+                                    error: e.to_string(),
+                                }
+                            };
+                            ([event, Event::DownloadFailed(vec![path])], Err(e))
+                        }
+                    };
+                    for event in events {
+                        event_sender
+                            .send(event)
+                            .await
+                            .expect("Event receiver not to be dropped.");
+                    }
+                    let _ = sender.send(reply);
+                });
+                self.downloads.retain(|download| !download.is_finished());
+                self.downloads.push(download);
+            }
+            Command::UpdateDirectoryItems {
                 directory_items,
                 sender,
             } => {
-                self.pending_directory_items
-                    .insert(peer_id, directory_items);
+                if !self.share_open {
+                    let _ = sender.send(Ok(()));
+                    return;
+                }
+                let mut registry = self.registry.write();
+                let update = registry.update(directory_items);
+                tracing::debug!("Published share manifest version {}", registry.version());
+                drop(registry);
 
+                if let Some(update) = update {
+                    self.push_share_update(&update);
+                }
                 let _ = sender.send(Ok(()));
             }
+            Command::RequestFileRange {
+                peer_id,
+                path,
+                range,
+                sender,
+            } => {
+                let mut stream_control = self.swarm.behaviour().file_stream.new_control();
+                let request = FileRequest {
+                    path,
+                    offset: range.start,
+                    length: Some(range.end.saturating_sub(range.start)),
+                    delta: false,
+                    compression: self.peer_compression(&peer_id),
+                    archive: false,
+                };
+                let length =
+                    usize::try_from(range.end.saturating_sub(range.start)).unwrap_or(usize::MAX);
+                let download_limit = self.download_limit.clone();
+                let request_timeout = self.request_timeout;
+                tokio::spawn(async move {
+                    let result = async {
+                        let opening = async {
+                            let mut stream = stream_control
+                                .open_stream(peer_id, JUNKANOO_FILE_PROTOCOL)
+                                .await?;
+                            request.write_to(&mut stream).await?;
+                            Ok::<_, JunkanooError>(stream)
+                        };
+                        let mut stream = tokio::time::timeout(request_timeout, opening)
+                            .await
+                            .map_err(|_| {
+                                JunkanooError::Timeout(format!("requesting '{}'", request.path))
+                            })??;
+                        FileReceiver::new()
+                            .with_rate_limit(download_limit)
+                            .receive_range(&mut stream, length)
+                            .await
+                    }
+                    .await;
+                    let _ = sender.send(result);
+                });
+            }
             Command::RequestDisplay { peer_id, sender } => {
                 let request_id = self
                     .swarm
@@ -594,9 +2758,802 @@ impl EventLoop {
                     .send_request(&peer_id, DisplayRequest);
                 self.pending_request_display.insert(request_id, sender);
             }
+            Command::ListDirectory {
+                peer_id,
+                path,
+                recursive,
+                sender,
+            } => {
+                let request_id = self
+                    .swarm
+                    .behaviour_mut()
+                    .list
+                    .send_request(&peer_id, ListDirectoryRequest { path, recursive });
+                self.pending_listings.insert(request_id, sender);
+            }
+            Command::ResolveCode { code, sender } => {
+                if !self.dht_enabled && self.rendezvous.is_none() {
+                    let _ = sender.send(Err(JunkanooError::other(
+                        "Share codes are looked up in the DHT, which --lan-only leaves out, \
+                         or at a --rendezvous point",
+                    )));
+                } else if self.code_lookups_ready() {
+                    self.look_up_code(&code, sender);
+                } else {
+                    self.deferred_code_lookups.push((code, sender));
+                }
+            }
             Command::GetListeningAddrs { sender } => {
                 let _ = sender.send(Ok(self.swarm.listeners().cloned().collect()));
             }
+            Command::CloseShare { sender } => {
+                self.close_share();
+                let _ = sender.send(Ok(()));
+            }
+            Command::Shutdown { sender } => {
+                tracing::info!("Shutting down the network");
+                self.cancel_downloads.store(true, Ordering::SeqCst);
+                // Paused downloads wake up to stop as well
+                for control in self.transfer_controls.lock().values() {
+                    control.send_replace(TransferControl::Cancel);
+                }
+                // A download still asking about conflicts keeps the existing files
+                self.pending_conflicts.lock().take();
+                // Nobody is redialed from here on
+                self.reconnects = ReconnectManager::default();
+                let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+                for peer_id in peers {
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                }
+                self.shutdown = Some(sender);
+            }
+            Command::PauseTransfer {
+                path,
+                paused,
+                sender,
+            } => {
+                let control = if paused {
+                    TransferControl::Pause
+                } else {
+                    TransferControl::Run
+                };
+                let _ = sender.send(self.signal_transfer(&path, control));
+            }
+            Command::CancelTransfer { path, sender } => {
+                let _ = sender.send(self.signal_transfer(&path, TransferControl::Cancel));
+            }
+            Command::ResolveConflicts { decisions, sender } => {
+                let pending = self.pending_conflicts.lock().take();
+                let result = pending.map_or_else(
+                    || {
+                        Err(JunkanooError::other(
+                            "No conflicts are waiting for a decision",
+                        ))
+                    },
+                    |pending| {
+                        let _ = pending.send(decisions);
+                        Ok(())
+                    },
+                );
+                let _ = sender.send(result);
+            }
+            Command::Disconnect { peer_id, sender } => {
+                // Not being connected is fine, the caller only wants the peer gone.
+                self.reconnects.forget(&peer_id);
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                let _ = sender.send(Ok(()));
+            }
+        }
+    }
+}
+
+/// Settle what the manifest already answers: paths the host doesn't share fail and files
+/// already at their destination are done. Returns the files still to download, with the
+/// hashes the host has now.
+#[allow(clippy::too_many_arguments)]
+async fn apply_manifest(
+    manifest: &Manifest,
+    files: Vec<RequestedFile>,
+    hashes: bool,
+    conflict: ConflictPolicy,
+    pending_conflicts: &PendingConflicts,
+    directory: Option<&PathBuf>,
+    event_sender: &mut mpsc::Sender<Event>,
+    successful_transfers: &mut Vec<String>,
+    failed_transfers: &mut Vec<String>,
+) -> Vec<(RequestedFile, Option<String>)> {
+    tracing::info!(
+        "Host announced {} files of {} bytes in total",
+        manifest.entries.len(),
+        manifest.total_size()
+    );
+    let sizes = manifest
+        .entries
+        .iter()
+        .map(|entry| (entry.path.clone(), entry.size))
+        .collect();
+    event_sender
+        .send(Event::TransfersSized(sizes))
+        .await
+        .expect("Event receiver not to be dropped.");
+
+    let mut remaining = Vec::new();
+    // Existing files to ask about, with where their file is in `remaining`
+    let mut undecided = Vec::new();
+    for mut file in files {
+        if manifest.missing.contains(&file.path) {
+            event_sender
+                .send(Event::TransferFailed {
+                    path: file.path.clone(),
+                    error: "not shared by the host".to_string(),
+                })
+                .await
+                .expect("Event receiver not to be dropped.");
+            failed_transfers.push(file.path);
+            continue;
+        }
+        let Some(entry) = manifest.entry(&file.path) else {
+            remaining.push((file, None));
+            continue;
+        };
+        if let Some(target) = &entry.link_target {
+            let event = receive_link(
+                file.path,
+                entry,
+                target,
+                directory,
+                successful_transfers,
+                failed_transfers,
+            )
+            .await;
+            event_sender
+                .send(event)
+                .await
+                .expect("Event receiver not to be dropped.");
+            continue;
+        }
+        if hashes && entry.hash.is_some() {
+            file.hash.clone_from(&entry.hash);
+        }
+        let receiver = FileReceiver::new()
+            .with_expected_hash(file.hash.clone())
+            .with_directory(directory.cloned())
+            .with_conflict(conflict);
+        if receiver.has_file(&entry.relative_path, entry.size).await {
+            event_sender
+                .send(Event::TransferUpToDate(file.path))
+                .await
+                .expect("Event receiver not to be dropped.");
+            successful_transfers.push(entry.relative_path.clone());
+            continue;
+        }
+        if conflict == ConflictPolicy::Ask {
+            if let Some(conflict) = existing_file(&receiver, &file.path, entry).await {
+                undecided.push((remaining.len(), conflict));
+                remaining.push((file, None));
+                continue;
+            }
+        }
+        let save_as = settle_up_front(
+            &receiver,
+            &file.path,
+            &entry.relative_path,
+            event_sender,
+            successful_transfers,
+        )
+        .await;
+        if let Some(save_as) = save_as {
+            remaining.push((file, Some(save_as)));
+        }
+    }
+    if undecided.is_empty() {
+        return remaining;
+    }
+    ask_about_conflicts(
+        undecided,
+        remaining,
+        pending_conflicts,
+        directory,
+        event_sender,
+        successful_transfers,
+    )
+    .await
+}
+
+/// Recreate a link the host shares as a link. Returns the event reporting how it went.
+async fn receive_link(
+    path: String,
+    entry: &ManifestEntry,
+    target: &str,
+    directory: Option<&PathBuf>,
+    successful_transfers: &mut Vec<String>,
+    failed_transfers: &mut Vec<String>,
+) -> Event {
+    match FileReceiver::new()
+        .with_directory(directory.cloned())
+        .create_link(&entry.relative_path, target)
+        .await
+    {
+        Ok(_) => {
+            successful_transfers.push(entry.relative_path.clone());
+            Event::TransferCompleted(path)
+        }
+        Err(e) => {
+            tracing::warn!("{}", e);
+            failed_transfers.push(path.clone());
+            Event::TransferFailed {
+                path,
+                error: e.to_string(),
+            }
+        }
+    }
+}
+
+/// The file already at the destination of `entry`, as a conflict to ask about.
+async fn existing_file(
+    receiver: &FileReceiver,
+    path: &str,
+    entry: &ManifestEntry,
+) -> Option<FileConflict> {
+    let destination = receiver.destination(&entry.relative_path).ok()?;
+    let metadata = tokio::fs::symlink_metadata(&destination).await.ok()?;
+    Some(FileConflict {
+        path: path.to_string(),
+        relative_path: entry.relative_path.clone(),
+        size: entry.size,
+        local_size: metadata.len(),
+        local_modified: metadata.modified().ok(),
+    })
+}
+
+/// Ask about all conflicts of a download at once and settle each file as decided. Returns
+/// `remaining` without the files the user chose to skip.
+async fn ask_about_conflicts(
+    undecided: Vec<(usize, FileConflict)>,
+    mut remaining: Vec<(RequestedFile, Option<String>)>,
+    pending_conflicts: &PendingConflicts,
+    directory: Option<&PathBuf>,
+    event_sender: &mut mpsc::Sender<Event>,
+    successful_transfers: &mut Vec<String>,
+) -> Vec<(RequestedFile, Option<String>)> {
+    let count = undecided.len();
+    let (sender, receiver) = oneshot::channel();
+    *pending_conflicts.lock() = Some(sender);
+    let conflicts = undecided
+        .iter()
+        .map(|(_, conflict)| conflict.clone())
+        .collect();
+    event_sender
+        .send(Event::ConflictsFound(conflicts))
+        .await
+        .expect("Event receiver not to be dropped.");
+    let decisions = match receiver.await {
+        Ok(decisions) if decisions.len() == count => decisions,
+        Ok(_) => {
+            tracing::warn!("Conflicts were answered for the wrong files, keeping them all");
+            vec![ConflictPolicy::Skip; count]
+        }
+        Err(_) => vec![ConflictPolicy::Skip; count],
+    };
+    let mut skipped = HashSet::new();
+    for ((index, conflict), decision) in undecided.into_iter().zip(decisions) {
+        let receiver = FileReceiver::new()
+            .with_directory(directory.cloned())
+            .with_conflict(decision);
+        match settle_up_front(
+            &receiver,
+            &conflict.path,
+            &conflict.relative_path,
+            event_sender,
+            successful_transfers,
+        )
+        .await
+        {
+            Some(save_as) => remaining[index].1 = Some(save_as),
+            None => {
+                skipped.insert(index);
+            }
+        }
+    }
+    remaining
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !skipped.contains(index))
+        .map(|(_, file)| file)
+        .collect()
+}
+
+/// Settle what happens to an existing file at the destination of `relative_path` once up
+/// front, so retries write to the same place. The path to save the file under, `None` if
+/// it is skipped.
+async fn settle_up_front(
+    receiver: &FileReceiver,
+    path: &str,
+    relative_path: &str,
+    event_sender: &mut mpsc::Sender<Event>,
+    successful_transfers: &mut Vec<String>,
+) -> Option<String> {
+    let resolved = match receiver.resolve_conflict(relative_path).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            tracing::warn!("Failed to check for {}: {}", relative_path, e);
+            None
+        }
+    };
+    let save_as = match &resolved {
+        Some(Conflict::Renamed(renamed)) => renamed.clone(),
+        _ => relative_path.to_string(),
+    };
+    if let Some(resolved) = resolved {
+        tracing::info!("'{}' already exists, {}", relative_path, resolved);
+        let skipped = resolved == Conflict::Skipped;
+        event_sender
+            .send(Event::TransferConflict {
+                path: path.to_string(),
+                conflict: resolved,
+            })
+            .await
+            .expect("Event receiver not to be dropped.");
+        if skipped {
+            successful_transfers.push(relative_path.to_string());
+            return None;
+        }
+    }
+    Some(save_as)
+}
+
+/// What became of a file on the host since its manifest entry was sent.
+enum SourceUpdate {
+    Unchanged,
+    Changed(ManifestEntry),
+    Removed,
+}
+
+/// Ask the host for the current manifest entry of a file whose transfer broke off. A host
+/// that doesn't answer, e.g. because the connection dropped, counts as unchanged.
+async fn source_update(
+    manifest_requests: &mpsc::UnboundedSender<ManifestQuery>,
+    peer_id: PeerId,
+    entry: &ManifestEntry,
+) -> SourceUpdate {
+    let (sender, receiver) = oneshot::channel();
+    if manifest_requests
+        .unbounded_send((peer_id, vec![entry.path.clone()], sender))
+        .is_err()
+    {
+        return SourceUpdate::Unchanged;
+    }
+    match receiver.await {
+        Ok(Ok(manifest)) => match manifest.entry(&entry.path) {
+            Some(current) if current.size == entry.size && current.hash == entry.hash => {
+                SourceUpdate::Unchanged
+            }
+            Some(current) => SourceUpdate::Changed(current.clone()),
+            None => SourceUpdate::Removed,
+        },
+        _ => SourceUpdate::Unchanged,
+    }
+}
+
+/// Open a stream to the host, send the request and receive the file it answers with.
+#[allow(clippy::too_many_arguments)]
+async fn download_file(
+    stream_control: &mut stream::Control,
+    peer_id: PeerId,
+    request: &FileRequest,
+    request_timeout: Duration,
+    expected_hash: Option<String>,
+    expected_size: Option<u64>,
+    directory: Option<PathBuf>,
+    preserve: bool,
+    conflict: ConflictPolicy,
+    save_as: Option<String>,
+    download_limit: Option<Arc<RateLimiter>>,
+    cancel: Arc<AtomicBool>,
+    control: Option<watch::Receiver<TransferControl>>,
+    event_sender: mpsc::Sender<Event>,
+) -> Result<ReceivedFile, JunkanooError> {
+    tracing::info!("Requesting file '{}' from peer {}", request.path, peer_id);
+    // Only up to the request, a busy host may keep the stream queued for a while after
+    let opening = async {
+        let mut stream = stream_control
+            .open_stream(peer_id, JUNKANOO_FILE_PROTOCOL)
+            .await?;
+        request.write_to(&mut stream).await?;
+        Ok::<_, JunkanooError>(stream)
+    };
+    let mut stream = tokio::time::timeout(request_timeout, opening)
+        .await
+        .map_err(|_| JunkanooError::Timeout(format!("requesting '{}'", request.path)))??;
+    let path = request.path.clone();
+    let event_sender = parking_lot::Mutex::new(event_sender);
+    let receiver = FileReceiver::new()
+        .with_rate_limit(download_limit)
+        .with_expected_hash(expected_hash)
+        .with_expected_size(expected_size)
+        .with_directory(directory)
+        .with_preserve(preserve)
+        .with_conflict(conflict)
+        .with_save_as(save_as)
+        .with_cancel(cancel)
+        .with_control(control)
+        .with_progress(move |bytes, total| {
+            // Progress is best effort, a busy receiver must not stall the transfer
+            let _ = event_sender.lock().try_send(Event::TransferProgress {
+                path: path.clone(),
+                bytes,
+                total,
+            });
+        });
+    if request.delta {
+        receiver.receive_delta(&mut stream).await
+    } else {
+        receiver.receive_file(&mut stream).await
+    }
+}
+
+/// Open a stream to the host, ask for a directory as an archive and save it.
+#[allow(clippy::too_many_arguments)]
+async fn download_archive(
+    stream_control: &mut stream::Control,
+    peer_id: PeerId,
+    request: &FileRequest,
+    request_timeout: Duration,
+    format: ArchiveFormat,
+    directory: Option<PathBuf>,
+    conflict: ConflictPolicy,
+    download_limit: Option<Arc<RateLimiter>>,
+    cancel: Arc<AtomicBool>,
+    event_sender: mpsc::Sender<Event>,
+) -> Result<ReceivedFile, JunkanooError> {
+    tracing::info!(
+        "Requesting '{}' as an archive from peer {}",
+        request.path,
+        peer_id
+    );
+    let opening = async {
+        let mut stream = stream_control
+            .open_stream(peer_id, JUNKANOO_FILE_PROTOCOL)
+            .await?;
+        request.write_to(&mut stream).await?;
+        Ok::<_, JunkanooError>(stream)
+    };
+    let mut stream = tokio::time::timeout(request_timeout, opening)
+        .await
+        .map_err(|_| JunkanooError::Timeout(format!("requesting '{}'", request.path)))??;
+    let path = request.path.clone();
+    let event_sender = parking_lot::Mutex::new(event_sender);
+    FileReceiver::new()
+        .with_rate_limit(download_limit)
+        .with_directory(directory)
+        .with_conflict(conflict)
+        .with_cancel(cancel)
+        .with_progress(move |bytes, total| {
+            let _ = event_sender.lock().try_send(Event::TransferProgress {
+                path: path.clone(),
+                bytes,
+                total,
+            });
+        })
+        .receive_archive(&mut stream, format)
+        .await
+}
+
+/// Read the [`FileRequest`] a downloader sent, rejecting it if this peer isn't served.
+async fn read_file_request(
+    peer: PeerId,
+    stream: &mut libp2p::Stream,
+    rejection: Option<&str>,
+) -> Option<FileRequest> {
+    let request = match FileRequest::read_from(stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!("Failed to read file request from peer {}: {}", peer, e);
+            // Tell a newer downloader why, rather than leaving it with a closed stream
+            if let JunkanooError::UnsupportedVersion(_) = e {
+                if let Err(reject_error) = reject_request(stream, &e.to_string()).await {
+                    tracing::error!(
+                        "Failed to reject request from peer {}: {}",
+                        peer,
+                        reject_error
+                    );
+                }
+            }
+            return None;
+        }
+    };
+
+    if let Some(reason) = rejection {
+        tracing::warn!("Rejecting request from peer {}: {}", peer, reason);
+        if let Err(e) = reject_request(stream, reason).await {
+            tracing::error!("Failed to reject request from peer {}: {}", peer, e);
+        }
+        return None;
+    }
+    Some(request)
+}
+
+/// Report the upload of a whole file as started and have `transfer` report its progress.
+async fn start_upload(
+    transfer: FileTransfer,
+    peer: PeerId,
+    path: &str,
+    event_sender: &mut mpsc::Sender<Event>,
+) -> FileTransfer {
+    let progress_sender = parking_lot::Mutex::new(event_sender.clone());
+    let progress_path = path.to_string();
+    let transfer = transfer.with_progress(move |bytes, total| {
+        // Best effort like download progress, a busy UI must not stall the upload
+        let _ = progress_sender.lock().try_send(Event::UploadProgress {
+            peer_id: peer,
+            path: progress_path.clone(),
+            bytes,
+            total,
+        });
+    });
+    let _ = event_sender
+        .send(Event::UploadStarted {
+            peer_id: peer,
+            path: path.to_string(),
+        })
+        .await;
+    transfer
+}
+
+/// The shared file at `path`, rejecting the request if there is none.
+async fn shared_file(
+    peer: PeerId,
+    stream: &mut libp2p::Stream,
+    registry: &SharedRegistry,
+    path: &str,
+) -> Option<ShareEntry> {
+    let (entry, read_only) = {
+        let registry = registry.read();
+        let entry = registry.resolve_file(Path::new(path)).cloned();
+        (entry, registry.is_read_only())
+    };
+    let entry = entry.filter(|entry| !read_only || entry.handle.is_some());
+    if entry.is_none() {
+        tracing::warn!("Rejecting request for unshared file '{path}' from peer {peer}");
+        if let Err(e) = reject_request(stream, "file is not shared").await {
+            tracing::error!("Failed to reject request from peer {}: {}", peer, e);
+        }
+    }
+    entry
+}
+
+/// Answer a [`FileRequest`] read from a stream opened by a downloader.
+///
+/// Only files in the current share are served, anything else is rejected. Returns the
+/// paths of the files sent completely, several for an archive. Uploads of whole files are
+/// reported with [`Event::UploadStarted`] and what became of them.
+#[allow(clippy::too_many_arguments)]
+async fn serve_file_request(
+    peer: PeerId,
+    mut stream: libp2p::Stream,
+    registry: &SharedRegistry,
+    upload_limit: Option<Arc<RateLimiter>>,
+    fair_share: Arc<FairScheduler>,
+    compression: bool,
+    attributes: bool,
+    rejection: Option<&str>,
+    mut event_sender: mpsc::Sender<Event>,
+) -> Vec<PathBuf> {
+    let Some(request) = read_file_request(peer, &mut stream, rejection).await else {
+        return Vec::new();
+    };
+    tracing::info!(
+        "Received file request for '{}' at offset {} ({:?} bytes) from peer {}",
+        request.path,
+        request.offset,
+        request.length,
+        peer
+    );
+    if request.archive {
+        let transfer = |path, archive| {
+            ArchiveTransfer::new(path, archive)
+                .with_rate_limit(upload_limit)
+                .with_fair_share(fair_share, peer)
+                .with_compression(compression && request.compression == COMPRESSION_ZSTD)
+        };
+        return serve_archive_request(peer, stream, registry, transfer, &request, event_sender)
+            .await;
+    }
+
+    let Some(entry) = shared_file(peer, &mut stream, registry, &request.path).await else {
+        return Vec::new();
+    };
+    let path = entry.absolute_path;
+
+    // Send the file
+    let mut transfer = FileTransfer::new(&path)
+        .with_offset(request.offset)
+        .with_length(request.length)
+        .with_rate_limit(upload_limit)
+        .with_fair_share(fair_share, peer)
+        .with_compression(compression && request.compression == COMPRESSION_ZSTD)
+        .with_attributes(attributes);
+    // Read-only handles share their file offset, transfers of the same file take turns
+    let _guard = match &entry.handle {
+        Some(handle) => match handle.file.try_clone() {
+            Ok(file) => {
+                transfer = transfer.with_handle(file);
+                Some(handle.lock.lock().await)
+            }
+            Err(e) => {
+                tracing::error!("Failed to clone read-only handle for {:?}: {}", path, e);
+                return Vec::new();
+            }
+        },
+        None => None,
+    };
+    // A range, e.g. a preview, isn't an upload of the file
+    let whole_file = request.length.is_none();
+    if whole_file {
+        transfer = start_upload(transfer, peer, &request.path, &mut event_sender).await;
+    }
+    let result = if request.delta {
+        transfer.stream_delta(&mut stream).await
+    } else {
+        transfer.stream_file(&mut stream).await
+    };
+    if whole_file {
+        let (peer_id, path, bytes) = (peer, request.path.clone(), transfer.bytes_sent());
+        let event = if result.is_ok() {
+            Event::UploadCompleted {
+                peer_id,
+                path,
+                bytes,
+                hash: entry.hash.clone(),
+            }
+        } else {
+            Event::UploadFailed {
+                peer_id,
+                path,
+                bytes,
+            }
+        };
+        let _ = event_sender.send(event).await;
+    }
+    match result {
+        Ok(()) => {
+            tracing::info!("Successfully sent file '{}' to peer {}", request.path, peer);
+            // A range, e.g. a preview, doesn't count as having downloaded the file
+            request
+                .length
+                .is_none()
+                .then_some(path)
+                .into_iter()
+                .collect()
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to send file '{}' to peer {}: {}",
+                request.path,
+                peer,
+                e
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// The archive named after the shared directory at `path` and everything in it, `None`
+/// if it isn't one or the share is read-only.
+fn archive_sources(registry: &ShareRegistry, path: &Path) -> Option<(String, Vec<ArchiveSource>)> {
+    let directory = registry
+        .resolve(path)
+        .filter(|entry| entry.is_dir && !registry.is_read_only())
+        .map(|entry| entry.absolute_path.clone());
+    directory.map(|directory| {
+        let name = directory.file_name().map_or_else(
+            || "share".to_string(),
+            |name| name.to_string_lossy().to_string(),
+        );
+        let mut sources = vec![ArchiveSource {
+            name: name.clone(),
+            path: directory.clone(),
+            is_dir: true,
+            link_target: None,
+        }];
+        sources.extend(registry.list(&directory, true).into_iter().map(|item| {
+            let relative = item.path.strip_prefix(&directory).unwrap_or(&item.path);
+            let components: Vec<_> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect();
+            ArchiveSource {
+                name: format!("{name}/{}", components.join("/")),
+                link_target: registry
+                    .resolve(&item.path)
+                    .and_then(|entry| entry.link_target.clone()),
+                path: item.path,
+                is_dir: item.is_dir,
+            }
+        }));
+        (name, sources)
+    })
+}
+
+/// Answer a [`FileRequest`] for a shared directory with a tar archive of everything below
+/// it, named after the directory. Returns the paths of the files in it once it was sent
+/// completely.
+async fn serve_archive_request(
+    peer: PeerId,
+    mut stream: libp2p::Stream,
+    registry: &SharedRegistry,
+    transfer: impl FnOnce(String, Archive) -> ArchiveTransfer,
+    request: &FileRequest,
+    mut event_sender: mpsc::Sender<Event>,
+) -> Vec<PathBuf> {
+    let sources = archive_sources(&registry.read(), Path::new(&request.path));
+    // Read-only shares only ever serve from the handles opened when they were published
+    let Some((name, sources)) = sources else {
+        tracing::warn!(
+            "Rejecting archive request for '{}' from peer {}",
+            request.path,
+            peer
+        );
+        if let Err(e) = reject_request(&mut stream, "directory is not shared as an archive").await {
+            tracing::error!("Failed to reject request from peer {}: {}", peer, e);
+        }
+        return Vec::new();
+    };
+    let archive = match tokio::task::spawn_blocking(move || Archive::new(&sources)).await {
+        Ok(Ok(archive)) => archive,
+        result => {
+            let error = result.map_or_else(|e| e.to_string(), |e| e.unwrap_err().to_string());
+            tracing::error!("Failed to archive '{}': {}", request.path, error);
+            let _ = reject_request(&mut stream, "directory cannot be read").await;
+            return Vec::new();
+        }
+    };
+    let files: Vec<PathBuf> = archive.files().cloned().collect();
+    let progress_sender = parking_lot::Mutex::new(event_sender.clone());
+    let path = request.path.clone();
+    let transfer = transfer(format!("{name}.tar"), archive).with_progress(move |bytes, total| {
+        let _ = progress_sender.lock().try_send(Event::UploadProgress {
+            peer_id: peer,
+            path: path.clone(),
+            bytes,
+            total,
+        });
+    });
+    let _ = event_sender
+        .send(Event::UploadStarted {
+            peer_id: peer,
+            path: request.path.clone(),
+        })
+        .await;
+    let result = transfer.stream_archive(&mut stream).await;
+    let (peer_id, path, bytes) = (peer, request.path.clone(), transfer.bytes_sent());
+    let event = match &result {
+        Ok(()) => Event::UploadCompleted {
+            peer_id,
+            path,
+            bytes,
+            hash: None,
+        },
+        Err(_) => Event::UploadFailed {
+            peer_id,
+            path,
+            bytes,
+        },
+    };
+    let _ = event_sender.send(event).await;
+    match result {
+        Ok(()) => {
+            tracing::info!("Sent '{}' as an archive to peer {}", request.path, peer);
+            files
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to send '{}' as an archive to peer {}: {}",
+                request.path,
+                peer,
+                e
+            );
+            Vec::new()
         }
     }
 }
@@ -604,71 +3561,270 @@ impl EventLoop {
 #[derive(NetworkBehaviour)]
 struct Behaviour {
     request_response: request_response::cbor::Behaviour<DisplayRequest, DisplayResponse>,
+    greeting: request_response::cbor::Behaviour<Hello, Welcome>,
+    manifest: request_response::cbor::Behaviour<ManifestRequest, Manifest>,
+    push: request_response::cbor::Behaviour<PushRequest, PushResponse>,
+    list: request_response::cbor::Behaviour<ListDirectoryRequest, ListDirectoryResponse>,
+    updates: request_response::cbor::Behaviour<ShareUpdate, ShareUpdateAck>,
     kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    rendezvous: rendezvous::client::Behaviour,
     file_stream: stream::Behaviour,
+    limits: connection_limits::Behaviour,
+    ping: ping::Behaviour,
+    identify: identify::Behaviour,
+}
+
+impl Behaviour {
+    fn new(key: &libp2p::identity::Keypair, config: &NodeConfig) -> Self {
+        Self {
+            kademlia: kad::Behaviour::with_config(
+                key.public().to_peer_id(),
+                kad::store::MemoryStore::new(key.public().to_peer_id()),
+                // Provider records others store here are kept, other records aren't, see
+                // `handle_kademlia_event`
+                kad::Config::new(kad::PROTOCOL_NAME)
+                    .set_record_filtering(kad::StoreInserts::FilterBoth)
+                    .clone(),
+            ),
+            request_response: request_response::cbor::Behaviour::new(
+                [(JUNKANOO_REQUEST_RESPONSE_PROTOCOL, ProtocolSupport::Full)],
+                config.request_response_config(),
+            ),
+            greeting: request_response::cbor::Behaviour::new(
+                [(JUNKANOO_GREETING_PROTOCOL, ProtocolSupport::Full)],
+                config.request_response_config(),
+            ),
+            manifest: request_response::cbor::Behaviour::new(
+                [(JUNKANOO_MANIFEST_PROTOCOL, ProtocolSupport::Full)],
+                config.request_response_config(),
+            ),
+            push: request_response::cbor::Behaviour::new(
+                [(JUNKANOO_PUSH_PROTOCOL, ProtocolSupport::Full)],
+                config.request_response_config(),
+            ),
+            list: request_response::cbor::Behaviour::new(
+                [(JUNKANOO_LIST_PROTOCOL, ProtocolSupport::Full)],
+                config.request_response_config(),
+            ),
+            updates: request_response::cbor::Behaviour::new(
+                [(JUNKANOO_UPDATES_PROTOCOL, ProtocolSupport::Full)],
+                config.request_response_config(),
+            ),
+            rendezvous: rendezvous::client::Behaviour::new(key.clone()),
+            file_stream: stream::Behaviour::new(),
+            limits: connection_limits::Behaviour::new(config.connection_limits()),
+            ping: ping::Behaviour::new(ping::Config::new().with_interval(PING_INTERVAL)),
+            identify: identify::Behaviour::new(
+                identify::Config::new(greeting::identify_protocol(), key.public())
+                    .with_agent_version(format!("junkanoo/{}", env!("CARGO_PKG_VERSION"))),
+            ),
+        }
+    }
 }
 
 #[derive(Debug)]
 enum Command {
     StartListening {
         addr: Multiaddr,
-        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
     },
     Dial {
         peer_id: PeerId,
         peer_addr: Multiaddr,
-        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
     },
-    InsertDirectoryItems {
-        peer_id: PeerId,
+    /// Replace the published items. Downloaders that negotiated `updates` are told what
+    /// changed, the others pick up the new version on their next poll.
+    UpdateDirectoryItems {
         directory_items: Vec<DirectoryItem>,
-        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
+    },
+    /// Download a shared directory as one archive.
+    RequestArchive {
+        peer_id: PeerId,
+        path: String,
+        directory: Option<PathBuf>,
+        format: ArchiveFormat,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
     },
     RequestFiles {
         peer_id: PeerId,
-        file_names: Vec<String>,
-        sender: oneshot::Sender<Result<Vec<u8>, Box<dyn Error + Send>>>,
+        files: Vec<RequestedFile>,
+        /// Saved below this directory, relative to the working directory.
+        directory: Option<PathBuf>,
+        /// Only transfer what changed compared to files already there.
+        delta: bool,
+        sender: oneshot::Sender<Result<Vec<u8>, JunkanooError>>,
+    },
+    /// Offer shared files to a host that accepts pushes.
+    Push {
+        peer_id: PeerId,
+        paths: Vec<String>,
+        sender: oneshot::Sender<Result<PushResponse, JunkanooError>>,
+    },
+    RequestFileRange {
+        peer_id: PeerId,
+        path: String,
+        range: Range<u64>,
+        sender: oneshot::Sender<Result<Vec<u8>, JunkanooError>>,
     },
     GetListeningAddrs {
-        sender: oneshot::Sender<Result<Vec<Multiaddr>, Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<Vec<Multiaddr>, JunkanooError>>,
+    },
+    ResolveCode {
+        code: String,
+        sender: oneshot::Sender<Result<Vec<Multiaddr>, JunkanooError>>,
+    },
+    Greet {
+        peer_id: PeerId,
+        password: Option<Secret>,
+        sender: oneshot::Sender<Result<Welcome, JunkanooError>>,
     },
     RequestDisplay {
         peer_id: PeerId,
-        sender: oneshot::Sender<Result<DisplayResponse, Box<dyn Error + Send>>>,
+        sender: oneshot::Sender<Result<DisplayResponse, JunkanooError>>,
+    },
+    ListDirectory {
+        peer_id: PeerId,
+        path: String,
+        recursive: bool,
+        sender: oneshot::Sender<Result<ListDirectoryResponse, JunkanooError>>,
+    },
+    Disconnect {
+        peer_id: PeerId,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
+    },
+    /// Pause or resume the download of one requested file.
+    PauseTransfer {
+        path: String,
+        paused: bool,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
+    },
+    CancelTransfer {
+        path: String,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
+    },
+    ResolveConflicts {
+        decisions: Vec<ConflictPolicy>,
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
+    },
+    CloseShare {
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
+    },
+    Shutdown {
+        sender: oneshot::Sender<Result<(), JunkanooError>>,
     },
 }
 
-#[derive(Debug)]
+impl Command {
+    /// Control commands are delivered on the priority channel.
+    const fn is_control(&self) -> bool {
+        matches!(
+            self,
+            Self::Disconnect { .. }
+                | Self::PauseTransfer { .. }
+                | Self::CancelTransfer { .. }
+                | Self::ResolveConflicts { .. }
+                | Self::CloseShare { .. }
+                | Self::Shutdown { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Event {
     NewListenAddr(Multiaddr),
     PeerConnected(PeerId),
     PeerDisconnected(),
+    /// A downloader introduced itself.
+    PeerGreeted {
+        peer_id: PeerId,
+        greeting: Greeting,
+    },
+    /// A peer told which software it runs, e.g. `junkanoo/0.3.1`.
+    PeerIdentified {
+        peer_id: PeerId,
+        agent_version: String,
+    },
+    /// The connection to a peer we dialed was lost, this redial is scheduled.
+    Reconnecting {
+        peer_id: PeerId,
+        attempt: u32,
+    },
     DownloadCompleted(Vec<String>),
     DownloadFailed(Vec<String>),
+    DhtStatus {
+        routing_table_size: usize,
+    },
+    /// Our addresses can be looked up under the share code.
+    ShareCodePublished,
+    /// Recomputed after every ping, see [`LinkQuality::score`].
+    ConnectionQuality {
+        peer_id: PeerId,
+        score: u8,
+        rtt: Duration,
+    },
+    ShareCompleted,
+    /// The host changed what it shares, the new listing as sent by the host.
+    ShareUpdated(Vec<DirectoryItem>),
+    /// The host pushed what changed in its listing since the version we had.
+    ShareChanged(ShareUpdate),
+    /// Files requested from the host, in the order they are asked for.
+    TransfersQueued(Vec<String>),
+    /// Size of each queued file, from the host's manifest.
+    TransfersSized(Vec<(String, u64)>),
+    TransferProgress {
+        path: String,
+        bytes: u64,
+        total: u64,
+    },
+    TransferCompleted(String),
+    /// The file at the destination already matches the host's hash, nothing was written.
+    TransferUpToDate(String),
+    /// The destination existed with other content, this is what was done about it.
+    TransferConflict {
+        path: String,
+        conflict: Conflict,
+    },
+    /// A peer pushed files we don't have, download them from it into `directory`.
+    PushAccepted {
+        peer_id: PeerId,
+        files: Vec<RequestedFile>,
+        directory: PathBuf,
+    },
+    /// Files of a download exist locally with other content, answer with
+    /// [`Client::resolve_conflicts`]. They wait for the answer, without one they are kept.
+    ConflictsFound(Vec<FileConflict>),
+    /// Stopped before it finished, see [`Client::cancel_transfer`].
+    TransferCancelled(String),
+    TransferFailed {
+        path: String,
+        error: String,
+    },
+    /// Arrived complete but hashing to something else than the host published.
+    TransferCorrupted(String),
+    /// A downloader started fetching a whole shared file from us.
+    UploadStarted {
+        peer_id: PeerId,
+        path: String,
+    },
+    UploadProgress {
+        peer_id: PeerId,
+        path: String,
+        bytes: u64,
+        total: u64,
+    },
+    UploadCompleted {
+        peer_id: PeerId,
+        path: String,
+        bytes: u64,
+        /// SHA-256 the file was published with.
+        hash: Option<String>,
+    },
+    /// The upload broke off after sending `bytes`.
+    UploadFailed {
+        peer_id: PeerId,
+        path: String,
+        bytes: u64,
+    },
 }
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct DisplayRequest;
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-enum FileResponse {
-    DirectoryListing(Vec<DisplayResponse>),
-    DownloadInfo(Vec<FileMetadata>),
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct DisplayResponse {
-    #[serde(default)]
-    pub items: Vec<DirectoryItem>,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct FileMetadata {
-    pub path: String,
-    pub size: u64,
-    pub chunks: u64,
-}
-
-// Simple file exchange protocol
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-struct FileRequest(Vec<String>);
@@ -0,0 +1,125 @@
+//! Startup check whether UDP gets out of this network at all.
+//!
+//! Corporate firewalls often drop it, which leaves QUIC dials and listeners hanging until
+//! they time out, so TCP is used instead when a STUN server doesn't answer.
+
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+
+/// Public STUN servers asked, any one answering shows UDP works.
+pub const STUN_SERVERS: [&str; 2] = ["stun.l.google.com:19302", "stun.cloudflare.com:3478"];
+/// How long to wait for an answer, a working network answers in well under a second.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+/// The transports the network ended up with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransportChoice {
+    /// QUIC and TCP, QUIC preferred.
+    #[default]
+    Quic,
+    /// UDP is blocked, only TCP is used.
+    Tcp,
+}
+
+impl TransportChoice {
+    /// Shown in the status bar when the choice wasn't the default.
+    #[must_use]
+    pub const fn note(self) -> Option<&'static str> {
+        match self {
+            Self::Quic => None,
+            Self::Tcp => Some("UDP blocked, using TCP"),
+        }
+    }
+}
+
+/// Ask the STUN `servers` for our address over UDP. `None` if none of them resolved, which
+/// says nothing about UDP, otherwise whether any answered within `timeout`.
+pub async fn udp_egress(servers: &[&str], timeout: Duration) -> Option<bool> {
+    let mut addresses: Vec<SocketAddr> = Vec::new();
+    for server in servers {
+        match tokio::net::lookup_host(server).await {
+            Ok(resolved) => addresses.extend(resolved.filter(SocketAddr::is_ipv4)),
+            Err(e) => tracing::debug!("Failed to resolve {}: {}", server, e),
+        }
+    }
+    if addresses.is_empty() {
+        return None;
+    }
+    let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await else {
+        return Some(false);
+    };
+
+    let transaction = transaction_id();
+    let request = binding_request(&transaction);
+    for address in &addresses {
+        if let Err(e) = socket.send_to(&request, address).await {
+            tracing::debug!("Failed to send a STUN request to {}: {}", address, e);
+        }
+    }
+    let answered = tokio::time::timeout(timeout, async {
+        let mut buffer = [0u8; 512];
+        loop {
+            match socket.recv_from(&mut buffer).await {
+                Ok((length, from)) if is_binding_success(&buffer[..length], &transaction) => {
+                    tracing::debug!("STUN server {} answered", from);
+                    return true;
+                }
+                Ok(_) => {}
+                // E.g. ICMP port unreachable from one of the servers, others may still answer
+                Err(e) => tracing::debug!("STUN probe error: {}", e),
+            }
+        }
+    })
+    .await;
+    Some(answered.unwrap_or(false))
+}
+
+/// Probe the network once at startup and pick the transports to use.
+pub async fn choose_transport() -> TransportChoice {
+    match udp_egress(&STUN_SERVERS, PROBE_TIMEOUT).await {
+        Some(false) => {
+            tracing::warn!("No STUN server answered over UDP, QUIC looks blocked, using TCP");
+            TransportChoice::Tcp
+        }
+        Some(true) => TransportChoice::Quic,
+        None => {
+            tracing::info!("Could not resolve any STUN server, keeping QUIC");
+            TransportChoice::Quic
+        }
+    }
+}
+
+/// A STUN Binding request without attributes, see RFC 5389.
+#[must_use]
+pub fn binding_request(transaction: &[u8; 12]) -> [u8; 20] {
+    let mut request = [0u8; 20];
+    request[..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // Message length stays 0, there are no attributes
+    request[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request[8..].copy_from_slice(transaction);
+    request
+}
+
+/// Whether `message` is a successful answer to the request with `transaction`.
+#[must_use]
+pub fn is_binding_success(message: &[u8], transaction: &[u8; 12]) -> bool {
+    message.len() >= 20
+        && message[..2] == BINDING_SUCCESS.to_be_bytes()
+        && message[4..8] == MAGIC_COOKIE.to_be_bytes()
+        && message[8..20] == transaction[..]
+}
+
+fn transaction_id() -> [u8; 12] {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut transaction = [0u8; 12];
+    transaction.copy_from_slice(&nanos.to_be_bytes()[4..]);
+    transaction
+}
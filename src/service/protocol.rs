@@ -0,0 +1,503 @@
+//! Everything junkanoo peers say to each other, in one place.
+//!
+//! That is the libp2p protocol names, the listing exchanged over request-response and the
+//! frames opening each file stream. The handshake is in [`super::greeting`].
+//!
+//! A file body follows its [`ResponseHeader`] as plain bytes, without chunk sequence
+//! numbers. Each file has a stream of its own, which delivers the bytes in order or breaks
+//! off, so there is nothing to reorder. A short body is told by the size in the header, a
+//! damaged one by the hash in the listing, and a broken off download continues from the
+//! part that arrived as a delta.
+
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::app::DirectoryItem;
+
+use super::error::JunkanooError;
+
+/// Request-response protocol the listing is asked for and sent over.
+pub const JUNKANOO_REQUEST_RESPONSE_PROTOCOL: StreamProtocol =
+    StreamProtocol::new("/junkanoo/request-response");
+/// Stream protocol files are sent over, each stream opens with a [`FileRequest`].
+pub const JUNKANOO_FILE_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/stream");
+/// Request-response protocol of the handshake, see [`super::greeting`].
+pub const JUNKANOO_GREETING_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/greeting");
+/// Request-response protocol the [`Manifest`] of a download is asked for over.
+pub const JUNKANOO_MANIFEST_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/manifest");
+/// Request-response protocol `sync --push` offers its files over, see [`PushRequest`].
+pub const JUNKANOO_PUSH_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/push");
+/// Request-response protocol single directories of the listing are asked for over, see
+/// [`ListDirectoryRequest`].
+pub const JUNKANOO_LIST_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/list");
+/// Request-response protocol the host tells downloaders about changes of its listing
+/// over, see [`ShareUpdate`].
+pub const JUNKANOO_UPDATES_PROTOCOL: StreamProtocol = StreamProtocol::new("/junkanoo/updates");
+
+/// Version of the wire protocol: the greeting, the listing and the file stream header.
+/// Peers only use what both of them speak, see [`super::greeting::Greeting::supports`].
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Asks the host for its listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayRequest;
+
+/// The host's listing, fields added later default for hosts of older releases.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayResponse {
+    /// Everything shared, left out for peers that negotiated `list`.
+    #[serde(default)]
+    pub items: Vec<DirectoryItem>,
+    /// How many file streams the host serves at once, more are queued on its side.
+    #[serde(default)]
+    pub max_concurrent_transfers: Option<usize>,
+    /// Unix timestamp at which a scheduled share opens, set while it is still closed.
+    #[serde(default)]
+    pub opens_at: Option<u64>,
+    /// Name the host gave the share.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Version of the listing, changes whenever the host adds or removes items.
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// Asks the host for the items of one directory.
+///
+/// A downloader only fetches the parts of a large share it looks at this way. Peers that negotiated `list` get a [`DisplayResponse`]
+/// without items and list directories as they enter them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListDirectoryRequest {
+    /// The directory as listed by the host, empty for the top of the share.
+    pub path: String,
+    /// Everything below the directory rather than only its children, e.g. to download it.
+    #[serde(default)]
+    pub recursive: bool,
+}
+
+/// The items of the directory a [`ListDirectoryRequest`] asked for.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListDirectoryResponse {
+    pub items: Vec<DirectoryItem>,
+    /// Version of the listing the items are from, see [`DisplayResponse::version`].
+    pub version: u64,
+}
+
+/// Sent by the host to downloaders that negotiated `updates` whenever its listing changes,
+/// so they learn about it without asking for the whole listing again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShareUpdate {
+    /// Version of the listing the changes apply to. A downloader that has another one asks
+    /// for the whole listing instead.
+    pub base: u64,
+    /// Version of the listing with the changes applied.
+    pub version: u64,
+    /// Items that are new or changed.
+    pub added: Vec<DirectoryItem>,
+    /// Paths of the items no longer shared, as listed.
+    pub removed: Vec<PathBuf>,
+}
+
+impl ShareUpdate {
+    /// Apply the changes to `items`, the listing at version [`Self::base`].
+    pub fn apply(&self, items: &mut Vec<DirectoryItem>) {
+        items.retain(|item| {
+            !self.removed.contains(&item.path)
+                && !self.added.iter().any(|added| added.path == item.path)
+        });
+        items.extend(self.added.iter().cloned());
+        items.sort_by_key(|item| item.index);
+    }
+}
+
+/// The downloader's acknowledgement of a [`ShareUpdate`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareUpdateAck;
+
+/// Asks the host about the files of a download before any of them is streamed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestRequest {
+    /// Paths as they would be sent in each [`FileRequest`].
+    pub paths: Vec<String>,
+}
+
+/// What the host is about to send for a [`ManifestRequest`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    /// Requested paths the host doesn't share, they would be rejected.
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The path as requested.
+    pub path: String,
+    /// Where the downloader saves the file, relative to its download directory.
+    pub relative_path: String,
+    pub size: u64,
+    /// SHA-256 of the content, if the host hashed it.
+    pub hash: Option<String>,
+    /// Set when the host shares a symlink as a link, the downloader creates one pointing
+    /// here instead of requesting the file.
+    #[serde(default)]
+    pub link_target: Option<String>,
+}
+
+impl Manifest {
+    /// The entry for a requested path.
+    #[must_use]
+    pub fn entry(&self, path: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// Bytes of all files together.
+    #[must_use]
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+}
+
+/// Offers a host the files of a directory, it downloads the missing and changed ones from
+/// the peer that sent the offer. Only hosts started with `share --accept-push` take it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PushRequest {
+    /// The files as the offering peer shares them, relative to the pushed directory.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// What the host makes of a [`PushRequest`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PushResponse {
+    /// Paths of the entries the host is about to request, the others it already has.
+    pub requested: Vec<String>,
+    /// Why nothing is requested, when the host turned the offer down.
+    pub rejection: Option<String>,
+}
+
+/// Stream header value: the file body follows uncompressed.
+pub const COMPRESSION_NONE: u8 = 0;
+/// Stream header value: the file body is a zstd frame. Sent by a downloader to advertise
+/// support, and by the host when it actually compressed the body.
+pub const COMPRESSION_ZSTD: u8 = 1;
+
+/// First byte of a versioned [`FileRequest`], followed by the downloader's
+/// [`PROTOCOL_VERSION`]. Hosts from before versioning reject it as an unknown opcode rather
+/// than misreading the request.
+const REQUEST_VERSION_MARKER: u8 = b'V';
+/// Opcode of a [`FileRequest`] asking the host to send a file.
+const REQUEST_SEND: u8 = 1;
+/// Opcode of a [`FileRequest`] asking for at most `length` bytes from the offset on.
+const REQUEST_RANGE: u8 = 2;
+/// Opcode of a [`FileRequest`] asking for a delta against the downloader's copy, see
+/// [`FileTransfer::stream_delta`].
+const REQUEST_DELTA: u8 = 3;
+/// Opcode of a [`FileRequest`] asking for a shared directory as one tar archive, see
+/// [`archive`](super::archive).
+const REQUEST_ARCHIVE: u8 = 4;
+/// First byte of the host's reply when it serves the request.
+const RESPONSE_OK: u8 = 0;
+/// First byte of the host's reply when it refuses the request, followed by a reason.
+const RESPONSE_REJECTED: u8 = 1;
+/// Like [`RESPONSE_OK`], with [`FileAttributes`] after the header. Only sent to downloaders
+/// that offered the `attributes` feature.
+const RESPONSE_OK_ATTRIBUTES: u8 = 2;
+/// Upper bound for paths and reasons read off the wire, so a peer can't make us allocate
+/// arbitrary amounts of memory.
+const MAX_FRAME_STRING_LEN: usize = 64 * 1024;
+
+/// Frame sent by the downloader on a fresh stream.
+///
+/// It says "send me `path`, starting at `offset`", optionally no more than `length` bytes of it, or only what changed compared to the
+/// downloader's copy when `delta` is set. With `archive` the path is a directory, sent
+/// with everything below it as a tar archive.
+///
+/// Encoding the request explicitly keeps the direction of the data flow in the protocol:
+/// whoever opens the stream asks, the host answers with the file or a rejection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileRequest {
+    pub path: String,
+    pub offset: u64,
+    pub length: Option<u64>,
+    pub delta: bool,
+    pub compression: u8,
+    pub archive: bool,
+}
+
+impl FileRequest {
+    /// Send the request over `stream`.
+    ///
+    /// # Errors
+    ///
+    /// If the stream breaks.
+    pub async fn write_to<S>(&self, stream: &mut S) -> Result<(), JunkanooError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let opcode = if self.archive {
+            REQUEST_ARCHIVE
+        } else if self.delta {
+            REQUEST_DELTA
+        } else if self.length.is_some() {
+            REQUEST_RANGE
+        } else {
+            REQUEST_SEND
+        };
+        stream
+            .write_all(&[REQUEST_VERSION_MARKER, PROTOCOL_VERSION, opcode])
+            .await?;
+        write_string(stream, &self.path).await?;
+        stream.write_all(&self.offset.to_le_bytes()).await?;
+        if let (Some(length), false, false) = (self.length, self.delta, self.archive) {
+            stream.write_all(&length.to_le_bytes()).await?;
+        }
+        stream.write_all(&[self.compression]).await?;
+        stream.flush().await.map_err(JunkanooError::from)
+    }
+
+    /// Read a request sent with [`Self::write_to`].
+    ///
+    /// # Errors
+    ///
+    /// If the stream breaks or the request is malformed.
+    pub async fn read_from<S>(stream: &mut S) -> Result<Self, JunkanooError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut opcode = [0u8; 1];
+        stream.read_exact(&mut opcode).await?;
+        // Downloaders from before versioning start with the opcode right away
+        if opcode[0] == REQUEST_VERSION_MARKER {
+            let mut version = [0u8; 1];
+            stream.read_exact(&mut version).await?;
+            if version[0] > PROTOCOL_VERSION {
+                return Err(JunkanooError::UnsupportedVersion(version[0]));
+            }
+            stream.read_exact(&mut opcode).await?;
+        }
+        if ![REQUEST_SEND, REQUEST_RANGE, REQUEST_DELTA, REQUEST_ARCHIVE].contains(&opcode[0]) {
+            return Err(JunkanooError::Protocol(format!(
+                "unknown request {}",
+                opcode[0]
+            )));
+        }
+        let path = read_string(stream).await?;
+        let mut offset = [0u8; 8];
+        stream.read_exact(&mut offset).await?;
+        let length = if opcode[0] == REQUEST_RANGE {
+            let mut bytes = [0u8; 8];
+            stream.read_exact(&mut bytes).await?;
+            Some(u64::from_le_bytes(bytes))
+        } else {
+            None
+        };
+        let mut compression = [0u8; 1];
+        stream.read_exact(&mut compression).await?;
+        Ok(Self {
+            path,
+            offset: u64::from_le_bytes(offset),
+            length,
+            delta: opcode[0] == REQUEST_DELTA,
+            compression: compression[0],
+            archive: opcode[0] == REQUEST_ARCHIVE,
+        })
+    }
+}
+
+/// Refuse a [`FileRequest`], telling the downloader why.
+///
+/// # Errors
+///
+/// If the stream breaks.
+pub async fn reject_request<S>(stream: &mut S, reason: &str) -> Result<(), JunkanooError>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream.write_all(&[RESPONSE_REJECTED]).await?;
+    write_string(stream, reason).await?;
+    stream.close().await.map_err(JunkanooError::from)
+}
+
+/// Permission bits and modification time of a file, as the host has them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttributes {
+    /// Unix permission bits, approximated from the read-only flag on Windows.
+    pub mode: u32,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileAttributes {
+    #[must_use]
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777;
+        #[cfg(not(unix))]
+        let mode = if metadata.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        };
+        Self {
+            mode,
+            modified: metadata.modified().ok(),
+        }
+    }
+
+    /// The mode, then the modification time as seconds and nanoseconds since the epoch,
+    /// all zero when it's unknown.
+    async fn write_to<S>(&self, stream: &mut S) -> Result<(), JunkanooError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let since_epoch = self
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let mut frame = Vec::with_capacity(16);
+        frame.extend_from_slice(&self.mode.to_le_bytes());
+        frame.extend_from_slice(&since_epoch.as_secs().to_le_bytes());
+        frame.extend_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+        stream.write_all(&frame).await.map_err(JunkanooError::from)
+    }
+
+    async fn read_from<S>(stream: &mut S) -> Result<Self, JunkanooError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut frame = [0u8; 16];
+        stream.read_exact(&mut frame).await?;
+        let mode = u32::from_le_bytes(frame[..4].try_into().expect("4 bytes"));
+        let secs = u64::from_le_bytes(frame[4..12].try_into().expect("8 bytes"));
+        let nanos = u32::from_le_bytes(frame[12..].try_into().expect("4 bytes"));
+        let since_epoch = Duration::new(secs, nanos.min(999_999_999));
+        Ok(Self {
+            mode,
+            modified: (!since_epoch.is_zero()).then(|| UNIX_EPOCH + since_epoch),
+        })
+    }
+}
+
+/// The host's reply up to the body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseHeader {
+    /// Where the downloader saves the file, relative to its download directory.
+    pub path: String,
+    pub size: usize,
+    /// Compression of the body, see [`COMPRESSION_ZSTD`].
+    pub compression: u8,
+    /// Sent by hosts that offered the `attributes` feature.
+    pub attributes: Option<FileAttributes>,
+}
+
+/// Accept a [`FileRequest`]: the path the file is sent under, the body size, its
+/// compression and, for downloaders that take them, the file's attributes.
+///
+/// # Errors
+///
+/// If the stream breaks.
+pub async fn write_response<S>(stream: &mut S, header: &ResponseHeader) -> Result<(), JunkanooError>
+where
+    S: AsyncWrite + Unpin,
+{
+    let status = if header.attributes.is_some() {
+        RESPONSE_OK_ATTRIBUTES
+    } else {
+        RESPONSE_OK
+    };
+    stream.write_all(&[status]).await?;
+    write_string(stream, &header.path).await?;
+    stream
+        .write_all(&(header.size as u64).to_le_bytes())
+        .await?;
+    stream.write_all(&[header.compression]).await?;
+    if let Some(attributes) = &header.attributes {
+        attributes.write_to(stream).await?;
+    }
+    Ok(())
+}
+
+/// Read the host's reply up to the body, see [`write_response`].
+///
+/// # Errors
+///
+/// If the stream breaks, the request was refused or the reply is malformed.
+pub async fn read_response<S>(stream: &mut S) -> Result<ResponseHeader, JunkanooError>
+where
+    S: AsyncRead + Unpin,
+{
+    // The host either accepts the request or tells us why it won't
+    let mut status = [0u8; 1];
+    stream.read_exact(&mut status).await?;
+    match status[0] {
+        RESPONSE_OK | RESPONSE_OK_ATTRIBUTES => {}
+        RESPONSE_REJECTED => {
+            let reason = read_string(stream).await?;
+            return Err(JunkanooError::Rejected(reason));
+        }
+        other => {
+            return Err(JunkanooError::Protocol(format!("unknown response {other}")));
+        }
+    }
+
+    // Read the relative path
+    let relative_path = read_string(stream).await?;
+    tracing::debug!("Relative path: {}", relative_path);
+
+    // Read the file size
+    let mut size_bytes = [0u8; 8];
+    stream.read_exact(&mut size_bytes).await?;
+    let file_size = usize::try_from(u64::from_le_bytes(size_bytes))?;
+    tracing::debug!("File size: {}", file_size);
+
+    // Read the compression used for the body
+    let mut compression = [0u8; 1];
+    stream.read_exact(&mut compression).await?;
+    let attributes = if status[0] == RESPONSE_OK_ATTRIBUTES {
+        Some(FileAttributes::read_from(stream).await?)
+    } else {
+        None
+    };
+    Ok(ResponseHeader {
+        path: relative_path,
+        size: file_size,
+        compression: compression[0],
+        attributes,
+    })
+}
+
+async fn write_string<S>(stream: &mut S, value: &str) -> Result<(), JunkanooError>
+where
+    S: AsyncWrite + Unpin,
+{
+    stream
+        .write_all(&(value.len() as u64).to_le_bytes())
+        .await?;
+    stream
+        .write_all(value.as_bytes())
+        .await
+        .map_err(JunkanooError::from)
+}
+
+async fn read_string<S>(stream: &mut S) -> Result<String, JunkanooError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len_bytes = [0u8; 8];
+    stream.read_exact(&mut len_bytes).await?;
+    let len = usize::try_from(u64::from_le_bytes(len_bytes))?;
+    if len > MAX_FRAME_STRING_LEN {
+        return Err(JunkanooError::Protocol(format!(
+            "string of {len} bytes is too long"
+        )));
+    }
+    let mut bytes = vec![0u8; len];
+    stream.read_exact(&mut bytes).await?;
+    String::from_utf8(bytes).map_err(JunkanooError::from)
+}
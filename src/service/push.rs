@@ -0,0 +1,44 @@
+//! Receiving side of `sync --push`.
+//!
+//! A peer offers the files of a directory and the host downloads the ones it is missing or
+//! has with other content, like `sync` the other way around.
+
+use std::path::Path;
+
+use super::hashing::hash_file;
+use super::protocol::ManifestEntry;
+use super::utils::is_contained;
+
+/// The offered `entries` that differ from what is in `directory`.
+///
+/// These are the files to download. Links and paths that would leave the directory are
+/// never taken. Hashing files of the same size reads them, so this blocks.
+#[must_use]
+pub fn wanted(directory: &Path, entries: &[ManifestEntry]) -> Vec<ManifestEntry> {
+    entries
+        .iter()
+        .filter(|entry| {
+            if entry.link_target.is_some() || !is_contained(Path::new(&entry.relative_path)) {
+                tracing::warn!("Ignoring pushed file {:?}", entry.relative_path);
+                return false;
+            }
+            differs(&directory.join(&entry.relative_path), entry)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Whether the local file at `path` is missing or has other content than `entry`.
+fn differs(path: &Path, entry: &ManifestEntry) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return true;
+    };
+    if !metadata.is_file() || metadata.len() != entry.size {
+        return true;
+    }
+    // Without a hash the same size is all there is to go by
+    entry
+        .hash
+        .as_ref()
+        .is_some_and(|hash| hash_file(path).map_or(true, |local| &local != hash))
+}
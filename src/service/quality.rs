@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Pings remembered per peer, older ones no longer affect the quality.
+const PING_WINDOW: usize = 10;
+
+/// Highest score, shown as five filled dots.
+pub const MAX_SCORE: u8 = 5;
+
+/// How well the link to a peer performed recently, judged from periodic pings.
+#[derive(Debug, Clone, Default)]
+pub struct LinkQuality {
+    /// Round trip times of recent pings, `None` for a ping that stalled.
+    pings: VecDeque<Option<Duration>>,
+    /// The connection goes through a relay rather than straight to the peer.
+    pub relayed: bool,
+}
+
+impl LinkQuality {
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        self.record(Some(rtt));
+    }
+
+    /// A ping that got no answer in time.
+    pub fn record_stall(&mut self) {
+        self.record(None);
+    }
+
+    fn record(&mut self, ping: Option<Duration>) {
+        if self.pings.len() == PING_WINDOW {
+            self.pings.pop_front();
+        }
+        self.pings.push_back(ping);
+    }
+
+    /// Average round trip time of the recent pings that got an answer.
+    #[must_use]
+    pub fn rtt(&self) -> Option<Duration> {
+        let rtts: Vec<Duration> = self.pings.iter().flatten().copied().collect();
+        let count = u32::try_from(rtts.len()).ok().filter(|count| *count > 0)?;
+        Some(rtts.iter().sum::<Duration>() / count)
+    }
+
+    /// Score from 1 to [`MAX_SCORE`], `None` until the first ping came back.
+    ///
+    /// Slow round trips, stalled pings and relayed connections each cost points.
+    #[must_use]
+    pub fn score(&self) -> Option<u8> {
+        let rtt = self.rtt()?;
+        let rtt_penalty = match rtt.as_millis() {
+            0..50 => 0,
+            50..150 => 1,
+            150..400 => 2,
+            _ => 3,
+        };
+        let stalls = self.pings.iter().filter(|ping| ping.is_none()).count();
+        let stall_penalty = u8::try_from(stalls.min(2)).unwrap_or(2);
+        let relay_penalty = u8::from(self.relayed);
+        Some(
+            MAX_SCORE
+                .saturating_sub(rtt_penalty + stall_penalty + relay_penalty)
+                .max(1),
+        )
+    }
+}
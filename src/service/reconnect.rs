@@ -0,0 +1,112 @@
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::time::Duration;
+// tokio's clock, which tests can pause and move forward
+use tokio::time::Instant;
+
+/// Wait before the first redial, doubled for every further attempt.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between two redials.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+/// Redials of a lost peer before giving up on it.
+pub const MAX_ATTEMPTS: u32 = 8;
+
+/// How long to wait before the given attempt, counting from 1.
+#[must_use]
+pub fn backoff(attempt: u32) -> Duration {
+    BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_DELAY)
+}
+
+/// What happens after a peer we dialed went away or a redial failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    /// This attempt is scheduled after its backoff.
+    Scheduled(u32),
+    /// Out of attempts, the peer is forgotten.
+    GaveUp,
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    attempt: u32,
+    /// When to dial, `None` while the dial is in flight.
+    due: Option<Instant>,
+}
+
+/// Redials peers we dialed ourselves when their last connection closes.
+#[derive(Debug, Default)]
+pub struct ReconnectManager {
+    /// Last address each dialed peer was reached at.
+    addresses: HashMap<PeerId, Multiaddr>,
+    pending: HashMap<PeerId, Pending>,
+}
+
+impl ReconnectManager {
+    pub fn remember(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.addresses.insert(peer_id, address);
+    }
+
+    /// Stop caring about a peer, e.g. because the user disconnected on purpose.
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.addresses.remove(peer_id);
+        self.pending.remove(peer_id);
+    }
+
+    /// The last connection to a peer closed, `None` if it's not one we dialed.
+    pub fn connection_lost(&mut self, peer_id: PeerId, now: Instant) -> Option<Attempt> {
+        if !self.addresses.contains_key(&peer_id) {
+            return None;
+        }
+        Some(self.schedule(peer_id, 1, now))
+    }
+
+    pub fn connected(&mut self, peer_id: &PeerId) {
+        self.pending.remove(peer_id);
+    }
+
+    /// A dial failed, `None` if the peer isn't being reconnected.
+    pub fn dial_failed(&mut self, peer_id: PeerId, now: Instant) -> Option<Attempt> {
+        let attempt = self.pending.get(&peer_id)?.attempt;
+        Some(self.schedule(peer_id, attempt + 1, now))
+    }
+
+    fn schedule(&mut self, peer_id: PeerId, attempt: u32, now: Instant) -> Attempt {
+        if attempt > MAX_ATTEMPTS {
+            self.forget(&peer_id);
+            return Attempt::GaveUp;
+        }
+        self.pending.insert(
+            peer_id,
+            Pending {
+                attempt,
+                due: Some(now + backoff(attempt)),
+            },
+        );
+        Attempt::Scheduled(attempt)
+    }
+
+    /// When the next redial is due.
+    #[must_use]
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .filter_map(|pending| pending.due)
+            .min()
+    }
+
+    /// Peers whose redial is due with the address to dial, marked as in flight.
+    pub fn take_due(&mut self, now: Instant) -> Vec<(PeerId, Multiaddr)> {
+        let mut due = Vec::new();
+        for (peer_id, pending) in &mut self.pending {
+            if pending.due.is_some_and(|at| at <= now) {
+                if let Some(address) = self.addresses.get(peer_id) {
+                    pending.due = None;
+                    due.push((*peer_id, address.clone()));
+                }
+            }
+        }
+        due
+    }
+}
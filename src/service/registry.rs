@@ -0,0 +1,277 @@
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::protocol::{Manifest, ManifestEntry, ShareUpdate};
+use super::utils::transfer_path;
+use crate::app::DirectoryItem;
+
+/// Registry shared between the event loop, which updates it, and transfer tasks, which
+/// resolve requested paths against it.
+pub type SharedRegistry = Arc<RwLock<ShareRegistry>>;
+
+/// Everything the host currently shares.
+///
+/// Entries are keyed by their virtual path, i.e. the path relative to the share's virtual
+/// root that downloaders see. Each entry maps back to the absolute path on disk.
+#[derive(Debug, Default)]
+pub struct ShareRegistry {
+    version: u64,
+    read_only: bool,
+    /// Directory files are sent relative to, instead of the working directory.
+    root: Option<PathBuf>,
+    items: Vec<DirectoryItem>,
+    /// Indices into `items` by the directory they are listed in, the top of the share
+    /// under the empty path.
+    children: HashMap<PathBuf, Vec<usize>>,
+    entries: HashMap<PathBuf, ShareEntry>,
+    by_absolute_path: HashMap<PathBuf, PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShareEntry {
+    pub absolute_path: PathBuf,
+    pub is_dir: bool,
+    /// Size and hash as published, what the [`Manifest`] tells downloaders.
+    pub size: u64,
+    pub hash: Option<String>,
+    /// Where the link points to, for symlinks shared as links.
+    pub link_target: Option<PathBuf>,
+    /// Read-only handle opened when the file was published, only in read-only mode.
+    pub handle: Option<Arc<ReadOnlyHandle>>,
+}
+
+/// A shared file opened read-only up front.
+///
+/// Transfers read from a clone of the handle, which shares the file offset, so the lock
+/// makes concurrent transfers of the same file take turns.
+#[derive(Debug)]
+pub struct ReadOnlyHandle {
+    pub file: std::fs::File,
+    pub lock: tokio::sync::Mutex<()>,
+}
+
+impl ReadOnlyHandle {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            file: std::fs::OpenOptions::new().read(true).open(path)?,
+            lock: tokio::sync::Mutex::new(()),
+        })
+    }
+}
+
+impl ShareRegistry {
+    #[must_use]
+    pub fn shared() -> SharedRegistry {
+        Arc::new(RwLock::new(Self::default()))
+    }
+
+    /// Open every shared file read-only as soon as it is published and only ever serve
+    /// from those handles.
+    pub const fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    #[must_use]
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Send files relative to `root`, so a directory synced with `sync` is mirrored
+    /// rather than saved below its own name.
+    pub fn set_root(&mut self, root: Option<PathBuf>) {
+        self.root = root;
+    }
+
+    /// Where a downloader saves the file at `absolute_path`, relative to its directory.
+    fn relative_path(&self, absolute_path: &Path) -> PathBuf {
+        self.root
+            .as_deref()
+            .and_then(|root| absolute_path.strip_prefix(root).ok())
+            .map_or_else(|| transfer_path(absolute_path), Path::to_path_buf)
+    }
+
+    /// Manifest version, bumped every time the shared items change.
+    #[must_use]
+    pub const fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The items as they are listed to downloaders.
+    #[must_use]
+    pub fn items(&self) -> &[DirectoryItem] {
+        &self.items
+    }
+
+    /// Replace the share with a new set of items, a no-op if nothing changed.
+    ///
+    /// Items whose virtual path is already taken, e.g. two roots containing a file with the
+    /// same name, get a numbered suffix so every entry stays addressable.
+    pub fn replace(&mut self, items: Vec<DirectoryItem>) {
+        if self.items == items {
+            return;
+        }
+        self.entries.clear();
+        self.by_absolute_path.clear();
+        for item in &items {
+            let virtual_path = self.unique_virtual_path(&item.display_path);
+            // Followed links were resolved when listed, only copied ones are still links
+            let link_target = if item.is_dir {
+                None
+            } else {
+                std::fs::read_link(&item.path).ok()
+            };
+            let handle = if self.read_only && !item.is_dir && link_target.is_none() {
+                match ReadOnlyHandle::open(&item.path) {
+                    Ok(handle) => Some(Arc::new(handle)),
+                    Err(e) => {
+                        tracing::warn!(
+                            "Not sharing {:?}, cannot open it read-only: {}",
+                            item.path,
+                            e
+                        );
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            self.by_absolute_path
+                .insert(item.path.clone(), virtual_path.clone());
+            self.entries.insert(
+                virtual_path,
+                ShareEntry {
+                    absolute_path: item.path.clone(),
+                    is_dir: item.is_dir,
+                    size: item.size,
+                    hash: item.hash.clone(),
+                    link_target,
+                    handle,
+                },
+            );
+        }
+        self.items = items;
+        self.index_children();
+        self.version += 1;
+    }
+
+    /// [`Self::replace`] the items and tell what changed, `None` if nothing did.
+    pub fn update(&mut self, items: Vec<DirectoryItem>) -> Option<ShareUpdate> {
+        let base = self.version;
+        let added = items
+            .iter()
+            .filter(|item| !self.items.contains(item))
+            .cloned()
+            .collect();
+        let removed = self
+            .items
+            .iter()
+            .filter(|old| !items.iter().any(|item| item.path == old.path))
+            .map(|old| old.path.clone())
+            .collect();
+        self.replace(items);
+        (self.version != base).then_some(ShareUpdate {
+            base,
+            version: self.version,
+            added,
+            removed,
+        })
+    }
+
+    /// Items whose parent isn't a shared directory are at the top of the share.
+    fn index_children(&mut self) {
+        let directories: HashSet<&Path> = self
+            .items
+            .iter()
+            .filter(|item| item.is_dir)
+            .map(|item| item.path.as_path())
+            .collect();
+        let mut children: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+        for (index, item) in self.items.iter().enumerate() {
+            let parent = item
+                .path
+                .parent()
+                .filter(|parent| directories.contains(parent))
+                .map_or_else(PathBuf::new, Path::to_path_buf);
+            children.entry(parent).or_default().push(index);
+        }
+        self.children = children;
+    }
+
+    /// The items listed in `directory`, as listed by downloaders and empty for the top of
+    /// the share, with `recursive` everything below it.
+    #[must_use]
+    pub fn list(&self, directory: &Path, recursive: bool) -> Vec<DirectoryItem> {
+        let mut items = Vec::new();
+        let mut pending = vec![directory];
+        while let Some(directory) = pending.pop() {
+            for &index in self.children.get(directory).into_iter().flatten() {
+                let item = &self.items[index];
+                if recursive && item.is_dir {
+                    pending.push(&item.path);
+                }
+                items.push(item.clone());
+            }
+        }
+        items
+    }
+
+    /// Look up a path requested by a downloader, either virtual or absolute.
+    #[must_use]
+    pub fn resolve(&self, requested: &Path) -> Option<&ShareEntry> {
+        self.entries.get(requested).or_else(|| {
+            self.by_absolute_path
+                .get(requested)
+                .and_then(|virtual_path| self.entries.get(virtual_path))
+        })
+    }
+
+    /// Resolve a requested path to a shared file, directories are never served.
+    #[must_use]
+    pub fn resolve_file(&self, requested: &Path) -> Option<&ShareEntry> {
+        self.resolve(requested).filter(|entry| !entry.is_dir)
+    }
+
+    /// What would be sent for each of the requested paths, as they resolve right now.
+    #[must_use]
+    pub fn manifest(&self, paths: &[String]) -> Manifest {
+        let mut manifest = Manifest::default();
+        for path in paths {
+            match self.resolve_file(Path::new(path)) {
+                Some(entry) => manifest.entries.push(ManifestEntry {
+                    path: path.clone(),
+                    relative_path: self
+                        .relative_path(&entry.absolute_path)
+                        .to_string_lossy()
+                        .to_string(),
+                    size: entry.size,
+                    hash: entry.hash.clone(),
+                    link_target: entry
+                        .link_target
+                        .as_ref()
+                        .map(|target| target.to_string_lossy().to_string()),
+                }),
+                None => manifest.missing.push(path.clone()),
+            }
+        }
+        manifest
+    }
+
+    fn unique_virtual_path(&self, path: &Path) -> PathBuf {
+        if !self.entries.contains_key(path) {
+            return path.to_path_buf();
+        }
+        let stem = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+        let extension = path
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        (2..=usize::MAX)
+            .map(|n| path.with_file_name(format!("{stem} ({n}){extension}")))
+            .find(|candidate| !self.entries.contains_key(candidate))
+            .expect("an unused suffix to exist")
+    }
+}
@@ -0,0 +1,52 @@
+//! Keeps debug logging on hot paths, such as every chunk of a 100 GB transfer, to a few
+//! lines per second.
+
+use std::time::{Duration, Instant};
+
+/// Interval the per-chunk logs of a single transfer are sampled over.
+pub const CHUNK_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Lets through at most `burst` occurrences per interval and counts the rest.
+#[derive(Debug, Clone)]
+pub struct LogSampler {
+    interval: Duration,
+    burst: u32,
+    window_start: Option<Instant>,
+    logged: u32,
+    suppressed: u64,
+}
+
+impl LogSampler {
+    #[must_use]
+    pub const fn new(interval: Duration, burst: u32) -> Self {
+        Self {
+            interval,
+            burst,
+            window_start: None,
+            logged: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Whether to log this occurrence, with the number skipped since the last one logged.
+    pub fn sample(&mut self) -> Option<u64> {
+        self.sample_at(Instant::now())
+    }
+
+    pub fn sample_at(&mut self, now: Instant) -> Option<u64> {
+        if self
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= self.interval)
+        {
+            self.window_start = Some(now);
+            self.logged = 0;
+        }
+        if self.logged < self.burst {
+            self.logged += 1;
+            Some(std::mem::take(&mut self.suppressed))
+        } else {
+            self.suppressed += 1;
+            None
+        }
+    }
+}
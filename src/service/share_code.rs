@@ -0,0 +1,157 @@
+//! Short codes like `7-guitar-sunset-otter-cedar-walnut` standing in for a host's addresses.
+//!
+//! They are easy to read out over the phone. The host announces itself in the DHT as a
+//! provider of a key derived from the code, or registers at a `--rendezvous` point under a
+//! namespace named after it. `download --code` looks it up in the same place.
+//!
+//! Anyone may provide any key or register any namespace, so neither tells who the host is.
+//! The whole code is derived from the host's peer ID instead: a downloader only dials a peer
+//! the code was made from, and finds another one would take about 2^41 made up identities.
+//! Two such peers answering for the same code fail the lookup rather than one being picked.
+
+use libp2p::{kad, multiaddr::Multiaddr, PeerId};
+use sha2::{Digest, Sha256};
+
+use super::node::AddressKind;
+
+/// Codes start with a number from 1 up to this.
+const MAX_NUMBER: u32 = 99;
+
+/// How many words follow the number.
+const WORD_COUNT: usize = 5;
+
+/// Multihash code of SHA2-256, which DHT keys are made of.
+const SHA2_256: u8 = 0x12;
+
+/// Short words that are hard to mishear, five of them follow the number.
+const WORDS: [&str; 128] = [
+    "acorn", "amber", "anchor", "apple", "arrow", "atlas", "badge", "bamboo", "banjo", "basket",
+    "beacon", "berry", "bishop", "blanket", "breeze", "bucket", "butter", "cactus", "camel",
+    "candle", "canyon", "carpet", "castle", "cedar", "cello", "cherry", "circus", "cobalt",
+    "cocoa", "comet", "copper", "coral", "cotton", "crayon", "cricket", "daisy", "denim", "desert",
+    "dolphin", "dragon", "eagle", "ember", "falcon", "feather", "fiddle", "forest", "fossil",
+    "garden", "ginger", "glacier", "goblet", "gravel", "guitar", "hammer", "harbor", "hazel",
+    "helmet", "honey", "island", "ivory", "jacket", "jungle", "kettle", "kitten", "ladder",
+    "lantern", "lemon", "lizard", "lobster", "magnet", "mango", "marble", "meadow", "meteor",
+    "mirror", "monkey", "muffin", "nectar", "noodle", "nutmeg", "oasis", "ocean", "olive", "orbit",
+    "otter", "oyster", "paddle", "panda", "parrot", "pebble", "pepper", "pickle", "pillow",
+    "pirate", "planet", "pocket", "puzzle", "rabbit", "radio", "raven", "ribbon", "river",
+    "rocket", "saddle", "salmon", "shadow", "silver", "sketch", "sunset", "tablet", "teapot",
+    "thunder", "timber", "tomato", "tractor", "tulip", "tunnel", "turtle", "velvet", "violin",
+    "walnut", "whistle", "willow", "window", "wizard", "yogurt", "zebra", "zipper",
+];
+
+/// The code of the host `peer_id`, the same every time.
+pub fn of(peer_id: &PeerId) -> String {
+    let hash = Sha256::digest(peer_id.to_bytes());
+    let number = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) % MAX_NUMBER + 1;
+    // 128 words, each picked by 7 bits of the hash
+    let words = hash[4..4 + WORD_COUNT]
+        .iter()
+        .map(|byte| WORDS[usize::from(byte & 0x7f)]);
+    std::iter::once(number.to_string())
+        .chain(words.map(str::to_string))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Whether `code` is the one of the host `peer_id`.
+#[must_use]
+pub fn belongs_to(code: &str, peer_id: &PeerId) -> bool {
+    of(peer_id) == code
+}
+
+/// A code as typed by someone, in any case and separated by dashes or spaces, written the
+/// way [`of`] does.
+///
+/// # Errors
+///
+/// If `input` is not a number followed by words codes are made of.
+pub fn parse(input: &str) -> Result<String, String> {
+    let input = input.trim().to_lowercase();
+    let parts: Vec<&str> = input
+        .split(|c: char| c == '-' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect();
+    let Some((number, words)) = parts
+        .split_first()
+        .filter(|(_, words)| words.len() == WORD_COUNT)
+    else {
+        return Err(format!(
+            "a code is a number and {WORD_COUNT} words, e.g. 7-guitar-sunset-otter-cedar-walnut"
+        ));
+    };
+    match number.parse::<u32>() {
+        Ok(1..=MAX_NUMBER) => {}
+        _ => {
+            return Err(format!(
+                "a code starts with a number from 1 to {MAX_NUMBER}"
+            ))
+        }
+    }
+    if let Some(word) = words.iter().find(|word| !WORDS.contains(word)) {
+        return Err(format!("'{word}' is not a word codes are made of"));
+    }
+    Ok(parts.join("-"))
+}
+
+/// The DHT key the host of the share with this code provides. A SHA2-256 multihash, like
+/// the keys of content, so that public DHT nodes keep the provider record.
+#[must_use]
+pub fn provider_key(code: &str) -> kad::RecordKey {
+    let digest = Sha256::digest(format!("/junkanoo/code/{code}").as_bytes());
+    // The multihash prefix: the hash function and the digest length
+    let mut key = vec![SHA2_256, 32];
+    key.extend_from_slice(&digest);
+    kad::RecordKey::new(&key)
+}
+
+/// The namespace the share with this code is registered under at a rendezvous point.
+#[must_use]
+pub fn namespace(code: &str) -> String {
+    format!("junkanoo/{code}")
+}
+
+/// The one host among `peers` the code belongs to, `None` if there is none. Fails if
+/// several are, then someone made up an identity for the code and neither can be trusted.
+///
+/// # Errors
+///
+/// If several of `peers` match the code.
+pub fn host(code: &str, peers: impl IntoIterator<Item = PeerId>) -> Result<Option<PeerId>, String> {
+    let mut hosts: Vec<PeerId> = peers
+        .into_iter()
+        .filter(|peer_id| belongs_to(code, peer_id))
+        .collect();
+    hosts.sort_unstable();
+    hosts.dedup();
+    match hosts[..] {
+        [] => Ok(None),
+        [host] => Ok(Some(host)),
+        _ => Err(format!(
+            "{} peers answer for the share code, ask the host for its address instead",
+            hosts.len()
+        )),
+    }
+}
+
+/// The addresses of the host `peer_id`, each ending in its peer ID, those other machines
+/// most likely reach first: public ones and relays, then the local network, loopback last.
+#[must_use]
+pub fn addresses(peer_id: PeerId, addresses: &[Multiaddr]) -> Vec<Multiaddr> {
+    let mut with_peer_id = Vec::new();
+    for address in addresses {
+        if let Ok(address) = address.clone().with_p2p(peer_id) {
+            if !with_peer_id.contains(&address) {
+                with_peer_id.push(address);
+            }
+        }
+    }
+    by_reachability(&mut with_peer_id);
+    with_peer_id
+}
+
+/// Sort addresses the way other machines most likely reach them first, see [`addresses`].
+pub fn by_reachability(addresses: &mut [Multiaddr]) {
+    addresses.sort_by_key(AddressKind::of);
+}
@@ -0,0 +1,65 @@
+//! Readiness and watchdog notifications for running as a systemd service, see
+//! `sd_notify(3)`. Outside of systemd `NOTIFY_SOCKET` isn't set and nothing is sent.
+
+use std::time::Duration;
+
+/// Tell the service manager about a state change, e.g. `READY=1`. Returns whether the
+/// message was sent.
+pub fn notify(state: &str) -> bool {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return false;
+    };
+    match send(&socket, state) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!("Failed to notify systemd of {}: {}", state, e);
+            false
+        }
+    }
+}
+
+/// Send `state` as one datagram to the notification socket at `socket`, a path or an
+/// abstract socket name starting with `@`.
+///
+/// # Errors
+///
+/// If the socket can't be reached.
+#[cfg(unix)]
+pub fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes() {
+        #[cfg(target_os = "linux")]
+        [b'@', name @ ..] => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &address)?;
+        }
+        _ => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn send(_socket: &std::ffi::OsStr, _state: &str) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// How often to send `WATCHDOG=1`, half the timeout systemd set for this process, `None`
+/// if it doesn't watch us.
+#[must_use]
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?;
+    // Meant for another process if the PID doesn't match, e.g. a parent shell
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let usec: u64 = usec.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
@@ -1,57 +1,232 @@
+use async_compression::futures::{bufread::ZstdDecoder, write::ZstdEncoder};
+use async_compression::tokio::write::GzipEncoder;
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use std::error::Error;
+use libp2p::PeerId;
 use std::io;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt as TokioAsyncReadExt;
+use tokio::io::AsyncSeekExt;
 use tokio::io::AsyncWriteExt as TokioAsyncWriteExt;
+use tokio::sync::watch;
 
-#[derive(Debug)]
-pub enum FileTransferError {
-    Io(io::Error),
-    Utf8(std::string::FromUtf8Error),
-}
+use super::archive::ArchiveFormat;
+use super::delta::{self, DeltaOp};
+use super::error::JunkanooError;
+use super::fairness::{FairScheduler, Turn};
+use super::hashing::hash_file;
+use super::limiter::RateLimiter;
+use super::protocol::{
+    read_response, write_response, FileAttributes, ResponseHeader, COMPRESSION_NONE,
+    COMPRESSION_ZSTD,
+};
+use super::sampling::{LogSampler, CHUNK_LOG_INTERVAL};
 
-impl std::fmt::Display for FileTransferError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Io(e) => write!(f, "IO error: {e}"),
-            Self::Utf8(e) => write!(f, "UTF-8 error: {e}"),
+/// Bytes read and written at a time by transfers, unless given `with_chunk_size`.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Bytes sent between checks whether the file being sent changed on disk, eight chunks.
+const SOURCE_CHECK_BYTES: usize = 8 * CHUNK_SIZE;
+
+/// Appended to the name of a file while it arrives, it only gets its own name once complete.
+/// Unusual enough not to clash with a shared file, like `notes.part` next to `notes`.
+pub const PARTIAL_SUFFIX: &str = ".junkanoo-partial";
+
+/// Why a link at `relative_path` pointing to `target` would lead out of the directory
+/// `relative_path` is relative to, or `None` if it stays inside.
+#[must_use]
+pub fn escaping_link(relative_path: &Path, target: &Path) -> Option<&'static str> {
+    if target.has_root() {
+        return Some("absolute targets are not followed");
+    }
+    // Resolve the target from the link's directory without touching the disk
+    let mut depth = 0usize;
+    let parent = relative_path.parent().unwrap_or_else(|| Path::new(""));
+    for component in parent.components().chain(target.components()) {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::ParentDir => match depth.checked_sub(1) {
+                Some(up) => depth = up,
+                None => return Some("it points outside the download directory"),
+            },
+            std::path::Component::CurDir => {}
+            _ => return Some("the path is not relative"),
         }
     }
+    None
 }
 
-impl Error for FileTransferError {}
+/// Where the file saved at `save_path` is written until it arrived in full.
+#[must_use]
+pub fn partial_path(save_path: &Path) -> PathBuf {
+    let mut name = save_path.file_name().unwrap_or_default().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    save_path.with_file_name(name)
+}
 
-impl From<std::string::FromUtf8Error> for FileTransferError {
-    fn from(err: std::string::FromUtf8Error) -> Self {
-        Self::Utf8(err)
+/// Whether the file at `path` has `size` bytes hashing to `expected_hash`.
+async fn is_up_to_date(path: &Path, size: u64, expected_hash: &str) -> bool {
+    let same_size = tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.len() == size);
+    if !same_size {
+        return false;
     }
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .is_ok_and(|hash| hash.is_ok_and(|hash| hash == expected_hash))
 }
 
-impl From<FileTransferError> for Box<dyn Error + Send> {
-    fn from(err: FileTransferError) -> Self {
-        Box::new(err)
+/// Whether compressing the file is likely to pay off, judged by its MIME type.
+#[must_use]
+pub fn is_compressible(path: &Path) -> bool {
+    let Some(mime) = mime_guess::from_path(path).first() else {
+        return true;
+    };
+    match mime.type_() {
+        mime_guess::mime::IMAGE | mime_guess::mime::VIDEO | mime_guess::mime::AUDIO => false,
+        mime_guess::mime::APPLICATION => !matches!(
+            mime.subtype().as_str(),
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "x-7z-compressed"
+                | "vnd.rar"
+                | "x-rar-compressed"
+                | "zstd"
+                | "java-archive"
+                | "pdf"
+        ),
+        _ => true,
     }
 }
 
 pub struct FileTransfer {
+    /// The file that is read.
+    source: PathBuf,
+    /// The path it is sent under, see [`transfer_path`].
     path: PathBuf,
     chunk_size: usize,
     progress: Arc<AtomicUsize>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    fair_share: Option<(Arc<FairScheduler>, PeerId)>,
+    compression: bool,
+    offset: u64,
+    length: Option<u64>,
+    handle: Option<std::fs::File>,
+    attributes: bool,
+    on_progress: Option<ProgressCallback>,
+}
+
+/// Size and modification time of a file when its transfer started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SourceState {
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl SourceState {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// The path a shared file is sent under, relative to the working directory when inside it.
+/// Outside it the root is left off, downloaders don't take absolute paths.
+#[must_use]
+pub fn transfer_path(path: &Path) -> PathBuf {
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    path.strip_prefix(&current_dir)
+        .unwrap_or(path)
+        .components()
+        .filter(|component| matches!(component, std::path::Component::Normal(_)))
+        .collect()
+}
+
+/// Whether the relative `path` stays below the directory it is joined to: not empty, not
+/// absolute and without `..` or `.` in it.
+#[must_use]
+pub fn is_contained(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+/// The error for a path the host sent that would be saved outside the download directory.
+fn outside_download_directory(relative_path: &str) -> JunkanooError {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Refusing to save {relative_path:?}, it leads outside the download directory"),
+    )
+    .into()
+}
+
+/// `relative_path` numbered like `notes (1).txt`, the first number not taken next to
+/// `destination`, where the file itself would be saved.
+fn free_name(relative_path: &str, destination: &Path) -> String {
+    let path = Path::new(relative_path);
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..=usize::MAX)
+        .map(|n| format!("{stem} ({n}){extension}"))
+        .find(|name| std::fs::symlink_metadata(destination.with_file_name(name)).is_err())
+        .map(|name| path.with_file_name(name).to_string_lossy().to_string())
+        .expect("an unused number to exist")
+}
+
+/// What sharing a directory does with the symlinks inside it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Share what links point to as if it were there, descending into linked directories.
+    #[default]
+    Follow,
+    /// Leave links out of the share.
+    Skip,
+    /// Share links as links, downloaders recreate them pointing to the same target.
+    Copy,
+}
+
+/// `root` and everything below it, with symlinks handled by `symlinks`. Links leading back
+/// into a directory being walked are left out rather than followed forever.
+pub fn walk(root: &Path, symlinks: SymlinkPolicy) -> impl Iterator<Item = PathBuf> {
+    walkdir::WalkDir::new(root)
+        .follow_links(symlinks == SymlinkPolicy::Follow)
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                if let (Some(path), Some(ancestor)) = (e.path(), e.loop_ancestor()) {
+                    tracing::warn!("Not following {:?}, it links back to {:?}", path, ancestor);
+                } else {
+                    tracing::debug!("Skipping an unreadable entry: {}", e);
+                }
+                None
+            }
+        })
+        .filter(move |entry| symlinks != SymlinkPolicy::Skip || !entry.path_is_symlink())
+        .map(walkdir::DirEntry::into_path)
 }
 
 #[allow(clippy::ptr_arg)]
 impl FileTransfer {
     pub fn new(path: &PathBuf) -> Self {
         // Convert to relative path immediately
-        let current_dir = std::env::current_dir().unwrap_or_default();
-        let relative_path = path
-            .strip_prefix(&current_dir)
-            .unwrap_or(path)
-            .to_path_buf();
+        let relative_path = transfer_path(path);
 
         tracing::debug!(
             "Relative path being used for file transfer: {:?}",
@@ -59,173 +234,1156 @@ impl FileTransfer {
         );
 
         Self {
+            source: path.clone(),
             path: relative_path,
-            chunk_size: 1024 * 1024, // 1MB chunks
+            chunk_size: CHUNK_SIZE,
             progress: Arc::new(AtomicUsize::new(0)),
+            rate_limit: None,
+            fair_share: None,
+            compression: false,
+            offset: 0,
+            length: None,
+            handle: None,
+            attributes: false,
+            on_progress: None,
+        }
+    }
+
+    /// Skip the first `offset` bytes of the file, only the remainder is sent.
+    #[must_use]
+    pub const fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Send at most `length` bytes from the offset on, the whole rest if `None`.
+    #[must_use]
+    pub const fn with_length(mut self, length: Option<u64>) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// Compress the body with zstd if the peer supports it and the file isn't already
+    /// compressed.
+    #[must_use]
+    pub const fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Read and send the file this many bytes at a time, [`CHUNK_SIZE`] by default.
+    #[must_use]
+    pub const fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Throttle the upload with a limiter shared across all outgoing transfers.
+    #[must_use]
+    pub fn with_rate_limit(mut self, rate_limit: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Send each chunk in `peer`'s turn, shared fairly with uploads to other peers.
+    #[must_use]
+    pub fn with_fair_share(mut self, scheduler: Arc<FairScheduler>, peer: PeerId) -> Self {
+        self.fair_share = Some((scheduler, peer));
+        self
+    }
+
+    async fn take_turn(&self, bytes: usize) -> Option<Turn> {
+        match &self.fair_share {
+            Some((scheduler, peer)) => Some(scheduler.turn(*peer, bytes).await),
+            None => None,
         }
     }
 
+    /// Send the file's permissions and modification time along, for downloaders that
+    /// offered the `attributes` feature.
+    #[must_use]
+    pub const fn with_attributes(mut self, attributes: bool) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Read from an already opened handle instead of opening the path again.
+    #[must_use]
+    pub fn with_handle(mut self, handle: std::fs::File) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Report progress after every chunk sent.
+    #[must_use]
+    pub fn with_progress(mut self, on_progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    fn report_progress(&self, bytes: usize, total: usize) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(bytes as u64, total as u64);
+        }
+    }
+
+    /// Bytes of the file sent so far, before compression.
+    #[must_use]
+    pub fn bytes_sent(&self) -> u64 {
+        self.progress.load(Ordering::SeqCst) as u64
+    }
+
     #[cfg(test)]
+    #[must_use]
     pub const fn path(&self) -> &PathBuf {
         &self.path
     }
 
-    pub async fn stream_file<S>(&self, stream: &mut S) -> Result<(), Box<dyn Error + Send>>
+    /// Fail with [`JunkanooError::SourceChanged`] if the file was modified or deleted
+    /// since it was `opened`. A read-only handle is checked rather than the path, replacing
+    /// the file doesn't change what the handle reads.
+    fn check_source(&self, opened: &SourceState) -> Result<(), JunkanooError> {
+        let metadata = self.handle.as_ref().map_or_else(
+            || std::env::current_dir().and_then(|dir| std::fs::metadata(dir.join(&self.source))),
+            std::fs::File::metadata,
+        );
+        if metadata.is_ok_and(|metadata| SourceState::of(&metadata) == *opened) {
+            return Ok(());
+        }
+        tracing::warn!("{:?} changed while it was being sent", self.path);
+        Err(JunkanooError::SourceChanged)
+    }
+
+    async fn open(&self) -> Result<File, JunkanooError> {
+        if let Some(handle) = &self.handle {
+            let handle = handle.try_clone()?;
+            return Ok(File::from_std(handle));
+        }
+        let current_dir = std::env::current_dir()?;
+        let full_path = current_dir.join(&self.source);
+
+        tracing::debug!("Full path being used for file transfer: {:?}", full_path);
+
+        File::open(&full_path).await.map_err(JunkanooError::from)
+    }
+
+    /// Accept the request with the relative path, the body size and its compression.
+    async fn write_header<S>(
+        &self,
+        stream: &mut S,
+        size: usize,
+        compression: u8,
+        metadata: &std::fs::Metadata,
+    ) -> Result<(), JunkanooError>
     where
         S: AsyncWrite + Unpin,
     {
-        let current_dir =
-            std::env::current_dir().map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let full_path = current_dir.join(&self.path);
+        let header = ResponseHeader {
+            path: self.path.to_string_lossy().to_string(),
+            size,
+            compression,
+            attributes: self
+                .attributes
+                .then(|| FileAttributes::from_metadata(metadata)),
+        };
+        write_response(stream, &header).await
+    }
 
-        tracing::debug!("Full path being used for file transfer: {:?}", full_path);
+    /// Answer a file request with the header and the file's contents.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read or the stream breaks.
+    pub async fn stream_file<S>(&self, stream: &mut S) -> Result<(), JunkanooError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let mut file = self.open().await?;
+        let metadata = file.metadata().await?;
+        if self.offset > metadata.len() {
+            return Err(JunkanooError::Protocol(format!(
+                "offset {} is past the end of the file",
+                self.offset
+            )));
+        }
+        file.seek(std::io::SeekFrom::Start(self.offset)).await?;
+        // Only the bytes after the offset, up to the requested length, follow the header
+        let remaining = metadata.len() - self.offset;
+        let body_size = self
+            .length
+            .map_or(remaining, |length| length.min(remaining));
+        let file_size = usize::try_from(body_size)?;
+        let file = file.take(body_size);
 
-        let file = File::open(&full_path)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let metadata = file
-            .metadata()
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let file_size =
-            usize::try_from(metadata.len()).map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-
-        // Send the relative path and file size
-        let path_str = self.path.to_string_lossy().to_string();
-        let path_bytes = path_str.as_bytes();
-        stream
-            .write_all(&(path_bytes.len() as u64).to_le_bytes())
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        stream
-            .write_all(path_bytes)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        stream
-            .write_all(&file_size.to_le_bytes())
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+        let compress = self.compression && is_compressible(&self.path);
+        let compression = if compress {
+            COMPRESSION_ZSTD
+        } else {
+            COMPRESSION_NONE
+        };
+        self.write_header(stream, file_size, compression, &metadata)
+            .await?;
 
+        let source = SourceState::of(&metadata);
+        if compress {
+            let mut encoder = ZstdEncoder::new(&mut *stream);
+            self.copy_file(file, file_size, &source, &mut encoder)
+                .await?;
+            // Closing writes the end of the zstd frame
+            encoder.close().await?;
+            return Ok(());
+        }
+
+        self.copy_file(file, file_size, &source, stream).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// Answer a delta request: send the usual header, read the signatures of the
+    /// downloader's copy, then the blocks it can reuse and the bytes it lacks.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read, the signatures are malformed or the stream breaks.
+    pub async fn stream_delta<S>(&self, stream: &mut S) -> Result<(), JunkanooError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut file = self.open().await?;
+        let metadata = file.metadata().await?;
+        let file_size = usize::try_from(metadata.len())?;
+        // A shared handle may have been read before
+        file.seek(std::io::SeekFrom::Start(0)).await?;
+        self.write_header(stream, file_size, COMPRESSION_NONE, &metadata)
+            .await?;
+        stream.flush().await?;
+
+        let (block_size, signatures) = delta::read_signatures(stream).await?;
+        let source = SourceState::of(&metadata);
+        let file = file.into_std().await;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+        let worker = tokio::task::spawn_blocking(move || {
+            let mut reader = io::Read::take(file, metadata.len());
+            delta::compute_delta(&mut reader, &signatures, block_size, |op| {
+                sender
+                    .blocking_send(op)
+                    .map_err(|_| io::Error::other("delta stream closed"))
+            })
+        });
+
+        let mut sent = 0;
+        let mut checked = 0;
+        while let Some(op) = receiver.recv().await {
+            // Held until the op is written
+            let _turn = if let DeltaOp::Data(bytes) = &op {
+                if sent - checked >= SOURCE_CHECK_BYTES {
+                    self.check_source(&source)?;
+                    checked = sent;
+                }
+                let turn = self.take_turn(bytes.len()).await;
+                if let Some(limiter) = &self.rate_limit {
+                    limiter.acquire(bytes.len()).await;
+                }
+                sent += bytes.len();
+                self.progress.store(sent, Ordering::SeqCst);
+                // Only the changed bytes are sent, so this rarely reaches the file size
+                self.report_progress(sent, file_size);
+                turn
+            } else {
+                None
+            };
+            delta::write_op(stream, Some(&op)).await?;
+        }
+        worker.await??;
+        // Only a consistent delta is finished, the downloader keeps its copy otherwise
+        self.check_source(&source)?;
+        delta::write_op(stream, None).await?;
+        stream.flush().await.map_err(JunkanooError::from)
+    }
+
+    async fn copy_file<R, W>(
+        &self,
+        file: R,
+        file_size: usize,
+        source: &SourceState,
+        writer: &mut W,
+    ) -> Result<(), JunkanooError>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
         let mut reader = tokio::io::BufReader::with_capacity(self.chunk_size, file);
         let mut buffer = vec![0u8; self.chunk_size];
         let mut total_read = 0;
+        let mut checked = 0;
+        let mut chunk_log = LogSampler::new(CHUNK_LOG_INTERVAL, 1);
 
         loop {
-            let bytes_read = reader
-                .read(&mut buffer)
-                .await
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            let bytes_read = reader.read(&mut buffer).await?;
             if bytes_read == 0 {
+                // The last window, the body may already hold bytes of two versions
+                self.check_source(source)?;
                 break;
             }
+            if total_read - checked >= SOURCE_CHECK_BYTES {
+                self.check_source(source)?;
+                checked = total_read;
+            }
 
-            stream
-                .write_all(&buffer[..bytes_read])
-                .await
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            let _turn = self.take_turn(bytes_read).await;
+            if let Some(limiter) = &self.rate_limit {
+                limiter.acquire(bytes_read).await;
+            }
+            writer.write_all(&buffer[..bytes_read]).await?;
             total_read += bytes_read;
             self.progress.store(total_read, Ordering::SeqCst);
+            self.report_progress(total_read, file_size);
+            if let Some(skipped) = chunk_log.sample() {
+                tracing::debug!(
+                    "Sent {} bytes of {:?}, {} chunks not logged",
+                    total_read,
+                    self.path,
+                    skipped
+                );
+            }
         }
-
-        stream
-            .flush()
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
         Ok(())
     }
 }
 
+/// Called with the bytes transferred so far and the total size of the body.
+pub type ProgressCallback = Box<dyn Fn(u64, u64) + Send + Sync>;
+
 pub struct FileReceiver {
     chunk_size: usize,
     progress: Arc<AtomicUsize>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    on_progress: Option<ProgressCallback>,
+    expected_hash: Option<String>,
+    expected_size: Option<u64>,
+    directory: Option<PathBuf>,
+    cancel: Option<Arc<AtomicBool>>,
+    preserve: bool,
+    conflict: ConflictPolicy,
+    save_as: Option<String>,
+    control: Option<watch::Receiver<TransferControl>>,
+}
+
+/// Signal to a single running download, sent through a watch channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TransferControl {
+    #[default]
+    Run,
+    /// Stop between chunks until told to run again.
+    Pause,
+    /// Stop for good, keeping what was written so far.
+    Cancel,
+}
+
+/// Wait while `control` says pause, then whether the transfer goes on.
+pub async fn proceed(control: &mut watch::Receiver<TransferControl>) -> bool {
+    control
+        .wait_for(|control| *control != TransferControl::Pause)
+        .await
+        .map_or(true, |control| *control != TransferControl::Cancel)
+}
+
+/// What to do when a file being downloaded already exists with other content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Keep the existing file and don't download.
+    Skip,
+    /// Save next to the existing file as `name (1).ext`, or the first number not taken.
+    Rename,
+    /// Ask about all existing files of a download at once, see
+    /// [`Event::ConflictsFound`](super::node::Event::ConflictsFound). Renamed when the
+    /// host sent no manifest to find them with up front.
+    Ask,
+}
+
+/// What was done about a file that already existed at the destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    Overwritten,
+    Skipped,
+    /// Saved under this path instead, relative to the download directory.
+    Renamed(String),
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Overwritten => f.write_str("replaced the existing file"),
+            Self::Skipped => f.write_str("kept the existing file"),
+            Self::Renamed(path) => write!(f, "saved as {path}"),
+        }
+    }
+}
+
+/// Outcome of [`FileReceiver::receive_file`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFile {
+    /// Where the file was saved, relative to the download directory. The path the host
+    /// sent unless it was renamed.
+    pub path: String,
+    /// The destination already held the same content, so nothing was written.
+    pub up_to_date: bool,
+    /// Set when the destination existed with other content.
+    pub conflict: Option<Conflict>,
+}
+
+impl Default for FileReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FileReceiver {
+    #[must_use]
     pub fn new() -> Self {
         Self {
-            chunk_size: 1024 * 1024, // 1MB chunks
+            chunk_size: CHUNK_SIZE,
             progress: Arc::new(AtomicUsize::new(0)),
+            rate_limit: None,
+            on_progress: None,
+            expected_hash: None,
+            expected_size: None,
+            directory: None,
+            cancel: None,
+            preserve: false,
+            conflict: ConflictPolicy::default(),
+            save_as: None,
+            control: None,
         }
     }
 
-    pub async fn receive_file<S>(&self, stream: &mut S) -> Result<String, Box<dyn Error + Send>>
+    /// Receive and write the body this many bytes at a time, [`CHUNK_SIZE`] by default.
+    #[must_use]
+    pub const fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Throttle the download with a limiter shared across all incoming transfers.
+    #[must_use]
+    pub fn with_rate_limit(mut self, rate_limit: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Skip the body if the destination already has content with this SHA-256, and only
+    /// save a body that hashes to it.
+    #[must_use]
+    pub fn with_expected_hash(mut self, expected_hash: Option<String>) -> Self {
+        self.expected_hash = expected_hash;
+        self
+    }
+
+    /// Size the manifest announced. If the host sends that size the file is allocated in
+    /// full before the body arrives, and a body that ends early fails the transfer.
+    #[must_use]
+    pub const fn with_expected_size(mut self, expected_size: Option<u64>) -> Self {
+        self.expected_size = expected_size;
+        self
+    }
+
+    /// Save below this directory instead of directly in the working directory.
+    #[must_use]
+    pub fn with_directory(mut self, directory: Option<PathBuf>) -> Self {
+        self.directory = directory;
+        self
+    }
+
+    /// Report progress once the header arrived and after every chunk written.
+    #[must_use]
+    pub fn with_progress(mut self, on_progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// Stop between chunks once `cancel` is set, keeping what was written so far.
+    #[must_use]
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Pause and cancel this transfer alone through `control`.
+    #[must_use]
+    pub fn with_control(mut self, control: Option<watch::Receiver<TransferControl>>) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Give received files the permissions and modification time the host sent.
+    #[must_use]
+    pub const fn with_preserve(mut self, preserve: bool) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// What to do when the destination already exists with other content.
+    #[must_use]
+    pub const fn with_conflict(mut self, conflict: ConflictPolicy) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// Save under this path, relative to the download directory, rather than the one the
+    /// host sends. For conflicts settled before the transfer started, see
+    /// [`Self::resolve_conflict`].
+    #[must_use]
+    pub fn with_save_as(mut self, save_as: Option<String>) -> Self {
+        self.save_as = save_as;
+        self
+    }
+
+    /// Apply the attributes the host sent for the file at `save_path`. Best effort, the
+    /// content arrived either way.
+    async fn preserve_attributes(&self, save_path: &Path, attributes: Option<FileAttributes>) {
+        let Some(attributes) = attributes.filter(|_| self.preserve) else {
+            return;
+        };
+        let path = save_path.to_path_buf();
+        let result = tokio::task::spawn_blocking(move || -> io::Result<()> {
+            if let Some(modified) = attributes.modified {
+                std::fs::File::options()
+                    .write(true)
+                    .open(&path)?
+                    .set_modified(modified)?;
+            }
+            let mut permissions = std::fs::metadata(&path)?.permissions();
+            #[cfg(unix)]
+            std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, attributes.mode & 0o777);
+            #[cfg(not(unix))]
+            permissions.set_readonly(attributes.mode & 0o200 == 0);
+            std::fs::set_permissions(&path, permissions)
+        })
+        .await;
+        if let Ok(Err(e)) = result {
+            tracing::warn!(
+                "Failed to preserve the attributes of {:?}: {}",
+                save_path,
+                e
+            );
+        }
+    }
+
+    /// Wait here while the transfer is paused. Flush what arrived so far if it was
+    /// cancelled, a later download picks the partial file up as the base of a delta.
+    async fn check_cancelled<W>(&self, file: &mut W) -> Result<(), JunkanooError>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let mut proceeding = true;
+        if let Some(control) = &self.control {
+            if *control.borrow() != TransferControl::Run {
+                // What arrived so far is on disk while the transfer waits
+                file.flush().await?;
+                proceeding = proceed(&mut control.clone()).await;
+            }
+        }
+        if proceeding
+            && !self
+                .cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+        {
+            return Ok(());
+        }
+        file.flush().await?;
+        Err(JunkanooError::Cancelled)
+    }
+
+    fn report_progress(&self, bytes: usize, total: usize) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(bytes as u64, total as u64);
+        }
+    }
+
+    /// Receive the answer to a file request and save it.
+    ///
+    /// # Errors
+    ///
+    /// If the host refuses, the file can't be written or the stream breaks.
+    pub async fn receive_file<S>(&self, stream: &mut S) -> Result<ReceivedFile, JunkanooError>
     where
         S: AsyncRead + AsyncWrite + Unpin,
     {
         tracing::debug!("Receiving file");
 
-        // Read the relative path length
-        let mut path_len_bytes = [0u8; 8];
-        stream
-            .read_exact(&mut path_len_bytes)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let path_len = usize::try_from(u64::from_le_bytes(path_len_bytes))
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+        let ResponseHeader {
+            path: relative_path,
+            size: file_size,
+            compression,
+            attributes,
+        } = read_response(stream).await?;
+        let save_path = self.save_path(&relative_path).await?;
+
+        if self.is_up_to_date(&save_path, file_size as u64).await {
+            // Dropping the stream stops the host from sending the rest of the body
+            return Ok(ReceivedFile {
+                path: relative_path,
+                up_to_date: true,
+                conflict: None,
+            });
+        }
+        let (relative_path, conflict) = self.settle_conflict(relative_path).await?;
+        if conflict == Some(Conflict::Skipped) {
+            return Ok(ReceivedFile {
+                path: relative_path,
+                up_to_date: false,
+                conflict,
+            });
+        }
+        let save_path = self.save_path(&relative_path).await?;
 
-        tracing::debug!("Path length: {}", path_len);
+        // Written next to the destination, which it replaces once complete. A transfer that
+        // breaks off leaves the part received there, to resume from with a delta
+        tracing::debug!("Creating file");
+        let partial_path = partial_path(&save_path);
+        let mut file = File::create(&partial_path).await?;
+        let preallocated = self.expected_size == Some(file_size as u64);
+        if preallocated {
+            file.set_len(file_size as u64).await?;
+        }
+        self.report_progress(0, file_size);
+        let written = match compression {
+            COMPRESSION_NONE => self.write_file(stream, &mut file, file_size).await,
+            COMPRESSION_ZSTD => {
+                let mut decoder = ZstdDecoder::new(futures::io::BufReader::new(&mut *stream));
+                self.write_file(&mut decoder, &mut file, file_size).await
+            }
+            other => Err(JunkanooError::UnsupportedCompression(other)),
+        };
+        // The allocated size no longer tells how much arrived, so what's missing is cut
+        // off again
+        let received = self.progress.load(Ordering::SeqCst);
+        if preallocated && (written.is_err() || received < file_size) {
+            file.set_len(received as u64).await?;
+        }
+        written?;
+        file.flush().await?;
+        drop(file);
+        if received < file_size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("received {received} of {file_size} bytes"),
+            )
+            .into());
+        }
 
-        // Read the relative path
-        let mut path_bytes = vec![0u8; path_len];
-        stream
-            .read_exact(&mut path_bytes)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let relative_path = String::from_utf8(path_bytes)
-            .map_err(|e| Box::new(FileTransferError::from(e)) as Box<dyn Error + Send>)?;
-
-        // Read the file size
-        let mut size_bytes = [0u8; 8];
-        stream
-            .read_exact(&mut size_bytes)
+        self.complete(&partial_path, &save_path, file_size).await?;
+        self.preserve_attributes(&save_path, attributes).await;
+        Ok(ReceivedFile {
+            path: relative_path,
+            up_to_date: false,
+            conflict,
+        })
+    }
+
+    /// Receive the answer to an archive request, see [`super::archive`], gzipped on the way
+    /// to disk for [`ArchiveFormat::TarGz`].
+    ///
+    /// # Errors
+    ///
+    /// If the host refuses, the archive can't be written or the stream breaks.
+    pub async fn receive_archive<S>(
+        &self,
+        stream: &mut S,
+        format: ArchiveFormat,
+    ) -> Result<ReceivedFile, JunkanooError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ResponseHeader {
+            path,
+            size,
+            compression,
+            ..
+        } = read_response(stream).await?;
+        let relative_path = match format {
+            ArchiveFormat::Tar => path,
+            ArchiveFormat::TarGz => format!("{path}.gz"),
+        };
+        let (relative_path, conflict) = self.settle_conflict(relative_path).await?;
+        if conflict == Some(Conflict::Skipped) {
+            return Ok(ReceivedFile {
+                path: relative_path,
+                up_to_date: false,
+                conflict,
+            });
+        }
+        let save_path = self.save_path(&relative_path).await?;
+
+        // A broken off archive can't be resumed, so its part is removed again
+        let partial_path = partial_path(&save_path);
+        let result = self
+            .write_archive(stream, &partial_path, format, size, compression)
+            .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&partial_path).await;
+        }
+        result?;
+        tokio::fs::rename(&partial_path, &save_path).await?;
+        Ok(ReceivedFile {
+            path: relative_path,
+            up_to_date: false,
+            conflict,
+        })
+    }
+
+    async fn write_archive<S>(
+        &self,
+        stream: &mut S,
+        partial_path: &Path,
+        format: ArchiveFormat,
+        size: usize,
+        compression: u8,
+    ) -> Result<(), JunkanooError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let file = File::create(partial_path).await?;
+        let mut writer: Box<dyn tokio::io::AsyncWrite + Send + Unpin> = match format {
+            ArchiveFormat::Tar => Box::new(file),
+            ArchiveFormat::TarGz => Box::new(GzipEncoder::new(file)),
+        };
+        self.report_progress(0, size);
+        match compression {
+            COMPRESSION_NONE => self.write_file(stream, &mut writer, size).await?,
+            COMPRESSION_ZSTD => {
+                let mut decoder = ZstdDecoder::new(futures::io::BufReader::new(&mut *stream));
+                self.write_file(&mut decoder, &mut writer, size).await?;
+            }
+            other => return Err(JunkanooError::UnsupportedCompression(other)),
+        }
+        // Without a manifest entry to check it against, the size is all there is
+        let received = self.progress.load(Ordering::SeqCst);
+        if received < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("received {received} of {size} bytes"),
+            )
+            .into());
+        }
+        // Writes the end of the gzip stream
+        writer.shutdown().await.map_err(JunkanooError::from)
+    }
+
+    /// Receive the answer to a delta request, rebuilding the file from the blocks of the
+    /// existing copy and the bytes the host sends. The part left by a download that broke
+    /// off is preferred as the copy, it is what this one continues. The destination is only
+    /// replaced once the new content is complete.
+    ///
+    /// # Errors
+    ///
+    /// If the host refuses, the file can't be written or the stream breaks.
+    pub async fn receive_delta<S>(&self, stream: &mut S) -> Result<ReceivedFile, JunkanooError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ResponseHeader {
+            path: relative_path,
+            size: file_size,
+            attributes,
+            ..
+        } = read_response(stream).await?;
+        let save_path = self.save_path(&relative_path).await?;
+
+        if self.is_up_to_date(&save_path, file_size as u64).await {
+            return Ok(ReceivedFile {
+                path: relative_path,
+                up_to_date: true,
+                conflict: None,
+            });
+        }
+        // A renamed copy is still rebuilt from the blocks of the existing file
+        let (relative_path, conflict) = self.settle_conflict(relative_path).await?;
+        if conflict == Some(Conflict::Skipped) {
+            return Ok(ReceivedFile {
+                path: relative_path,
+                up_to_date: false,
+                conflict,
+            });
+        }
+
+        let existing = save_path;
+        let save_path = self.save_path(&relative_path).await?;
+        let partial_path = partial_path(&save_path);
+        let basis = match std::fs::File::open(&partial_path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => std::fs::File::open(&existing),
+            opened => opened,
+        };
+        let basis = match basis {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let signatures = match &basis {
+            Some(file) => {
+                let mut file = file.try_clone()?;
+                tokio::task::spawn_blocking(move || {
+                    delta::signatures(&mut io::BufReader::new(&mut file), delta::BLOCK_SIZE)
+                })
+                .await??
+            }
+            None => Vec::new(),
+        };
+        delta::write_signatures(stream, delta::BLOCK_SIZE, &signatures).await?;
+
+        // A part left over is still read through `basis`, the rebuilt file gets a new one
+        // under its name rather than overwriting what is being copied from
+        match tokio::fs::remove_file(&partial_path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        self.apply_delta(stream, basis.map(File::from_std), &partial_path, file_size)
+            .await?;
+        self.complete(&partial_path, &save_path, file_size).await?;
+        self.preserve_attributes(&save_path, attributes).await;
+        Ok(ReceivedFile {
+            path: relative_path,
+            up_to_date: false,
+            conflict,
+        })
+    }
+
+    async fn apply_delta<S>(
+        &self,
+        stream: &mut S,
+        mut basis: Option<File>,
+        partial_path: &Path,
+        file_size: usize,
+    ) -> Result<(), JunkanooError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut file = File::create(partial_path).await?;
+        let mut block = vec![0u8; delta::BLOCK_SIZE];
+        let mut written = 0;
+        self.report_progress(0, file_size);
+        while let Some(op) = delta::read_op(stream).await? {
+            self.check_cancelled(&mut file).await?;
+            let bytes = match &op {
+                DeltaOp::Copy(index) => {
+                    let basis = basis.as_mut().ok_or_else(|| {
+                        JunkanooError::Protocol(format!("block {index} of an empty copy"))
+                    })?;
+                    let start = u64::from(*index) * delta::BLOCK_SIZE as u64;
+                    basis.seek(std::io::SeekFrom::Start(start)).await?;
+                    let mut filled = 0;
+                    while filled < block.len() {
+                        let read = basis.read(&mut block[filled..]).await?;
+                        if read == 0 {
+                            break;
+                        }
+                        filled += read;
+                    }
+                    if filled == 0 {
+                        return Err(JunkanooError::Protocol(format!(
+                            "block {index} is past the end of the copy"
+                        )));
+                    }
+                    &block[..filled]
+                }
+                DeltaOp::Data(bytes) => {
+                    if let Some(limiter) = &self.rate_limit {
+                        limiter.acquire(bytes.len()).await;
+                    }
+                    bytes.as_slice()
+                }
+            };
+            written += bytes.len();
+            if written > file_size {
+                return Err(JunkanooError::Protocol(format!(
+                    "delta is longer than the {file_size} byte file"
+                )));
+            }
+            file.write_all(bytes).await?;
+            self.progress.store(written, Ordering::SeqCst);
+            self.report_progress(written, file_size);
+        }
+        if written != file_size {
+            return Err(JunkanooError::Protocol(format!(
+                "delta rebuilt {written} of {file_size} bytes"
+            )));
+        }
+        file.flush().await.map_err(JunkanooError::from)
+    }
+
+    /// Check the file that arrived at `partial_path` is what was announced and give it the
+    /// name of `save_path`, so that a file only ever carries that name once complete. One
+    /// with other content than the expected hash is removed, it can't be used.
+    async fn complete(
+        &self,
+        partial_path: &Path,
+        save_path: &Path,
+        size: usize,
+    ) -> Result<(), JunkanooError> {
+        if let Some(expected) = self
+            .expected_size
+            .filter(|&expected| expected != size as u64)
+        {
+            return Err(JunkanooError::other(format!(
+                "expected {expected} bytes, found {size}"
+            )));
+        }
+        if let Some(expected_hash) = &self.expected_hash {
+            let path = partial_path.to_path_buf();
+            let hash = tokio::task::spawn_blocking(move || hash_file(&path))
+                .await
+                .map_err(|e| JunkanooError::other(e.to_string()))??;
+            if hash != *expected_hash {
+                let _ = tokio::fs::remove_file(partial_path).await;
+                return Err(JunkanooError::HashMismatch);
+            }
+        }
+        tokio::fs::rename(partial_path, save_path).await?;
+        Ok(())
+    }
+
+    /// Where a file the host sends as `relative_path` is saved. Absolute paths and paths
+    /// with `..` are refused, they could point anywhere.
+    ///
+    /// # Errors
+    ///
+    /// If the path is absolute or leads out of the download directory.
+    pub fn destination(&self, relative_path: &str) -> Result<PathBuf, JunkanooError> {
+        if !is_contained(Path::new(relative_path)) {
+            return Err(outside_download_directory(relative_path));
+        }
+        Ok(self.download_directory()?.join(relative_path))
+    }
+
+    /// The directory downloads are saved in, the current one unless set.
+    fn download_directory(&self) -> Result<PathBuf, JunkanooError> {
+        let mut current_dir = std::env::current_dir()?;
+        if let Some(directory) = &self.directory {
+            current_dir.push(directory);
+        }
+        Ok(current_dir)
+    }
+
+    /// Fail if `save_path` leads out of the download directory through a link already on
+    /// disk, in its parents or the path itself.
+    async fn check_links(
+        &self,
+        relative_path: &str,
+        save_path: &Path,
+    ) -> Result<(), JunkanooError> {
+        let Ok(base) = tokio::fs::canonicalize(self.download_directory()?).await else {
+            // Nothing below a directory that doesn't exist yet can be a link
+            return Ok(());
+        };
+        // The closest part of the path that exists decides where the rest ends up
+        for existing in save_path.ancestors() {
+            if let Ok(resolved) = tokio::fs::canonicalize(existing).await {
+                if resolved.starts_with(&base) {
+                    return Ok(());
+                }
+                return Err(outside_download_directory(relative_path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Recreate a link the host shares as a link, pointing to `target` like it does there.
+    /// Targets that would lead out of the download directory are refused.
+    ///
+    /// # Errors
+    ///
+    /// If the target leads out of the download directory or the link can't be created.
+    pub async fn create_link(
+        &self,
+        relative_path: &str,
+        target: &str,
+    ) -> Result<PathBuf, JunkanooError> {
+        let refuse = |reason: &str| {
+            JunkanooError::from(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Not linking {relative_path} to {target}, {reason}"),
+            ))
+        };
+        let target_path = Path::new(target);
+        if let Some(reason) = escaping_link(Path::new(relative_path), target_path) {
+            return Err(refuse(reason));
+        }
+
+        let save_path = self.save_path(relative_path).await?;
+        // An earlier copy of the link is replaced, anything else is left alone
+        if tokio::fs::symlink_metadata(&save_path)
             .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let file_size = usize::try_from(u64::from_le_bytes(size_bytes))
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        tracing::debug!("File size: {}", file_size);
-
-        // Create the full save path by joining with current directory
-        let current_dir =
-            std::env::current_dir().map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        let save_path = current_dir.join(&relative_path);
+            .is_ok_and(|metadata| metadata.is_symlink())
+        {
+            tokio::fs::remove_file(&save_path).await?;
+        }
+        #[cfg(unix)]
+        let created = tokio::fs::symlink(target_path, &save_path).await;
+        #[cfg(not(unix))]
+        let created = Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symlinks can only be recreated on unix",
+        ));
+        created?;
+        Ok(save_path)
+    }
+
+    /// What the conflict policy does with the file the host sends as `relative_path`, `None`
+    /// if nothing is in the way.
+    ///
+    /// # Errors
+    ///
+    /// If the existing file can't be inspected or moved aside.
+    pub async fn resolve_conflict(
+        &self,
+        relative_path: &str,
+    ) -> Result<Option<Conflict>, JunkanooError> {
+        let destination = self.destination(relative_path)?;
+        if tokio::fs::symlink_metadata(&destination).await.is_err() {
+            return Ok(None);
+        }
+        Ok(Some(match self.conflict {
+            ConflictPolicy::Overwrite => Conflict::Overwritten,
+            ConflictPolicy::Skip => Conflict::Skipped,
+            ConflictPolicy::Rename | ConflictPolicy::Ask => {
+                Conflict::Renamed(free_name(relative_path, &destination))
+            }
+        }))
+    }
+
+    /// The path to save the file the host sent as `relative_path` under, and what was
+    /// decided about an existing file there.
+    async fn settle_conflict(
+        &self,
+        relative_path: String,
+    ) -> Result<(String, Option<Conflict>), JunkanooError> {
+        if let Some(save_as) = &self.save_as {
+            return Ok((save_as.clone(), None));
+        }
+        Ok(match self.resolve_conflict(&relative_path).await? {
+            Some(Conflict::Renamed(renamed)) => (renamed.clone(), Some(Conflict::Renamed(renamed))),
+            conflict => (relative_path, conflict),
+        })
+    }
+
+    /// Whether the destination of `relative_path` already holds `size` bytes with the
+    /// expected hash, so the file doesn't need to be requested at all.
+    pub async fn has_file(&self, relative_path: &str, size: u64) -> bool {
+        match self.destination(relative_path) {
+            Ok(save_path) => self.is_up_to_date(&save_path, size).await,
+            Err(_) => false,
+        }
+    }
+
+    /// Where a file the host sent as `relative_path` is saved, with its parent directories
+    /// created.
+    async fn save_path(&self, relative_path: &str) -> Result<PathBuf, JunkanooError> {
+        let save_path = self.destination(relative_path)?;
+        self.check_links(relative_path, &save_path).await?;
         tracing::debug!("Creating file at save path: {:?}", save_path);
 
         // Create parent directories if they don't exist
         if let Some(parent) = save_path.parent() {
-            tokio::fs::create_dir_all(parent)
-                .await
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            tokio::fs::create_dir_all(parent).await?;
         }
+        Ok(save_path)
+    }
 
-        // Create the file and write the contents
-        tracing::debug!("Creating file");
+    async fn is_up_to_date(&self, save_path: &Path, file_size: u64) -> bool {
+        let Some(expected_hash) = &self.expected_hash else {
+            return false;
+        };
+        let up_to_date = is_up_to_date(save_path, file_size, expected_hash).await;
+        if up_to_date {
+            tracing::info!("{:?} is already up to date, skipping it", save_path);
+        }
+        up_to_date
+    }
 
-        let mut file = File::create(&save_path)
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+    /// Receive the body of a ranged request for up to `length` bytes into memory, nothing is
+    /// written to disk.
+    ///
+    /// # Errors
+    ///
+    /// If the host refuses, announces more than `length` bytes or sends fewer than it
+    /// announced, or the stream breaks.
+    pub async fn receive_range<S>(
+        &self,
+        stream: &mut S,
+        length: usize,
+    ) -> Result<Vec<u8>, JunkanooError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ResponseHeader {
+            size, compression, ..
+        } = read_response(stream).await?;
+        // Everything is held in memory, a host must not make us buffer more than asked for
+        if size > length {
+            return Err(JunkanooError::Protocol(format!(
+                "host announced {size} bytes for a range of {length}"
+            )));
+        }
+        let mut body = Vec::with_capacity(size.min(self.chunk_size));
+        match compression {
+            COMPRESSION_NONE => self.write_file(stream, &mut body, size).await?,
+            COMPRESSION_ZSTD => {
+                let mut decoder = ZstdDecoder::new(futures::io::BufReader::new(&mut *stream));
+                self.write_file(&mut decoder, &mut body, size).await?;
+            }
+            other => return Err(JunkanooError::UnsupportedCompression(other)),
+        }
+        if body.len() < size {
+            return Err(JunkanooError::Protocol(format!(
+                "host sent {} of {size} bytes",
+                body.len()
+            )));
+        }
+        Ok(body)
+    }
+
+    async fn write_file<R, W>(
+        &self,
+        reader: &mut R,
+        file: &mut W,
+        file_size: usize,
+    ) -> Result<(), JunkanooError>
+    where
+        R: AsyncRead + Unpin,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
         let mut buffer = vec![0u8; self.chunk_size];
         let mut total_read = 0;
+        let mut chunk_log = LogSampler::new(CHUNK_LOG_INTERVAL, 1);
 
         while total_read < file_size {
+            self.check_cancelled(file).await?;
             let bytes_to_read = std::cmp::min(self.chunk_size, file_size - total_read);
-            let bytes_read = stream
-                .read(&mut buffer[..bytes_to_read])
-                .await
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            let bytes_read = reader.read(&mut buffer[..bytes_to_read]).await?;
             if bytes_read == 0 {
                 break;
             }
 
-            file.write_all(&buffer[..bytes_read])
-                .await
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+            if let Some(limiter) = &self.rate_limit {
+                limiter.acquire(bytes_read).await;
+            }
+            file.write_all(&buffer[..bytes_read]).await?;
             total_read += bytes_read;
             self.progress.store(total_read, Ordering::SeqCst);
+            self.report_progress(total_read, file_size);
+            if let Some(skipped) = chunk_log.sample() {
+                tracing::debug!(
+                    "Received {} of {} bytes, {} chunks not logged",
+                    total_read,
+                    file_size,
+                    skipped
+                );
+            }
         }
-
-        file.flush()
-            .await
-            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
-        Ok(relative_path)
+        Ok(())
     }
 }
@@ -0,0 +1,509 @@
+//! Sharing and downloading without the terminal UI, for programs that embed junkanoo.
+
+use futures::StreamExt;
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::app::DirectoryItem;
+use crate::service::archive::ArchiveFormat;
+use crate::service::error::JunkanooError;
+use crate::service::hashing::HashCache;
+use crate::service::node::{self, Client, Event, NodeConfig, RequestedFile};
+use crate::service::protocol::DisplayResponse;
+use crate::service::secret::Secret;
+use crate::service::utils::{self, SymlinkPolicy};
+
+/// Events kept for subscribers that fall behind, older ones are dropped for them.
+const EVENT_CAPACITY: usize = 1024;
+
+/// A running junkanoo peer, turned into a [`ShareSession`] or a [`DownloadSession`].
+pub struct JunkanooNode {
+    client: Client,
+    peer_id: PeerId,
+    /// How directories given to [`Self::share`] are walked.
+    symlinks: SymlinkPolicy,
+    events: broadcast::Sender<Event>,
+    /// How each batch of requested files ended, see [`DownloadSession::download`].
+    outcomes: mpsc::UnboundedReceiver<Event>,
+}
+
+impl JunkanooNode {
+    /// Start the network on QUIC and TCP on every interface, ports picked by the system.
+    ///
+    /// # Errors
+    ///
+    /// If the node can't be created or listen on any address.
+    pub async fn start(config: NodeConfig) -> Result<Self, JunkanooError> {
+        Self::start_on(config, node::listen_addrs(None, 0)).await
+    }
+
+    /// Start the network listening on the given addresses, see [`node::listen_addrs`].
+    ///
+    /// # Errors
+    ///
+    /// If the node can't be created or listen on any of `addrs`.
+    pub async fn start_on(
+        config: NodeConfig,
+        addrs: Vec<Multiaddr>,
+    ) -> Result<Self, JunkanooError> {
+        let symlinks = config.symlinks;
+        let (mut client, event_stream, event_loop, peer_id) = node::new(&config)?;
+        tokio::spawn(event_loop.run());
+
+        // The event loop waits for every event to be taken, so they are always drained here
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let (outcome_sender, outcomes) = mpsc::unbounded_channel();
+        let forward = events.clone();
+        tokio::spawn(async move {
+            let mut event_stream = Box::pin(event_stream);
+            while let Some(event) = event_stream.next().await {
+                if matches!(
+                    event,
+                    Event::DownloadCompleted(_) | Event::DownloadFailed(_)
+                ) {
+                    let _ = outcome_sender.send(event.clone());
+                }
+                // Nobody subscribed is fine
+                let _ = forward.send(event);
+            }
+        });
+
+        let mut listening = 0;
+        for addr in addrs {
+            match client.start_listening(addr.clone()).await {
+                Ok(()) => listening += 1,
+                Err(e) => tracing::warn!("Failed to listen on {}: {}", addr, e),
+            }
+        }
+        if listening == 0 {
+            return Err(JunkanooError::other("Could not listen on any address"));
+        }
+
+        Ok(Self {
+            client,
+            peer_id,
+            symlinks,
+            events,
+            outcomes,
+        })
+    }
+
+    #[must_use]
+    pub const fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Network events from now on, e.g. transfer progress.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Addresses others reach this peer at, each ending in its peer ID.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn addresses(&mut self) -> Result<Vec<Multiaddr>, JunkanooError> {
+        let peer_id = self.peer_id;
+        Ok(self
+            .client
+            .get_listening_addrs()
+            .await?
+            .into_iter()
+            .map(|addr| addr.with(Protocol::P2p(peer_id)))
+            .collect())
+    }
+
+    /// Offer files and directories, with everything below them, to downloaders.
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn share(mut self, paths: Vec<PathBuf>) -> Result<ShareSession, JunkanooError> {
+        let symlinks = self.symlinks;
+        let items = tokio::task::spawn_blocking(move || {
+            let paths: Vec<PathBuf> = paths
+                .iter()
+                .flat_map(|path| utils::walk(path, symlinks))
+                .collect();
+            shared_items(&paths, symlinks, &mut HashCache::default())
+        })
+        .await?;
+        self.client.update_directory_items(items.clone()).await?;
+        Ok(ShareSession { node: self, items })
+    }
+
+    /// Connect to a host, introduce ourselves and fetch what it shares.
+    ///
+    /// # Errors
+    ///
+    /// If `address` has no peer ID, the host can't be reached, or it refuses us, e.g. for
+    /// a wrong password.
+    pub async fn download(
+        mut self,
+        address: Multiaddr,
+        password: Option<Secret>,
+    ) -> Result<DownloadSession, JunkanooError> {
+        let host = address
+            .iter()
+            .find_map(|protocol| match protocol {
+                Protocol::P2p(peer_id) => Some(peer_id),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                JunkanooError::other("Peer address must contain a peer ID component (/p2p/...)")
+            })?;
+        self.client.dial(host, address).await?;
+
+        let sent_password = password.is_some();
+        let on_demand = match self.client.greet(host, password).await {
+            Ok(welcome) if !welcome.authorized => {
+                return Err(JunkanooError::Rejected(
+                    if sent_password {
+                        "Wrong password for the share"
+                    } else {
+                        "The share requires a password"
+                    }
+                    .into(),
+                ));
+            }
+            Ok(welcome) => welcome.greeting.supports("list"),
+            // Hosts of older releases don't answer
+            Err(e) => {
+                tracing::warn!("Host didn't answer the greeting: {}", e);
+                false
+            }
+        };
+
+        let mut listing = self.client.request_directory(host).await?;
+        if on_demand {
+            // Such hosts leave the items out of the listing, all of them are asked for once
+            listing.items = self
+                .client
+                .list_directory(host, String::new(), true)
+                .await?
+                .items;
+        }
+        Ok(DownloadSession {
+            node: self,
+            host,
+            listing,
+        })
+    }
+
+    /// Stop the network, telling connected peers goodbye.
+    ///
+    /// # Errors
+    ///
+    /// If the network already shut down.
+    pub async fn shutdown(mut self) -> Result<(), JunkanooError> {
+        self.client.shutdown().await
+    }
+}
+
+/// Files offered to downloaders until it is closed.
+pub struct ShareSession {
+    node: JunkanooNode,
+    items: Vec<DirectoryItem>,
+}
+
+impl ShareSession {
+    /// What downloaders are offered.
+    #[must_use]
+    pub fn items(&self) -> &[DirectoryItem] {
+        &self.items
+    }
+
+    /// Addresses downloaders connect to, pass one to [`JunkanooNode::download`].
+    ///
+    /// # Errors
+    ///
+    /// If the network has shut down.
+    pub async fn addresses(&mut self) -> Result<Vec<Multiaddr>, JunkanooError> {
+        self.node.addresses().await
+    }
+
+    /// Network events from now on, e.g. uploads to downloaders.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.node.subscribe()
+    }
+
+    /// Stop offering the files and shut the network down.
+    ///
+    /// # Errors
+    ///
+    /// If the network already shut down.
+    pub async fn close(mut self) -> Result<(), JunkanooError> {
+        self.node.client.close_share().await?;
+        self.node.shutdown().await
+    }
+}
+
+/// A connection to a host, files it shares are downloaded with [`Self::download`].
+pub struct DownloadSession {
+    node: JunkanooNode,
+    host: PeerId,
+    listing: DisplayResponse,
+}
+
+impl DownloadSession {
+    #[must_use]
+    pub const fn host(&self) -> PeerId {
+        self.host
+    }
+
+    /// The host's listing as received when connecting, with its label and limits.
+    #[must_use]
+    pub const fn listing(&self) -> &DisplayResponse {
+        &self.listing
+    }
+
+    /// Everything the host shares.
+    #[must_use]
+    pub fn items(&self) -> &[DirectoryItem] {
+        &self.listing.items
+    }
+
+    /// Network events from now on, e.g. download progress.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.node.subscribe()
+    }
+
+    /// Download the listed files at `paths`, or below them for directories, into
+    /// `directory` or else the working directory. Resolves with the saved paths once all
+    /// of them arrived.
+    ///
+    /// # Errors
+    ///
+    /// If none of `paths` are shared, or with the ones that failed to download.
+    pub async fn download(
+        &mut self,
+        paths: &[PathBuf],
+        directory: Option<PathBuf>,
+    ) -> Result<Vec<String>, JunkanooError> {
+        let wanted = |item: &DirectoryItem| {
+            paths
+                .iter()
+                .any(|path| item.path.starts_with(path) || item.display_path.starts_with(path))
+        };
+        let files: Vec<RequestedFile> = self
+            .listing
+            .items
+            .iter()
+            .filter(|item| !item.is_dir && wanted(item))
+            .map(|item| RequestedFile {
+                path: item.path.to_string_lossy().to_string(),
+                hash: item.hash.clone(),
+            })
+            .collect();
+        if files.is_empty() {
+            return Err(JunkanooError::other(
+                "None of the paths are shared by the host",
+            ));
+        }
+
+        let count = files.len();
+        self.node
+            .client
+            .request_files(self.host, files, directory, false)
+            .await?;
+        let (mut saved, mut failed) = (Vec::new(), Vec::new());
+        while saved.len() + failed.len() < count {
+            match self.node.outcomes.recv().await {
+                Some(Event::DownloadCompleted(paths)) => saved.extend(paths),
+                Some(Event::DownloadFailed(paths)) => failed.extend(paths),
+                Some(_) => {}
+                None => return Err(JunkanooError::Shutdown),
+            }
+        }
+        if failed.is_empty() {
+            Ok(saved)
+        } else {
+            Err(JunkanooError::other(format!(
+                "Failed to download {}",
+                failed.join(", ")
+            )))
+        }
+    }
+
+    /// Download the listed directory at `path` as a single archive into `directory` or
+    /// else the working directory. Resolves with the saved path of the archive.
+    ///
+    /// # Errors
+    ///
+    /// If `path` isn't a shared directory or the archive failed to download.
+    pub async fn download_archive(
+        &mut self,
+        path: &Path,
+        directory: Option<PathBuf>,
+        format: ArchiveFormat,
+    ) -> Result<String, JunkanooError> {
+        let item = self
+            .listing
+            .items
+            .iter()
+            .find(|item| item.is_dir && (item.path == path || item.display_path == path))
+            .ok_or_else(|| {
+                JunkanooError::other(format!(
+                    "{} is not a directory shared by the host",
+                    path.display()
+                ))
+            })?;
+        let path = item.path.to_string_lossy().to_string();
+        self.node
+            .client
+            .request_archive(self.host, path, directory, format)
+            .await?;
+        loop {
+            match self.node.outcomes.recv().await {
+                Some(Event::DownloadCompleted(mut paths)) if !paths.is_empty() => {
+                    return Ok(paths.remove(0));
+                }
+                Some(Event::DownloadFailed(paths)) => {
+                    return Err(JunkanooError::other(format!(
+                        "Failed to download {}",
+                        paths.join(", ")
+                    )));
+                }
+                Some(_) => {}
+                None => return Err(JunkanooError::Shutdown),
+            }
+        }
+    }
+
+    /// Disconnect and shut the network down.
+    ///
+    /// # Errors
+    ///
+    /// If the network already shut down.
+    pub async fn close(self) -> Result<(), JunkanooError> {
+        self.node.shutdown().await
+    }
+}
+
+/// The listing published for `paths`, each directory and file given separately.
+///
+/// Paths are shown relative to the deepest directory they all share. Symlinks are shared
+/// as `symlinks` says. Hashing new or changed files reads all of them, so this blocks.
+pub fn shared_items(
+    paths: &[PathBuf],
+    symlinks: SymlinkPolicy,
+    hash_cache: &mut HashCache,
+) -> Vec<DirectoryItem> {
+    let Some(first) = paths.first() else {
+        return Vec::new();
+    };
+    let mut virtual_root = first.clone();
+    for path in &paths[1..] {
+        virtual_root = virtual_root
+            .ancestors()
+            .find(|ancestor| path.starts_with(ancestor))
+            .unwrap_or(&virtual_root)
+            .to_path_buf();
+    }
+    // Runs on every rescan, so per-item details only show at trace level
+    tracing::trace!("Virtual root path: {:?}", virtual_root);
+    paths
+        .iter()
+        .filter(|path| symlinks != SymlinkPolicy::Skip || !path.is_symlink())
+        .enumerate()
+        .map(|(index, path)| {
+            let link = symlinks == SymlinkPolicy::Copy && path.is_symlink();
+            shared_item(index, path, link, &virtual_root, hash_cache)
+        })
+        .collect()
+}
+
+fn shared_item(
+    index: usize,
+    path: &Path,
+    link: bool,
+    virtual_root: &Path,
+    hash_cache: &mut HashCache,
+) -> DirectoryItem {
+    tracing::trace!("Processing path: {:?}", path);
+
+    // Get the absolute path for file operations, of the link itself when it is copied
+    let abs_path = if link {
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty());
+        std::fs::canonicalize(parent.unwrap_or_else(|| Path::new(".")))
+            .ok()
+            .zip(path.file_name())
+            .map_or_else(|| path.to_path_buf(), |(parent, name)| parent.join(name))
+    } else {
+        std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    };
+    tracing::trace!("Absolute path: {:?}", abs_path);
+
+    // Get the name from the path
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    // Get the relative path for UI display
+    let rel_path = path
+        .strip_prefix(virtual_root)
+        .unwrap_or(path)
+        .to_path_buf();
+
+    tracing::trace!(
+        "Name: {}, Relative path: {:?}, Absolute path: {:?}",
+        name,
+        rel_path,
+        abs_path
+    );
+
+    let is_dir = !link && path.is_dir();
+    let depth = rel_path.components().count();
+    let preview = if link {
+        let target = std::fs::read_link(path).unwrap_or_default();
+        format!("Link to {}", target.display())
+    } else if is_dir {
+        format!("Directory: {name}")
+    } else {
+        std::fs::File::open(path).map_or_else(
+            |_| "Unable to read file contents".to_string(),
+            |file| {
+                let reader = BufReader::new(file);
+                let mut buffer = String::new();
+                reader.take(4000).read_to_string(&mut buffer).ok();
+                buffer.chars().take(1000).collect()
+            },
+        )
+    };
+    let (size, modified) = if link {
+        let modified = std::fs::symlink_metadata(path).and_then(|metadata| metadata.modified());
+        (0, modified.ok())
+    } else {
+        DirectoryItem::read_metadata(path, is_dir)
+    };
+    let hash = if is_dir || link {
+        None
+    } else {
+        hash_cache.hash(&abs_path, size, modified)
+    };
+    let item = DirectoryItem {
+        name,
+        path: abs_path,         // Use the absolute path for file operations
+        display_path: rel_path, // Use the relative path for UI display
+        is_dir,
+        index,
+        depth,
+        selected: true,
+        preview,
+        size,
+        modified,
+        hash,
+    };
+    tracing::trace!("Created DirectoryItem: {:?}", item);
+    item
+}
@@ -1,13 +1,15 @@
 #[cfg(test)]
 #[allow(clippy::module_inception)]
 mod tests {
-    use crate::app::{App, AppState, ConnectionState, DirectoryItem};
-    use crate::service::utils::{FileReceiver, FileTransfer};
+    use crate::app::{
+        label_directory, parse_peer_address, App, AppState, ConnectionState, DirectoryItem,
+    };
+    use crate::service::utils::{transfer_path, FileReceiver, FileTransfer};
     use futures::io::{AsyncRead, AsyncWrite};
     use libp2p::PeerId;
     use std::fs::{self, File};
     use std::io::Write;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
     use tempfile::TempDir;
     use tokio::io::{AsyncRead as TokioAsyncRead, AsyncWrite as TokioAsyncWrite};
 
@@ -61,6 +63,12 @@ mod tests {
         }
     }
 
+    /// A receiver saving files over the ones they were sent from. Files outside the working
+    /// directory are sent under their path without the root, so it is put back.
+    fn root_receiver() -> FileReceiver {
+        FileReceiver::new().with_directory(Some(PathBuf::from("/")))
+    }
+
     // Helper function to create a temporary directory structure for testing
     fn setup_test_directory() -> TempDir {
         let temp_dir = TempDir::new().unwrap();
@@ -97,7 +105,7 @@ mod tests {
         app.selected_index = None;
         app.is_loading = false;
         app.clear_warning();
-        app.clipboard_success = false;
+        app.clipboard_copied_at = None;
         app
     }
 
@@ -282,6 +290,9 @@ mod tests {
             selected: false,
             preview: String::new(),
             display_path: PathBuf::new(),
+            size: 0,
+            modified: None,
+            hash: None,
         };
 
         assert_eq!(item.name, "test");
@@ -326,7 +337,7 @@ mod tests {
         let transfer_handle = tokio::spawn(async move { transfer.stream_file(&mut sender).await });
 
         // Receive the file
-        let file_receiver = FileReceiver::new();
+        let file_receiver = root_receiver();
         let result = file_receiver.receive_file(&mut receiver).await;
 
         // Wait for transfer to complete
@@ -334,8 +345,9 @@ mod tests {
 
         // Verify the received file
         assert!(result.is_ok());
-        let received_path = result.unwrap();
-        let received_content = fs::read_to_string(temp_dir.path().join(received_path)).unwrap();
+        let received_path = result.unwrap().path;
+        assert_eq!(Path::new("/").join(received_path), file_path);
+        let received_content = fs::read_to_string(&file_path).unwrap();
         assert_eq!(received_content, "test content");
     }
 
@@ -358,14 +370,4292 @@ mod tests {
             transfer.stream_file(&mut sender_wrapper).await.unwrap();
         });
 
-        let file_receiver = FileReceiver::new();
+        let file_receiver = root_receiver();
         let received_path = file_receiver
             .receive_file(&mut receiver_wrapper)
             .await
-            .unwrap();
+            .unwrap()
+            .path;
         assert!(received_path.ends_with("test_file.txt"));
         assert!(!received_path.is_empty());
 
         transfer_task.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_client_errors_when_event_loop_is_gone() {
+        let (mut client, _events, event_loop, peer_id) =
+            crate::service::node::new(&crate::service::node::NodeConfig::default()).unwrap();
+        drop(event_loop);
+
+        assert!(client.get_listening_addrs().await.is_err());
+        assert!(client.disconnect(peer_id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_event_loop() {
+        use crate::service::node::NodeConfig;
+
+        let (mut client, _events, event_loop, _) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        let running = tokio::spawn(event_loop.run());
+        client.shutdown().await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(client.get_listening_addrs().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_download_stops_between_chunks() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+
+        // A cancelled download stops between chunks instead of running to the end
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("big.txt");
+        fs::write(&file_path, "junkanoo ".repeat(10_000)).unwrap();
+        let mut wire = futures::io::Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        wire.set_position(0);
+        let body = root_receiver()
+            .with_cancel(Arc::new(AtomicBool::new(true)))
+            .receive_range(&mut wire, 90_000)
+            .await;
+        let error = body.unwrap_err();
+        assert!(error.to_string().contains("cancelled"));
+    }
+
+    #[cfg(feature = "webrtc")]
+    #[tokio::test]
+    async fn test_webrtc_listen_address_has_certhash() {
+        use crate::service::node::NodeConfig;
+        use futures::StreamExt;
+
+        let (mut client, events, event_loop, _) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(event_loop.run());
+        tokio::spawn(events.for_each(|_| async {}));
+
+        client
+            .start_listening("/ip4/127.0.0.1/udp/0/webrtc-direct".parse().unwrap())
+            .await
+            .unwrap();
+        let address = loop {
+            if let Some(address) = client.get_listening_addrs().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert!(address.to_string().contains("/webrtc-direct/certhash/"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_embedded_share_and_download() {
+        use crate::service::node::listen_addrs;
+        use crate::{JunkanooNode, NodeConfig};
+
+        // Hosts send paths relative to their working directory
+        let shared = tempfile::Builder::new()
+            .tempdir_in(std::env::current_dir().unwrap())
+            .unwrap();
+        fs::write(shared.path().join("notes.txt"), b"embedded").unwrap();
+        let config = NodeConfig {
+            lan_only: true,
+            ..NodeConfig::default()
+        };
+        let loopback = Some("127.0.0.1".parse().unwrap());
+
+        let host = JunkanooNode::start_on(config.clone(), listen_addrs(loopback, 0))
+            .await
+            .unwrap();
+        let mut share = host.share(vec![shared.path().to_path_buf()]).await.unwrap();
+        assert_eq!(share.items().len(), 2);
+        let address = loop {
+            if let Some(address) = share.addresses().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+
+        let downloader = JunkanooNode::start_on(config, listen_addrs(loopback, 0))
+            .await
+            .unwrap();
+        let mut download = downloader.download(address, None).await.unwrap();
+        assert_eq!(download.items().len(), 2);
+        let destination = TempDir::new().unwrap();
+        let saved = download
+            .download(
+                &[PathBuf::from("notes.txt")],
+                Some(destination.path().to_path_buf()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(saved.len(), 1);
+        let relative = shared
+            .path()
+            .strip_prefix(std::env::current_dir().unwrap())
+            .unwrap();
+        assert_eq!(
+            fs::read(destination.path().join(relative).join("notes.txt")).unwrap(),
+            b"embedded"
+        );
+        assert!(download
+            .download(&[PathBuf::from("missing.txt")], None)
+            .await
+            .is_err());
+
+        download.close().await.unwrap();
+        share.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_greeting_and_password() {
+        use crate::service::greeting::AuthRequirement;
+        use crate::service::node::{Event, NodeConfig};
+        use futures::StreamExt;
+
+        let (mut host, host_events, host_loop, host_id) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            label: Some("holiday-photos".into()),
+            display_name: Some("Chad's laptop".into()),
+            password: Some("sorrel".into()),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        let (mut downloader, downloader_events, downloader_loop, _) =
+            crate::service::node::new(&NodeConfig {
+                lan_only: true,
+                display_name: Some("desktop".into()),
+                ..NodeConfig::default()
+            })
+            .unwrap();
+        tokio::spawn(host_loop.run());
+        tokio::spawn(downloader_loop.run());
+        tokio::spawn(downloader_events.for_each(|_| async {}));
+        let (greeted_sender, mut greeted) = futures::channel::mpsc::unbounded();
+        tokio::spawn(host_events.for_each(move |event| {
+            if let Event::PeerGreeted { greeting, .. } = event {
+                let _ = greeted_sender.unbounded_send(greeting);
+            }
+            async {}
+        }));
+
+        host.start_listening("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap())
+            .await
+            .unwrap();
+        let address = loop {
+            if let Some(address) = host.get_listening_addrs().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        downloader.dial(host_id, address).await.unwrap();
+
+        // Nothing is listed before the right password was given
+        assert!(downloader.request_directory(host_id).await.is_err());
+        let welcome = downloader.greet(host_id, Some("oak".into())).await.unwrap();
+        assert!(!welcome.authorized);
+        assert_eq!(welcome.greeting.auth, AuthRequirement::Password);
+        assert!(welcome.greeting.features.iter().any(|f| f == "delta"));
+        assert_eq!(
+            welcome.greeting.describe(&host_id),
+            "Connected to Chad's laptop — share 'holiday-photos' (password required)"
+        );
+        let from_downloader = greeted.next().await.unwrap();
+        assert_eq!(from_downloader.display_name.as_deref(), Some("desktop"));
+        assert_eq!(from_downloader.label, None);
+
+        let welcome = downloader
+            .greet(host_id, Some("sorrel".into()))
+            .await
+            .unwrap();
+        assert!(welcome.authorized);
+        let listing = downloader.request_directory(host_id).await.unwrap();
+        assert_eq!(listing.label.as_deref(), Some("holiday-photos"));
+
+        host.shutdown().await.unwrap();
+        downloader.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        use crate::service::greeting::{Greeting, Hello};
+        use crate::service::node::NodeConfig;
+        use crate::service::secret::{Secret, REDACTED};
+
+        let config = NodeConfig {
+            password: Some("sorrel".into()),
+            ..NodeConfig::default()
+        };
+        let hello = Hello {
+            greeting: Greeting::default(),
+            password: Some("sorrel".into()),
+        };
+        for formatted in [
+            format!("{config:?}"),
+            format!("{hello:?}"),
+            format!("{hello:#?}"),
+        ] {
+            assert!(!formatted.contains("sorrel"), "{formatted}");
+            assert!(formatted.contains(REDACTED));
+        }
+
+        // What a panic report shows is the payload, formatted like above
+        let payload =
+            std::panic::catch_unwind(|| panic!("Failed to start with {config:?}")).unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(!message.contains("sorrel"));
+
+        // Parsed from the command line straight into a secret
+        let matches = crate::cli::commands::get_args().get_matches_from([
+            "junkanoo",
+            "share",
+            ".",
+            "--password",
+            "sorrel",
+        ]);
+        let (_, share) = matches.subcommand().unwrap();
+        let password = share.get_one::<Secret>("password").unwrap();
+        assert_eq!(password, &Secret::from("sorrel"));
+        assert_ne!(password, &Secret::from("sorrels"));
+        assert_eq!(password.to_string(), REDACTED);
+
+        // Still sent in full to the host
+        let sent = serde_json::to_string(&hello).unwrap();
+        let received: Hello = serde_json::from_str(&sent).unwrap();
+        assert_eq!(received.password, Some(Secret::from("sorrel")));
+    }
+
+    #[test]
+    fn test_log_sampler() {
+        use crate::service::sampling::LogSampler;
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let mut sampler = LogSampler::new(Duration::from_secs(1), 2);
+        let logged: Vec<Option<u64>> = (0..5).map(|_| sampler.sample_at(start)).collect();
+        assert_eq!(logged, [Some(0), Some(0), None, None, None]);
+
+        // The next window reports how many lines were left out
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(sampler.sample_at(later), Some(3));
+        assert_eq!(sampler.sample_at(later), Some(0));
+        assert_eq!(sampler.sample_at(later), None);
+        assert_eq!(sampler.sample_at(later + Duration::from_secs(1)), Some(1));
+
+        // A million chunks in a second make one line
+        let mut chunks = LogSampler::new(Duration::from_secs(1), 1);
+        let lines = (0..1_000_000)
+            .filter(|_| chunks.sample_at(start).is_some())
+            .count();
+        assert_eq!(lines, 1);
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        use crate::service::limiter::parse_rate;
+
+        assert_eq!(parse_rate("5MiB/s"), Ok(5 * 1024 * 1024));
+        assert_eq!(parse_rate("500KB"), Ok(500_000));
+        assert_eq!(parse_rate("1.5k/s"), Ok(1500));
+        assert_eq!(parse_rate("2048"), Ok(2048));
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("5 parsecs").is_err());
+        assert!(parse_rate("0").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_file_transfer() {
+        use crate::service::utils::is_compressible;
+        use futures::io::Cursor;
+
+        assert!(is_compressible(std::path::Path::new("notes.txt")));
+        assert!(is_compressible(std::path::Path::new("no_extension")));
+        assert!(!is_compressible(std::path::Path::new("photo.jpg")));
+        assert!(!is_compressible(std::path::Path::new("archive.zip")));
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("big.txt");
+        let content = "junkanoo ".repeat(10_000);
+        fs::write(&file_path, &content).unwrap();
+
+        // Stream into memory first so the receiver doesn't race the sender on the same file
+        let mut wire = Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .with_compression(true)
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        assert!(wire.get_ref().len() < content.len());
+
+        let mut wire = Cursor::new(wire.into_inner());
+        let received_path = root_receiver().receive_file(&mut wire).await.unwrap().path;
+        assert_eq!(Path::new("/").join(received_path), file_path);
+        let received_content = fs::read_to_string(&file_path).unwrap();
+        assert_eq!(received_content, content);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_registry_serves_from_handles() {
+        use crate::service::registry::ShareRegistry;
+        use futures::io::Cursor;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("report.txt");
+        fs::write(&file_path, "read only").unwrap();
+
+        let mut registry = ShareRegistry::default();
+        registry.set_read_only(true);
+        registry.replace(vec![
+            shared_item(file_path.to_str().unwrap(), "report.txt", false),
+            // Files that can't be opened up front are left out of the share
+            shared_item("/nonexistent/missing.txt", "missing.txt", false),
+        ]);
+        assert!(registry
+            .resolve(std::path::Path::new("missing.txt"))
+            .is_none());
+        let entry = registry
+            .resolve_file(std::path::Path::new("report.txt"))
+            .unwrap();
+        let handle = entry.handle.clone().unwrap();
+
+        // The handle keeps serving the published content even once the path is gone
+        fs::remove_file(&file_path).unwrap();
+        let mut wire = Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .with_handle(handle.file.try_clone().unwrap())
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        assert!(wire.get_ref().ends_with(b"read only"));
+    }
+
+    #[tokio::test]
+    async fn test_file_request_frame() {
+        use crate::service::protocol::{reject_request, FileRequest, COMPRESSION_ZSTD};
+        use futures::io::Cursor;
+
+        let request = FileRequest {
+            path: "/shared/report.pdf".to_string(),
+            offset: 42,
+            length: None,
+            delta: false,
+            compression: COMPRESSION_ZSTD,
+            archive: false,
+        };
+        let mut wire = Cursor::new(Vec::new());
+        request.write_to(&mut wire).await.unwrap();
+        wire.set_position(0);
+        assert_eq!(FileRequest::read_from(&mut wire).await.unwrap(), request);
+
+        let ranged = FileRequest {
+            length: Some(1024),
+            ..request
+        };
+        let mut wire = Cursor::new(Vec::new());
+        ranged.write_to(&mut wire).await.unwrap();
+        wire.set_position(0);
+        assert_eq!(FileRequest::read_from(&mut wire).await.unwrap(), ranged);
+
+        let delta = FileRequest {
+            length: None,
+            delta: true,
+            ..ranged
+        };
+        let mut wire = Cursor::new(Vec::new());
+        delta.write_to(&mut wire).await.unwrap();
+        wire.set_position(0);
+        assert_eq!(FileRequest::read_from(&mut wire).await.unwrap(), delta);
+
+        // A rejected request surfaces the host's reason on the downloader
+        let mut wire = Cursor::new(Vec::new());
+        reject_request(&mut wire, "file is not shared")
+            .await
+            .unwrap();
+        wire.set_position(0);
+        let error = root_receiver().receive_file(&mut wire).await.unwrap_err();
+        assert!(error.to_string().contains("file is not shared"));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_versioning() {
+        use crate::service::error::JunkanooError;
+        use crate::service::greeting::{AuthRequirement, Greeting};
+        use crate::service::protocol::{FileRequest, COMPRESSION_NONE, PROTOCOL_VERSION};
+        use futures::io::Cursor;
+
+        let request = FileRequest {
+            path: "notes.txt".to_string(),
+            offset: 0,
+            length: None,
+            delta: false,
+            compression: COMPRESSION_NONE,
+            archive: false,
+        };
+        let mut wire = Cursor::new(Vec::new());
+        request.write_to(&mut wire).await.unwrap();
+        assert_eq!(wire.get_ref()[..2], [b'V', PROTOCOL_VERSION]);
+
+        // Downloaders from before versioning send the opcode first
+        let mut legacy = Cursor::new(wire.get_ref()[2..].to_vec());
+        assert_eq!(FileRequest::read_from(&mut legacy).await.unwrap(), request);
+
+        let mut newer = wire.get_ref().clone();
+        newer[1] = PROTOCOL_VERSION + 1;
+        let error = FileRequest::read_from(&mut Cursor::new(newer))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            JunkanooError::UnsupportedVersion(version) if version == PROTOCOL_VERSION + 1
+        ));
+
+        // Greetings of releases from before versioning read as version 0 without features
+        let old: Greeting = serde_json::from_str(r#"{"display_name":"laptop"}"#).unwrap();
+        assert_eq!(old.protocol_version, 0);
+        assert!(!old.supports("zstd"));
+        let current = Greeting::new(None, None, AuthRequirement::default());
+        assert_eq!(current.protocol_version, PROTOCOL_VERSION);
+        assert!(current.supports("zstd") && current.supports("hash"));
+        assert!(!current.supports("teleport"));
+    }
+
+    #[test]
+    fn test_protocol_serde_roundtrip() {
+        use crate::service::greeting::{AuthRequirement, Greeting, Hello, Welcome};
+        use crate::service::protocol::{DisplayRequest, DisplayResponse};
+
+        fn roundtrip<T>(value: &T) -> T
+        where
+            T: serde::Serialize + serde::de::DeserializeOwned,
+        {
+            serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+        }
+
+        assert_eq!(roundtrip(&DisplayRequest), DisplayRequest);
+        let listing = DisplayResponse {
+            items: vec![
+                shared_item("/shared/docs", "docs", true),
+                shared_item("/shared/docs/report.pdf", "docs/report.pdf", false),
+            ],
+            max_concurrent_transfers: Some(4),
+            opens_at: Some(1_700_000_000),
+            label: Some("Quarterly reports".to_string()),
+            version: 3,
+        };
+        assert_eq!(roundtrip(&listing), listing);
+
+        let hello = Hello {
+            greeting: Greeting::new(Some("laptop".to_string()), None, AuthRequirement::None),
+            password: Some("hunter2".into()),
+        };
+        assert_eq!(roundtrip(&hello), hello);
+        let welcome = Welcome {
+            greeting: Greeting::new(None, Some("Reports".to_string()), AuthRequirement::Password),
+            authorized: true,
+        };
+        assert_eq!(roundtrip(&welcome), welcome);
+
+        // Listings of hosts from before the optional fields still parse
+        let old: DisplayResponse = serde_json::from_str(r#"{"items":[]}"#).unwrap();
+        assert_eq!((old.max_concurrent_transfers, old.version), (None, 0));
+    }
+
+    #[tokio::test]
+    async fn test_file_range_transfer() {
+        use crate::service::error::JunkanooError;
+        use crate::service::protocol::{write_response, ResponseHeader, COMPRESSION_NONE};
+        use futures::io::{AsyncWriteExt, Cursor};
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("digits.txt");
+        fs::write(&file_path, "0123456789").unwrap();
+
+        for (offset, length, expected) in [
+            (2, Some(5), &b"23456"[..]),
+            (8, Some(5), &b"89"[..]),
+            (7, None, &b"789"[..]),
+        ] {
+            let mut wire = Cursor::new(Vec::new());
+            FileTransfer::new(&file_path)
+                .with_offset(offset)
+                .with_length(length)
+                .stream_file(&mut wire)
+                .await
+                .unwrap();
+            wire.set_position(0);
+            let body = root_receiver()
+                .receive_range(&mut wire, usize::try_from(length.unwrap_or(10)).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+
+        // A host announcing more than was asked for, or sending less than it announced
+        for (size, sent, length) in [(1 << 30, 0, 5), (5, 2, 5)] {
+            let mut wire = Cursor::new(Vec::new());
+            let header = ResponseHeader {
+                path: "digits.txt".to_string(),
+                size,
+                compression: COMPRESSION_NONE,
+                attributes: None,
+            };
+            write_response(&mut wire, &header).await.unwrap();
+            wire.write_all(&b"01234"[..sent]).await.unwrap();
+            wire.set_position(0);
+            let error = root_receiver()
+                .receive_range(&mut wire, length)
+                .await
+                .unwrap_err();
+            assert!(matches!(error, JunkanooError::Protocol(_)), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_structured_previews() {
+        use crate::cli::preview::render;
+
+        let plain = |path: &str, content: &str| -> Vec<String> {
+            render(std::path::Path::new(path), content)
+                .lines
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+
+        // Columns line up, quoted delimiters stay in their field and the cut off row is dropped
+        let table = plain(
+            "people.csv",
+            "name,city,age\nAda,\"London, UK\",36\nGrace,New York,85\nLin",
+        );
+        assert_eq!(
+            table,
+            [
+                "name  │ city       │ age",
+                "──────┼────────────┼────",
+                "Ada   │ London, UK │ 36",
+                "Grace │ New York   │ 85",
+            ]
+        );
+        assert_eq!(plain("scores.tsv", "a\tb\n1\t2\n")[2], "1 │ 2");
+
+        // Deep nesting is folded, a cut off document shows what arrived
+        let json = plain(
+            "config.json",
+            r#"{"name":"demo","deep":{"a":{"b":{"c":1}}},"list":[1,2"#,
+        );
+        assert_eq!(
+            json,
+            [
+                "{",
+                "  \"name\": \"demo\",",
+                "  \"deep\": {",
+                "    \"a\": {",
+                "      \"b\": {…}",
+                "    }",
+                "  },",
+                "  \"list\": [",
+                "    1,",
+                "    2",
+            ]
+        );
+
+        let yaml = plain(
+            "compose.yml",
+            "services:\n  web:\n    image: nginx\n      ports:\n        - 80\nvolumes: {}\n",
+        );
+        assert_eq!(
+            yaml,
+            [
+                "services:",
+                "  web:",
+                "    image: nginx",
+                "      … 2 more lines",
+                "volumes: {}",
+            ]
+        );
+
+        // Anything else is shown unchanged
+        assert_eq!(plain("notes.txt", "a,b\nc,d"), ["a,b", "c,d"]);
+    }
+
+    #[tokio::test]
+    async fn test_preserve_attributes() {
+        use futures::io::Cursor;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("run.sh");
+        fs::write(&file_path, "#!/bin/sh\necho hi\n").unwrap();
+        let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 5_000);
+        fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&file_path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let send = |attributes| {
+            let file_path = file_path.clone();
+            async move {
+                let mut wire = Cursor::new(Vec::new());
+                FileTransfer::new(&file_path)
+                    .with_attributes(attributes)
+                    .stream_file(&mut wire)
+                    .await
+                    .unwrap();
+                // The download lands where the file was, so the attributes come from the wire
+                fs::remove_file(&file_path).unwrap();
+                wire.set_position(0);
+                wire
+            }
+        };
+
+        let mut wire = send(true).await;
+        root_receiver()
+            .with_preserve(true)
+            .receive_file(&mut wire)
+            .await
+            .unwrap();
+        let metadata = fs::metadata(&file_path).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        }
+
+        // Without `--preserve`, or from hosts that don't send them, files are left as written
+        for (attributes, preserve) in [(true, false), (false, true)] {
+            let mut wire = send(attributes).await;
+            root_receiver()
+                .with_preserve(preserve)
+                .receive_file(&mut wire)
+                .await
+                .unwrap();
+            assert_ne!(
+                fs::metadata(&file_path).unwrap().modified().unwrap(),
+                modified
+            );
+            assert_eq!(
+                fs::read_to_string(&file_path).unwrap(),
+                "#!/bin/sh\necho hi\n"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_manifest() {
+        use crate::service::hashing::hash_file;
+        use crate::service::registry::ShareRegistry;
+        use futures::io::Cursor;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("digits.txt");
+        fs::write(&file_path, "0123456789").unwrap();
+        let hash = hash_file(&file_path).unwrap();
+        let mut item = shared_item(file_path.to_str().unwrap(), "digits.txt", false);
+        item.size = 10;
+        item.hash = Some(hash.clone());
+        let mut registry = ShareRegistry::default();
+        registry.replace(vec![item]);
+
+        let manifest = registry.manifest(&["digits.txt".to_string(), "secret.txt".to_string()]);
+        assert_eq!(manifest.missing, ["secret.txt"]);
+        assert_eq!(manifest.total_size(), 10);
+        let entry = manifest.entry("digits.txt").unwrap();
+        // Outside the host's working directory the file is sent under its path without the root
+        assert_eq!(
+            Path::new("/").join(&entry.relative_path),
+            file_path.as_path()
+        );
+        assert_eq!(entry.hash.as_deref(), Some(hash.as_str()));
+
+        // Already at the destination, so it doesn't need to be requested
+        let receiver = root_receiver().with_expected_hash(entry.hash.clone());
+        assert!(receiver.has_file(&entry.relative_path, entry.size).await);
+        assert!(!receiver.has_file(&entry.relative_path, 11).await);
+
+        // A body ending early fails a preallocated file, its part keeps only what arrived
+        // and the destination is left alone
+        let mut wire = Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        let mut bytes = wire.into_inner();
+        bytes.truncate(bytes.len() - 4);
+        let error = root_receiver()
+            .with_expected_size(Some(10))
+            .receive_file(&mut Cursor::new(bytes))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("received 6 of 10 bytes"));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "0123456789");
+        let partial = crate::service::utils::partial_path(&file_path);
+        assert_eq!(fs::read_to_string(&partial).unwrap(), "012345");
+    }
+
+    #[test]
+    fn test_delta_rebuilds_changed_file() {
+        use crate::service::delta::{compute_delta, signatures, DeltaOp};
+
+        const BLOCK: usize = 16;
+        let old: Vec<u8> = (0..200u8).collect();
+        // Bytes inserted near the start shift every later block, which must still be reused
+        let mut new = old.clone();
+        new.splice(5..5, *b"inserted");
+        new[150] = 0xff;
+        new.extend_from_slice(b"tail");
+
+        let signatures = signatures(&mut old.as_slice(), BLOCK).unwrap();
+        assert_eq!(signatures.len(), 13);
+        let mut ops = Vec::new();
+        compute_delta(&mut new.as_slice(), &signatures, BLOCK, |op| {
+            ops.push(op);
+            Ok(())
+        })
+        .unwrap();
+
+        let mut rebuilt = Vec::new();
+        let mut sent = 0;
+        for op in ops {
+            match op {
+                DeltaOp::Copy(index) => {
+                    let start = index as usize * BLOCK;
+                    rebuilt.extend_from_slice(&old[start..(start + BLOCK).min(old.len())]);
+                }
+                DeltaOp::Data(bytes) => {
+                    sent += bytes.len();
+                    rebuilt.extend_from_slice(&bytes);
+                }
+            }
+        }
+        assert_eq!(rebuilt, new);
+        assert!(sent < 4 * BLOCK, "sent {sent} of {} bytes", new.len());
+
+        // Without a copy everything is sent as data
+        let mut ops = Vec::new();
+        compute_delta(&mut new.as_slice(), &[], BLOCK, |op| {
+            ops.push(op);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(ops, vec![DeltaOp::Data(new)]);
+    }
+
+    fn shared_item(path: &str, display_path: &str, is_dir: bool) -> DirectoryItem {
+        let path = PathBuf::from(path);
+        DirectoryItem {
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            path,
+            display_path: PathBuf::from(display_path),
+            is_dir,
+            index: 0,
+            depth: 0,
+            selected: true,
+            preview: String::new(),
+            size: 0,
+            modified: None,
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn test_dry_run_plan() {
+        use crate::plan::{DownloadPlan, PlannedAction};
+        use crate::service::hashing::hash_file;
+
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path();
+        fs::write(target.join("same.txt"), "same").unwrap();
+        fs::write(target.join("changed.txt"), "old").unwrap();
+        fs::create_dir(target.join("dir.txt")).unwrap();
+        fs::write(target.join("docs"), "a file where a directory goes").unwrap();
+
+        let mut same = shared_item("/share/same.txt", "same.txt", false);
+        same.size = 4;
+        same.hash = Some(hash_file(&target.join("same.txt")).unwrap());
+        let mut changed = shared_item("/share/changed.txt", "changed.txt", false);
+        changed.size = 7;
+        let mut new = shared_item("/share/new.txt", "new.txt", false);
+        new.size = 10;
+        let in_the_way = shared_item("/share/dir.txt", "dir.txt", false);
+        let nested = shared_item("/share/docs/a.txt", "docs/a.txt", false);
+        let items = [&same, &changed, &new, &in_the_way, &nested];
+        let plan = DownloadPlan::new(
+            target,
+            items
+                .iter()
+                .map(|item| (PathBuf::from(&item.display_path), *item)),
+        );
+
+        let actions: Vec<(&str, &PlannedAction)> = plan
+            .files
+            .iter()
+            .map(|file| (file.path.to_str().unwrap(), &file.action))
+            .collect();
+        assert_eq!(actions[0], ("changed.txt", &PlannedAction::Overwrite));
+        assert!(matches!(actions[1], ("dir.txt", PlannedAction::Blocked(_))));
+        assert!(matches!(
+            actions[2],
+            ("docs/a.txt", PlannedAction::Blocked(_))
+        ));
+        assert_eq!(actions[3], ("new.txt", &PlannedAction::Create));
+        assert_eq!(actions[4], ("same.txt", &PlannedAction::UpToDate));
+        assert_eq!(plan.files[3].destination, target.join("new.txt"));
+        assert_eq!(plan.transfer_bytes(), 17);
+        assert!(!plan.is_ok());
+        let report = plan.report();
+        assert!(
+            report.contains("17 B to transfer in 2 files, 1 overwritten, 1 up to date, 2 blocked")
+        );
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["files"][3]["action"], "create");
+        assert_eq!(json["files"][1]["action"], "blocked");
+        assert_eq!(json["files"][1]["reason"], "a directory is in the way");
+
+        // The app plans the selection below the root of the share
+        let mut app = create_test_app();
+        let mut top = shared_item("/share/docs", "docs", true);
+        top.depth = 1;
+        let mut nested = nested.clone();
+        nested.depth = 2;
+        app.all_shared_items = vec![top, nested];
+        app.items_to_download
+            .insert(PathBuf::from("/share/docs/a.txt"));
+        let plan = app.plan_download();
+        assert_eq!(plan.files.len(), 1);
+        assert_eq!(plan.files[0].path, PathBuf::from("docs/a.txt"));
+    }
+
+    fn resolved(
+        registry: &crate::service::registry::ShareRegistry,
+        path: &std::path::Path,
+    ) -> Option<PathBuf> {
+        registry
+            .resolve_file(path)
+            .map(|entry| entry.absolute_path.clone())
+    }
+
+    #[test]
+    fn test_share_registry_multi_root_and_duplicates() {
+        use crate::service::registry::ShareRegistry;
+        use std::path::Path;
+
+        let mut registry = ShareRegistry::default();
+        registry.replace(vec![
+            shared_item("/home/a/docs", "a/docs", true),
+            shared_item("/home/a/docs/notes.txt", "a/docs/notes.txt", false),
+            shared_item("/home/b/notes.txt", "b/notes.txt", false),
+            // Same virtual path as the entry above, must not shadow it
+            shared_item("/mnt/b/notes.txt", "b/notes.txt", false),
+        ]);
+        assert_eq!(registry.version(), 1);
+
+        // Both virtual and absolute lookups work, directories are never served
+        assert_eq!(
+            resolved(&registry, Path::new("a/docs/notes.txt")),
+            Some(PathBuf::from("/home/a/docs/notes.txt"))
+        );
+        assert_eq!(
+            resolved(&registry, Path::new("/home/b/notes.txt")),
+            Some(PathBuf::from("/home/b/notes.txt"))
+        );
+        assert_eq!(
+            resolved(&registry, Path::new("b/notes (2).txt")),
+            Some(PathBuf::from("/mnt/b/notes.txt"))
+        );
+        assert!(registry.resolve(Path::new("a/docs")).unwrap().is_dir);
+        assert_eq!(resolved(&registry, Path::new("a/docs")), None);
+        assert_eq!(resolved(&registry, Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn test_share_registry_renames() {
+        use crate::service::registry::ShareRegistry;
+        use std::path::Path;
+
+        let mut registry = ShareRegistry::default();
+        let items = vec![shared_item("/share/draft.txt", "draft.txt", false)];
+        registry.replace(items.clone());
+        registry.replace(items);
+        assert_eq!(registry.version(), 1, "unchanged items keep the version");
+
+        // A file renamed on disk between two publications
+        registry.replace(vec![shared_item("/share/final.txt", "final.txt", false)]);
+        assert_eq!(registry.version(), 2);
+        assert_eq!(resolved(&registry, Path::new("draft.txt")), None);
+        assert_eq!(resolved(&registry, Path::new("/share/draft.txt")), None);
+        assert_eq!(
+            resolved(&registry, Path::new("final.txt")),
+            Some(PathBuf::from("/share/final.txt"))
+        );
+    }
+
+    #[test]
+    fn test_json_event_format() {
+        use crate::cli::output::{JsonEvent, ListingEntry};
+
+        let item = shared_item("/share/docs/a.txt", "docs/a.txt", false);
+        let listing = JsonEvent::Listing {
+            items: vec![ListingEntry::from(&item)],
+        };
+        assert_eq!(
+            serde_json::to_string(&listing).unwrap(),
+            r#"{"event":"listing","items":[{"name":"a.txt","path":"docs/a.txt","is_dir":false,"size":0}]}"#
+        );
+
+        let failed = JsonEvent::Failed {
+            files: vec!["a.txt".to_string()],
+            error: "timeout".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&failed).unwrap(),
+            r#"{"event":"failed","files":["a.txt"],"error":"timeout"}"#
+        );
+    }
+
+    #[test]
+    fn test_selection_survives_navigation() {
+        let temp_dir = setup_test_directory();
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+
+        // Enter test_dir/subdir and select the file there
+        let dir_index = app
+            .directory_items
+            .iter()
+            .position(|item| item.name == "test_dir")
+            .unwrap();
+        app.selected_index = Some(dir_index);
+        assert!(app.enter_directory());
+        let subdir_index = app
+            .directory_items
+            .iter()
+            .position(|item| item.name == "subdir")
+            .unwrap();
+        app.selected_index = Some(subdir_index);
+        assert!(app.enter_directory());
+        app.selected_index = Some(0);
+        app.select_item();
+        let nested_file = temp_dir.path().join("test_dir/subdir/test_file3.txt");
+        assert!(app.items_to_share.contains(&nested_file));
+
+        // Navigate all the way out and back in, the selection is still there
+        app.go_up_previous_directory();
+        app.go_up_previous_directory();
+        assert_eq!(app.current_path, temp_dir.path());
+        assert!(app.items_to_share.contains(&nested_file));
+
+        app.current_path = temp_dir.path().join("test_dir/subdir");
+        app.directory_cache.clear();
+        app.populate_directory_items();
+        let item = app
+            .directory_items
+            .iter()
+            .find(|item| item.name == "test_file3.txt")
+            .unwrap();
+        assert!(item.selected);
+        assert!(app.is_selected(item));
+
+        // Unselecting from another directory's view still finds the same entry
+        app.selected_index = Some(item.index);
+        app.unselect_item();
+        assert!(!app.items_to_share.contains(&nested_file));
+    }
+
+    #[test]
+    fn test_selecting_directory_selects_nested_items() {
+        let temp_dir = setup_test_directory();
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+
+        let dir_index = app
+            .directory_items
+            .iter()
+            .position(|item| item.name == "test_dir")
+            .unwrap();
+        app.selected_index = Some(dir_index);
+        app.select_item();
+
+        // Viewed from inside the directory, its children show up as selected
+        assert!(app.enter_directory());
+        assert!(app.directory_items.iter().all(|item| app.is_selected(item)));
+    }
+
+    #[test]
+    fn test_transfer_manager_lifecycle() {
+        use crate::transfers::{TransferManager, TransferState};
+
+        let mut transfers = TransferManager::default();
+        transfers.queue(vec!["a.txt".to_string(), "b.txt".to_string()]);
+        assert!(transfers
+            .transfers()
+            .iter()
+            .all(|transfer| transfer.state == TransferState::Queued));
+
+        transfers.progress("a.txt", 50, 200);
+        let active = &transfers.transfers()[0];
+        assert_eq!(active.state, TransferState::Active);
+        assert_eq!(active.progress(), Some(0.25));
+
+        transfers.complete("a.txt");
+        transfers.fail("b.txt", "connection reset".to_string());
+        let states: Vec<_> = transfers
+            .transfers()
+            .iter()
+            .map(|transfer| (transfer.bytes, transfer.state.clone()))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                (200, TransferState::Completed),
+                (0, TransferState::Failed("connection reset".to_string())),
+            ]
+        );
+        assert_eq!(transfers.transfers()[0].eta(), None);
+
+        // Requesting a file again starts it over at the end of the queue
+        transfers.queue(vec!["a.txt".to_string()]);
+        assert_eq!(transfers.transfers().len(), 2);
+        assert_eq!(transfers.transfers()[1].state, TransferState::Queued);
+    }
+
+    #[test]
+    fn test_directory_items_carry_size_and_modified_time() {
+        let temp_dir = setup_test_directory();
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+
+        let file = app
+            .directory_items
+            .iter()
+            .find(|item| item.name == "test_file1.txt")
+            .unwrap();
+        assert_eq!(file.size, "test content 1".len() as u64);
+        assert!(file.modified.is_some());
+        let dir = app
+            .directory_items
+            .iter()
+            .find(|item| item.name == "test_dir")
+            .unwrap();
+        assert_eq!(dir.size, 0);
+
+        // Both survive the trip to the downloader
+        let json = serde_json::to_string(file).unwrap();
+        let received: DirectoryItem = serde_json::from_str(&json).unwrap();
+        assert_eq!(&received, file);
+    }
+
+    #[test]
+    fn test_large_download_needs_confirmation() {
+        use crate::service::limiter::parse_size;
+
+        assert_eq!(parse_size("5GB"), Ok(5_000_000_000));
+        assert_eq!(parse_size("1.5KiB"), Ok(1536));
+        assert!(parse_size("lots").is_err());
+
+        let mut app = create_test_app();
+        let mut big = shared_item("/share/movie.mkv", "movie.mkv", false);
+        big.size = 6_000_000_000;
+        let mut small = shared_item("/share/notes.txt", "notes.txt", false);
+        small.size = 1_000;
+        app.all_shared_items = vec![big, small];
+
+        app.items_to_download
+            .insert(PathBuf::from("/share/notes.txt"));
+        assert!(!app.needs_download_confirmation());
+
+        // A stray select-all pulls in the big file
+        app.items_to_download
+            .insert(PathBuf::from("/share/movie.mkv"));
+        assert_eq!(app.selected_download_size(), 6_000_001_000);
+        assert!(app.needs_download_confirmation());
+
+        app.confirm_threshold = parse_size("10GB").unwrap();
+        assert!(!app.needs_download_confirmation());
+    }
+
+    #[tokio::test]
+    async fn test_matching_hash_skips_the_download() {
+        use crate::service::error::JunkanooError;
+        use crate::service::hashing::{hash_file, HashCache};
+        use futures::io::Cursor;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("report.txt");
+        fs::write(&file_path, "same content").unwrap();
+        let hash = hash_file(&file_path).unwrap();
+        assert_eq!(
+            HashCache::default().hash(&file_path, 12, None),
+            Some(hash.clone())
+        );
+
+        let mut wire = Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        let wire = wire.into_inner();
+
+        // The destination already holds the same content
+        let received = root_receiver()
+            .with_expected_hash(Some(hash))
+            .receive_file(&mut Cursor::new(wire.clone()))
+            .await
+            .unwrap();
+        assert!(received.up_to_date);
+
+        // Content that doesn't hash to what was expected never takes the destination's place
+        let error = root_receiver()
+            .with_expected_hash(Some("0".repeat(64)))
+            .receive_file(&mut Cursor::new(wire))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, JunkanooError::HashMismatch), "{error}");
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "same content");
+        assert!(!crate::service::utils::partial_path(&file_path).exists());
+    }
+
+    #[test]
+    fn test_cycling_sort_modes() {
+        use crate::app::SortKey;
+
+        let mut app = create_test_app();
+        let mut big = shared_item("/share/b.zip", "b.zip", false);
+        big.size = 300;
+        let mut small = shared_item("/share/c.txt", "c.txt", false);
+        small.size = 10;
+        let mut medium = shared_item("/share/a.pdf", "a.pdf", false);
+        medium.size = 20;
+        app.directory_items = vec![big, small, medium, shared_item("/share/docs", "docs", true)];
+        app.selected_index = Some(1);
+        let names = |app: &App| -> Vec<String> {
+            app.directory_items
+                .iter()
+                .map(|item| item.name.clone())
+                .collect()
+        };
+
+        app.cycle_sort();
+        assert!(app.sort_order.descending);
+        assert_eq!(names(&app), ["docs", "c.txt", "b.zip", "a.pdf"]);
+        // The cursor follows the entry it was on
+        assert_eq!(
+            app.directory_items[app.selected_index.unwrap()].name,
+            "c.txt"
+        );
+
+        app.cycle_sort();
+        assert_eq!(app.sort_order.key, SortKey::Size);
+        assert_eq!(names(&app), ["docs", "c.txt", "a.pdf", "b.zip"]);
+
+        for _ in 0..4 {
+            app.cycle_sort();
+        }
+        assert_eq!(app.sort_order.key, SortKey::Type);
+        assert_eq!(names(&app), ["docs", "a.pdf", "c.txt", "b.zip"]);
+        assert_eq!(app.sort_order.label(), "type ↑");
+    }
+
+    #[test]
+    fn test_manifest_diff_between_sessions() {
+        use crate::service::hashing::HashCache;
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("share");
+        fs::create_dir(&root).unwrap();
+        for name in ["a.txt", "b.txt", "same.txt"] {
+            fs::write(root.join(name), name).unwrap();
+        }
+
+        // First session hashes everything it publishes
+        let mut cache = HashCache::default();
+        for name in ["a.txt", "b.txt", "same.txt"] {
+            let path = root.join(name);
+            let metadata = fs::metadata(&path).unwrap();
+            cache.hash(&path, metadata.len(), metadata.modified().ok());
+        }
+        let history = temp_dir.path().join("history.json");
+        cache.save(&history).unwrap();
+        assert!(HashCache::load(&history).diff(&root).is_empty());
+
+        fs::remove_file(root.join("a.txt")).unwrap();
+        fs::write(root.join("b.txt"), "b.txt, edited").unwrap();
+        fs::write(root.join("secrets.env"), "TOKEN=1").unwrap();
+
+        let diff = HashCache::load(&history).diff(&root);
+        assert_eq!(diff.added, [PathBuf::from("secrets.env")]);
+        assert_eq!(diff.removed, [PathBuf::from("a.txt")]);
+        assert_eq!(diff.changed, [PathBuf::from("b.txt")]);
+
+        // A directory that was never shared has nothing to compare against
+        let other = temp_dir.path().join("other");
+        fs::create_dir(&other).unwrap();
+        fs::write(other.join("new.txt"), "new").unwrap();
+        assert!(HashCache::load(&history).diff(&other).is_empty());
+    }
+
+    #[test]
+    fn test_search_filters_listing() {
+        use crate::app::fuzzy_match;
+
+        assert_eq!(fuzzy_match("tf1", "test_file1.txt"), Some(vec![0, 5, 9]));
+        assert_eq!(fuzzy_match("TDIR", "test_dir"), Some(vec![0, 5, 6, 7]));
+        assert_eq!(fuzzy_match("xyz", "test_dir"), None);
+
+        let temp_dir = setup_test_directory();
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+        let total = app.directory_items.len();
+
+        app.start_search();
+        for c in "file".chars() {
+            app.push_search_char(c);
+        }
+        let names: Vec<_> = app.directory_items.iter().map(|item| &item.name).collect();
+        assert_eq!(names, ["test_file1.txt"]);
+        assert_eq!(app.selected_index, Some(0));
+
+        // The filter sticks while browsing other directories
+        app.search_active = false;
+        app.current_path = temp_dir.path().join("test_dir");
+        app.populate_directory_items();
+        let names: Vec<_> = app.directory_items.iter().map(|item| &item.name).collect();
+        assert_eq!(names, ["test_file2.txt"]);
+
+        app.current_path = temp_dir.path().to_path_buf();
+        app.clear_search();
+        assert_eq!(app.directory_items.len(), total);
+    }
+
+    #[test]
+    fn test_hidden_files_toggle() {
+        let temp_dir = setup_test_directory();
+        fs::write(temp_dir.path().join(".env"), "TOKEN=1").unwrap();
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+        assert!(!app.directory_items.iter().any(|item| item.name == ".env"));
+
+        // The cached listing must not hide the file once toggled
+        app.toggle_hidden();
+        assert!(app.directory_items.iter().any(|item| item.name == ".env"));
+        app.toggle_hidden();
+        assert!(!app.directory_items.iter().any(|item| item.name == ".env"));
+    }
+
+    #[test]
+    fn test_sensitive_items_need_confirmation() {
+        let mut app = create_test_app();
+        app.sensitive_patterns.push("*.kdbx".to_string());
+        let env = PathBuf::from("/project/.env");
+        let key = PathBuf::from("/home/user/.ssh/config");
+        let vault = PathBuf::from("/project/passwords.kdbx");
+        let notes = PathBuf::from("/project/notes.txt");
+        app.items_to_share
+            .extend([env.clone(), key.clone(), vault.clone(), notes.clone()]);
+
+        app.sensitive_pending = app.unconfirmed_sensitive_items();
+        assert_eq!(
+            app.sensitive_pending,
+            [key.clone(), env.clone(), vault.clone()]
+        );
+
+        // Confirmed paths are not asked about again, unselected ones leave the selection
+        app.sensitive_pending = vec![env.clone()];
+        app.confirm_sensitive_items();
+        app.sensitive_pending = app.unconfirmed_sensitive_items();
+        assert_eq!(app.sensitive_pending, [key.clone(), vault]);
+        app.unselect_sensitive_items();
+        assert!(app.unconfirmed_sensitive_items().is_empty());
+        assert!(app.items_to_share.contains(&env));
+        assert!(app.items_to_share.contains(&notes));
+        assert!(!app.items_to_share.contains(&key));
+    }
+
+    #[test]
+    fn test_label_directory() {
+        assert_eq!(
+            label_directory("release-v1.2-artifacts"),
+            Some(PathBuf::from("release-v1.2-artifacts"))
+        );
+        // A label never leads outside the working directory
+        assert_eq!(
+            label_directory("../etc/passwd"),
+            Some(PathBuf::from("_etc_passwd"))
+        );
+        assert_eq!(label_directory("/tmp"), Some(PathBuf::from("_tmp")));
+        assert_eq!(label_directory(" .. "), None);
+        assert_eq!(label_directory(""), None);
+    }
+
+    #[test]
+    fn test_add_items_to_active_share() {
+        let temp_dir = setup_test_directory();
+        let mut app = create_test_app();
+        app.connection_state = ConnectionState::Connected;
+        app.current_path = temp_dir.path().join("test_dir");
+        app.populate_directory_items();
+        let file2 = temp_dir.path().join("test_dir/test_file2.txt");
+        app.selected_index = app
+            .directory_items
+            .iter()
+            .position(|item| item.path == file2);
+        app.select_item();
+        app.start_share();
+
+        // The host can still leave the shared directory and add more
+        app.go_up_previous_directory();
+        assert_eq!(app.current_path, temp_dir.path());
+        let file1 = temp_dir.path().join("test_file1.txt");
+        app.selected_index = app
+            .directory_items
+            .iter()
+            .position(|item| item.path == file1);
+        app.select_item();
+        assert!(app.items_being_shared.contains(&file1));
+        assert!(app.items_being_shared.contains(&file2));
+
+        app.unselect_item();
+        assert!(!app.items_being_shared.contains(&file1));
+        assert!(app.items_being_shared.contains(&file2));
+    }
+
+    #[test]
+    fn test_entered_peer_address() {
+        let peer_id = PeerId::random();
+        let addr = format!("/ip4/127.0.0.1/udp/4001/quic-v1/p2p/{peer_id}");
+        assert_eq!(
+            parse_peer_address(&format!(" {addr}\n")),
+            Ok(addr.parse().unwrap())
+        );
+        assert!(parse_peer_address("/ip4/127.0.0.1/udp/4001/quic-v1").is_err());
+        assert!(parse_peer_address("not an address").is_err());
+
+        // A valid address is handed to the network task and closes the input box
+        let mut app = create_test_app();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        app.address_sender = Some(tx);
+        app.address_input = Some("/ip4/127.0.0.1".to_string());
+        app.submit_address();
+        assert!(app.address_input.is_some());
+        assert!(app.warning.is_some());
+        app.address_input = Some(addr.clone());
+        app.submit_address();
+        assert!(app.address_input.is_none());
+        assert_eq!(rx.try_recv().unwrap().to_string(), addr);
+    }
+
+    #[test]
+    fn test_link_quality_score() {
+        use crate::service::quality::{LinkQuality, MAX_SCORE};
+        use std::time::Duration;
+
+        let mut quality = LinkQuality::default();
+        assert_eq!(quality.score(), None);
+        quality.record_rtt(Duration::from_millis(20));
+        quality.record_rtt(Duration::from_millis(40));
+        assert_eq!(quality.rtt(), Some(Duration::from_millis(30)));
+        assert_eq!(quality.score(), Some(MAX_SCORE));
+
+        // Stalls and relays cost points, but a live link never drops below one
+        quality.record_stall();
+        assert_eq!(quality.score(), Some(MAX_SCORE - 1));
+        quality.relayed = true;
+        assert_eq!(quality.score(), Some(MAX_SCORE - 2));
+        for _ in 0..10 {
+            quality.record_rtt(Duration::from_secs(1));
+        }
+        quality.record_stall();
+        assert_eq!(quality.score(), Some(1));
+    }
+
+    #[test]
+    fn test_transfer_history_roundtrip() {
+        use crate::transfers::{TransferHistory, TransferManager};
+
+        let mut transfers = TransferManager::default();
+        transfers.queue(vec!["a.txt".to_string()]);
+        transfers.progress("a.txt", 0, 4096);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        transfers.progress("a.txt", 2048, 4096);
+        assert!(transfers.transfers()[0].smoothed_speed().unwrap() > 0.0);
+        let record = transfers.complete("a.txt").unwrap();
+        assert_eq!(record.bytes, 4096);
+        assert!(record.throughput().unwrap() > 0.0);
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("history/transfers.jsonl");
+        let mut older = record.clone();
+        older.seconds *= 2.0;
+        TransferHistory::append(&path, &older).unwrap();
+        TransferHistory::append(&path, &record).unwrap();
+
+        // The latest record of a file wins, unknown files have none
+        let history = TransferHistory::load(&path);
+        assert_eq!(history.previous("a.txt"), Some(&record));
+        assert_eq!(history.previous("b.txt"), None);
+    }
+
+    #[test]
+    fn test_display_names() {
+        use crate::config::Config;
+        use crate::service::greeting::{AuthRequirement, Greeting};
+        use crate::transfers::TransferRecord;
+
+        let config = Config::parse("display_name = \"Chad's laptop\"\n").unwrap();
+        assert_eq!(config.display_name.as_deref(), Some("Chad's laptop"));
+
+        // The name only applies while the peer that sent it is connected
+        let mut app = create_test_app();
+        let host = PeerId::random();
+        let greeting = Greeting::new(
+            config.display_name,
+            Some("holiday-photos".into()),
+            AuthRequirement::None,
+        );
+        app.peer_greeting = Some((host, greeting));
+        assert_eq!(app.connected_peer_name(), None);
+        app.connected_peer_id = Some(host);
+        assert_eq!(app.connected_peer_name().as_deref(), Some("Chad's laptop"));
+        app.connected_peer_id = Some(PeerId::random());
+        assert_eq!(app.connected_peer_name(), None);
+
+        // Records written before peers had names still load
+        let record: TransferRecord =
+            serde_json::from_str(r#"{"path":"a.txt","bytes":1,"seconds":1.0,"finished_at":0}"#)
+                .unwrap();
+        assert_eq!(record.peer, None);
+        assert_eq!(record.peer_id, None);
+    }
+
+    #[test]
+    fn test_notification_severities() {
+        use crate::config::{Config, Severity};
+        use std::time::Duration;
+
+        let config = Config::parse("[notifications]\ninfo_seconds = 1\n").unwrap();
+        assert_eq!(
+            config.notifications.timeout(Severity::Info),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            config.notifications.timeout(Severity::Warning),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(config.notifications.timeout(Severity::Error), None);
+        assert!(Config::parse("[notifications]\ninfo = 1\n").is_err());
+
+        // Errors don't expire and aren't pushed aside by less severe messages
+        let mut app = create_test_app();
+        app.notifications = config.notifications;
+        app.notify(Severity::Error, "Failed to download: a.txt".to_string());
+        app.notify(Severity::Info, "Downloaded 1 files".to_string());
+        assert_eq!(app.warning_message(), "Failed to download: a.txt");
+        app.warning.as_mut().unwrap().timer -= Duration::from_secs(60);
+        assert!(!app.warning_expired());
+
+        app.clear_warning();
+        app.notify(Severity::Info, "Downloaded 1 files".to_string());
+        assert!(!app.warning_expired());
+        app.warning.as_mut().unwrap().timer -= Duration::from_secs(1);
+        assert!(app.warning_expired());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timers_on_paused_clock() {
+        use crate::config::Severity;
+        use crate::service::reconnect::ReconnectManager;
+        use std::time::Duration;
+
+        let mut app = create_test_app();
+        app.notify(Severity::Warning, "Cannot read the clipboard".to_string());
+        tokio::time::advance(Duration::from_millis(1999)).await;
+        assert!(!app.warning_expired());
+        tokio::time::advance(Duration::from_millis(1)).await;
+        assert!(app.warning_expired());
+
+        app.copied_address();
+        assert!(app.clipboard_success());
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(!app.clipboard_success());
+
+        // A redial sleeps until its backoff ran out, which a paused clock skips instantly
+        let mut reconnects = ReconnectManager::default();
+        let host = PeerId::random();
+        let address: libp2p::Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        reconnects.remember(host, address.clone());
+        reconnects.connection_lost(host, tokio::time::Instant::now());
+        reconnects.dial_failed(host, tokio::time::Instant::now());
+        let started = tokio::time::Instant::now();
+        tokio::time::sleep_until(reconnects.next_due().unwrap()).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert_eq!(
+            reconnects.take_due(tokio::time::Instant::now()),
+            vec![(host, address)]
+        );
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        use crate::service::reconnect::{backoff, Attempt, ReconnectManager, MAX_ATTEMPTS};
+        use libp2p::PeerId;
+        use std::time::Duration;
+        use tokio::time::Instant;
+
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(4), Duration::from_secs(8));
+        assert_eq!(backoff(MAX_ATTEMPTS), Duration::from_secs(30));
+
+        let mut reconnects = ReconnectManager::default();
+        let host = PeerId::random();
+        let address: libp2p::Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let now = Instant::now();
+        // Only peers we dialed are redialed
+        assert_eq!(reconnects.connection_lost(host, now), None);
+
+        reconnects.remember(host, address.clone());
+        assert_eq!(
+            reconnects.connection_lost(host, now),
+            Some(Attempt::Scheduled(1))
+        );
+        assert!(reconnects.take_due(now).is_empty());
+        let due = reconnects.next_due().unwrap();
+        assert_eq!(due, now + Duration::from_secs(1));
+        assert_eq!(reconnects.take_due(due), vec![(host, address)]);
+        // In flight until the dial succeeds or fails
+        assert_eq!(reconnects.next_due(), None);
+
+        for attempt in 2..=MAX_ATTEMPTS {
+            assert_eq!(
+                reconnects.dial_failed(host, now),
+                Some(Attempt::Scheduled(attempt))
+            );
+        }
+        assert_eq!(reconnects.dial_failed(host, now), Some(Attempt::GaveUp));
+        assert_eq!(reconnects.dial_failed(host, now), None);
+
+        // A user disconnect is not redialed
+        reconnects.remember(host, "/ip4/127.0.0.1/tcp/4001".parse().unwrap());
+        reconnects.forget(&host);
+        assert_eq!(reconnects.connection_lost(host, now), None);
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        use crate::config::Config;
+
+        let temp_dir = TempDir::new().unwrap();
+        let docs = temp_dir.path().join("docs");
+        fs::create_dir(&docs).unwrap();
+        fs::write(docs.join("plan.txt"), "plan").unwrap();
+        let config = Config::parse(&format!(
+            "[bookmarks]\nwork-docs = {:?}\ngone = \"/nonexistent/junkanoo\"\n",
+            docs.display().to_string()
+        ))
+        .unwrap();
+        assert_eq!(config.bookmark("work-docs"), Ok(docs.as_path()));
+        assert!(config
+            .bookmark("home")
+            .unwrap_err()
+            .contains("gone, work-docs"));
+        if let Some(home) = dirs_next::home_dir() {
+            let config = Config::parse("[bookmarks]\nnotes = \"~/notes\"\n").unwrap();
+            assert_eq!(config.bookmark("notes"), Ok(home.join("notes").as_path()));
+        }
+
+        let mut app = create_test_app();
+        app.open_bookmarks();
+        assert_eq!(app.bookmark_picker, None);
+        assert!(app.is_warning());
+
+        app.bookmarks = config.bookmarks;
+        app.open_bookmarks();
+        app.navigate_bookmarks(true);
+        app.navigate_bookmarks(true);
+        assert_eq!(app.bookmark_picker, Some(1));
+        app.jump_to_bookmark(1);
+        assert_eq!(app.bookmark_picker, None);
+        assert_eq!(app.current_path, fs::canonicalize(&docs).unwrap());
+        assert_eq!(app.directory_items[0].name, "plan.txt");
+
+        app.jump_to_bookmark(0);
+        assert!(app.warning_message().contains("'gone'"));
+        assert_eq!(app.current_path, fs::canonicalize(&docs).unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bandwidth_schedule() {
+        use crate::config::Config;
+        use crate::service::limiter::{Limit, RateLimiter};
+        use chrono::NaiveTime;
+
+        let config = Config::parse(
+            r#"[bandwidth]
+upload = "2MB/s"
+
+[[bandwidth.windows]]
+from = "23:00"
+to = "07:00"
+upload = "unlimited"
+download = "500KB/s"
+"#,
+        )
+        .unwrap();
+        let schedule = &config.bandwidth;
+        let at = |time: &str| schedule.limits_at(NaiveTime::parse_from_str(time, "%H:%M").unwrap());
+        assert_eq!(at("12:00"), (Limit::Rate(2_000_000), Limit::Unlimited));
+        // The window passes midnight and ends where the daytime limits start again
+        assert_eq!(at("23:30"), (Limit::Unlimited, Limit::Rate(500_000)));
+        assert_eq!(at("03:00"), (Limit::Unlimited, Limit::Rate(500_000)));
+        assert_eq!(at("07:00"), (Limit::Rate(2_000_000), Limit::Unlimited));
+        assert!(schedule.limits_upload() && schedule.limits_download());
+        assert!(Config::parse("[bandwidth]\nupload = \"fast\"").is_err());
+
+        // A running transfer picks up a new limit with its next chunk
+        let limiter = RateLimiter::with_limit(Limit::Rate(1000));
+        let start = tokio::time::Instant::now();
+        limiter.acquire(1000).await;
+        limiter.acquire(1000).await;
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(1));
+        limiter.set_limit(Limit::Unlimited);
+        limiter.acquire(1_000_000).await;
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_held_key_accelerates_navigation() {
+        use crate::config::Config;
+        use std::time::{Duration, Instant};
+
+        let config = Config::parse(
+            "[navigation]
+accelerate_after = 2
+accelerated_rows = 10
+",
+        )
+        .unwrap();
+        assert_eq!(config.navigation.repeat_interval_ms, 150);
+
+        let mut app = create_test_app();
+        app.navigation = config.navigation;
+        app.directory_items = (0..50)
+            .map(|i| shared_item(&format!("/shared/{i}.txt"), &format!("{i}.txt"), false))
+            .collect();
+        app.selected_index = Some(0);
+
+        // Separate presses move one row each
+        let mut now = Instant::now();
+        for _ in 0..3 {
+            app.navigate(true, now);
+            now += Duration::from_secs(1);
+        }
+        assert_eq!(app.selected_index, Some(3));
+
+        // Holding the key moves one row per repeat until the threshold, then ten
+        for _ in 0..4 {
+            app.navigate(true, now);
+            now += Duration::from_millis(30);
+        }
+        assert_eq!(app.selected_index, Some(3 + 1 + 1 + 10 + 10));
+
+        // Turning around starts slow again, and the cursor stops at the ends
+        app.navigate(false, now);
+        assert_eq!(app.selected_index, Some(24));
+        for _ in 0..10 {
+            now += Duration::from_millis(30);
+            app.navigate(true, now);
+        }
+        assert_eq!(app.selected_index, Some(49));
+    }
+
+    #[test]
+    fn test_recent_choices() {
+        use crate::recent::{Recent, RecentChoice};
+
+        let mut recent = Recent::default();
+        for at in 0..7 {
+            recent.add_share(PathBuf::from(format!("/srv/share{at}")), None, at);
+        }
+        recent.add_share(PathBuf::from("/srv/share3"), Some("reports".into()), 10);
+        assert_eq!(recent.shares.len(), 5);
+        assert_eq!(recent.shares[0].label.as_deref(), Some("reports"));
+        assert_eq!(
+            recent
+                .shares
+                .iter()
+                .filter(|s| s.path.ends_with("share3"))
+                .count(),
+            1
+        );
+
+        recent.add_peer("/ip4/10.0.0.2/tcp/4001".into(), Some("laptop".into()), 8);
+        let choices = recent.choices();
+        assert_eq!(
+            choices[0].describe(),
+            "Share /srv/share3 again as 'reports'"
+        );
+        assert_eq!(
+            choices[0].args(),
+            ["share", "/srv/share3", "--label", "reports"]
+        );
+        assert!(matches!(&choices[1], RecentChoice::Connect(_)));
+        assert_eq!(choices[1].describe(), "Reconnect to laptop");
+        assert_eq!(choices[1].args(), ["download", "/ip4/10.0.0.2/tcp/4001"]);
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("junkanoo").join("recent.json");
+        assert_eq!(Recent::load(&path), Recent::default());
+        recent.save(&path).unwrap();
+        assert_eq!(Recent::load(&path), recent);
+    }
+
+    /// An app with nothing taken from the machine it runs on, so snapshots are stable.
+    fn snapshot_app(is_host: bool) -> App {
+        let mut app = create_test_app();
+        app.peer_id = libp2p::identity::Keypair::ed25519_from_bytes([7; 32])
+            .unwrap()
+            .public()
+            .to_peer_id();
+        app.is_host = is_host;
+        app.state = if is_host {
+            AppState::Share
+        } else {
+            AppState::Download
+        };
+        app.current_path = PathBuf::from("/srv/photos");
+        app.listening_addrs = vec!["/ip4/192.168.1.20/udp/4001/quic-v1".parse().unwrap()];
+        let item = |index, path: &str, is_dir, depth, size| DirectoryItem {
+            name: PathBuf::from(path)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            path: PathBuf::from(path),
+            display_path: PathBuf::from(path),
+            is_dir,
+            index,
+            depth,
+            selected: false,
+            preview: if is_dir {
+                String::new()
+            } else {
+                format!("Contents of {path}")
+            },
+            size,
+            modified: None,
+            hash: None,
+        };
+        app.directory_items = vec![
+            item(0, "holiday", true, 0, 0),
+            item(1, "holiday/beach.jpg", false, 1, 2_400_000),
+            item(2, "holiday/sunset.jpg", false, 1, 3_100_000),
+            item(3, "notes.txt", false, 0, 1_200),
+        ];
+        app.selected_index = Some(1);
+        app
+    }
+
+    fn render_snapshot(app: &App) -> String {
+        let mut terminal =
+            ratatui::Terminal::new(ratatui::backend::TestBackend::new(140, 32)).unwrap();
+        terminal
+            .draw(|frame| crate::cli::ui::render(frame, app))
+            .unwrap();
+        terminal.backend().to_string()
+    }
+
+    #[test]
+    fn test_ui_snapshot_idle_host() {
+        let mut app = snapshot_app(true);
+        app.directory_items.clear();
+        app.selected_index = None;
+        insta::assert_snapshot!(render_snapshot(&app));
+    }
+
+    #[test]
+    fn test_ui_snapshot_waiting_for_peer() {
+        let mut app = snapshot_app(true);
+        app.display_name = Some("Chad's laptop".into());
+        app.share_label = Some("holiday-photos".into());
+        app.items_being_shared = app
+            .directory_items
+            .iter()
+            .map(|item| item.path.clone())
+            .collect();
+        insta::assert_snapshot!(render_snapshot(&app));
+    }
+
+    #[test]
+    fn test_ui_snapshot_browsing_remote_tree() {
+        use crate::service::greeting::{AuthRequirement, Greeting};
+
+        let mut app = snapshot_app(false);
+        let host = libp2p::identity::Keypair::ed25519_from_bytes([8; 32])
+            .unwrap()
+            .public()
+            .to_peer_id();
+        app.connection_state = ConnectionState::Connected;
+        app.connected_peer_id = Some(host);
+        app.peer_greeting = Some((
+            host,
+            Greeting::new(
+                Some("Chad's laptop".into()),
+                Some("holiday-photos".into()),
+                AuthRequirement::Password,
+            ),
+        ));
+        app.connection_quality = Some((4, std::time::Duration::from_millis(23)));
+        app.items_to_download.insert(PathBuf::from("notes.txt"));
+        insta::assert_snapshot!(render_snapshot(&app));
+    }
+
+    #[test]
+    fn test_ui_snapshot_transferring() {
+        let mut app = snapshot_app(false);
+        app.connection_state = ConnectionState::Connected;
+        app.show_transfers = true;
+        app.transfers.queue(vec![
+            "holiday/beach.jpg".into(),
+            "holiday/sunset.jpg".into(),
+            "notes.txt".into(),
+        ]);
+        app.transfers
+            .progress("holiday/beach.jpg", 1_200_000, 2_400_000);
+        app.transfers.progress("notes.txt", 1_200, 1_200);
+        app.transfers.complete("notes.txt");
+        app.transfers
+            .fail("holiday/sunset.jpg", "connection closed".into());
+        // Speeds and times depend on how fast the test runs
+        insta::with_settings!({filters => vec![
+            (r"(\d+%|done)[^│]*│", "$1 [speed] │"),
+        ]}, {
+            insta::assert_snapshot!(render_snapshot(&app));
+        });
+    }
+
+    #[test]
+    fn test_ui_snapshot_error() {
+        let mut app = snapshot_app(false);
+        app.notify(
+            crate::config::Severity::Error,
+            "Wrong password for the share".into(),
+        );
+        insta::assert_snapshot!(render_snapshot(&app));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_policies() {
+        use crate::service::hashing::HashCache;
+        use crate::service::registry::ShareRegistry;
+        use crate::service::utils::{walk, SymlinkPolicy};
+        use std::os::unix::fs::symlink;
+
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        fs::write(root.join("docs/notes.txt"), "notes").unwrap();
+        symlink("notes.txt", root.join("docs/latest.txt")).unwrap();
+        // Following this would walk the same directory forever
+        symlink("..", root.join("docs/up")).unwrap();
+
+        let names = |symlinks| {
+            let mut names: Vec<String> = walk(&root, symlinks)
+                .map(|path| path.strip_prefix(&root).unwrap().display().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(SymlinkPolicy::Follow),
+            ["", "docs", "docs/latest.txt", "docs/notes.txt"]
+        );
+        assert_eq!(names(SymlinkPolicy::Skip), ["", "docs", "docs/notes.txt"]);
+        assert_eq!(
+            names(SymlinkPolicy::Copy),
+            ["", "docs", "docs/latest.txt", "docs/notes.txt", "docs/up"]
+        );
+
+        // Copied links are listed as files and announced with their target
+        let paths: Vec<PathBuf> = walk(&root.join("docs"), SymlinkPolicy::Copy).collect();
+        let items = crate::shared_items(&paths, SymlinkPolicy::Copy, &mut HashCache::default());
+        let link = items.iter().find(|item| item.name == "latest.txt").unwrap();
+        assert!(!link.is_dir);
+        assert_eq!(link.path, root.join("docs/latest.txt"));
+        assert_eq!(link.preview, "Link to notes.txt");
+        let mut registry = ShareRegistry::default();
+        registry.replace(items);
+        let manifest = registry.manifest(&["latest.txt".to_string(), "notes.txt".to_string()]);
+        assert_eq!(
+            manifest.entry("latest.txt").unwrap().link_target.as_deref(),
+            Some("notes.txt")
+        );
+        assert_eq!(manifest.entry("notes.txt").unwrap().link_target, None);
+
+        // Downloaders recreate links, as long as they stay inside the download directory
+        let download_dir = TempDir::new().unwrap();
+        let receiver = FileReceiver::new().with_directory(Some(download_dir.path().to_path_buf()));
+        let created = receiver
+            .create_link("docs/latest.txt", "notes.txt")
+            .await
+            .unwrap();
+        assert_eq!(fs::read_link(created).unwrap(), PathBuf::from("notes.txt"));
+        assert!(receiver
+            .create_link("docs/up", "../../outside")
+            .await
+            .is_err());
+        assert!(receiver.create_link("passwd", "/etc/passwd").await.is_err());
+        assert!(!download_dir.path().join("passwd").exists());
+    }
+
+    #[tokio::test]
+    async fn test_udp_probe() {
+        use crate::service::probe::{binding_request, is_binding_success, udp_egress};
+        use std::time::Duration;
+        use tokio::net::UdpSocket;
+
+        // A STUN server that answers every binding request with an empty success
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            while let Ok((length, from)) = server.recv_from(&mut buffer).await {
+                let mut answer = buffer[..length.min(20)].to_vec();
+                answer[..2].copy_from_slice(&0x0101u16.to_be_bytes());
+                let _ = server.send_to(&answer, from).await;
+            }
+        });
+        // Bound but never answering, like a firewall dropping the packets
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let silent_addr = silent.local_addr().unwrap().to_string();
+
+        let timeout = Duration::from_millis(300);
+        assert_eq!(
+            udp_egress(&[&silent_addr, &server_addr], timeout).await,
+            Some(true)
+        );
+        assert_eq!(udp_egress(&[&silent_addr], timeout).await, Some(false));
+        assert_eq!(udp_egress(&[], timeout).await, None);
+
+        let transaction = [7u8; 12];
+        let request = binding_request(&transaction);
+        assert!(!is_binding_success(&request, &transaction));
+        let mut answer = request;
+        answer[..2].copy_from_slice(&0x0101u16.to_be_bytes());
+        assert!(is_binding_success(&answer, &transaction));
+        assert!(!is_binding_success(&answer, &[8u8; 12]));
+    }
+
+    #[tokio::test]
+    async fn test_download_conflicts() {
+        use crate::cli::commands::parse_conflict_policy;
+        use crate::service::utils::{Conflict, ConflictPolicy};
+        use crate::transfers::{TransferManager, TransferState};
+        use futures::io::Cursor;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("notes.txt");
+        fs::write(&file_path, "new content").unwrap();
+        let mut wire = Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        let bytes = wire.into_inner();
+        // Outside the working directory the file is sent under its full path, so the
+        // destination is the same file, now with other content
+        fs::write(&file_path, "old").unwrap();
+        let receive = |conflict| {
+            let bytes = bytes.clone();
+            async move {
+                root_receiver()
+                    .with_conflict(conflict)
+                    .receive_file(&mut Cursor::new(bytes))
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let skipped = receive(ConflictPolicy::Skip).await;
+        assert_eq!(skipped.conflict, Some(Conflict::Skipped));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "old");
+
+        for n in 1..=2 {
+            let renamed = receive(ConflictPolicy::Rename).await;
+            let expected = temp_dir.path().join(format!("notes ({n}).txt"));
+            let sent_as = transfer_path(&expected).to_string_lossy().to_string();
+            assert_eq!(renamed.conflict, Some(Conflict::Renamed(sent_as.clone())));
+            assert_eq!(renamed.path, sent_as);
+            assert_eq!(fs::read_to_string(&expected).unwrap(), "new content");
+        }
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "old");
+
+        let overwritten = receive(ConflictPolicy::Overwrite).await;
+        assert_eq!(overwritten.conflict, Some(Conflict::Overwritten));
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "new content");
+
+        // Each decision shows in the transfer list
+        let mut transfers = TransferManager::default();
+        transfers.queue(vec!["a.txt".to_string(), "b.txt".to_string()]);
+        transfers.conflict("a.txt", &Conflict::Skipped);
+        transfers.conflict("b.txt", &Conflict::Renamed("b (1).txt".to_string()));
+        assert_eq!(transfers.transfers()[0].state, TransferState::Kept);
+        assert_eq!(
+            transfers.transfers()[1].note.as_deref(),
+            Some("saved as b (1).txt")
+        );
+
+        assert_eq!(parse_conflict_policy("Rename"), Ok(ConflictPolicy::Rename));
+        assert!(parse_conflict_policy("merge").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_cancel_transfer() {
+        use crate::service::utils::{proceed, TransferControl};
+        use crate::transfers::{TransferManager, TransferState};
+        use futures::io::Cursor;
+        use std::time::Duration;
+        use tokio::sync::watch;
+
+        let (control, mut receiver) = watch::channel(TransferControl::Pause);
+        let waiting = tokio::time::timeout(Duration::from_millis(50), proceed(&mut receiver));
+        assert!(waiting.await.is_err(), "a paused transfer waits");
+        control.send_replace(TransferControl::Run);
+        assert!(proceed(&mut receiver).await);
+        control.send_replace(TransferControl::Cancel);
+        assert!(!proceed(&mut receiver).await);
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("movie.mkv");
+        fs::write(&file_path, "frames").unwrap();
+        let mut wire = Cursor::new(Vec::new());
+        FileTransfer::new(&file_path)
+            .stream_file(&mut wire)
+            .await
+            .unwrap();
+        let bytes = wire.into_inner();
+
+        // Cancelled, nothing more is written
+        let error = root_receiver()
+            .with_control(Some(receiver))
+            .receive_file(&mut Cursor::new(bytes.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "transfer cancelled");
+
+        // Paused, the file only arrives once resumed
+        let (control, receiver) = watch::channel(TransferControl::Pause);
+        let receive = tokio::spawn(async move {
+            root_receiver()
+                .with_control(Some(receiver))
+                .receive_file(&mut Cursor::new(bytes))
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!receive.is_finished());
+        control.send_replace(TransferControl::Run);
+        assert!(receive.await.unwrap().is_ok());
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "frames");
+
+        let mut transfers = TransferManager::default();
+        transfers.queue(vec!["movie.mkv".to_string(), "notes.txt".to_string()]);
+        transfers.progress("movie.mkv", 10, 100);
+        transfers.pause("movie.mkv", true);
+        transfers.pause("notes.txt", true);
+        assert_eq!(transfers.transfers()[0].state, TransferState::Paused);
+        transfers.pause("movie.mkv", false);
+        transfers.pause("notes.txt", false);
+        assert_eq!(transfers.transfers()[0].state, TransferState::Active);
+        assert_eq!(transfers.transfers()[1].state, TransferState::Queued);
+        transfers.cancel("notes.txt");
+        // Only queued and running transfers can be paused
+        transfers.pause("notes.txt", true);
+        assert_eq!(transfers.transfers()[1].state, TransferState::Cancelled);
+    }
+
+    #[test]
+    fn test_share_stats() {
+        use crate::transfers::ShareStats;
+
+        let peer = |seed| {
+            libp2p::identity::Keypair::ed25519_from_bytes([seed; 32])
+                .unwrap()
+                .public()
+                .to_peer_id()
+        };
+        let (alice, bob) = (peer(1), peer(2));
+        let mut stats = ShareStats::default();
+        stats.upload_started(alice, "a.txt".into());
+        stats.upload_started(bob, "a.txt".into());
+        stats.upload_started(bob, "b.txt".into());
+        assert_eq!(stats.active_uploads(), 3);
+        assert_eq!(stats.peers_served(), 0);
+
+        stats.upload_completed(alice, "a.txt".into(), 100);
+        stats.upload_failed(bob, "b.txt", 30);
+        assert_eq!(stats.active_uploads(), 1);
+        assert_eq!(stats.peers_served(), 1);
+        assert_eq!(stats.bytes_served(), 130);
+
+        stats.upload_completed(bob, "a.txt".into(), 100);
+        stats.upload_started(bob, "b.txt".into());
+        stats.upload_completed(bob, "b.txt".into(), 50);
+        assert_eq!(stats.active_uploads(), 0);
+        assert_eq!(stats.peers_served(), 2);
+        assert_eq!(stats.top_files(5), vec![("a.txt", 2), ("b.txt", 1)]);
+        assert_eq!(stats.top_files(1), vec![("a.txt", 2)]);
+        // An upload nobody saw start doesn't make the count go wrong
+        stats.upload_failed(alice, "c.txt", 0);
+        assert_eq!(stats.active_uploads(), 0);
+    }
+
+    #[test]
+    fn test_ui_snapshot_host_uploads() {
+        let mut app = snapshot_app(true);
+        app.items_being_shared = app
+            .directory_items
+            .iter()
+            .map(|item| item.path.clone())
+            .collect();
+        let peer = |seed| {
+            libp2p::identity::Keypair::ed25519_from_bytes([seed; 32])
+                .unwrap()
+                .public()
+                .to_peer_id()
+        };
+        let (laptop, phone) = (peer(3), peer(4));
+        app.peer_greeting = Some((
+            laptop,
+            crate::service::greeting::Greeting::new(
+                Some("Ana's laptop".into()),
+                None,
+                crate::service::greeting::AuthRequirement::None,
+            ),
+        ));
+        let stats = &mut app.share_stats;
+        stats.upload_started(phone, "notes.txt".into());
+        stats.upload_completed(phone, "notes.txt".into(), 1_200);
+        stats.upload_started(laptop, "holiday/beach.jpg".into());
+        stats.upload_progress(laptop, "holiday/beach.jpg", 600_000, 2_400_000);
+        stats.upload_started(phone, "holiday/sunset.jpg".into());
+        insta::assert_snapshot!(render_snapshot(&app));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_systemd_notify() {
+        use crate::service::systemd::send;
+        use std::os::unix::net::UnixDatagram;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("notify");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        send(path.as_os_str(), "READY=1").unwrap();
+        send(path.as_os_str(), "WATCHDOG=1").unwrap();
+        let mut buffer = [0u8; 64];
+        let length = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"READY=1");
+        let length = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"WATCHDOG=1");
+
+        // Nobody listening there
+        assert!(send(temp_dir.path().join("missing").as_os_str(), "READY=1").is_err());
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+
+            let name = format!("junkanoo-test-{}", std::process::id());
+            let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+            let socket = UnixDatagram::bind_addr(&address).unwrap();
+            send(std::ffi::OsStr::new(&format!("@{name}")), "STOPPING=1").unwrap();
+            let length = socket.recv(&mut buffer).unwrap();
+            assert_eq!(&buffer[..length], b"STOPPING=1");
+        }
+    }
+
+    #[test]
+    fn test_session_report_html() {
+        use crate::report::{DeliveredFile, SessionReport};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let peer = |seed| {
+            libp2p::identity::Keypair::ed25519_from_bytes([seed; 32])
+                .unwrap()
+                .public()
+                .to_peer_id()
+        };
+        let (host, downloader) = (peer(5), peer(6));
+        let mut report = SessionReport {
+            started_at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            files: Vec::new(),
+        };
+        report.add(DeliveredFile {
+            path: "reports/<q3>.pdf".into(),
+            size: 2_400_000,
+            hash: Some("ab".repeat(32)),
+            peer_id: downloader,
+            peer: Some("Ana & Bo".into()),
+            finished_at: UNIX_EPOCH + Duration::from_secs(1_700_000_090),
+        });
+        report.add(DeliveredFile {
+            path: "notes.txt".into(),
+            size: 12,
+            hash: None,
+            peer_id: downloader,
+            peer: None,
+            finished_at: UNIX_EPOCH + Duration::from_secs(1_700_000_095),
+        });
+
+        let html = report.to_html(
+            &host,
+            true,
+            Some("q3 <final>"),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_100),
+        );
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>junkanoo delivery report: q3 &lt;final&gt;</title>"));
+        assert!(html.contains(&format!("<dt>Host</dt><dd><code>{host}</code></dd>")));
+        assert!(html.contains("2023-11-14 22:13:20 UTC to 2023-11-14 22:15:00 UTC"));
+        assert!(html.contains("<dt>Sent</dt><dd>2 files, 2.3 MiB</dd>"));
+        assert!(html.contains("<td>reports/&lt;q3&gt;.pdf</td>"));
+        assert!(html.contains(&"ab".repeat(32)));
+        assert!(html.contains(&format!("Ana &amp; Bo<br><code>{downloader}</code>")));
+        assert!(html.contains("<td><code>unknown</code></td>"));
+        assert!(html.contains("2023-11-14 22:14:50 UTC"));
+        // Self-contained, nothing is loaded from elsewhere
+        assert!(!html.contains("http"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn test_visual_range_selection() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"] {
+            fs::write(temp_dir.path().join(name), name).unwrap();
+        }
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+        let path = |name: &str| temp_dir.path().join(name);
+        let index = |app: &App, name: &str| {
+            app.directory_items
+                .iter()
+                .position(|item| item.path == path(name))
+        };
+
+        // Anchored at b with the cursor moved down to d, b, c and d are in range
+        app.selected_index = index(&app, "b.txt");
+        app.toggle_visual();
+        app.navigate_next_file();
+        app.navigate_next_file();
+        let range = app.visual_range().unwrap();
+        assert_eq!(
+            range,
+            index(&app, "b.txt").unwrap()..=index(&app, "d.txt").unwrap()
+        );
+        app.select_item();
+        assert_eq!(app.selection_anchor, None);
+        for name in ["b.txt", "c.txt", "d.txt"] {
+            assert!(app.items_to_share.contains(&path(name)), "{name}");
+        }
+        assert!(!app.items_to_share.contains(&path("a.txt")));
+        assert!(!app.items_to_share.contains(&path("e.txt")));
+
+        // Ranges extend backwards too, and n unselects all of them
+        app.selected_index = index(&app, "c.txt");
+        app.toggle_visual();
+        app.navigate_previous_file();
+        app.navigate_previous_file();
+        app.unselect_item();
+        assert_eq!(
+            app.items_to_share.iter().cloned().collect::<Vec<_>>(),
+            vec![path("d.txt")]
+        );
+        assert!(!app.directory_items[index(&app, "b.txt").unwrap()].selected);
+
+        // Leaving visual mode without applying it changes nothing
+        app.toggle_visual();
+        app.navigate_next_file();
+        app.toggle_visual();
+        assert_eq!(app.visual_range(), None);
+        app.select_item();
+        assert_eq!(app.items_to_share.len(), 2);
+    }
+
+    #[test]
+    fn test_conflict_prompt_decisions() {
+        use crate::app::ConflictPrompt;
+        use crate::service::node::FileConflict;
+        use crate::service::utils::ConflictPolicy;
+
+        let conflict = |name: &str| FileConflict {
+            path: format!("/share/{name}"),
+            relative_path: name.to_string(),
+            size: 10,
+            local_size: 5,
+            local_modified: None,
+        };
+        let mut prompt = ConflictPrompt::new(vec![conflict("a.txt"), conflict("b.txt")]);
+        // Nothing is replaced unless asked to
+        assert_eq!(prompt.decisions, vec![ConflictPolicy::Skip; 2]);
+
+        prompt.navigate(true);
+        prompt.navigate(true);
+        assert_eq!(prompt.selected, 1);
+        prompt.cycle();
+        assert_eq!(
+            prompt.decisions,
+            vec![ConflictPolicy::Skip, ConflictPolicy::Overwrite]
+        );
+        prompt.cycle();
+        prompt.cycle();
+        assert_eq!(prompt.decisions[1], ConflictPolicy::Skip);
+
+        prompt.navigate(false);
+        prompt.navigate(false);
+        assert_eq!(prompt.selected, 0);
+        prompt.decide_all(ConflictPolicy::Rename);
+        assert_eq!(prompt.decisions, vec![ConflictPolicy::Rename; 2]);
+    }
+
+    #[test]
+    fn test_list_scrolling_and_row_lookup() {
+        let temp_dir = TempDir::new().unwrap();
+        for index in 0..10 {
+            fs::write(temp_dir.path().join(format!("{index}.txt")), "x").unwrap();
+        }
+        let mut app = create_test_app();
+        app.current_path = temp_dir.path().to_path_buf();
+        app.populate_directory_items();
+        let count = app.directory_items.len();
+
+        // Four rows tall, the cursor on the sixth item scrolls it to the bottom row
+        app.selected_index = Some(5);
+        app.scroll_to_cursor(4);
+        assert_eq!(app.list_offset, 2);
+        assert_eq!(app.item_at_row(3), Some(5));
+        // Moving up within view keeps the list where it is, above it scrolls back
+        app.selected_index = Some(3);
+        app.scroll_to_cursor(4);
+        assert_eq!(app.list_offset, 2);
+        app.selected_index = Some(0);
+        app.scroll_to_cursor(4);
+        assert_eq!(app.list_offset, 0);
+        assert_eq!(app.item_at_row(count), None);
+
+        app.toggle_selection();
+        assert!(app.is_selected(&app.directory_items[0]));
+        app.toggle_selection();
+        assert!(!app.is_selected(&app.directory_items[0]));
+    }
+
+    #[tokio::test]
+    async fn test_fair_scheduler_takes_turns() {
+        use crate::service::fairness::{FairScheduler, CHUNKS_IN_FLIGHT, QUANTUM};
+        use std::collections::BTreeMap;
+        use std::sync::Arc;
+
+        let scheduler = Arc::new(FairScheduler::new(4, &BTreeMap::new()));
+        let (busy, light) = (PeerId::random(), PeerId::random());
+        // The busy peer fills every slot, then queues three more chunks before the light
+        // peer asks for one
+        #[allow(clippy::collection_is_never_read)]
+        let mut held = Vec::new();
+        for _ in 0..CHUNKS_IN_FLIGHT {
+            held.push(scheduler.turn(busy, QUANTUM).await);
+        }
+        let (order_sender, mut order) = tokio::sync::mpsc::unbounded_channel();
+        for (peer, label) in [
+            (busy, "busy"),
+            (busy, "busy"),
+            (busy, "busy"),
+            (light, "light"),
+        ] {
+            let scheduler = Arc::clone(&scheduler);
+            let order_sender = order_sender.clone();
+            tokio::spawn(async move {
+                let turn = scheduler.turn(peer, QUANTUM).await;
+                order_sender.send(label).unwrap();
+                drop(turn);
+            });
+            tokio::task::yield_now().await;
+        }
+        // The busy peer used up its round, the first chunk to finish makes room for the
+        // light peer although it asked last
+        held.pop();
+        assert_eq!(order.recv().await, Some("light"));
+        held.clear();
+        for _ in 0..3 {
+            assert_eq!(order.recv().await, Some("busy"));
+        }
+
+        // Streams past the limit wait, but a peer without one is let in regardless
+        #[allow(clippy::collection_is_never_read)]
+        let mut admissions = Vec::new();
+        for _ in 0..4 {
+            admissions.push(scheduler.admit(busy).await);
+        }
+        let waiting = {
+            let scheduler = Arc::clone(&scheduler);
+            tokio::spawn(async move { scheduler.admit(busy).await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        let light_admission = scheduler.admit(light).await;
+        drop(light_admission);
+        admissions.pop();
+        waiting.await.unwrap();
+    }
+
+    #[test]
+    fn test_binary_preview_hex_dump() {
+        use crate::cli::preview::{binary_mime, from_bytes, render};
+        use std::path::Path;
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(0u8..24);
+        let preview = from_bytes(Path::new("logo.png"), &png);
+        // Recognized by its content even under another name
+        assert_eq!(
+            binary_mime(&from_bytes(Path::new("logo"), &png)),
+            Some("image/png")
+        );
+        assert_eq!(binary_mime(&preview), Some("image/png"));
+        let lines: Vec<String> = render(Path::new("logo.png"), &preview)
+            .lines
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "00000000  89 50 4e 47 0d 0a 1a 0a  00 01 02 03 04 05 06 07  |.PNG............|",
+                "00000010  08 09 0a 0b 0c 0d 0e 0f  10 11 12 13 14 15 16 17  |................|",
+            ]
+        );
+
+        // Unknown content falls back to the extension, text and a character cut off at
+        // the end stay text
+        assert_eq!(
+            binary_mime(&from_bytes(Path::new("data.bin"), &[0, 159, 146, 150])),
+            Some("application/octet-stream")
+        );
+        assert_eq!(
+            binary_mime(&from_bytes(Path::new("a.txt"), "héllo".as_bytes())),
+            None
+        );
+        assert_eq!(
+            from_bytes(Path::new("a.txt"), &"hé".as_bytes()[..2]),
+            "h\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn test_push_wants_missing_and_changed_files() {
+        use crate::service::hashing::hash_file;
+        use crate::service::protocol::ManifestEntry;
+        use crate::service::push;
+
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        fs::write(directory.join("same.txt"), b"unchanged").unwrap();
+        fs::write(directory.join("changed.txt"), b"old text!").unwrap();
+        let entry = |relative_path: &str, content: &[u8]| {
+            let source = directory.join("source");
+            fs::write(&source, content).unwrap();
+            ManifestEntry {
+                path: format!("/pusher/{relative_path}"),
+                relative_path: relative_path.to_string(),
+                size: content.len() as u64,
+                hash: Some(hash_file(&source).unwrap()),
+                link_target: None,
+            }
+        };
+        let mut link = entry("link", b"");
+        link.link_target = Some("same.txt".to_string());
+        let entries = vec![
+            entry("same.txt", b"unchanged"),
+            // Same size, other content
+            entry("changed.txt", b"new text!"),
+            entry("nested/missing.txt", b"new"),
+            entry("../outside.txt", b"escape"),
+            entry("/etc/passwd", b"root"),
+            link,
+        ];
+
+        let wanted: Vec<String> = push::wanted(directory, &entries)
+            .into_iter()
+            .map(|entry| entry.relative_path)
+            .collect();
+        assert_eq!(wanted, ["changed.txt", "nested/missing.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_stops_when_source_changes() {
+        use crate::service::error::JunkanooError;
+        use futures::io::Cursor;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("growing.log");
+        fs::write(&file_path, vec![b'x'; 10 * 1024 * 1024]).unwrap();
+
+        // Written to after the first chunk went out, checked again a few chunks later
+        let appended = Arc::new(AtomicBool::new(false));
+        let (source, flag) = (file_path.clone(), appended.clone());
+        let mut wire = Cursor::new(Vec::new());
+        let error = FileTransfer::new(&file_path)
+            .with_progress(move |_, _| {
+                if !flag.swap(true, Ordering::SeqCst) {
+                    let mut file = fs::OpenOptions::new().append(true).open(&source).unwrap();
+                    file.write_all(b"one more line\n").unwrap();
+                }
+            })
+            .stream_file(&mut wire)
+            .await
+            .unwrap_err();
+        assert!(appended.load(Ordering::SeqCst));
+        assert!(matches!(error, JunkanooError::SourceChanged));
+        assert_eq!(error.to_string(), "source changed during transfer");
+    }
+
+    #[test]
+    fn test_remote_preview_cache() {
+        use crate::app::PreviewCache;
+        use std::time::{Duration, Instant};
+
+        let item = |name: &str, hash: &str| DirectoryItem {
+            name: name.to_string(),
+            path: PathBuf::from(format!("/share/{name}")),
+            display_path: PathBuf::from(name),
+            is_dir: false,
+            index: 0,
+            depth: 1,
+            selected: false,
+            preview: String::new(),
+            size: 10,
+            modified: None,
+            hash: Some(hash.to_string()),
+        };
+        let notes = item("notes.txt", "aaa");
+        let mut cache = PreviewCache::default();
+        let start = Instant::now();
+
+        // Only fetched once it stayed highlighted a moment, and only once
+        assert!(!cache.due(Some(&notes), start));
+        assert!(!cache.due(Some(&notes), start + Duration::from_millis(50)));
+        assert!(cache.due(Some(&notes), start + Duration::from_millis(200)));
+        assert!(!cache.due(Some(&notes), start + Duration::from_millis(300)));
+        cache.insert(notes.path.clone(), notes.hash.clone(), "hello".to_string());
+        assert_eq!(cache.get(&notes), Some("hello"));
+
+        // Moving on restarts the wait, coming back to a cached file fetches nothing
+        let other = item("other.txt", "bbb");
+        assert!(!cache.due(Some(&other), start + Duration::from_millis(400)));
+        assert!(!cache.due(Some(&notes), start + Duration::from_millis(900)));
+
+        // A new version on the host is fetched again
+        let changed = item("notes.txt", "ccc");
+        assert_eq!(cache.get(&changed), None);
+        assert!(!cache.due(Some(&changed), start + Duration::from_secs(1)));
+        assert!(cache.due(Some(&changed), start + Duration::from_millis(1200)));
+
+        // The oldest previews make room for new ones
+        for n in 0..64 {
+            let file = item(&format!("{n}.txt"), "ddd");
+            cache.insert(file.path, file.hash, String::new());
+        }
+        assert_eq!(cache.get(&notes), None);
+    }
+
+    #[test]
+    fn test_feature_negotiation() {
+        use crate::service::greeting::{AuthRequirement, Greeting};
+        use crate::service::protocol::{COMPRESSION_NONE, COMPRESSION_ZSTD};
+
+        let ours = Greeting::new(None, None, AuthRequirement::None);
+        let no_compress = Greeting::new(None, None, AuthRequirement::None).without("zstd");
+        assert!(!no_compress.supports("zstd") && no_compress.supports("delta"));
+
+        // A peer not compressing turns compression off for both sides
+        let negotiated = no_compress.clone().negotiate(&ours);
+        assert!(!negotiated.supports("zstd"));
+        assert!(negotiated.supports("delta") && negotiated.supports("hash"));
+        // Files, archives and ranges are all requested uncompressed from it then
+        assert_eq!(negotiated.compression(), COMPRESSION_NONE);
+        assert_eq!(
+            ours.clone().negotiate(&no_compress).compression(),
+            COMPRESSION_NONE
+        );
+        assert_eq!(
+            ours.clone().negotiate(&ours).compression(),
+            COMPRESSION_ZSTD
+        );
+
+        // Features only one side knows are left out, an older peer gets what it speaks
+        let older = Greeting {
+            features: vec![
+                "zstd".to_string(),
+                "range".to_string(),
+                "teleport".to_string(),
+            ],
+            protocol_version: 1,
+            ..Greeting::default()
+        };
+        assert_eq!(older.negotiate(&ours).features, ["zstd", "range"]);
+        let ancient: Greeting = serde_json::from_str(r#"{"display_name":"laptop"}"#).unwrap();
+        assert!(ancient.negotiate(&ours).features.is_empty());
+    }
+
+    #[test]
+    fn test_remote_directories_listed_on_demand() {
+        use crate::service::registry::ShareRegistry;
+        use std::path::Path;
+
+        let mut registry = ShareRegistry::default();
+        registry.replace(vec![
+            shared_item("/share/docs", "docs", true),
+            shared_item("/share/docs/a.txt", "docs/a.txt", false),
+            shared_item("/share/docs/old", "docs/old", true),
+            shared_item("/share/docs/old/b.txt", "docs/old/b.txt", false),
+            shared_item("/share/notes.txt", "notes.txt", false),
+        ]);
+        let names = |items: Vec<DirectoryItem>| -> Vec<String> {
+            let mut names: Vec<String> = items.into_iter().map(|item| item.name).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(registry.list(Path::new(""), false)),
+            ["docs", "notes.txt"]
+        );
+        assert_eq!(
+            names(registry.list(Path::new("/share/docs"), false)),
+            ["a.txt", "old"]
+        );
+        assert_eq!(
+            names(registry.list(Path::new("/share/docs"), true)),
+            ["a.txt", "b.txt", "old"]
+        );
+        assert!(registry.list(Path::new("/elsewhere"), true).is_empty());
+
+        let mut app = create_test_app();
+        app.state = AppState::Download;
+        app.current_path = PathBuf::new();
+        app.remote_listing.on_demand = true;
+        assert_eq!(app.due_listings(), [(PathBuf::new(), false)]);
+        // Asked for already
+        assert!(app.due_listings().is_empty());
+        app.apply_listing(PathBuf::new(), false, registry.list(Path::new(""), false));
+        assert_eq!(app.directory_items.len(), 2);
+
+        app.selected_index = Some(0);
+        assert!(app.enter_directory());
+        let docs = PathBuf::from("/share/docs");
+        assert_eq!(app.due_listings(), [(docs.clone(), false)]);
+        app.apply_listing(docs.clone(), false, registry.list(&docs, false));
+        assert_eq!(app.directory_items.len(), 2);
+
+        // Selecting a directory lists everything below it before it can be downloaded
+        app.current_path = PathBuf::new();
+        app.populate_directory_items();
+        assert_eq!(app.directory_items.len(), 2);
+        app.selected_index = Some(0);
+        app.select_item();
+        assert!(app.remote_listing.is_expanding());
+        assert_eq!(app.due_listings(), [(docs.clone(), true)]);
+        app.apply_listing(docs.clone(), true, registry.list(&docs, true));
+        assert!(!app.remote_listing.is_expanding());
+        assert!(app
+            .items_to_download
+            .contains(Path::new("/share/docs/old/b.txt")));
+
+        // A directory the host no longer shares goes with everything below it
+        app.remote_listing.invalidate();
+        assert!(!app.remote_listing.is_listed(&docs));
+        app.apply_listing(
+            docs,
+            false,
+            vec![shared_item("/share/docs/a.txt", "docs/a.txt", false)],
+        );
+        assert_eq!(app.all_shared_items.len(), 3);
+        assert!(!app
+            .items_to_download
+            .contains(Path::new("/share/docs/old/b.txt")));
+    }
+
+    #[test]
+    fn test_locale_aware_formatting() {
+        use crate::format::{self, Locale};
+        use std::time::{Duration, SystemTime};
+
+        let german = Locale::from_name("de_DE.UTF-8");
+        assert_eq!(german.decimal_separator, ',');
+        assert_eq!(german.size(1536), "1,5 KiB");
+        assert_eq!(german.speed(3.0 * 1024.0 * 1024.0), "3,0 MiB/s");
+        assert_eq!(Locale::from_name("en_US.UTF-8").size(1536), "1.5 KiB");
+        assert_eq!(Locale::from_name("C"), Locale::ENGLISH);
+        assert_eq!(Locale::ENGLISH.size(512), "512 B");
+
+        assert_eq!(format::duration(Duration::from_secs(75)), "01:15");
+        assert_eq!(format::duration(Duration::from_secs(3725)), "1:02:05");
+        assert_eq!(format::percent(0.425), " 42%");
+
+        let now = SystemTime::now();
+        let ago = |seconds: u64| format::relative(now - Duration::from_secs(seconds), now);
+        assert_eq!(ago(20), "just now");
+        assert_eq!(ago(5 * 60), "5 min ago");
+        assert_eq!(ago(3 * 3600), "3 h ago");
+        assert_eq!(ago(30 * 3600), "yesterday");
+        assert_eq!(ago(4 * 86400), "4 days ago");
+        assert_eq!(ago(30 * 86400).len(), "2026-10-17".len());
+    }
+
+    #[test]
+    fn test_remote_listing_generations_and_refresh() {
+        use std::path::Path;
+
+        let mut app = create_test_app();
+        app.state = AppState::Download;
+        app.current_path = PathBuf::new();
+        app.remote_listing.on_demand = true;
+        let docs = shared_item("/share/docs", "docs", true);
+        assert_eq!(app.due_listings(), [(PathBuf::new(), false)]);
+        app.apply_listing(PathBuf::new(), false, vec![docs.clone()]);
+        assert!(app.due_listings().is_empty());
+
+        // Pressing r lists the directory again, keeping what was shown meanwhile
+        app.refresh_remote_directory();
+        assert_eq!(app.due_listings(), [(PathBuf::new(), false)]);
+        assert_eq!(app.directory_items.len(), 1);
+        app.apply_listing(PathBuf::new(), false, vec![docs.clone()]);
+        assert!(app.remote_listing.is_listed(Path::new("")));
+
+        // A listing asked for before the share changed is fetched again once it arrives
+        app.remote_listing.invalidate();
+        assert_eq!(app.due_listings(), [(PathBuf::new(), false)]);
+        app.remote_listing.invalidate();
+        app.apply_listing(PathBuf::new(), false, vec![docs.clone()]);
+        assert!(!app.remote_listing.is_listed(Path::new("")));
+        assert_eq!(app.due_listings(), [(PathBuf::new(), false)]);
+        app.apply_listing(PathBuf::new(), false, vec![docs]);
+        assert!(app.remote_listing.is_listed(Path::new("")));
+    }
+
+    #[test]
+    fn test_selected_download_size_and_space() {
+        let mut app = create_test_app();
+        app.state = AppState::Download;
+        let docs = shared_item("/share/docs", "docs", true);
+        let mut small = shared_item("/share/docs/a.txt", "docs/a.txt", false);
+        small.size = 1024;
+        let mut other = shared_item("/share/docs/b.txt", "docs/b.txt", false);
+        other.size = 512;
+        app.all_shared_items = vec![docs, small, other];
+        app.items_to_download = [
+            PathBuf::from("/share/docs"),
+            PathBuf::from("/share/docs/a.txt"),
+            PathBuf::from("/share/docs/b.txt"),
+        ]
+        .into();
+        // Directories only contribute their files
+        assert_eq!(app.selected_download(), (2, 1536));
+        assert_eq!(app.download_space_shortfall(), None);
+
+        let mut huge = shared_item("/share/huge.iso", "huge.iso", false);
+        huge.size = u64::MAX / 2;
+        app.items_to_download.insert(huge.path.clone());
+        app.all_shared_items.push(huge);
+        if crate::plan::available_space(&std::env::current_dir().unwrap()).is_some() {
+            assert!(app.download_space_shortfall().is_some());
+        }
+    }
+
+    #[test]
+    fn test_share_codes() {
+        use crate::service::share_code;
+        use libp2p::{multiaddr::Protocol, multihash::Multihash, Multiaddr};
+
+        // A host always has the same code, nobody else has it
+        let host = PeerId::random();
+        let code = share_code::of(&host);
+        assert_eq!(share_code::of(&host), code);
+        assert_eq!(share_code::parse(&code), Ok(code.clone()));
+        assert!(share_code::belongs_to(&code, &host));
+        assert!(!share_code::belongs_to(&code, &PeerId::random()));
+        assert_eq!(
+            share_code::parse(" 7 Guitar-SUNSET otter Cedar-walnut ").as_deref(),
+            Ok("7-guitar-sunset-otter-cedar-walnut")
+        );
+        assert!(share_code::parse("7-guitar-sunset").is_err());
+        assert!(share_code::parse("0-guitar-sunset-otter-cedar-walnut").is_err());
+        assert!(share_code::parse("7-guitar-sunrise-otter-cedar-walnut").is_err());
+
+        // Keys are SHA2-256 multihashes, as public DHT nodes expect, one per code
+        let key = share_code::provider_key(&code);
+        let multihash = Multihash::<64>::from_bytes(&key.to_vec()).unwrap();
+        assert_eq!((multihash.code(), multihash.size()), (0x12, 32));
+        assert_eq!(key, share_code::provider_key(&code));
+        assert_ne!(
+            key,
+            share_code::provider_key(&share_code::of(&PeerId::random()))
+        );
+
+        // Only the peer the code belongs to answers for it
+        let stranger = PeerId::random();
+        assert_eq!(
+            share_code::host(&code, [stranger, host, host]),
+            Ok(Some(host))
+        );
+        assert_eq!(share_code::host(&code, [stranger]), Ok(None));
+
+        // Addresses reachable from further away come first, all of them lead to the host
+        let addresses: Vec<Multiaddr> = [
+            "/ip4/127.0.0.1/udp/4001/quic-v1",
+            "/ip4/192.168.1.20/tcp/4001",
+            "/ip4/203.0.113.5/tcp/4001",
+            "/ip4/203.0.113.5/tcp/4001",
+        ]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+        assert_eq!(
+            share_code::addresses(host, &addresses),
+            [2, 1, 0]
+                .map(|i| addresses[i].clone().with(Protocol::P2p(host)))
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_identity_from_seed() {
+        use crate::service::identity;
+
+        let phrase = identity::new_phrase();
+        let seed = identity::parse_seed(phrase.expose()).unwrap();
+        let peer_id = |seed| identity::keypair(seed).unwrap().public().to_peer_id();
+        // The same phrase gives the same peer ID every time, another one a different ID
+        assert_eq!(peer_id(&seed), peer_id(&seed));
+        assert_ne!(peer_id(&seed), peer_id(&identity::new_phrase()));
+
+        let error = identity::parse_seed("correct horse battery staple").unwrap_err();
+        assert!(error.contains("BIP39"));
+        assert!(!error.contains("correct horse"));
+    }
+
+    #[test]
+    fn test_peer_version_in_status_line() {
+        use crate::service::greeting::{AuthRequirement, Greeting};
+
+        let mut app = snapshot_app(false);
+        let host = PeerId::random();
+        app.connection_state = ConnectionState::Connected;
+        app.connected_peer_id = Some(host);
+        app.connection_quality = Some((90, std::time::Duration::from_millis(42)));
+        assert_eq!(app.connected_version(), None);
+
+        app.peer_agents.insert(host, "junkanoo/0.3.1".to_string());
+        assert_eq!(app.connected_version().as_deref(), Some("v0.3.1"));
+        app.peer_greeting = Some((
+            host,
+            Greeting::new(Some("studio".into()), None, AuthRequirement::None),
+        ));
+        let screen = render_snapshot(&app);
+        assert!(screen.contains("Connected to studio (v0.3.1)"));
+        assert!(screen.contains("42ms"));
+
+        // Other software is shown as it introduced itself
+        app.peer_agents.insert(host, "rust-libp2p/0.56".to_string());
+        assert_eq!(app.connected_version().as_deref(), Some("rust-libp2p/0.56"));
+    }
+
+    #[tokio::test]
+    async fn test_share_code_at_rendezvous_point() {
+        use crate::service::node::{Event, NodeConfig};
+        use crate::service::share_code;
+        use futures::StreamExt;
+        use libp2p::multiaddr::Protocol;
+        use libp2p::{identify, noise, rendezvous, swarm::NetworkBehaviour, tcp, yamux};
+
+        #[derive(NetworkBehaviour)]
+        struct Point {
+            rendezvous: rendezvous::server::Behaviour,
+            identify: identify::Behaviour,
+        }
+        let mut point = libp2p::SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .unwrap()
+            .with_behaviour(|key| Point {
+                rendezvous: rendezvous::server::Behaviour::new(
+                    rendezvous::server::Config::default(),
+                ),
+                identify: identify::Behaviour::new(identify::Config::new(
+                    "/rendezvous/1.0.0".into(),
+                    key.public(),
+                )),
+            })
+            .unwrap()
+            .build();
+        point
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let point_address = loop {
+            if let libp2p::swarm::SwarmEvent::NewListenAddr { address, .. } =
+                point.select_next_some().await
+            {
+                break address.with_p2p(*point.local_peer_id()).unwrap();
+            }
+        };
+        tokio::spawn(async move { while point.next().await.is_some() {} });
+
+        // Codes are derived from the host's peer ID, so it has to be known up front
+        let seed = crate::service::identity::new_phrase();
+        let peer_id = crate::service::identity::keypair(&seed)
+            .unwrap()
+            .public()
+            .to_peer_id();
+        let code = share_code::of(&peer_id);
+        let unknown = share_code::of(&PeerId::random());
+        let (mut host, host_events, host_loop, host_id) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            identity_seed: Some(seed),
+            share_code: Some(code.clone()),
+            rendezvous: Some(point_address.clone()),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        let (mut downloader, downloader_events, downloader_loop, _) =
+            crate::service::node::new(&NodeConfig {
+                lan_only: true,
+                rendezvous: Some(point_address),
+                ..NodeConfig::default()
+            })
+            .unwrap();
+        tokio::spawn(host_loop.run());
+        tokio::spawn(downloader_loop.run());
+        tokio::spawn(downloader_events.for_each(|_| async {}));
+        let (published_sender, mut published) = futures::channel::mpsc::unbounded();
+        tokio::spawn(host_events.for_each(move |event| {
+            if matches!(event, Event::ShareCodePublished) {
+                let _ = published_sender.unbounded_send(());
+            }
+            async {}
+        }));
+
+        host.start_listening("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap())
+            .await
+            .unwrap();
+        downloader
+            .start_listening("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap())
+            .await
+            .unwrap();
+        published.next().await.unwrap();
+
+        // The code leads to the host without any DHT, an unknown one to nothing
+        let addresses = downloader.resolve_code(code).await.unwrap();
+        assert!(addresses
+            .iter()
+            .all(|address| address.iter().last() == Some(Protocol::P2p(host_id))));
+        downloader
+            .dial(host_id, addresses[0].clone())
+            .await
+            .unwrap();
+        assert!(downloader.resolve_code(unknown).await.is_err());
+
+        host.shutdown().await.unwrap();
+        downloader.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[allow(clippy::too_many_lines)]
+    async fn test_share_code_through_dht_node() {
+        use crate::service::node::{Event, NodeConfig};
+        use crate::service::share_code;
+        use futures::StreamExt;
+        use libp2p::multiaddr::Protocol;
+        use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+        use libp2p::{identify, kad, noise, tcp, yamux};
+
+        // A plain DHT node keeping what it is given, like the public ones
+        #[derive(NetworkBehaviour)]
+        struct DhtNode {
+            kademlia: kad::Behaviour<kad::store::MemoryStore>,
+            identify: identify::Behaviour,
+        }
+        let mut dht = libp2p::SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .unwrap()
+            .with_behaviour(|key| DhtNode {
+                kademlia: kad::Behaviour::new(
+                    key.public().to_peer_id(),
+                    kad::store::MemoryStore::new(key.public().to_peer_id()),
+                ),
+                identify: identify::Behaviour::new(identify::Config::new(
+                    "/ipfs/id/1.0.0".into(),
+                    key.public(),
+                )),
+            })
+            .unwrap()
+            .build();
+        dht.behaviour_mut()
+            .kademlia
+            .set_mode(Some(kad::Mode::Server));
+        dht.listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let dht_address = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = dht.select_next_some().await {
+                break address.with_p2p(*dht.local_peer_id()).unwrap();
+            }
+        };
+        let (provided_sender, mut provided) = futures::channel::mpsc::unbounded();
+        tokio::spawn(async move {
+            while let Some(event) = dht.next().await {
+                match event {
+                    SwarmEvent::Behaviour(DhtNodeEvent::Identify(identify::Event::Received {
+                        peer_id,
+                        info,
+                        ..
+                    })) => {
+                        for address in info.listen_addrs {
+                            dht.behaviour_mut().kademlia.add_address(&peer_id, address);
+                        }
+                    }
+                    SwarmEvent::Behaviour(DhtNodeEvent::Kademlia(kad::Event::InboundRequest {
+                        request: kad::InboundRequest::AddProvider { .. },
+                    })) => {
+                        let _ = provided_sender.unbounded_send(());
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let seed = crate::service::identity::new_phrase();
+        let peer_id = crate::service::identity::keypair(&seed)
+            .unwrap()
+            .public()
+            .to_peer_id();
+        let code = share_code::of(&peer_id);
+        let (mut host, host_events, host_loop, host_id) = crate::service::node::new(&NodeConfig {
+            bootnodes: vec![dht_address.clone()],
+            identity_seed: Some(seed),
+            share_code: Some(code.clone()),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        let (mut downloader, downloader_events, downloader_loop, _) =
+            crate::service::node::new(&NodeConfig {
+                bootnodes: vec![dht_address],
+                ..NodeConfig::default()
+            })
+            .unwrap();
+        tokio::spawn(host_loop.run());
+        tokio::spawn(downloader_loop.run());
+        tokio::spawn(downloader_events.for_each(|_| async {}));
+        let (published_sender, mut published) = futures::channel::mpsc::unbounded();
+        tokio::spawn(host_events.for_each(move |event| {
+            if matches!(event, Event::ShareCodePublished) {
+                let _ = published_sender.unbounded_send(());
+            }
+            async {}
+        }));
+
+        let timeout = std::time::Duration::from_secs(10);
+        host.start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        tokio::time::timeout(timeout, published.next())
+            .await
+            .unwrap()
+            .unwrap();
+        // The DHT node took the record, the downloader finds the host through it
+        tokio::time::timeout(timeout, provided.next())
+            .await
+            .unwrap()
+            .unwrap();
+        downloader
+            .start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let addresses = tokio::time::timeout(timeout, downloader.resolve_code(code))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!addresses.is_empty());
+        assert!(addresses
+            .iter()
+            .all(|address| address.iter().last() == Some(Protocol::P2p(host_id))));
+        downloader
+            .dial(host_id, addresses[0].clone())
+            .await
+            .unwrap();
+
+        // Nobody provides the code of another peer
+        let unknown = share_code::of(&PeerId::random());
+        assert!(
+            tokio::time::timeout(timeout, downloader.resolve_code(unknown))
+                .await
+                .unwrap()
+                .is_err()
+        );
+
+        host.shutdown().await.unwrap();
+        downloader.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn test_address_selector() {
+        use crate::service::node::AddressKind;
+        use libp2p::Multiaddr;
+
+        let addresses: Vec<Multiaddr> = [
+            "/ip4/127.0.0.1/tcp/4001",
+            "/ip4/192.168.1.20/udp/4001/quic-v1",
+            "/ip4/203.0.113.7/tcp/4001",
+        ]
+        .iter()
+        .map(|address| address.parse().unwrap())
+        .collect();
+        let kinds: Vec<AddressKind> = addresses.iter().map(AddressKind::of).collect();
+        assert_eq!(
+            kinds,
+            [AddressKind::Loopback, AddressKind::Lan, AddressKind::Public]
+        );
+        let relayed: Multiaddr = format!(
+            "/ip4/203.0.113.9/tcp/4001/p2p/{}/p2p-circuit",
+            PeerId::random()
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(AddressKind::of(&relayed), AddressKind::Relayed);
+
+        let mut app = snapshot_app(true);
+        app.listening_addrs = addresses;
+        app.show_transfers = true;
+        assert_eq!(
+            app.selected_address(),
+            Some(format!("/ip4/127.0.0.1/tcp/4001/p2p/{}", app.peer_id))
+        );
+
+        // Tab goes from the files to the transfers, the addresses and back
+        app.cycle_focus();
+        assert!(app.transfers_focused);
+        app.cycle_focus();
+        assert!(app.addresses_focused && !app.transfers_focused);
+
+        app.navigate_addresses(true);
+        app.navigate_addresses(true);
+        app.navigate_addresses(true);
+        assert_eq!(
+            app.selected_address(),
+            Some(format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", app.peer_id))
+        );
+        let screen = render_snapshot(&app);
+        assert!(screen.contains("public"));
+        assert!(screen.contains("loopback"));
+
+        app.cycle_focus();
+        assert!(!app.addresses_focused && !app.transfers_focused);
+    }
+
+    #[tokio::test]
+    async fn test_received_paths_stay_in_download_directory() {
+        use crate::service::protocol::{write_response, ResponseHeader, COMPRESSION_NONE};
+        use futures::io::{AsyncWriteExt, Cursor};
+
+        // A host answering with whatever path it likes
+        let malicious = |path: &str| {
+            let path = path.to_string();
+            async move {
+                let mut wire = Cursor::new(Vec::new());
+                let header = ResponseHeader {
+                    path,
+                    size: 4,
+                    compression: COMPRESSION_NONE,
+                    attributes: None,
+                };
+                write_response(&mut wire, &header).await.unwrap();
+                wire.write_all(b"evil").await.unwrap();
+                wire.set_position(0);
+                wire
+            }
+        };
+
+        let temp_dir = TempDir::new().unwrap();
+        let downloads = temp_dir.path().join("downloads");
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let receiver = FileReceiver::new().with_directory(Some(downloads.clone()));
+
+        let absolute = outside.join("absolute.txt");
+        for path in [
+            "../outside/parent.txt",
+            "docs/../../outside/nested.txt",
+            absolute.to_str().unwrap(),
+            "./dot.txt",
+            "",
+        ] {
+            let error = receiver
+                .receive_file(&mut malicious(path).await)
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("outside the download directory"));
+            assert!(receiver.destination(path).is_err());
+        }
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+
+        // Nor through a directory linked to somewhere else
+        #[cfg(unix)]
+        {
+            fs::create_dir_all(&downloads).unwrap();
+            std::os::unix::fs::symlink(&outside, downloads.join("linked")).unwrap();
+            assert!(receiver
+                .receive_file(&mut malicious("linked/through.txt").await)
+                .await
+                .is_err());
+            assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+        }
+
+        // Paths below the download directory are saved as before
+        let saved = receiver
+            .receive_file(&mut malicious("docs/fine.txt").await)
+            .await
+            .unwrap();
+        assert_eq!(saved.path, "docs/fine.txt");
+        assert_eq!(
+            fs::read_to_string(downloads.join("docs/fine.txt")).unwrap(),
+            "evil"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_share_changes_are_pushed() {
+        use crate::service::node::{Event, NodeConfig};
+        use crate::service::registry::ShareRegistry;
+        use futures::StreamExt;
+
+        let item = |path: &str, index: usize, size: u64| DirectoryItem {
+            index,
+            size,
+            ..shared_item(path, path.trim_start_matches("/share/"), false)
+        };
+        let first = vec![item("/share/a.txt", 0, 1), item("/share/b.txt", 1, 2)];
+        let second = vec![item("/share/a.txt", 0, 5), item("/share/c.txt", 1, 3)];
+
+        // Only what changed is sent, and applying it gives the new listing
+        let mut registry = ShareRegistry::default();
+        registry.replace(first.clone());
+        assert!(registry.update(first.clone()).is_none());
+        let update = registry.update(second.clone()).unwrap();
+        assert_eq!((update.base, update.version), (1, 2));
+        assert_eq!(update.added, second);
+        assert_eq!(update.removed, [PathBuf::from("/share/b.txt")]);
+        let mut listing = first.clone();
+        update.apply(&mut listing);
+        assert_eq!(listing, second);
+
+        // Selection changes notify the host loop, several of them only once
+        let mut app = App::new();
+        let (share_sender, mut share_changes) = tokio::sync::mpsc::channel(1);
+        app.share_sender = Some(share_sender);
+        app.unselect_all();
+        app.confirm_manifest_diff();
+        assert!(share_changes.try_recv().is_ok());
+        assert!(share_changes.try_recv().is_err());
+
+        // Greeted downloaders hear about the change without asking
+        let (mut host, host_events, host_loop, host_id) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        let (mut downloader, downloader_events, downloader_loop, _) =
+            crate::service::node::new(&NodeConfig {
+                lan_only: true,
+                ..NodeConfig::default()
+            })
+            .unwrap();
+        tokio::spawn(host_loop.run());
+        tokio::spawn(downloader_loop.run());
+        tokio::spawn(host_events.for_each(|_| async {}));
+        let (changed_sender, mut changed) = futures::channel::mpsc::unbounded();
+        tokio::spawn(downloader_events.for_each(move |event| {
+            if let Event::ShareChanged(update) = event {
+                let _ = changed_sender.unbounded_send(update);
+            }
+            async {}
+        }));
+
+        host.update_directory_items(first.clone()).await.unwrap();
+        host.start_listening("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap())
+            .await
+            .unwrap();
+        let address = loop {
+            if let Some(address) = host.get_listening_addrs().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        downloader.dial(host_id, address).await.unwrap();
+        assert!(downloader
+            .greet(host_id, None)
+            .await
+            .unwrap()
+            .greeting
+            .supports("updates"));
+        // Peers listing on demand get no items here, only the version updates apply to
+        let version = downloader.request_directory(host_id).await.unwrap().version;
+
+        host.update_directory_items(second.clone()).await.unwrap();
+        let update = tokio::time::timeout(std::time::Duration::from_secs(5), changed.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.base, version);
+        let mut listing = first;
+        update.apply(&mut listing);
+        assert_eq!(listing, second);
+
+        host.shutdown().await.unwrap();
+        downloader.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pushed_update_leaves_once_share_unclaimed() {
+        use crate::service::node::NodeConfig;
+        use futures::StreamExt;
+
+        let (mut host, host_events, host_loop, host_id) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            once: true,
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(host_loop.run());
+        tokio::spawn(host_events.for_each(|_| async {}));
+        host.update_directory_items(vec![shared_item("/share/a.txt", "a.txt", false)])
+            .await
+            .unwrap();
+        host.start_listening("/ip4/127.0.0.1/udp/0/quic-v1".parse().unwrap())
+            .await
+            .unwrap();
+        let address = loop {
+            if let Some(address) = host.get_listening_addrs().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+
+        // Only the first greets, so only it is told about changes
+        let mut downloaders = Vec::new();
+        for greets in [true, false] {
+            let (mut downloader, events, event_loop, _) = crate::service::node::new(&NodeConfig {
+                lan_only: true,
+                ..NodeConfig::default()
+            })
+            .unwrap();
+            tokio::spawn(event_loop.run());
+            tokio::spawn(events.for_each(|_| async {}));
+            downloader.dial(host_id, address.clone()).await.unwrap();
+            if greets {
+                downloader.greet(host_id, None).await.unwrap();
+            }
+            downloaders.push(downloader);
+        }
+
+        // Telling it about a change doesn't hand it the share
+        host.update_directory_items(vec![shared_item("/share/b.txt", "b.txt", false)])
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(downloaders[1].request_directory(host_id).await.is_ok());
+        // The first to ask claimed it, the other one is turned away
+        assert!(downloaders[0].request_directory(host_id).await.is_err());
+
+        host.shutdown().await.unwrap();
+        for mut downloader in downloaders {
+            downloader.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_with_mock_client() {
+        use crate::service::client::{Call, MockClient, NetworkClient};
+        use std::sync::Arc;
+
+        let mock = Arc::new(MockClient::default());
+        let mut app = create_test_app();
+        app.state = AppState::Download;
+        app.set_client(mock.clone());
+        let host = PeerId::random();
+        app.all_shared_items = vec![
+            shared_item("/share/docs", "docs", true),
+            shared_item("/share/docs/a.txt", "docs/a.txt", false),
+        ];
+        app.items_to_download = [
+            PathBuf::from("/share/docs"),
+            PathBuf::from("/share/docs/a.txt"),
+        ]
+        .into();
+
+        // Nothing is asked of the network before a host is connected
+        app.start_download().await;
+        assert!(mock.calls().is_empty());
+
+        app.connection_state = ConnectionState::Connected;
+        app.connected_peer_id = Some(host);
+        app.share_label = Some("Holiday photos".into());
+        mock.respond(
+            &Call::RequestFiles(host, Vec::new(), None, false),
+            Ok(Vec::<u8>::new()),
+        );
+        app.start_download().await;
+        assert_eq!(
+            mock.calls(),
+            [Call::RequestFiles(
+                host,
+                vec!["/share/docs/a.txt".to_string()],
+                Some(PathBuf::from("Holiday photos")),
+                false
+            )]
+        );
+        assert!(app
+            .items_being_downloaded
+            .contains(Path::new("/share/docs/a.txt")));
+
+        // Replies without a script fail, scripted ones come back in order
+        assert!(mock.request_directory(host).await.is_err());
+        mock.respond(
+            &Call::ResolveCode(String::new()),
+            Ok(Vec::<libp2p::Multiaddr>::new()),
+        );
+        mock.respond::<Vec<libp2p::Multiaddr>>(
+            &Call::ResolveCode(String::new()),
+            Err("no share".into()),
+        );
+        assert!(mock
+            .resolve_code("7-guitar-sunset".into())
+            .await
+            .unwrap()
+            .is_empty());
+        let error = mock
+            .resolve_code("7-guitar-sunset".into())
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "no share");
+
+        // Disconnecting tells the network from a spawned task
+        app.disconnect();
+        tokio::task::yield_now().await;
+        assert_eq!(mock.calls().last(), Some(&Call::Disconnect(host)));
+    }
+
+    #[tokio::test]
+    async fn test_loopback_benchmark() {
+        use crate::bench::{loopback_transfer, report, Sample};
+        use std::time::Duration;
+
+        let source = TempDir::new().unwrap();
+        let destination = TempDir::new().unwrap();
+        let path = source.path().join("data.bin");
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &contents).unwrap();
+
+        // Chunks that don't divide the file still deliver all of it, and again on top
+        for chunk_size in [7, 4096, 1024 * 1024] {
+            loopback_transfer(&path, destination.path(), chunk_size)
+                .await
+                .unwrap();
+            let received = destination.path().join(transfer_path(&path));
+            assert_eq!(fs::read(received).unwrap(), contents);
+        }
+
+        let samples = [
+            Sample {
+                path: "a.bin".into(),
+                bytes: 1024 * 1024,
+                elapsed: Duration::from_millis(500),
+            },
+            Sample {
+                path: "b.bin".into(),
+                bytes: 1024 * 1024,
+                elapsed: Duration::from_millis(1500),
+            },
+        ];
+        assert!((samples[0].throughput() - f64::from(2 * 1024 * 1024)).abs() < 1.0);
+        let report = report(&samples);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("a.bin"));
+        assert!(lines[2].contains("2.000s"));
+        assert!(lines[2].ends_with("2 files in total"));
+    }
+
+    #[test]
+    fn test_error_causes() {
+        use crate::service::error::JunkanooError;
+        use crate::service::node::NodeConfig;
+        use crate::service::secret::Secret;
+        use libp2p::request_response::OutboundFailure;
+
+        let timeout = JunkanooError::from(OutboundFailure::Timeout);
+        assert!(matches!(timeout, JunkanooError::Timeout(_)));
+        assert!(!timeout.is_connection_error());
+        assert!(timeout.explain().ends_with("try again"));
+
+        // Lost connections are retried, whatever layer noticed
+        let closed = JunkanooError::from(OutboundFailure::ConnectionClosed);
+        assert!(matches!(closed, JunkanooError::Dial(_)));
+        assert!(closed.is_connection_error());
+        let eof = JunkanooError::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert!(eof.is_connection_error());
+        assert!(!JunkanooError::Cancelled.is_connection_error());
+
+        assert!(matches!(
+            JunkanooError::from(OutboundFailure::UnsupportedProtocols),
+            JunkanooError::Protocol(_)
+        ));
+        assert!(
+            JunkanooError::from(String::from_utf8(vec![0xff]).unwrap_err())
+                .explain()
+                .contains("update both")
+        );
+        // Without a hint the message stands alone
+        let rejected = JunkanooError::Rejected("not shared".into());
+        assert_eq!(rejected.explain(), "Request rejected by host: not shared");
+
+        // A node that can't be set up says so, e.g. with an identity phrase that isn't one
+        let Err(error) = crate::service::node::new(&NodeConfig {
+            identity_seed: Some(Secret::from("not a phrase")),
+            ..NodeConfig::default()
+        }) else {
+            panic!("a node started without a valid identity");
+        };
+        assert!(matches!(error, JunkanooError::Setup(_)), "{error}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dial_falls_back_to_other_addresses() {
+        use crate::service::client::{Call, MockClient};
+        use crate::service::dial::{dial_any, DialPolicy};
+
+        let host = PeerId::random();
+        let lan: libp2p::Multiaddr = "/ip4/192.168.1.2/tcp/4001".parse().unwrap();
+        let public: libp2p::Multiaddr = "/ip4/203.0.113.7/tcp/4001".parse().unwrap();
+        let addresses = [lan.clone(), public.clone()];
+        let policy = DialPolicy {
+            attempt_timeout: std::time::Duration::from_secs(1),
+            rounds: 2,
+        };
+
+        // The first address that connects is the one reported
+        let mock = MockClient::default();
+        mock.respond::<()>(&Call::Dial(host, lan.clone()), Err("unreachable".into()));
+        assert_eq!(
+            dial_any(&mock, host, &addresses, policy).await.unwrap(),
+            public
+        );
+        assert_eq!(
+            mock.calls(),
+            [
+                Call::Dial(host, lan.clone()),
+                Call::Dial(host, public.clone())
+            ]
+        );
+
+        // Every round goes through all addresses, the last error is kept
+        let mock = MockClient::default();
+        for error in ["one", "two", "three", "four"] {
+            mock.respond::<()>(&Call::Dial(host, lan.clone()), Err(error.into()));
+        }
+        let error = dial_any(&mock, host, &addresses, policy).await.unwrap_err();
+        assert_eq!(error.to_string(), "four");
+        assert_eq!(mock.calls().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_request_times_out() {
+        use crate::service::error::JunkanooError;
+        use crate::service::node::NodeConfig;
+        use crate::service::protocol::{
+            DisplayRequest, DisplayResponse, JUNKANOO_REQUEST_RESPONSE_PROTOCOL,
+        };
+        use futures::StreamExt;
+        use libp2p::request_response::{self, ProtocolSupport};
+        use libp2p::swarm::SwarmEvent;
+        use std::time::Duration;
+
+        // A host that takes directory requests and never answers them
+        let mut silent = libp2p::SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                libp2p::tcp::Config::default(),
+                libp2p::noise::Config::new,
+                libp2p::yamux::Config::default,
+            )
+            .unwrap()
+            .with_behaviour(|_| {
+                request_response::cbor::Behaviour::<DisplayRequest, DisplayResponse>::new(
+                    [(JUNKANOO_REQUEST_RESPONSE_PROTOCOL, ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                )
+            })
+            .unwrap()
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
+        silent
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .unwrap();
+        let address = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = silent.select_next_some().await {
+                break address;
+            }
+        };
+        let host = *silent.local_peer_id();
+        tokio::spawn(async move {
+            #[allow(clippy::collection_is_never_read)]
+            let mut unanswered = Vec::new();
+            loop {
+                if let SwarmEvent::Behaviour(request_response::Event::Message {
+                    message: request_response::Message::Request { channel, .. },
+                    ..
+                }) = silent.select_next_some().await
+                {
+                    unanswered.push(channel);
+                }
+            }
+        });
+
+        let (mut client, events, event_loop, _) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            request_timeout: Some(Duration::from_millis(200)),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(event_loop.run());
+        tokio::spawn(events.for_each(|_| async {}));
+        client.dial(host, address).await.unwrap();
+
+        let error = tokio::time::timeout(Duration::from_secs(5), client.request_directory(host))
+            .await
+            .expect("the request to fail on its own")
+            .unwrap_err();
+        assert!(matches!(error, JunkanooError::Timeout(_)), "{error}");
+        assert!(error.explain().contains("try again"));
+    }
+
+    #[tokio::test]
+    async fn test_host_survives_requester_leaving_before_the_listing() {
+        use crate::service::node::{Event, NodeConfig};
+        use crate::service::protocol::{
+            DisplayRequest, DisplayResponse, JUNKANOO_REQUEST_RESPONSE_PROTOCOL,
+        };
+        use futures::StreamExt;
+        use libp2p::request_response::{self, ProtocolSupport};
+        use libp2p::swarm::SwarmEvent;
+        use std::time::Duration;
+        use tokio::sync::oneshot;
+
+        let (mut host, host_events, event_loop, host_id) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(event_loop.run());
+        // The host's events are taken up to the first connection, then held back until the
+        // requester is gone. The event loop waits for them to be taken in the meantime.
+        let (connected_sender, connected) = oneshot::channel();
+        let (resume, resumed) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let mut host_events = Box::pin(host_events);
+            while let Some(event) = host_events.next().await {
+                if matches!(event, Event::PeerConnected(_)) {
+                    break;
+                }
+            }
+            let _ = connected_sender.send(());
+            let _ = resumed.await;
+            host_events.for_each(|_| async {}).await;
+        });
+        host.start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let address = loop {
+            if let Some(address) = host.get_listening_addrs().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let new_peer = || {
+            libp2p::SwarmBuilder::with_new_identity()
+                .with_tokio()
+                .with_tcp(
+                    libp2p::tcp::Config::default(),
+                    libp2p::noise::Config::new,
+                    libp2p::yamux::Config::default,
+                )
+                .unwrap()
+                .with_behaviour(|_| {
+                    request_response::cbor::Behaviour::<DisplayRequest, DisplayResponse>::new(
+                        [(JUNKANOO_REQUEST_RESPONSE_PROTOCOL, ProtocolSupport::Full)],
+                        request_response::Config::default(),
+                    )
+                })
+                .unwrap()
+                .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+                .build()
+        };
+        let connect = async |peer: &mut libp2p::Swarm<_>| {
+            peer.dial(address.clone()).unwrap();
+            tokio::time::timeout(Duration::from_secs(1), async {
+                loop {
+                    if let SwarmEvent::ConnectionEstablished { .. } = peer.select_next_some().await
+                    {
+                        break;
+                    }
+                }
+            })
+            .await
+            .is_ok()
+        };
+        let mut requester = new_peer();
+        assert!(connect(&mut requester).await);
+        connected.await.unwrap();
+        // Other peers connect until the host is held telling about one of them, it no
+        // longer takes connections then
+        #[allow(clippy::collection_is_never_read)]
+        let mut others = Vec::new();
+        loop {
+            let mut other = new_peer();
+            let accepted = connect(&mut other).await;
+            others.push(other);
+            if !accepted {
+                break;
+            }
+        }
+
+        requester
+            .behaviour_mut()
+            .send_request(&host_id, DisplayRequest);
+        let _ = tokio::time::timeout(Duration::from_millis(500), async {
+            loop {
+                requester.select_next_some().await;
+            }
+        })
+        .await;
+        // The requester gives up before the host got to answer
+        drop(requester);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        resume.send(()).unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(
+            host.get_listening_addrs().await.is_ok(),
+            "the host's event loop stopped"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_share_expires() {
+        use crate::run::network::expire_share;
+        use crate::service::node::{Event, NodeConfig};
+        use futures::StreamExt;
+        use parking_lot::Mutex;
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        let (mut host, host_events, host_loop, host_id) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(host_loop.run());
+        tokio::spawn(host_events.for_each(|_| async {}));
+        host.start_listening("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .await
+            .unwrap();
+        let address = loop {
+            if let Some(address) = host.get_listening_addrs().await.unwrap().pop() {
+                break address;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let (mut downloader, events, event_loop, _) = crate::service::node::new(&NodeConfig {
+            lan_only: true,
+            request_timeout: Some(Duration::from_millis(200)),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+        tokio::spawn(event_loop.run());
+        let (disconnected_sender, mut disconnected) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(events.for_each(move |event| {
+            if matches!(event, Event::PeerDisconnected()) {
+                let _ = disconnected_sender.send(());
+            }
+            async {}
+        }));
+        downloader.dial(host_id, address.clone()).await.unwrap();
+        assert!(downloader.request_directory(host_id).await.is_ok());
+
+        let app = Arc::new(Mutex::new(create_test_app()));
+        let ttl = Duration::from_secs(60);
+        let expires_at = Instant::now() + ttl;
+        tokio::time::pause();
+        let expiring = tokio::spawn(expire_share(
+            Arc::new(host.clone()),
+            expires_at,
+            app.clone(),
+        ));
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(!app.lock().should_quit);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        tokio::time::resume();
+        tokio::time::timeout(Duration::from_secs(5), expiring)
+            .await
+            .expect("the share to expire")
+            .unwrap();
+        assert!(app.lock().should_quit);
+
+        // The host dropped the downloader and no longer answers it
+        tokio::time::timeout(Duration::from_secs(5), disconnected.recv())
+            .await
+            .expect("the host to close the connection");
+        let _ = downloader.dial(host_id, address).await;
+        let listing = tokio::time::timeout(
+            Duration::from_secs(5),
+            downloader.request_directory(host_id),
+        )
+        .await
+        .expect("the request to fail on its own");
+        assert!(listing.is_err());
+    }
+
+    #[test]
+    fn test_configured_keys() {
+        use crate::config::Config;
+        use crate::keys::{Action, KeyBindings};
+        use crossterm::event::KeyCode;
+
+        let defaults = KeyBindings::default();
+        assert_eq!(defaults.action(KeyCode::Char('y')), Some(Action::Select));
+        assert_eq!(defaults.action(KeyCode::Char('Y')), None);
+        assert_eq!(defaults.label(Action::Open), "Enter");
+
+        let config = Config::parse(
+            "[keys]\ndown = \"j\"\nup = \"k\"\nselect = \"space\"\ncopy-address = \"F2\"\n",
+        )
+        .unwrap();
+        let keys = &config.keys;
+        assert_eq!(keys.action(KeyCode::Char('j')), Some(Action::Down));
+        assert_eq!(keys.action(KeyCode::Down), None);
+        assert_eq!(keys.action(KeyCode::Char(' ')), Some(Action::Select));
+        assert_eq!(keys.action(KeyCode::F(2)), Some(Action::CopyAddress));
+        // Actions left out keep their default
+        assert_eq!(keys.action(KeyCode::Char('d')), Some(Action::Download));
+
+        // The title bar shows the keys as bound
+        let mut app = snapshot_app(false);
+        app.keys = config.keys.clone();
+        let screen = render_snapshot(&app);
+        assert!(screen.contains("KJ Navigate"), "{screen}");
+        assert!(screen.contains("Open dir | Space"), "{screen}");
+
+        // Unknown keys and actions, and a key doing two things, are refused
+        assert!(Config::parse("[keys]\nselect = \"hyper\"\n").is_err());
+        assert!(Config::parse("[keys]\nfly = \"f\"\n").is_err());
+        let error = Config::parse("[keys]\nselect = \"d\"\n").unwrap_err();
+        assert!(error.contains("D is bound to two actions"), "{error}");
+    }
+
+    #[test]
+    fn test_theme_config() {
+        use crate::cli::theme::{Preset, Theme};
+        use crate::config::Config;
+        use ratatui::style::Color;
+
+        let config =
+            Config::parse("[theme]\npreset = \"light\"\ndirectory = \"#0000ff\"\n").unwrap();
+        assert_eq!(config.theme.preset, Preset::Light);
+        let theme = Theme::new(&config.theme, false);
+        assert_eq!(theme.directory, Color::Rgb(0, 0, 255));
+        // Colours left out come from the preset
+        assert_eq!(theme.accent, Theme::preset(Preset::Light).accent);
+        assert!(Config::parse("[theme]\naccent = \"sparkly\"\n").is_err());
+        assert!(Config::parse("[theme]\npreset = \"neon\"\n").is_err());
+
+        // NO_COLOR wins over the config, and nothing drawn keeps a colour
+        let mut app = snapshot_app(false);
+        app.theme = Theme::new(&config.theme, true);
+        assert!(app.theme.monochrome);
+        let mut terminal =
+            ratatui::Terminal::new(ratatui::backend::TestBackend::new(140, 32)).unwrap();
+        terminal
+            .draw(|frame| crate::cli::ui::render(frame, &app))
+            .unwrap();
+        assert!(terminal
+            .backend()
+            .buffer()
+            .content
+            .iter()
+            .all(|cell| cell.fg == Color::Reset && cell.bg == Color::Reset));
+    }
+
+    #[test]
+    fn test_plain_output() {
+        use crate::app::ConnectionState;
+        use crate::cli::plain::{find_item, Command, Reporter};
+
+        assert_eq!(
+            Command::parse("cd holiday"),
+            Ok(Command::Open("holiday".to_string()))
+        );
+        assert_eq!(Command::parse("cd .."), Ok(Command::Back));
+        assert_eq!(
+            Command::parse("select  my notes.txt "),
+            Ok(Command::Select("my notes.txt".to_string()))
+        );
+        assert_eq!(
+            Command::parse("Yes"),
+            Ok(Command::Answer("yes".to_string()))
+        );
+        assert!(Command::parse("select").is_err());
+        assert!(Command::parse("dance").is_err());
+
+        let mut app = snapshot_app(false);
+        assert_eq!(find_item(&app, "holiday/"), Some(0));
+        assert_eq!(find_item(&app, "beach.jpg"), Some(1));
+
+        // The listing comes first, as plain text a screen reader can read out
+        let mut reporter = Reporter::default();
+        let lines = reporter.lines(&app);
+        assert_eq!(lines[0], "Contents of /srv/photos:");
+        assert_eq!(lines[1], "  holiday/ directory");
+        assert!(lines[2].starts_with("    beach.jpg "), "{lines:?}");
+        assert!(lines.iter().all(|line| line.is_ascii()), "{lines:?}");
+        // Each change is printed once
+        assert!(reporter.lines(&app).is_empty());
+
+        app.connection_state = ConnectionState::Connected;
+        app.transfers.queue(vec!["notes.txt".to_string()]);
+        app.transfers.progress("notes.txt", 600, 1_200);
+        app.confirming_download = true;
+        let lines = reporter.lines(&app);
+        assert_eq!(lines.len(), 3, "{lines:?}");
+        assert!(lines[0].starts_with("Connected to "));
+        assert!(lines[1].starts_with("notes.txt: 50% of "), "{lines:?}");
+        assert!(lines[2].ends_with("? yes or no"), "{lines:?}");
+
+        // Progress within the same tenth isn't repeated, finishing is
+        app.transfers.progress("notes.txt", 650, 1_200);
+        assert!(reporter.lines(&app).is_empty());
+        app.transfers.complete("notes.txt");
+        assert_eq!(reporter.lines(&app), ["notes.txt: done"]);
+    }
+
+    #[test]
+    fn test_desktop_notices() {
+        use crate::desktop::Notice;
+        use crate::report::{DeliveredFile, SessionReport};
+
+        let files = vec!["holiday/beach.jpg".to_string()];
+        let notice = Notice::downloaded(&files, Some("Chad's laptop"));
+        assert_eq!(notice.summary, "Download finished");
+        assert_eq!(notice.body, "holiday/beach.jpg from Chad's laptop");
+        let files = vec!["a.txt".to_string(), "b.txt".to_string()];
+        assert_eq!(Notice::downloaded(&files, None).body, "2 files");
+        assert_eq!(Notice::download_failed(&files).summary, "Download failed");
+
+        // What a downloader took from the host is summed up per peer
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+        let mut report = SessionReport::default();
+        for (peer_id, size) in [(alice, 1_000), (bob, 5), (alice, 500)] {
+            report.add(DeliveredFile {
+                path: "notes.txt".to_string(),
+                size,
+                hash: None,
+                peer_id,
+                peer: None,
+                finished_at: std::time::SystemTime::now(),
+            });
+        }
+        assert_eq!(report.delivered(&alice), (2, 1_500));
+        let notice = Notice::served("alice", 2, 1_500);
+        assert_eq!(notice.summary, "alice finished downloading");
+        assert!(notice.body.starts_with("2 files, "), "{}", notice.body);
+
+        let matches = crate::cli::commands::get_args()
+            .try_get_matches_from(["junkanoo", "--notify", "share"])
+            .unwrap();
+        assert!(matches.get_flag("notify"));
+    }
+
+    #[test]
+    fn test_download_summary() {
+        use crate::transfers::{TransferHistory, TransferManager, TransferSummary};
+
+        let mut transfers = TransferManager::default();
+        assert!(transfers.take_summary().is_none());
+        transfers.queue(
+            ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"]
+                .map(String::from)
+                .to_vec(),
+        );
+        transfers.progress("a.txt", 400, 1_000);
+        transfers.complete("a.txt");
+        transfers.up_to_date("b.txt");
+        transfers.fail("c.txt", "host went away".to_string());
+        transfers.corrupted("d.txt");
+        let summary = transfers.take_summary().unwrap();
+        assert_eq!(
+            (
+                summary.transferred,
+                summary.skipped,
+                summary.failed,
+                summary.mismatched
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(summary.bytes, 1_000);
+        assert!(summary
+            .headline()
+            .starts_with("Downloaded 1 files, 1 skipped, 2 failed"));
+        let table = summary.table();
+        assert!(table.contains("Hash mismatches 1"), "{table}");
+        assert!(table.contains("Average speed"), "{table}");
+
+        // Only what finished since is counted the next time
+        assert!(transfers.take_summary().is_none());
+        transfers.complete("e.txt");
+        let later = transfers.take_summary().unwrap();
+        assert_eq!((later.transferred, later.files()), (1, 1));
+        let mut session = summary.clone();
+        session.merge(&later);
+        assert_eq!((session.transferred, session.files()), (2, 5));
+
+        // Summaries share the history file with the records without being mistaken for one
+        let dir = TempDir::new().unwrap();
+        let history = dir.path().join("history.jsonl");
+        TransferHistory::append(&history, &summary).unwrap();
+        let record = transfers.complete("a.txt").unwrap();
+        TransferHistory::append(&history, &record).unwrap();
+        let lines = fs::read_to_string(&history).unwrap();
+        let stored: TransferSummary = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+        assert_eq!(stored, summary);
+        let loaded = TransferHistory::load(&history);
+        assert_eq!(loaded.previous("a.txt"), Some(&record));
+    }
+
+    #[test]
+    fn test_ls_conflicts_with_json() {
+        assert!(crate::cli::commands::get_args()
+            .try_get_matches_from(["junkanoo", "--json", "ls", "/ip4/127.0.0.1/tcp/1"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_get_progress_bars() {
+        use crate::get::{self, ProgressBars};
+        use crate::service::node::Event;
+
+        let half = get::bar("a.bin", 512, 1024);
+        assert!(
+            half.starts_with("[############------------]  50%"),
+            "{half}"
+        );
+        assert!(half.ends_with("  a.bin"));
+        let mut bars = ProgressBars::default();
+        assert_eq!(bars.line(&Event::PeerDisconnected()), None);
+    }
+
+    #[test]
+    fn test_pipe_spool_and_deliver() {
+        use crate::pipe;
+
+        let scratch = TempDir::new().unwrap();
+        let directory = scratch.path().join("share");
+        let mut stdin = std::io::Cursor::new(b"piped bytes".to_vec());
+        let spooled = pipe::spool(&mut stdin, &directory, pipe::STDIN_NAME).unwrap();
+        assert_eq!(spooled, directory.join("stdin"));
+        assert_eq!(fs::read(&spooled).unwrap(), b"piped bytes");
+
+        let error = pipe::spool(&mut std::io::empty(), &directory, "../escape").unwrap_err();
+        assert!(error.to_string().contains("not a file name"), "{error}");
+        assert!(!scratch.path().join("escape").exists());
+
+        let delivered = scratch.path().join("out.bin");
+        pipe::deliver(&spooled, &delivered).unwrap();
+        assert_eq!(fs::read(&delivered).unwrap(), b"piped bytes");
+
+        let args = crate::cli::commands::get_args();
+        let matches = args
+            .clone()
+            .try_get_matches_from(["junkanoo", "share", "-", "--as", "backup.tar"])
+            .unwrap();
+        let share = matches.subcommand_matches("share").unwrap();
+        assert_eq!(share.get_one::<String>("FILE_PATH").unwrap(), "-");
+        assert_eq!(share.get_one::<String>("as").unwrap(), "backup.tar");
+        let address = format!("/ip4/127.0.0.1/tcp/1/p2p/{}", PeerId::random());
+        assert!(args
+            .try_get_matches_from(["junkanoo", "get", &address, "a", "-O", "-", "-o", "dir"])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_partial_file() {
+        use crate::service::utils::partial_path;
+
+        let source_dir = TempDir::new().unwrap();
+        let source = source_dir.path().join("movie.bin");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content).unwrap();
+        let target = TempDir::new().unwrap();
+        let receiver = || FileReceiver::new().with_directory(Some(target.path().to_path_buf()));
+        let relative = source.strip_prefix("/").unwrap().to_str().unwrap();
+        let destination = receiver().destination(relative).unwrap();
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+
+        // What a broken off download left, under a name of its own, not that of a file
+        // the host may share as well
+        fs::write(partial_path(&destination), &content[..40_000]).unwrap();
+        let neighbour = destination.with_file_name("movie.bin.part");
+        fs::write(&neighbour, b"shared too").unwrap();
+        let (host, downloader) = tokio::io::duplex(64 * 1024);
+        let transfer = FileTransfer::new(&source);
+        let sending =
+            tokio::spawn(async move { transfer.stream_delta(&mut StreamWrapper(host)).await });
+        let saved = receiver()
+            .receive_delta(&mut StreamWrapper(downloader))
+            .await
+            .unwrap();
+        sending.await.unwrap().unwrap();
+        assert_eq!(target.path().join(&saved.path), destination);
+        assert_eq!(fs::read(&destination).unwrap(), content);
+        assert!(!partial_path(&destination).exists());
+        assert_eq!(fs::read(&neighbour).unwrap(), b"shared too");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_archive_leaves_out_escaping_links() {
+        use crate::service::archive::{Archive, ArchiveSource, ArchiveTransfer};
+        use crate::service::protocol::read_response;
+        use futures::io::Cursor;
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new().unwrap();
+        let docs = dir.path().join("docs");
+        fs::create_dir(&docs).unwrap();
+        fs::write(docs.join("a.txt"), b"a").unwrap();
+        let mut sources = vec![
+            ArchiveSource {
+                name: "docs".to_string(),
+                path: docs.clone(),
+                is_dir: true,
+                link_target: None,
+            },
+            ArchiveSource {
+                name: "docs/a.txt".to_string(),
+                path: docs.join("a.txt"),
+                is_dir: false,
+                link_target: None,
+            },
+        ];
+        for (name, target) in [
+            ("inside", "a.txt"),
+            ("up", "../secret"),
+            ("abs", "/etc/passwd"),
+        ] {
+            symlink(target, docs.join(name)).unwrap();
+            sources.push(ArchiveSource {
+                name: format!("docs/{name}"),
+                path: docs.join(name),
+                is_dir: false,
+                link_target: Some(PathBuf::from(target)),
+            });
+        }
+
+        let archive = Archive::new(&sources).unwrap();
+        let mut wire = Cursor::new(Vec::new());
+        ArchiveTransfer::new("docs.tar".to_string(), archive)
+            .stream_archive(&mut wire)
+            .await
+            .unwrap();
+        let mut wire = Cursor::new(wire.into_inner());
+        let header = read_response(&mut wire).await.unwrap();
+        let body = &wire.get_ref()[usize::try_from(wire.position()).unwrap()..];
+        assert_eq!(body.len(), header.size);
+        let entries: Vec<(String, Option<String>)> = tar::Archive::new(body)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let link = entry.link_name().unwrap();
+                (
+                    entry.path().unwrap().to_string_lossy().to_string(),
+                    link.map(|link| link.to_string_lossy().to_string()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("docs".to_string(), None),
+                ("docs/a.txt".to_string(), None),
+                ("docs/inside".to_string(), Some("a.txt".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_share_window_conflicts_with_expires() {
+        let args = crate::cli::commands::get_args();
+        let share = |extra: &[&str]| {
+            args.clone()
+                .try_get_matches_from(["junkanoo", "share", "."].iter().chain(extra))
+        };
+
+        // Both name when the share stops, only one of them may
+        let error = share(&["--expires", "30m", "--window", "1h"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(share(&["--expires", "30m"]).is_ok());
+        assert!(share(&["--start-at", "22:00", "--window", "1h"]).is_ok());
+    }
 }
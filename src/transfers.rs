@@ -0,0 +1,622 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::service::utils::Conflict;
+
+/// Weight of the newest sample in the smoothed speed, lower is steadier.
+const SPEED_SMOOTHING: f64 = 0.2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferState {
+    Queued,
+    Active,
+    Completed,
+    /// Skipped, the destination already held the same content.
+    UpToDate,
+    /// Skipped, the destination held other content that was kept.
+    Kept,
+    /// Held between chunks until resumed.
+    Paused,
+    /// Stopped from the transfer list, what arrived so far was kept.
+    Cancelled,
+    Failed(String),
+    /// Arrived, but with other content than the host published.
+    Corrupted,
+}
+
+impl TransferState {
+    /// Whether the transfer is over, one way or another.
+    #[must_use]
+    pub const fn is_finished(&self) -> bool {
+        !matches!(self, Self::Queued | Self::Active | Self::Paused)
+    }
+}
+
+/// A single file requested from the host.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub path: String,
+    pub state: TransferState,
+    pub bytes: u64,
+    pub total: Option<u64>,
+    /// What was done about a file already at the destination, see [`Conflict`].
+    pub note: Option<String>,
+    started_at: Option<Instant>,
+    finished_at: Option<Instant>,
+    /// Already counted in a [`TransferSummary`].
+    summarized: bool,
+    /// Exponentially weighted moving average of the speed, in bytes per second.
+    smoothed_speed: Option<f64>,
+    /// When the last progress report arrived and how many bytes it had.
+    last_sample: Option<(Instant, u64)>,
+}
+
+impl Transfer {
+    const fn new(path: String) -> Self {
+        Self {
+            path,
+            state: TransferState::Queued,
+            bytes: 0,
+            total: None,
+            note: None,
+            started_at: None,
+            finished_at: None,
+            summarized: false,
+            smoothed_speed: None,
+            last_sample: None,
+        }
+    }
+
+    /// Fold the speed since the previous progress report into the smoothed speed.
+    fn sample(&mut self, bytes: u64) {
+        let now = Instant::now();
+        if let Some((sampled_at, sampled_bytes)) = self.last_sample {
+            let elapsed = now.duration_since(sampled_at).as_secs_f64();
+            if elapsed > 0.0 {
+                #[allow(clippy::cast_precision_loss)]
+                let speed = bytes.saturating_sub(sampled_bytes) as f64 / elapsed;
+                self.smoothed_speed = Some(self.smoothed_speed.map_or(speed, |smoothed| {
+                    SPEED_SMOOTHING.mul_add(speed - smoothed, smoothed)
+                }));
+            }
+        }
+        self.last_sample = Some((now, bytes));
+    }
+
+    /// Share of the file received so far, between 0 and 1.
+    #[must_use]
+    pub fn progress(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            #[allow(clippy::cast_precision_loss)]
+            Some(total) => Some(self.bytes as f64 / total as f64),
+            None => None,
+        }
+    }
+
+    /// Average speed in bytes per second since the transfer started.
+    pub fn speed(&self) -> Option<f64> {
+        let started_at = self.started_at?;
+        let elapsed = self
+            .finished_at
+            .unwrap_or_else(Instant::now)
+            .duration_since(started_at)
+            .as_secs_f64();
+        #[allow(clippy::cast_precision_loss)]
+        (elapsed > 0.0).then(|| self.bytes as f64 / elapsed)
+    }
+
+    /// Recent speed, smoothed so it doesn't jump with every chunk. Falls back to the
+    /// average until there are two progress reports to compare.
+    #[must_use]
+    pub fn smoothed_speed(&self) -> Option<f64> {
+        self.smoothed_speed.or_else(|| self.speed())
+    }
+
+    /// Time left at the smoothed speed, only known while active.
+    #[must_use]
+    pub fn eta(&self) -> Option<Duration> {
+        if self.state != TransferState::Active {
+            return None;
+        }
+        let speed = self.smoothed_speed().filter(|speed| *speed > 0.0)?;
+        let remaining = self.total?.saturating_sub(self.bytes);
+        #[allow(clippy::cast_precision_loss)]
+        Some(Duration::from_secs_f64(remaining as f64 / speed))
+    }
+
+    /// Throughput of a completed transfer, to be kept in the [`TransferHistory`].
+    #[must_use]
+    pub fn record(&self) -> Option<TransferRecord> {
+        if self.state != TransferState::Completed {
+            return None;
+        }
+        let seconds = self
+            .finished_at?
+            .duration_since(self.started_at?)
+            .as_secs_f64();
+        Some(TransferRecord {
+            path: self.path.clone(),
+            bytes: self.bytes,
+            seconds,
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            peer: None,
+            peer_id: None,
+        })
+    }
+}
+
+/// A completed transfer as remembered between sessions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransferRecord {
+    pub path: String,
+    pub bytes: u64,
+    pub seconds: f64,
+    /// Unix timestamp of when the transfer completed.
+    pub finished_at: u64,
+    /// Display name of the host the file came from, if it gave one.
+    #[serde(default)]
+    pub peer: Option<String>,
+    /// Peer ID of the host, to tell apart hosts that use the same name.
+    #[serde(default)]
+    pub peer_id: Option<String>,
+}
+
+impl TransferRecord {
+    /// Average speed in bytes per second.
+    #[must_use]
+    pub fn throughput(&self) -> Option<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        (self.seconds > 0.0).then(|| self.bytes as f64 / self.seconds)
+    }
+}
+
+/// How a batch of downloads went, shown when it ends and kept in the [`TransferHistory`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransferSummary {
+    pub transferred: usize,
+    /// Up to date already, kept as they were or cancelled.
+    pub skipped: usize,
+    pub failed: usize,
+    /// Arrived with other content than the host published.
+    pub mismatched: usize,
+    /// Bytes of the files transferred.
+    pub bytes: u64,
+    /// From the first transfer starting to the last one finishing.
+    pub seconds: f64,
+    /// Unix timestamp of when the batch ended.
+    pub finished_at: u64,
+}
+
+impl TransferSummary {
+    #[must_use]
+    pub const fn files(&self) -> usize {
+        self.transferred + self.skipped + self.failed + self.mismatched
+    }
+
+    /// Average speed in bytes per second.
+    #[must_use]
+    pub fn average_speed(&self) -> Option<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        (self.seconds > 0.0).then(|| self.bytes as f64 / self.seconds)
+    }
+
+    /// Add a later batch, the time spent adds up.
+    pub fn merge(&mut self, other: &Self) {
+        self.transferred += other.transferred;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.mismatched += other.mismatched;
+        self.bytes += other.bytes;
+        self.seconds += other.seconds;
+        self.finished_at = self.finished_at.max(other.finished_at);
+    }
+
+    /// One line for the status bar.
+    #[must_use]
+    pub fn headline(&self) -> String {
+        let mut line = format!(
+            "Downloaded {} files, {} skipped, {} failed",
+            self.transferred,
+            self.skipped,
+            self.failed + self.mismatched
+        );
+        if let Some(speed) = self.average_speed() {
+            let _ = write!(
+                line,
+                ", {} at {}",
+                crate::format::size(self.bytes),
+                crate::format::speed(speed)
+            );
+        }
+        line
+    }
+
+    /// A table to print once the UI closed.
+    pub fn table(&self) -> String {
+        let speed = self
+            .average_speed()
+            .map_or_else(|| "-".to_string(), crate::format::speed);
+        let rows = [
+            ("Transferred", self.transferred.to_string()),
+            ("Skipped", self.skipped.to_string()),
+            ("Failed", self.failed.to_string()),
+            ("Hash mismatches", self.mismatched.to_string()),
+            ("Total size", crate::format::size(self.bytes)),
+            (
+                "Elapsed",
+                crate::format::duration(Duration::from_secs_f64(self.seconds)),
+            ),
+            ("Average speed", speed),
+        ];
+        let mut table = "Download summary".to_string();
+        for (label, value) in rows {
+            let _ = write!(table, "\n  {label:<16}{value}");
+        }
+        table
+    }
+}
+
+/// Where completed transfers are recorded, one JSON line each.
+#[must_use]
+pub fn history_path() -> Option<PathBuf> {
+    crate::config::state_dir().map(|dir| dir.join("transfer-history.jsonl"))
+}
+
+/// Transfers completed in earlier sessions.
+#[derive(Debug, Clone, Default)]
+pub struct TransferHistory {
+    records: Vec<TransferRecord>,
+}
+
+impl TransferHistory {
+    /// Load the records written so far, lines that don't parse are skipped.
+    #[must_use]
+    pub fn load(path: &Path) -> Self {
+        let records = std::fs::read_to_string(path)
+            .map(|history| {
+                history
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { records }
+    }
+
+    /// Add a record to the file, later sessions see it. [`TransferSummary`] lines go into
+    /// the same file and are left out when it is loaded.
+    ///
+    /// # Errors
+    ///
+    /// If `path` or its directory can't be written.
+    pub fn append(path: &Path, record: &impl Serialize) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)
+    }
+
+    /// The most recent earlier transfer of the same file.
+    #[must_use]
+    pub fn previous(&self, path: &str) -> Option<&TransferRecord> {
+        self.records.iter().rev().find(|record| record.path == path)
+    }
+}
+
+/// Downloads of the current session, fed by the network events.
+#[derive(Debug, Clone, Default)]
+pub struct TransferManager {
+    transfers: Vec<Transfer>,
+}
+
+impl TransferManager {
+    #[must_use]
+    pub fn transfers(&self) -> &[Transfer] {
+        &self.transfers
+    }
+
+    /// Average speed of the transfers seen so far, a rough measure of the link.
+    pub fn link_speed(&self) -> Option<f64> {
+        let speeds: Vec<f64> = self
+            .transfers
+            .iter()
+            .filter(|transfer| transfer.state != TransferState::Queued)
+            .filter_map(Transfer::speed)
+            .collect();
+        #[allow(clippy::cast_precision_loss)]
+        (!speeds.is_empty()).then(|| speeds.iter().sum::<f64>() / speeds.len() as f64)
+    }
+
+    /// Add requested files, a file requested again starts over.
+    pub fn queue(&mut self, paths: Vec<String>) {
+        for path in paths {
+            self.transfers.retain(|transfer| transfer.path != path);
+            self.transfers.push(Transfer::new(path));
+        }
+    }
+
+    /// Record the size a queued file will have, before its transfer starts.
+    pub fn expect(&mut self, path: &str, total: u64) {
+        self.get_or_insert(path).total.get_or_insert(total);
+    }
+
+    /// Bytes received and expected over all transfers whose size is known.
+    #[must_use]
+    pub fn totals(&self) -> (u64, u64) {
+        self.transfers
+            .iter()
+            .filter_map(|transfer| {
+                let total = transfer.total?;
+                let bytes = match transfer.state {
+                    TransferState::Completed | TransferState::UpToDate | TransferState::Kept => {
+                        total
+                    }
+                    _ => transfer.bytes,
+                };
+                Some((bytes, total))
+            })
+            .fold((0, 0), |(bytes, total), (b, t)| (bytes + b, total + t))
+    }
+
+    pub fn progress(&mut self, path: &str, bytes: u64, total: u64) {
+        let transfer = self.get_or_insert(path);
+        if transfer.state == TransferState::Queued {
+            transfer.state = TransferState::Active;
+            transfer.started_at = Some(Instant::now());
+        }
+        transfer.sample(bytes);
+        transfer.bytes = bytes;
+        transfer.total = Some(total);
+    }
+
+    /// Mark a transfer done, returns its record for the [`TransferHistory`].
+    pub fn complete(&mut self, path: &str) -> Option<TransferRecord> {
+        let transfer = self.get_or_insert(path);
+        if let Some(total) = transfer.total {
+            transfer.bytes = total;
+        }
+        transfer.state = TransferState::Completed;
+        transfer.finished_at = Some(Instant::now());
+        transfer.record()
+    }
+
+    pub fn up_to_date(&mut self, path: &str) {
+        let transfer = self.get_or_insert(path);
+        transfer.state = TransferState::UpToDate;
+        transfer.finished_at = Some(Instant::now());
+    }
+
+    /// Hold a queued or running transfer, or let it go on again.
+    pub fn pause(&mut self, path: &str, paused: bool) {
+        let transfer = self.get_or_insert(path);
+        transfer.state = match (paused, &transfer.state) {
+            (true, TransferState::Queued | TransferState::Active) => TransferState::Paused,
+            (false, TransferState::Paused) if transfer.started_at.is_some() => {
+                TransferState::Active
+            }
+            (false, TransferState::Paused) => TransferState::Queued,
+            (_, state) => state.clone(),
+        };
+    }
+
+    pub fn cancel(&mut self, path: &str) {
+        let transfer = self.get_or_insert(path);
+        transfer.state = TransferState::Cancelled;
+        transfer.finished_at = Some(Instant::now());
+    }
+
+    /// Note what was done about an existing file at the destination.
+    pub fn conflict(&mut self, path: &str, conflict: &Conflict) {
+        let transfer = self.get_or_insert(path);
+        if *conflict == Conflict::Skipped {
+            transfer.state = TransferState::Kept;
+            transfer.finished_at = Some(Instant::now());
+        } else {
+            transfer.note = Some(conflict.to_string());
+        }
+    }
+
+    pub fn fail(&mut self, path: &str, error: String) {
+        let transfer = self.get_or_insert(path);
+        transfer.state = TransferState::Failed(error);
+        transfer.finished_at = Some(Instant::now());
+    }
+
+    pub fn corrupted(&mut self, path: &str) {
+        let transfer = self.get_or_insert(path);
+        transfer.state = TransferState::Corrupted;
+        transfer.finished_at = Some(Instant::now());
+    }
+
+    /// Sum up the transfers that finished since the last summary, `None` if none did.
+    pub fn take_summary(&mut self) -> Option<TransferSummary> {
+        let mut summary = TransferSummary::default();
+        let (mut started, mut finished): (Option<Instant>, Option<Instant>) = (None, None);
+        for transfer in &mut self.transfers {
+            if transfer.summarized || !transfer.state.is_finished() {
+                continue;
+            }
+            transfer.summarized = true;
+            match transfer.state {
+                TransferState::Completed => {
+                    summary.transferred += 1;
+                    summary.bytes += transfer.bytes;
+                }
+                TransferState::UpToDate | TransferState::Kept | TransferState::Cancelled => {
+                    summary.skipped += 1;
+                }
+                TransferState::Failed(_) => summary.failed += 1,
+                TransferState::Corrupted => summary.mismatched += 1,
+                TransferState::Queued | TransferState::Active | TransferState::Paused => {}
+            }
+            if let Some(at) = transfer.started_at {
+                started = Some(started.map_or(at, |started| started.min(at)));
+            }
+            finished = finished.max(transfer.finished_at);
+        }
+        if summary.files() == 0 {
+            return None;
+        }
+        if let (Some(started), Some(finished)) = (started, finished) {
+            summary.seconds = finished.duration_since(started).as_secs_f64();
+        }
+        summary.finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        Some(summary)
+    }
+
+    fn get_or_insert(&mut self, path: &str) -> &mut Transfer {
+        let index = self
+            .transfers
+            .iter()
+            .position(|transfer| transfer.path == path)
+            .unwrap_or_else(|| {
+                self.transfers.push(Transfer::new(path.to_string()));
+                self.transfers.len() - 1
+            });
+        &mut self.transfers[index]
+    }
+}
+
+/// A file a peer is downloading from us right now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    pub peer_id: PeerId,
+    pub path: String,
+    pub bytes: u64,
+    pub total: Option<u64>,
+}
+
+impl Upload {
+    /// Share of the file sent so far, between 0 and 1.
+    #[must_use]
+    pub fn progress(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            #[allow(clippy::cast_precision_loss)]
+            Some(total) => Some(self.bytes as f64 / total as f64),
+            None => None,
+        }
+    }
+}
+
+/// Uploads of a share since it started, summed up for the host's dashboard.
+#[derive(Debug, Clone)]
+pub struct ShareStats {
+    started_at: Instant,
+    peers: HashSet<PeerId>,
+    bytes: u64,
+    /// Completed downloads of each file.
+    downloads: HashMap<String, usize>,
+    /// Uploads running right now, oldest first.
+    active: Vec<Upload>,
+}
+
+impl Default for ShareStats {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            peers: HashSet::new(),
+            bytes: 0,
+            downloads: HashMap::new(),
+            active: Vec::new(),
+        }
+    }
+}
+
+impl ShareStats {
+    pub fn upload_started(&mut self, peer_id: PeerId, path: String) {
+        self.active.push(Upload {
+            peer_id,
+            path,
+            bytes: 0,
+            total: None,
+        });
+    }
+
+    pub fn upload_progress(&mut self, peer_id: PeerId, path: &str, bytes: u64, total: u64) {
+        if let Some(upload) = self
+            .active
+            .iter_mut()
+            .find(|upload| upload.peer_id == peer_id && upload.path == path)
+        {
+            upload.bytes = bytes;
+            upload.total = Some(total);
+        }
+    }
+
+    pub fn upload_completed(&mut self, peer_id: PeerId, path: String, bytes: u64) {
+        self.peers.insert(peer_id);
+        self.bytes += bytes;
+        self.finish(peer_id, &path);
+        *self.downloads.entry(path).or_default() += 1;
+    }
+
+    /// The upload stopped early, what was sent still counts towards the bytes.
+    pub fn upload_failed(&mut self, peer_id: PeerId, path: &str, bytes: u64) {
+        self.bytes += bytes;
+        self.finish(peer_id, path);
+    }
+
+    fn finish(&mut self, peer_id: PeerId, path: &str) {
+        if let Some(index) = self
+            .active
+            .iter()
+            .position(|upload| upload.peer_id == peer_id && upload.path == path)
+        {
+            self.active.remove(index);
+        }
+    }
+
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Peers that downloaded at least one whole file.
+    #[must_use]
+    pub fn peers_served(&self) -> usize {
+        self.peers.len()
+    }
+
+    #[must_use]
+    pub const fn bytes_served(&self) -> u64 {
+        self.bytes
+    }
+
+    #[must_use]
+    pub const fn active_uploads(&self) -> usize {
+        self.active.len()
+    }
+
+    #[must_use]
+    pub fn uploads(&self) -> &[Upload] {
+        &self.active
+    }
+
+    /// The `count` files downloaded most often, ties by path.
+    #[must_use]
+    pub fn top_files(&self, count: usize) -> Vec<(&str, usize)> {
+        let mut files: Vec<(&str, usize)> = self
+            .downloads
+            .iter()
+            .map(|(path, downloads)| (path.as_str(), *downloads))
+            .collect();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        files.truncate(count);
+        files
+    }
+}
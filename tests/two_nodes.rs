@@ -0,0 +1,415 @@
+//! Two real peers on loopback going through share → browse → select → download, the way the
+//! terminal app does it, so that regressions only a live swarm shows, like files flowing
+//! the wrong way, fail here.
+
+use futures::StreamExt;
+use junkanoo::app::{App, AppState, ConnectionState};
+use junkanoo::get::{self, ProgressBars};
+use junkanoo::service::archive::ArchiveFormat;
+use junkanoo::service::client::NetworkClient;
+use junkanoo::service::hashing::HashCache;
+use junkanoo::service::node::{self, listen_addrs, Client, Event};
+use junkanoo::service::utils::{self, transfer_path, SymlinkPolicy};
+use junkanoo::{ls, shared_items, JunkanooNode, NodeConfig, ShareSession};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::mpsc;
+
+/// How long a step may take before the test gives up on it.
+const STEP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A running node listening on loopback, with its events collected.
+struct Peer {
+    client: Client,
+    id: PeerId,
+    events: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Peer {
+    async fn start() -> Self {
+        let (client, events, event_loop, peer_id) = node::new(&lan_only()).unwrap();
+        tokio::spawn(event_loop.run());
+
+        // The event loop waits for every event to be taken
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(events.for_each(move |event| {
+            let _ = sender.send(event);
+            async {}
+        }));
+
+        for addr in listen_addrs(Some("127.0.0.1".parse().unwrap()), 0) {
+            client.start_listening(addr).await.unwrap();
+        }
+        Self {
+            client,
+            id: peer_id,
+            events: receiver,
+        }
+    }
+
+    /// A TCP address this peer is reachable at, once it has one.
+    async fn address(&self) -> Multiaddr {
+        loop {
+            let tcp = self
+                .client
+                .get_listening_addrs()
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|addr| addr.iter().any(|p| matches!(p, Protocol::Tcp(_))));
+            if let Some(addr) = tcp {
+                return addr.with(Protocol::P2p(self.id));
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Wait for the first event `matches` picks, skipping the others.
+    async fn wait_for<T>(&mut self, mut matches: impl FnMut(Event) -> Option<T>) -> T {
+        tokio::time::timeout(STEP_TIMEOUT, async {
+            loop {
+                let event = self.events.recv().await.expect("node stopped");
+                if let Some(found) = matches(event) {
+                    return found;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for an event")
+    }
+}
+
+/// A host sharing a directory and a downloader browsing it through an [`App`].
+struct Harness {
+    host: Peer,
+    downloader: Peer,
+    shared: TempDir,
+    app: App,
+}
+
+impl Harness {
+    /// Share `files`, relative paths with their contents, and connect a downloader to them.
+    async fn new(files: &[(&str, &[u8])]) -> Self {
+        // Hidden names are left out of the listing, like the default `.tmp` prefix
+        let shared = tempfile::Builder::new().prefix("share").tempdir().unwrap();
+        for (path, contents) in files {
+            let path = shared.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let host = Peer::start().await;
+        let paths: Vec<PathBuf> = utils::walk(shared.path(), SymlinkPolicy::default()).collect();
+        let items = shared_items(&paths, SymlinkPolicy::default(), &mut HashCache::default());
+        host.client.update_directory_items(items).await.unwrap();
+
+        let downloader = Peer::start().await;
+        let address = host.address().await;
+        downloader.client.dial(host.id, address).await.unwrap();
+        downloader.client.greet(host.id, None).await.unwrap();
+
+        let mut app = App::new();
+        app.state = AppState::Download;
+        app.set_client(Arc::new(downloader.client.clone()));
+        app.connection_state = ConnectionState::Connected;
+        app.connected_peer_id = Some(host.id);
+
+        Self {
+            host,
+            downloader,
+            shared,
+            app,
+        }
+    }
+
+    /// Browse the whole share, as the app does once connected, and open the shared directory.
+    async fn browse(&mut self) {
+        let display = NetworkClient::request_directory(&self.downloader.client, self.host.id)
+            .await
+            .unwrap();
+        self.app.share_label = display.label;
+        self.app.current_path = PathBuf::new();
+        let listing = NetworkClient::list_directory(
+            &self.downloader.client,
+            self.host.id,
+            String::new(),
+            true,
+        )
+        .await
+        .unwrap();
+        self.app.apply_listing(PathBuf::new(), true, listing.items);
+        let root = self
+            .shared
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        self.move_to(&root);
+        assert!(self.app.enter_directory());
+    }
+
+    /// Put the cursor on the row named `name`.
+    fn move_to(&mut self, name: &str) {
+        let index = self
+            .app
+            .directory_items
+            .iter()
+            .position(|item| item.name == name)
+            .unwrap_or_else(|| panic!("{name} is not listed"));
+        self.app.selected_index = Some(index);
+    }
+
+    /// Select the row named `name` in the directory being viewed.
+    fn select(&mut self, name: &str) {
+        self.move_to(name);
+        self.app.select_item();
+    }
+
+    /// Download the selection into `destination` and wait for both sides to finish.
+    async fn download(&mut self, destination: &Path) -> Vec<String> {
+        self.app.sync_directory = Some(destination.to_path_buf());
+        self.app.start_download().await;
+        let saved = self
+            .downloader
+            .wait_for(|event| match event {
+                Event::DownloadCompleted(saved) => Some(saved),
+                Event::DownloadFailed(failed) => panic!("download failed: {failed:?}"),
+                _ => None,
+            })
+            .await;
+        for _ in &saved {
+            let downloader = self.downloader.id;
+            self.host
+                .wait_for(|event| match event {
+                    Event::UploadCompleted { peer_id, .. } => {
+                        assert_eq!(peer_id, downloader, "the host uploaded to someone else");
+                        Some(())
+                    }
+                    Event::DownloadCompleted(_) => panic!("the host downloaded instead"),
+                    _ => None,
+                })
+                .await;
+        }
+        saved
+    }
+
+    /// Where a shared file ends up below `destination`.
+    fn received(&self, destination: &Path, path: &str) -> PathBuf {
+        destination.join(transfer_path(&self.shared.path().join(path)))
+    }
+}
+
+/// Share `files`, relative paths with their contents, through the embedding API. Returns
+/// the share with an address downloaders dial, and the shared directory.
+async fn start_share(files: &[(&str, &[u8])]) -> (ShareSession, Multiaddr, TempDir) {
+    let shared = tempfile::Builder::new().prefix("share").tempdir().unwrap();
+    for (path, contents) in files {
+        let path = shared.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let host = JunkanooNode::start_on(
+        lan_only(),
+        listen_addrs(Some("127.0.0.1".parse().unwrap()), 0),
+    )
+    .await
+    .unwrap();
+    let mut share = host.share(vec![shared.path().to_path_buf()]).await.unwrap();
+    let address = tokio::time::timeout(STEP_TIMEOUT, async {
+        loop {
+            if let Some(address) = share.addresses().await.unwrap().pop() {
+                return address;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the share's address");
+    (share, address, shared)
+}
+
+/// A node that stays off the public DHT.
+fn lan_only() -> NodeConfig {
+    NodeConfig {
+        lan_only: true,
+        ..NodeConfig::default()
+    }
+}
+
+/// Everything below `directory`, relative to it.
+fn tree(directory: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = utils::walk(directory, SymlinkPolicy::Skip)
+        .map(|path| path.strip_prefix(directory).unwrap().to_path_buf())
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[tokio::test]
+async fn test_share_browse_select_download() {
+    let mut harness = Harness::new(&[
+        ("notes.txt", b"from the host"),
+        ("other.txt", b"not selected"),
+    ])
+    .await;
+    let shared_before = tree(harness.shared.path());
+
+    harness.browse().await;
+    harness.select("notes.txt");
+    let destination = TempDir::new().unwrap();
+    let saved = harness.download(destination.path()).await;
+
+    assert_eq!(saved.len(), 1);
+    assert_eq!(
+        fs::read(harness.received(destination.path(), "notes.txt")).unwrap(),
+        b"from the host"
+    );
+    assert!(!harness.received(destination.path(), "other.txt").exists());
+    // Files flow from host to downloader, never the other way
+    assert_eq!(tree(harness.shared.path()), shared_before);
+    assert_eq!(
+        fs::read(harness.shared.path().join("notes.txt")).unwrap(),
+        b"from the host"
+    );
+}
+
+#[tokio::test]
+async fn test_selected_directory_downloads_its_files() {
+    let mut harness = Harness::new(&[
+        ("docs/a.txt", b"first"),
+        ("docs/deeper/b.txt", b"second"),
+        ("readme.txt", b"left behind"),
+    ])
+    .await;
+
+    harness.browse().await;
+    harness.select("docs");
+    let destination = TempDir::new().unwrap();
+    let saved = harness.download(destination.path()).await;
+
+    assert_eq!(saved.len(), 2);
+    assert_eq!(
+        fs::read(harness.received(destination.path(), "docs/a.txt")).unwrap(),
+        b"first"
+    );
+    assert_eq!(
+        fs::read(harness.received(destination.path(), "docs/deeper/b.txt")).unwrap(),
+        b"second"
+    );
+    assert!(!harness.received(destination.path(), "readme.txt").exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ls_remote_share() {
+    let (share, address, _shared) =
+        start_share(&[("docs/report.txt", &[0u8; 2048]), ("notes.txt", b"hello")]).await;
+
+    let listing = ls::fetch(lan_only(), address, None).await.unwrap();
+    let tree = ls::tree(&listing);
+    let lines: Vec<&str> = tree.lines().collect();
+    assert_eq!(lines[0], "docs/", "{tree}");
+    assert!(lines[1].starts_with("  report.txt "), "{tree}");
+    assert!(lines[2].starts_with("notes.txt "), "{tree}");
+    assert_eq!(lines[3], "2 files, 2.0 KiB in total", "{tree}");
+    share.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_get_named_paths() {
+    let (share, address, _shared) =
+        start_share(&[("docs/report.txt", &[7u8; 2048]), ("notes.txt", b"hello")]).await;
+
+    let target = TempDir::new().unwrap();
+    let mut bars = ProgressBars::default();
+    let mut finished = Vec::new();
+    let saved = get::fetch(
+        lan_only(),
+        address.clone(),
+        None,
+        &[PathBuf::from("docs")],
+        Some(target.path().to_path_buf()),
+        None,
+        |event| finished.extend(bars.line(event).filter(|(_, stays)| *stays)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 1, "{saved:?}");
+    let report = utils::walk(target.path(), SymlinkPolicy::default())
+        .find(|path| path.ends_with("report.txt"))
+        .unwrap();
+    assert_eq!(fs::read(report).unwrap(), [7u8; 2048]);
+    assert!(
+        finished.iter().any(|(line, _)| line.contains("100%")),
+        "{finished:?}"
+    );
+
+    let error = get::fetch(
+        lan_only(),
+        address,
+        None,
+        &[PathBuf::from("missing.txt")],
+        Some(target.path().to_path_buf()),
+        None,
+        |_| {},
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.to_string(), "missing.txt is not shared by the host");
+    share.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_get_directory_as_archive() {
+    use async_compression::tokio::bufread::GzipDecoder;
+    use std::io::Read;
+    use tokio::io::AsyncReadExt;
+
+    let long_name = format!("docs/{}/{}.txt", "a".repeat(60), "b".repeat(80));
+    let (share, address, _shared) = start_share(&[
+        (&long_name, &[3u8; 1500]),
+        ("docs/report.txt", b"report"),
+        ("notes.txt", b"hello"),
+    ])
+    .await;
+
+    let target = TempDir::new().unwrap();
+    let saved = get::fetch(
+        lan_only(),
+        address,
+        None,
+        &[PathBuf::from("docs"), PathBuf::from("notes.txt")],
+        Some(target.path().to_path_buf()),
+        Some(ArchiveFormat::TarGz),
+        |_| {},
+    )
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2, "{saved:?}");
+    assert_eq!(saved[0], "docs.tar.gz");
+    assert_eq!(fs::read(target.path().join(&saved[1])).unwrap(), b"hello");
+
+    let compressed = fs::read(target.path().join("docs.tar.gz")).unwrap();
+    let mut archive = Vec::new();
+    GzipDecoder::new(compressed.as_slice())
+        .read_to_end(&mut archive)
+        .await
+        .unwrap();
+    let mut contents = std::collections::BTreeMap::new();
+    for entry in tar::Archive::new(archive.as_slice()).entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_string_lossy().to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).unwrap();
+        contents.insert(path, bytes);
+    }
+    assert_eq!(contents[&long_name], [3u8; 1500]);
+    assert_eq!(contents["docs/report.txt"], b"report");
+    assert!(contents.contains_key("docs"), "{:?}", contents.keys());
+    share.close().await.unwrap();
+}